@@ -0,0 +1,11 @@
+CREATE TABLE ai_usage (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL,
+    prompt_tokens BIGINT NOT NULL,
+    candidates_tokens BIGINT NOT NULL,
+    total_tokens BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX ai_usage_user_created_at_idx ON ai_usage (user_id, created_at);
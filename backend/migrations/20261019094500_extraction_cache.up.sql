@@ -0,0 +1,9 @@
+CREATE TABLE extraction_cache (
+    cache_key TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    events JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX extraction_cache_expires_at_idx ON extraction_cache (expires_at);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS extraction_feedback;
+DROP TABLE IF EXISTS extractions;
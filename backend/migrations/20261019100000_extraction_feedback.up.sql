@@ -0,0 +1,22 @@
+CREATE TABLE extractions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    timezone TEXT NOT NULL,
+    locale TEXT,
+    output_language TEXT,
+    events JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX extractions_user_id_idx ON extractions (user_id, created_at);
+
+CREATE TABLE extraction_feedback (
+    extraction_id UUID PRIMARY KEY REFERENCES extractions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    saved JSONB NOT NULL,
+    diff JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX extraction_feedback_user_id_idx ON extraction_feedback (user_id, updated_at);
@@ -0,0 +1,13 @@
+ALTER TABLE recurring_event_groups DROP COLUMN IF EXISTS extraction_id;
+ALTER TABLE recurring_events DROP COLUMN IF EXISTS extraction_id;
+ALTER TABLE calendar_events DROP COLUMN IF EXISTS extraction_id;
+
+ALTER TABLE extractions
+    DROP COLUMN IF EXISTS modality,
+    DROP COLUMN IF EXISTS input_hash,
+    DROP COLUMN IF EXISTS input_content_type,
+    DROP COLUMN IF EXISTS input_context,
+    DROP COLUMN IF EXISTS input,
+    DROP COLUMN IF EXISTS model,
+    DROP COLUMN IF EXISTS prompt_version,
+    DROP COLUMN IF EXISTS extraction_text;
@@ -0,0 +1,22 @@
+-- Where an extraction came from; these are NULL for extractions recorded before they were kept
+ALTER TABLE extractions
+    ADD COLUMN modality VARCHAR,
+    ADD COLUMN input_hash VARCHAR,
+    ADD COLUMN input_content_type VARCHAR,
+    ADD COLUMN input_context TEXT,
+    -- only kept if it was small enough
+    ADD COLUMN input BYTEA,
+    ADD COLUMN model VARCHAR,
+    ADD COLUMN prompt_version VARCHAR,
+    ADD COLUMN extraction_text TEXT;
+
+ALTER TABLE calendar_events
+    ADD COLUMN extraction_id UUID REFERENCES extractions(id) ON DELETE SET NULL;
+ALTER TABLE recurring_events
+    ADD COLUMN extraction_id UUID REFERENCES extractions(id) ON DELETE SET NULL;
+ALTER TABLE recurring_event_groups
+    ADD COLUMN extraction_id UUID REFERENCES extractions(id) ON DELETE SET NULL;
+
+CREATE INDEX calendar_events_extraction_id_idx ON calendar_events (extraction_id);
+CREATE INDEX recurring_events_extraction_id_idx ON recurring_events (extraction_id);
+CREATE INDEX recurring_event_groups_extraction_id_idx ON recurring_event_groups (extraction_id);
@@ -0,0 +1,5 @@
+ALTER TABLE recurring_event_groups DROP COLUMN IF EXISTS import_batch_id;
+ALTER TABLE recurring_events DROP COLUMN IF EXISTS import_batch_id;
+ALTER TABLE calendar_events DROP COLUMN IF EXISTS import_batch_id;
+
+DROP TABLE IF EXISTS import_batches;
@@ -0,0 +1,22 @@
+-- Everything created in one go (e.g. saved from an extraction, or by an Outlook sync), so it can be undone in one go
+CREATE TABLE import_batches (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    source VARCHAR NOT NULL,
+    extraction_id UUID REFERENCES extractions(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    undone_at TIMESTAMPTZ
+);
+
+CREATE INDEX import_batches_user_id_idx ON import_batches (user_id, created_at);
+
+ALTER TABLE calendar_events
+    ADD COLUMN import_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+ALTER TABLE recurring_events
+    ADD COLUMN import_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+ALTER TABLE recurring_event_groups
+    ADD COLUMN import_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+
+CREATE INDEX calendar_events_import_batch_id_idx ON calendar_events (import_batch_id);
+CREATE INDEX recurring_events_import_batch_id_idx ON recurring_events (import_batch_id);
+CREATE INDEX recurring_event_groups_import_batch_id_idx ON recurring_event_groups (import_batch_id);
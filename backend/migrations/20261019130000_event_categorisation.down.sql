@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS categorisation_rules;
+
+DROP INDEX IF EXISTS calendar_events_group_id_idx;
+ALTER TABLE calendar_events DROP COLUMN IF EXISTS organiser;
+ALTER TABLE calendar_events DROP COLUMN IF EXISTS group_id;
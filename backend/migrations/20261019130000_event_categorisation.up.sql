@@ -0,0 +1,20 @@
+-- One-off events can be put in a group too (e.g. when categorising events from an Outlook sync), and keep who 
+-- organised them, to categorise by
+ALTER TABLE calendar_events
+    ADD COLUMN group_id UUID REFERENCES recurring_event_groups(id) ON DELETE SET NULL,
+    ADD COLUMN organiser VARCHAR;
+
+CREATE INDEX calendar_events_group_id_idx ON calendar_events (group_id);
+
+-- Rules putting imported events in a group, saved from accepted categorisation suggestions
+CREATE TABLE categorisation_rules (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    group_id UUID NOT NULL REFERENCES recurring_event_groups(id) ON DELETE CASCADE,
+    field VARCHAR NOT NULL,
+    -- lowercase text the field contains
+    pattern VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    UNIQUE (user_id, field, pattern)
+);
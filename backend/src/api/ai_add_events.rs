@@ -1,26 +1,204 @@
 use axum::{
-    extract::{Multipart, State}, 
-    routing::post, 
+    extract::{DefaultBodyLimit, Path, Query, State}, 
+    http::{header, StatusCode},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse},
+    routing::{get, post}, 
     Json, Router,
 };
+use futures::{stream, Stream};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize};
+use uuid::Uuid;
 use crate::{
-    api::{error::{ApiError, ApiResult}, AppState}, auth::types::AuthUser, llm::GeneratedEvents,
+    api::{ai_upload::{AIUpload, AudioField, EmailField, ImageField, UploadField}, error::{ApiError, ApiResult}, AppState}, 
+    auth::types::AuthUser, 
+    config::UploadLimitsConfig,
+    llm::GeneratedEvents, 
+    models::{
+        ai_usage::AIAllowance, 
+        calendar_edit::{CalendarEdit, EditProposal}, 
+        calendar_query::CalendarAnswer, 
+        extraction::Extraction, 
+        language::LanguageTag, 
+        time::UserTimezone
+    },
+    services::{
+        ai_add_events_service::{ExtractionOptions, ExtractionProgress},
+        extractions_service::FeedbackFixture,
+        extraction_jobs_service::{ExtractionInput, ExtractionJob},
+        recurrence_builder_service::BuiltRecurrence
+    },
 };
 
 /// The struct for a text request.
+/// 
+/// Exactly one of `timezone` (an IANA name, preferred) or `timezone_offset_minutes` must be given.
+/// `use_calendar_context` (default `false`) gives the LLM the user's existing groups and events,
+/// `include_extraction_text` (default `false`) returns the LLM's intermediate extraction text, and
+/// `bypass_cache` (default `false`) skips looking for a cached extraction of the same input.
+/// `locale` and `output_language` are optional BCP 47 tags; the locale is used to interpret ambiguous dates,
+/// and titles, descriptions and locations are written in the output language (by default, the input's language).
 #[derive(Deserialize)]
 struct TextToEventRequest {
     text: String,
-    timezone_offset_minutes: i32
+    timezone: Option<String>,
+    timezone_offset_minutes: Option<i32>,
+    #[serde(default)]
+    use_calendar_context: bool,
+    #[serde(default)]
+    include_extraction_text: bool,
+    #[serde(default)]
+    bypass_cache: bool,
+    locale: Option<String>,
+    output_language: Option<String>
+}
+
+impl TextToEventRequest {
+    /// Split the request into its text and the extraction options.
+    fn into_parts(self) -> ApiResult<(String, ExtractionOptions)> {
+        let options = ExtractionOptions {
+            timezone: parse_timezone(self.timezone, self.timezone_offset_minutes)?,
+            now: Utc::now(),
+            use_calendar_context: self.use_calendar_context,
+            include_extraction_text: self.include_extraction_text,
+            bypass_cache: self.bypass_cache,
+            locale: parse_language_tag("locale", self.locale)?,
+            output_language: parse_language_tag("output_language", self.output_language)?,
+            progress: ExtractionProgress::default()
+        };
+        Ok((self.text, options))
+    }
+}
+
+/// The struct for an edit request.
+/// 
+/// Timezones are given as in `TextToEventRequest`.
+#[derive(Deserialize)]
+struct EditRequest {
+    instruction: String,
+    timezone: Option<String>,
+    timezone_offset_minutes: Option<i32>
+}
+
+/// The struct for a question about the user's calendar, like "when is my next dentist appointment?".
+/// 
+/// Timezones are given as in `TextToEventRequest`.
+#[derive(Deserialize)]
+struct QuestionRequest {
+    question: String,
+    timezone: Option<String>,
+    timezone_offset_minutes: Option<i32>
+}
+
+/// The struct for building a recurrence rule from a phrase, like "every other Tuesday until the end of June".
+/// 
+/// `start` is the local datetime of the first instance, and timezones are given as in `TextToEventRequest`.
+/// `preview_instances` (default 10, at most 100) is how many of the first instances to return.
+#[derive(Deserialize)]
+struct RecurrenceRequest {
+    text: String,
+    start: NaiveDateTime,
+    timezone: Option<String>,
+    timezone_offset_minutes: Option<i32>,
+    preview_instances: Option<u16>
+}
+
+/// The struct for applying edits the user has confirmed.
+#[derive(Deserialize)]
+struct ApplyEditsRequest {
+    edits: Vec<CalendarEdit>
+}
+
+/// The query params for exporting feedback; `since` only exports extractions with feedback recorded since then.
+#[derive(Deserialize)]
+struct FeedbackExportQuery {
+    since: Option<DateTime<Utc>>
+}
+
+/// The struct for re-running an extraction; the options are as in `TextToEventRequest`, while the timezone, locale and
+/// output language are the extraction's own.
+#[derive(Deserialize)]
+struct RerunExtractionRequest {
+    #[serde(default)]
+    use_calendar_context: bool,
+    #[serde(default)]
+    include_extraction_text: bool
 }
 
 /// Build the router for AI event routes.
-pub(super) fn router() -> Router<AppState> {
+pub(super) fn router(limits: &UploadLimitsConfig) -> Router<AppState> {
     Router::new()
-        .route("/text", post(process_text_to_events))
-        .route("/audio", post(process_audio_to_events))
-        .route("/image", post(process_image_to_events))
+        .route(
+            "/text", 
+            post(process_text_to_events).layer(DefaultBodyLimit::max(limits.max_text_bytes))
+        )
+        .route(
+            "/audio", 
+            post(process_audio_to_events).layer(DefaultBodyLimit::max(AudioField::max_body_bytes(limits)))
+        )
+        .route(
+            "/image", 
+            post(process_image_to_events).layer(DefaultBodyLimit::max(ImageField::max_body_bytes(limits)))
+        )
+        .route(
+            "/email", 
+            post(process_email_to_events).layer(DefaultBodyLimit::max(EmailField::max_body_bytes(limits)))
+        )
+        .route(
+            "/edit", 
+            post(propose_edits).layer(DefaultBodyLimit::max(limits.max_text_bytes))
+        )
+        .route("/edit/apply", post(apply_edits))
+        .route(
+            "/ask", 
+            post(ask_question).layer(DefaultBodyLimit::max(limits.max_text_bytes))
+        )
+        .route(
+            "/rrule", 
+            post(build_recurrence).layer(DefaultBodyLimit::max(limits.max_text_bytes))
+        )
+        .route(
+            "/jobs/text", 
+            post(submit_text_job).layer(DefaultBodyLimit::max(limits.max_text_bytes))
+        )
+        .route(
+            "/jobs/audio", 
+            post(submit_audio_job).layer(DefaultBodyLimit::max(AudioField::max_body_bytes(limits)))
+        )
+        .route(
+            "/jobs/image", 
+            post(submit_image_job).layer(DefaultBodyLimit::max(ImageField::max_body_bytes(limits)))
+        )
+        .route(
+            "/jobs/email", 
+            post(submit_email_job).layer(DefaultBodyLimit::max(EmailField::max_body_bytes(limits)))
+        )
+        .route("/jobs/{job_id}", get(get_job))
+        .route("/jobs/{job_id}/events", get(stream_job))
+        .route("/usage", get(get_usage))
+        .route("/feedback/export", get(export_feedback))
+        .route("/extractions/{extraction_id}", get(get_extraction))
+        .route("/extractions/{extraction_id}/input", get(get_extraction_input))
+        .route("/extractions/{extraction_id}/rerun", post(rerun_extraction))
+}
+
+/// Parse the timezone fields of a JSON request, where exactly one must be given.
+pub(super) fn parse_timezone(timezone: Option<String>, timezone_offset_minutes: Option<i32>) -> ApiResult<UserTimezone> {
+    match (timezone, timezone_offset_minutes) {
+        (Some(name), None) => UserTimezone::from_name(&name)
+            .ok_or_else(|| ApiError::unprocessable_entity([("timezone", "is not a valid IANA timezone name")])),
+        (None, Some(offset)) => UserTimezone::from_offset_minutes(offset)
+            .ok_or_else(|| ApiError::unprocessable_entity([("timezone_offset_minutes", "is not a valid offset in minutes")])),
+        _ => Err(ApiError::unprocessable_entity([
+            ("timezone", "exactly one of `timezone` or `timezone_offset_minutes` is required")
+        ]))
+    }
+}
+
+/// Parse an optional BCP 47 language tag field of a JSON request.
+fn parse_language_tag(field: &'static str, tag: Option<String>) -> ApiResult<Option<LanguageTag>> {
+    tag.map(|tag| LanguageTag::parse(&tag).ok_or_else(|| ApiError::unprocessable_entity([(field, "is not a valid BCP 47 language tag")])))
+        .transpose()
 }
 
 async fn process_text_to_events(
@@ -28,83 +206,291 @@ async fn process_text_to_events(
     user: AuthUser,
     Json(request): Json<TextToEventRequest>,
 ) -> ApiResult<Json<GeneratedEvents>> {
+    let (text, options) = request.into_parts()?;
     let events = app_state.services.ai_add_events
-        .generate_from_text(request.text, request.timezone_offset_minutes)
+        .generate_from_text(user.id, text, options)
         .await?;
     Ok(Json(events))
 }
 
 /// Handler for processing audio into generated events.
 /// 
-/// The multipart expects 2 fields:
-/// - `audio`, containing the binary audio data
-/// - `timezone_offset_minutes`, the user's timezone's UTC offset in minutes
+/// The multipart expects an `audio` field containing the binary audio data (MP3, WAV, AIFF, AAC, OGG, FLAC or M4A);
+/// see `AIUpload` for the other fields.
 async fn process_audio_to_events(
     State(app_state): State<AppState>,
     user: AuthUser,
-    mut multipart: Multipart
+    upload: AIUpload<AudioField>
 ) -> ApiResult<Json<GeneratedEvents>> {
-    if let Some(audio_field) = multipart.next_field().await? {
-        let audio_bytes = audio_field.bytes().await?;
-
-        if let Some(offset_field) = multipart.next_field().await? 
-        && let Some("timezone_offset_minutes") = offset_field.name()
-        && let Ok(timezone_offset_minutes) = offset_field.text().await?.parse::<i32>()
-        {
-            let events = app_state.services.ai_add_events
-                .generate_from_audio(audio_bytes, timezone_offset_minutes)
-                .await?;
-            return Ok(Json(events));
-        }
-    }
-    return Err(
-        ApiError::unprocessable_entity(vec![("multipart", "the multipart form had no fields (expected audio + time offset field)")])
-    );
+    let options = upload.options();
+    let events = app_state.services.ai_add_events
+        .generate_from_audio(
+            user.id,
+            upload.file, 
+            upload.context, 
+            options,
+            &app_state.config.upload_limits
+        )
+        .await?;
+    Ok(Json(events))
 }
 
 /// Handler for processing an image into generated events.
 /// 
-/// The multipart expects 2 fields:
-/// - `image`, containing the binary JPG data
-/// - `timezone_offset_minutes`, the user's timezone's UTC offset in minutes
+/// The multipart expects an `image` field containing the binary image data;
+/// see `AIUpload` for the other fields. Events in QR codes and barcodes in the image are read without the LLM.
 async fn process_image_to_events(
     State(app_state): State<AppState>,
     user: AuthUser,
-    mut multipart: Multipart,
+    upload: AIUpload<ImageField>
 ) -> ApiResult<Json<GeneratedEvents>> {
-    let offset_field = multipart
-        .next_field()
-        .await?
-        .ok_or_else(|| ApiError::unprocessable_entity(vec![("multipart", "Only 1 field found")]))?;
-    if offset_field.name() != Some("timezone_offset_minutes") {
-        return Err(ApiError::unprocessable_entity(vec![(
-            "multipart",
-            format!("Second field must be 'timezone_offset_minutes', got: {:?}", offset_field.name()),
-        )]));
-    }
-    let timezone_offset_minutes = offset_field
-        .text()
-        .await?
-        .parse::<i32>()
-        .map_err(|_| ApiError::unprocessable_entity(vec![("timezone_offset_minutes", "Unable to parse into i32")]))?;
-
-    let image_field = multipart
-        .next_field()
-        .await?
-        .ok_or(ApiError::unprocessable_entity(vec![("multipart", "No fields found")]))?;
-    if image_field.name() != Some("image") {
-        return Err(ApiError::unprocessable_entity(vec![(
-            "multipart",
-            format!("First field must be 'image', got: {:?}", image_field.name()),
-        )]));
-    }
-    let image_bytes = image_field.bytes().await?;
+    let options = upload.options();
+    let events = app_state.services.ai_add_events
+        .generate_from_image(
+            user.id,
+            upload.file.body, 
+            upload.context, 
+            options,
+            &app_state.config.upload_limits
+        )
+        .await?;
+    Ok(Json(events))
+}
+
+/// Handler for processing an email into generated events.
+/// 
+/// The multipart expects an `email` field containing the raw RFC 822 message (an `.eml` file) or an Outlook `.msg`
+/// file; see `AIUpload` for the other fields. Events in calendar attachments (like a meeting invite's) are read without
+/// the LLM, and otherwise the body and any image or PDF attachments are extracted from together.
+async fn process_email_to_events(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    upload: AIUpload<EmailField>
+) -> ApiResult<Json<GeneratedEvents>> {
+    let options = upload.options();
+    let events = app_state.services.ai_add_events
+        .generate_from_email(
+            user.id,
+            upload.file.body, 
+            upload.context, 
+            options,
+            &app_state.config.upload_limits
+        )
+        .await?;
+    Ok(Json(events))
+}
+
+/// Handler for proposing edits to the user's calendar from a natural language instruction.
+/// 
+/// Nothing is changed; the client shows the proposal's diff, and sends the edits the user confirms to `/edit/apply`.
+async fn propose_edits(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<EditRequest>,
+) -> ApiResult<Json<EditProposal>> {
+    let timezone = parse_timezone(request.timezone, request.timezone_offset_minutes)?;
+    let proposal = app_state.services.ai_edit_events
+        .propose_edits(user.id, request.instruction, timezone)
+        .await?;
+    Ok(Json(proposal))
+}
+
+/// Handler for applying confirmed edits.
+async fn apply_edits(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<ApplyEditsRequest>,
+) -> ApiResult<()> {
+    app_state.services.ai_edit_events
+        .apply_edits(user.id, request.edits)
+        .await?;
+    Ok(())
+}
 
-    let events = app_state
-        .services
-        .ai_add_events
-        .generate_from_image(image_bytes, timezone_offset_minutes)
+/// Handler for answering a question about the user's calendar.
+/// 
+/// The question is turned into a structured search, which is run over the user's events; the matching events are 
+/// returned along with a short answer generated from them.
+async fn ask_question(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<QuestionRequest>,
+) -> ApiResult<Json<CalendarAnswer>> {
+    let timezone = parse_timezone(request.timezone, request.timezone_offset_minutes)?;
+    let answer = app_state.services.calendar_query
+        .ask(user.id, request.question, timezone)
+        .await?;
+    Ok(Json(answer))
+}
+
+/// Handler for building a recurrence rule from a phrase, with a preview of its first instances.
+async fn build_recurrence(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<RecurrenceRequest>,
+) -> ApiResult<Json<BuiltRecurrence>> {
+    let timezone = parse_timezone(request.timezone, request.timezone_offset_minutes)?;
+    let recurrence = app_state.services.recurrence_builder
+        .build(user.id, request.text, request.start, timezone, request.preview_instances)
+        .await?;
+    Ok(Json(recurrence))
+}
+
+/// Handler for submitting text to be processed into generated events by a background job.
+/// 
+/// Takes the same body as `/text`; responds straight away with the queued job, whose result can be fetched
+/// from `/jobs/{job_id}` (or followed at `/jobs/{job_id}/events`).
+async fn submit_text_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<TextToEventRequest>,
+) -> ApiResult<(StatusCode, Json<ExtractionJob>)> {
+    let (text, options) = request.into_parts()?;
+    let job = app_state.services.extraction_jobs.submit(
+        user.id,
+        ExtractionInput::Text(text),
+        options,
+        &app_state.config.upload_limits
+    ).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Handler for submitting audio to be processed by a background job; takes the same multipart as `/audio`.
+async fn submit_audio_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    upload: AIUpload<AudioField>
+) -> ApiResult<(StatusCode, Json<ExtractionJob>)> {
+    let options = upload.options();
+    let job = app_state.services.extraction_jobs.submit(
+        user.id,
+        ExtractionInput::Audio { audio: upload.file, context: upload.context },
+        options,
+        &app_state.config.upload_limits
+    ).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Handler for submitting an image to be processed by a background job; takes the same multipart as `/image`.
+async fn submit_image_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    upload: AIUpload<ImageField>
+) -> ApiResult<(StatusCode, Json<ExtractionJob>)> {
+    let options = upload.options();
+    let job = app_state.services.extraction_jobs.submit(
+        user.id,
+        ExtractionInput::Image { image: upload.file.body, context: upload.context },
+        options,
+        &app_state.config.upload_limits
+    ).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Handler for submitting an email to be processed by a background job; takes the same multipart as `/email`.
+async fn submit_email_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    upload: AIUpload<EmailField>
+) -> ApiResult<(StatusCode, Json<ExtractionJob>)> {
+    let options = upload.options();
+    let job = app_state.services.extraction_jobs.submit(
+        user.id,
+        ExtractionInput::Email { email: upload.file.body, context: upload.context },
+        options,
+        &app_state.config.upload_limits
+    ).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Handler for polling a job.
+async fn get_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(job_id): Path<Uuid>
+) -> ApiResult<Json<ExtractionJob>> {
+    let job = app_state.services.extraction_jobs.get_job(user.id, job_id)?;
+    Ok(Json(job))
+}
+
+/// Handler for following a job with server-sent events.
+/// 
+/// A `job` event is sent with the job's current snapshot, then again whenever its state changes;
+/// the stream ends after the job is done or has failed.
+async fn stream_job(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(job_id): Path<Uuid>
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, axum::Error>>>> {
+    let receiver = app_state.services.extraction_jobs.subscribe(user.id, job_id)?;
+    let events = stream::unfold(Some((receiver, true)), |state| async move {
+        let (mut receiver, first) = state?;
+        // the sender is dropped if the job expires
+        if !first && receiver.changed().await.is_err() {
+            return None;
+        }
+        let job = receiver.borrow_and_update().clone();
+        let next = (!job.state.is_finished()).then_some((receiver, false));
+        Some((Event::default().event("job").json_data(&job), next))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Handler for getting how much of their AI quotas the user has left.
+async fn get_usage(
+    State(app_state): State<AppState>,
+    user: AuthUser
+) -> ApiResult<Json<AIAllowance>> {
+    let allowance = app_state.services.ai_usage.allowance(user.id).await?;
+    Ok(Json(allowance))
+}
+
+/// Handler for exporting the events the user has saved from extractions (and how they differ from what was generated),
+/// as fixtures for the extraction eval.
+async fn export_feedback(
+    State(app_state): State<AppState>,
+    Query(params): Query<FeedbackExportQuery>,
+    user: AuthUser
+) -> ApiResult<Json<Vec<FeedbackFixture>>> {
+    let fixtures = app_state.services.extractions.export_fixtures(user.id, params.since).await?;
+    Ok(Json(fixtures))
+}
+
+/// Handler for getting an extraction, i.e. where events saved from it came from.
+async fn get_extraction(
+    State(app_state): State<AppState>,
+    Path(extraction_id): Path<Uuid>,
+    user: AuthUser
+) -> ApiResult<Json<Extraction>> {
+    let extraction = app_state.services.extractions.get_extraction(user.id, extraction_id).await?;
+    Ok(Json(extraction))
+}
+
+/// Handler for downloading the input of an extraction, if it was kept.
+async fn get_extraction_input(
+    State(app_state): State<AppState>,
+    Path(extraction_id): Path<Uuid>,
+    user: AuthUser
+) -> ApiResult<impl IntoResponse> {
+    let input = app_state.services.extractions.get_input(user.id, extraction_id).await?;
+    Ok(([(header::CONTENT_TYPE, input.content_type)], input.bytes))
+}
+
+/// Handler for re-running an extraction from its kept input, with the current model and prompts.
+async fn rerun_extraction(
+    State(app_state): State<AppState>,
+    Path(extraction_id): Path<Uuid>,
+    user: AuthUser,
+    Json(request): Json<RerunExtractionRequest>
+) -> ApiResult<Json<GeneratedEvents>> {
+    let events = app_state.services.ai_add_events
+        .rerun_extraction(
+            user.id, 
+            extraction_id, 
+            request.use_calendar_context, 
+            request.include_extraction_text, 
+            &app_state.config.upload_limits
+        )
         .await?;
-    
     Ok(Json(events))
-}
\ No newline at end of file
+}
@@ -0,0 +1,427 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    marker::PhantomData
+};
+use axum::{
+    body::Bytes,
+    extract::{multipart::Field, FromRequest, Multipart, Request}
+};
+use chrono::Utc;
+use symphonia::core::io::MediaSource;
+use tokio::io::AsyncWriteExt;
+use crate::{
+    api::{error::{ApiError, ApiResult}, AppState},
+    config::UploadLimitsConfig,
+    models::{language::LanguageTag, time::UserTimezone},
+    services::ai_add_events_service::{ExtractionOptions, ExtractionProgress}
+};
+
+/// The max size of the non-file fields (timezone, context etc).
+const MAX_TEXT_FIELD_BYTES: usize = 16 * 1024;
+
+/// Describes the file field of an AI upload route.
+pub trait UploadField {
+    /// The name of the multipart field holding the file.
+    const NAME: &'static str;
+
+    /// The max size of the file.
+    fn max_bytes(limits: &UploadLimitsConfig) -> usize;
+
+    /// The max size of the whole request body, for use with `DefaultBodyLimit`.
+    fn max_body_bytes(limits: &UploadLimitsConfig) -> usize {
+        // leave some room for the other fields + multipart boundaries
+        Self::max_bytes(limits) + 4 * MAX_TEXT_FIELD_BYTES
+    }
+}
+
+/// An image upload, under the `image` field.
+pub struct ImageField;
+
+impl UploadField for ImageField {
+    const NAME: &'static str = "image";
+
+    fn max_bytes(limits: &UploadLimitsConfig) -> usize {
+        limits.max_image_bytes
+    }
+}
+
+/// An audio upload, under the `audio` field.
+pub struct AudioField;
+
+impl UploadField for AudioField {
+    const NAME: &'static str = "audio";
+
+    fn max_bytes(limits: &UploadLimitsConfig) -> usize {
+        limits.max_audio_bytes
+    }
+}
+
+/// An email upload (`.eml` or `.msg`), under the `email` field.
+pub struct EmailField;
+
+impl UploadField for EmailField {
+    const NAME: &'static str = "email";
+
+    fn max_bytes(limits: &UploadLimitsConfig) -> usize {
+        limits.max_email_bytes
+    }
+}
+
+/// A file received in a multipart upload.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub body: UploadBody,
+    pub content_type: Option<String>
+}
+
+/// The contents of an uploaded file.
+///
+/// Small uploads are kept in memory, while larger ones are spooled to an (anonymous) temp file.
+///
+/// **NOTE**: Reading from this is blocking, so do it inside `spawn_blocking`.
+#[derive(Debug)]
+pub enum UploadBody {
+    Memory(Bytes),
+    TempFile { file: File, len: usize }
+}
+
+impl UploadBody {
+    /// The size of the upload in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(bytes) => bytes.len(),
+            Self::TempFile { len, .. } => *len
+        }
+    }
+
+    /// Whether the upload is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read up to the first `n` bytes, e.g. for sniffing the format.
+    pub fn head(&self, n: usize) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Memory(bytes) => Ok(bytes[..n.min(bytes.len())].to_vec()),
+            Self::TempFile { file, .. } => {
+                let mut file = file.try_clone()?;
+                file.seek(SeekFrom::Start(0))?;
+                let mut head = Vec::with_capacity(n);
+                file.by_ref().take(n as u64).read_to_end(&mut head)?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(head)
+            }
+        }
+    }
+
+    /// Read the whole upload into memory.
+    pub fn into_bytes(self) -> io::Result<Bytes> {
+        match self {
+            Self::Memory(bytes) => Ok(bytes),
+            Self::TempFile { mut file, len } => {
+                let mut bytes = Vec::with_capacity(len);
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut bytes)?;
+                Ok(bytes.into())
+            }
+        }
+    }
+
+    /// Get a reader over the upload.
+    pub fn into_reader(self) -> io::Result<UploadReader> {
+        match self {
+            Self::Memory(bytes) => Ok(UploadReader::Memory(Cursor::new(bytes))),
+            Self::TempFile { mut file, .. } => {
+                file.seek(SeekFrom::Start(0))?;
+                Ok(UploadReader::File(BufReader::new(file)))
+            }
+        }
+    }
+}
+
+/// A (blocking) reader over an `UploadBody`.
+pub enum UploadReader {
+    Memory(Cursor<Bytes>),
+    File(BufReader<File>)
+}
+
+impl Read for UploadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(cursor) => cursor.read(buf),
+            Self::File(reader) => reader.read(buf)
+        }
+    }
+}
+
+impl BufRead for UploadReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::Memory(cursor) => cursor.fill_buf(),
+            Self::File(reader) => reader.fill_buf()
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Self::Memory(cursor) => cursor.consume(amount),
+            Self::File(reader) => reader.consume(amount)
+        }
+    }
+}
+
+impl Seek for UploadReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Memory(cursor) => cursor.seek(pos),
+            Self::File(reader) => reader.seek(pos)
+        }
+    }
+}
+
+// Allows decoding audio straight from the upload
+impl MediaSource for UploadReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        match self {
+            Self::Memory(cursor) => Some(cursor.get_ref().len() as u64),
+            Self::File(reader) => reader.get_ref().metadata().ok().map(|m| m.len())
+        }
+    }
+}
+
+/// Extracts a multipart upload for the AI routes.
+///
+/// Fields can be sent in any order:
+/// - `F::NAME` (required), containing the file
+/// - `timezone` (an IANA name) or `timezone_offset_minutes` (the UTC offset in minutes); exactly one is required
+/// - `context` (optional), any extra text to help with extraction
+/// - `use_calendar_context` (optional, `true`/`false`), whether to give the LLM the user's existing groups and events
+/// - `include_extraction_text` (optional, `true`/`false`), whether to return the LLM's intermediate extraction text
+/// - `bypass_cache` (optional, `true`/`false`), whether to skip looking for a cached extraction of the same upload
+/// - `locale` (optional, a BCP 47 tag), used to interpret ambiguous dates
+/// - `output_language` (optional, a BCP 47 tag), the language to write titles, descriptions and locations in
+///
+/// Any missing, duplicate or invalid fields are all returned together as a `422 Unprocessable Entity`,
+/// while a file over the route's limit returns a `413 Payload Too Large`.
+pub struct AIUpload<F: UploadField> {
+    pub file: UploadedFile,
+    pub timezone: UserTimezone,
+    pub context: Option<String>,
+    pub use_calendar_context: bool,
+    pub include_extraction_text: bool,
+    pub bypass_cache: bool,
+    pub locale: Option<LanguageTag>,
+    pub output_language: Option<LanguageTag>,
+    _field: PhantomData<F>
+}
+
+impl<F: UploadField> AIUpload<F> {
+    /// The extraction options given with the upload.
+    pub fn options(&self) -> ExtractionOptions {
+        ExtractionOptions {
+            timezone: self.timezone,
+            now: Utc::now(),
+            use_calendar_context: self.use_calendar_context,
+            include_extraction_text: self.include_extraction_text,
+            bypass_cache: self.bypass_cache,
+            locale: self.locale.clone(),
+            output_language: self.output_language.clone(),
+            progress: ExtractionProgress::default()
+        }
+    }
+}
+
+impl<F: UploadField> FromRequest<AppState> for AIUpload<F> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let limits = &state.config.upload_limits;
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+
+        let mut errors: Vec<(Cow<'static, str>, Cow<'static, str>)> = Vec::new();
+        let mut file = None;
+        let mut timezones = Vec::new();
+        let mut context = None;
+        let mut use_calendar_context = None;
+        let mut include_extraction_text = None;
+        let mut bypass_cache = None;
+        let mut locale = None;
+        let mut output_language = None;
+
+        while let Some(field) = multipart.next_field().await? {
+            let Some(name) = field.name().map(str::to_owned) else {
+                tracing::debug!("Skipping unnamed multipart field");
+                continue;
+            };
+            match name.as_str() {
+                name if name == F::NAME => {
+                    let content_type = field.content_type().map(str::to_owned);
+                    let body = read_file_field(field, F::NAME, F::max_bytes(limits), limits.max_in_memory_bytes).await?;
+                    if file.replace(UploadedFile { body, content_type }).is_some() {
+                        errors.push((F::NAME.into(), "was provided more than once".into()));
+                    }
+                },
+                "timezone" => {
+                    match UserTimezone::from_name(&read_text_field(field, "timezone").await?) {
+                        Some(timezone) => timezones.push(timezone),
+                        None => errors.push(("timezone".into(), "is not a valid IANA timezone name".into()))
+                    }
+                },
+                "timezone_offset_minutes" => {
+                    let text = read_text_field(field, "timezone_offset_minutes").await?;
+                    match text.trim().parse().ok().and_then(UserTimezone::from_offset_minutes) {
+                        Some(timezone) => timezones.push(timezone),
+                        None => errors.push(("timezone_offset_minutes".into(), "is not a valid offset in minutes".into()))
+                    }
+                },
+                "context" => {
+                    let text = read_text_field(field, "context").await?;
+                    if context.replace(text).is_some() {
+                        errors.push(("context".into(), "was provided more than once".into()));
+                    }
+                },
+                "use_calendar_context" => {
+                    let text = read_text_field(field, "use_calendar_context").await?;
+                    if let Err(error) = set_flag(&mut use_calendar_context, &text) {
+                        errors.push(("use_calendar_context".into(), error.into()));
+                    }
+                },
+                "include_extraction_text" => {
+                    let text = read_text_field(field, "include_extraction_text").await?;
+                    if let Err(error) = set_flag(&mut include_extraction_text, &text) {
+                        errors.push(("include_extraction_text".into(), error.into()));
+                    }
+                },
+                "bypass_cache" => {
+                    let text = read_text_field(field, "bypass_cache").await?;
+                    if let Err(error) = set_flag(&mut bypass_cache, &text) {
+                        errors.push(("bypass_cache".into(), error.into()));
+                    }
+                },
+                "locale" => {
+                    let text = read_text_field(field, "locale").await?;
+                    if let Err(error) = set_language_tag(&mut locale, &text) {
+                        errors.push(("locale".into(), error.into()));
+                    }
+                },
+                "output_language" => {
+                    let text = read_text_field(field, "output_language").await?;
+                    if let Err(error) = set_language_tag(&mut output_language, &text) {
+                        errors.push(("output_language".into(), error.into()));
+                    }
+                },
+                other => tracing::debug!("Ignoring unexpected multipart field `{other}`")
+            }
+        }
+
+        if file.as_ref().is_none_or(|f| f.body.is_empty()) {
+            errors.push((F::NAME.into(), "is missing".into()));
+        }
+        if timezones.len() > 1 {
+            errors.push(("timezone".into(), "only one of `timezone` or `timezone_offset_minutes` may be provided".into()));
+        }
+        if timezones.is_empty() && !errors.iter().any(|(key, _)| key.starts_with("timezone")) {
+            errors.push(("timezone".into(), "one of `timezone` or `timezone_offset_minutes` is required".into()));
+        }
+
+        match (file, timezones.pop()) {
+            (Some(file), Some(timezone)) if errors.is_empty() => Ok(Self {
+                file,
+                timezone,
+                context: context.filter(|c| !c.trim().is_empty()),
+                use_calendar_context: use_calendar_context.unwrap_or_default(),
+                include_extraction_text: include_extraction_text.unwrap_or_default(),
+                bypass_cache: bypass_cache.unwrap_or_default(),
+                locale,
+                output_language,
+                _field: PhantomData
+            }),
+            _ => Err(ApiError::unprocessable_entity(errors))
+        }
+    }
+}
+
+/// Stream a file field into memory, spooling it to a temp file once it's past `max_in_memory_bytes`.
+async fn read_file_field(
+    mut field: Field<'_>,
+    name: &'static str,
+    max_bytes: usize,
+    max_in_memory_bytes: usize
+) -> ApiResult<UploadBody> {
+    let mut buffer = Vec::new();
+    let mut spool: Option<tokio::fs::File> = None;
+    let mut len = 0;
+
+    while let Some(chunk) = field.chunk().await? {
+        len += chunk.len();
+        if len > max_bytes {
+            return Err(ApiError::PayloadTooLarge(
+                format!("`{name}` is too large (the limit is {:.1}MB)", max_bytes as f64 / (1024.0 * 1024.0))
+            ));
+        }
+        match &mut spool {
+            Some(file) => file.write_all(&chunk).await.map_err(spool_error)?,
+            None if len > max_in_memory_bytes => {
+                tracing::trace!("`{name}` is over {max_in_memory_bytes} bytes; spooling it to a temp file");
+                let mut file = tokio::fs::File::from_std(tempfile::tempfile().map_err(spool_error)?);
+                file.write_all(&buffer).await.map_err(spool_error)?;
+                file.write_all(&chunk).await.map_err(spool_error)?;
+                buffer = Vec::new();
+                spool = Some(file);
+            },
+            None => buffer.extend_from_slice(&chunk)
+        }
+    }
+
+    match spool {
+        Some(mut file) => {
+            file.flush().await.map_err(spool_error)?;
+            Ok(UploadBody::TempFile { file: file.into_std().await, len })
+        },
+        None => Ok(UploadBody::Memory(buffer.into()))
+    }
+}
+
+/// Parse a `true`/`false` field into `flag`, failing if it's invalid or was already set.
+fn set_flag(flag: &mut Option<bool>, text: &str) -> Result<(), &'static str> {
+    let value = text.trim().parse::<bool>().map_err(|_| "must be `true` or `false`")?;
+    match flag.replace(value) {
+        None => Ok(()),
+        Some(_) => Err("was provided more than once")
+    }
+}
+
+/// Parse a BCP 47 language tag field into `tag`, failing if it's invalid or was already set.
+fn set_language_tag(tag: &mut Option<LanguageTag>, text: &str) -> Result<(), &'static str> {
+    let value = LanguageTag::parse(text).ok_or("is not a valid BCP 47 language tag")?;
+    match tag.replace(value) {
+        None => Ok(()),
+        Some(_) => Err("was provided more than once")
+    }
+}
+
+/// Read a (small) text field, failing as soon as it's past `MAX_TEXT_FIELD_BYTES`.
+async fn read_text_field(mut field: Field<'_>, name: &'static str) -> ApiResult<String> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_TEXT_FIELD_BYTES {
+            return Err(ApiError::PayloadTooLarge(format!("`{name}` is too large")));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    String::from_utf8(bytes)
+        .map_err(|_| ApiError::unprocessable_entity([(name, "is not valid UTF-8")]))
+}
+
+fn spool_error(err: io::Error) -> ApiError {
+    tracing::warn!("Failed to spool an upload to a temp file: {err}");
+    ApiError::Internal("Failed to store the upload".into())
+}
@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router
+};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use crate::{
+    api::{ai_add_events::parse_timezone, error::ApiResult, AppState},
+    auth::types::AuthUser,
+    models::briefing::BriefingPeriod,
+    services::briefings_service::briefing_text
+};
+
+/// The query params for a briefing.
+/// 
+/// `date` (by default, the user's local today) is the day, or a day in the week, to brief; timezones are given as in 
+/// the AI routes. `format` (default `json`) is `json` for the structured briefing or `text` for plain text, and 
+/// `use_llm` (default `true`) set to `false` summarizes the briefing from a template, without the LLM.
+#[derive(Deserialize)]
+struct BriefingQuery {
+    date: Option<NaiveDate>,
+    timezone: Option<String>,
+    timezone_offset_minutes: Option<i32>,
+    #[serde(default)]
+    format: BriefingFormat,
+    use_llm: Option<bool>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum BriefingFormat {
+    #[default]
+    Json,
+    Text
+}
+
+/// Build the router for briefing routes.
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/{period}", get(get_briefing))
+}
+
+/// Handler for the user's daily (`/day`) or weekly (`/week`) briefing.
+async fn get_briefing(
+    State(app_state): State<AppState>,
+    Path(period): Path<BriefingPeriod>,
+    Query(params): Query<BriefingQuery>,
+    user: AuthUser
+) -> ApiResult<Response> {
+    let timezone = parse_timezone(params.timezone, params.timezone_offset_minutes)?;
+    let date = params.date.unwrap_or_else(|| timezone.utc_to_local(Utc::now()).date_naive());
+    let briefing = app_state.services.briefings
+        .briefing(user.id, period, date, timezone, params.use_llm.unwrap_or(true))
+        .await?;
+    let response = match params.format {
+        BriefingFormat::Json => Json(briefing).into_response(),
+        BriefingFormat::Text => {
+            ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], briefing_text(&briefing, &timezone)).into_response()
+        }
+    };
+    Ok(response)
+}
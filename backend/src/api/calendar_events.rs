@@ -13,6 +13,13 @@ struct EventsQuery {
     end: DateTime<Utc>
 }
 
+/// The query params for creating events.
+#[derive(Deserialize)]
+struct CreateEventsQuery {
+    /// The AI extraction the events were generated by, if any.
+    extraction_id: Option<Uuid>
+}
+
 /// Build the router for calendar event routes.
 pub(super) fn router() -> Router<AppState> {
     Router::new()
@@ -24,11 +31,12 @@ pub(super) fn router() -> Router<AppState> {
 
 async fn create_events(
     State(app_state): State<AppState>,
+    Query(params): Query<CreateEventsQuery>,
     user: AuthUser,
     Json(events): Json<Vec<NewCalendarEvent>>
 ) -> ApiResult<()> {
     let calendar_service = app_state.services.calendar_events;
-    calendar_service.create_events(user.id, events).await
+    calendar_service.create_events(user.id, events, params.extraction_id).await
 }
 
 async fn get_events(
@@ -0,0 +1,72 @@
+use axum::{extract::{Path, Query, State}, routing::{delete, get, post}, Json, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    api::{error::ApiResult, AppState},
+    auth::types::AuthUser,
+    models::categorisation::{AcceptedCategories, AcceptedCategory, CategorisationRule, CategorySuggestions}
+};
+
+/// The query params for categorisation suggestions.
+/// 
+/// `import_batch_id` limits the suggestions to that batch's events (by default, they're for the events from every 
+/// Outlook sync), and `use_llm` (default `false`) set to `true` has the LLM categorise what the rules and keywords don't.
+#[derive(Deserialize)]
+struct SuggestionsQuery {
+    import_batch_id: Option<Uuid>,
+    use_llm: Option<bool>
+}
+
+/// The body for accepting categorisation suggestions.
+#[derive(Deserialize)]
+struct AcceptRequest {
+    suggestions: Vec<AcceptedCategory>
+}
+
+/// Build the router for categorising imported events.
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/suggestions", get(get_suggestions))
+        .route("/accept", post(accept_suggestions))
+        .route("/rules", get(get_rules))
+        .route("/rules/{rule_id}", delete(delete_rule))
+}
+
+/// Handler for suggesting groups for the user's ungrouped imported events.
+async fn get_suggestions(
+    State(app_state): State<AppState>,
+    Query(params): Query<SuggestionsQuery>,
+    user: AuthUser
+) -> ApiResult<Json<CategorySuggestions>> {
+    let suggestions = app_state.services.categorisation
+        .suggest(user.id, params.import_batch_id, params.use_llm.unwrap_or(false))
+        .await?;
+    Ok(Json(suggestions))
+}
+
+/// Handler for accepting categorisation suggestions in bulk, saving any of their rules.
+async fn accept_suggestions(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<AcceptRequest>
+) -> ApiResult<Json<AcceptedCategories>> {
+    let accepted = app_state.services.categorisation.accept(user.id, request.suggestions).await?;
+    Ok(Json(accepted))
+}
+
+async fn get_rules(
+    State(app_state): State<AppState>,
+    user: AuthUser
+) -> ApiResult<Json<Vec<CategorisationRule>>> {
+    let rules = app_state.services.categorisation.rules(user.id).await?;
+    Ok(Json(rules))
+}
+
+async fn delete_rule(
+    State(app_state): State<AppState>,
+    Path(rule_id): Path<Uuid>,
+    user: AuthUser
+) -> ApiResult<()> {
+    app_state.services.categorisation.delete_rule(user.id, rule_id).await?;
+    Ok(())
+}
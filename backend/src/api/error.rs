@@ -1,7 +1,7 @@
 // NOTE: copied with modifications from realworld-axum-sqlx
 
 use axum::extract::multipart::MultipartError;
-use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
 use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -9,6 +9,7 @@ use graph_rs_sdk::GraphFailure;
 use sqlx::error::DatabaseError;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::time::Duration;
 
 use crate::llm::error::LLMError;
 
@@ -54,15 +55,18 @@ pub enum ApiError {
     #[error("An error occurred with the database: {0}")]
     Sqlx(#[from] sqlx::Error),
 
-    /// An error from `rust_graph_sdk`.
+    /// An error from `rust_graph_sdk` (boxed, as it's much larger than our other variants).
     #[error("An error occurred interacting with Microsoft Graph API: {0}")]
-    Graph(#[from] GraphFailure),
+    Graph(#[from] Box<GraphFailure>),
 
     /// An error from `reqwest`.
     #[error("An error occurred making a reqwest: {0}")]
     Reqwest(#[from] reqwest::Error),
 
     /// An error from the LLM.
+    /// 
+    /// Rate limits return `429 Too Many Requests` (with `Retry-After` if known), blocked content returns
+    /// `422 Unprocessable Entity`, and anything else is an upstream failure (`502 Bad Gateway`/`504 Gateway Timeout`).
     #[error("An error occurred interfacing with the LLM for event generation: {0}")]
     LLM(#[from] LLMError),
 
@@ -76,7 +80,19 @@ pub enum ApiError {
 
     /// Returns a `400 Bad Request`; useful for internal errors that can be exposed to the user.
     #[error("{0}")]
-    BadRequest(String)
+    BadRequest(String),
+
+    /// Returns a `415 Unsupported Media Type`; for uploads whose format we can't handle.
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+
+    /// Returns a `413 Payload Too Large`; for uploads that are (still) too large to process.
+    #[error("{0}")]
+    PayloadTooLarge(String),
+
+    /// Returns a `429 Too Many Requests` with a `Retry-After`; for users who've used up an AI quota.
+    #[error("{message}")]
+    QuotaExceeded { message: String, retry_after: Duration }
 }
 
 impl ApiError {
@@ -102,7 +118,8 @@ impl ApiError {
         Self::UnprocessableEntity { errors: error_map }
     }
 
-    fn status_code(&self) -> StatusCode {
+    /// The status code this error is returned with.
+    pub fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::Forbidden => StatusCode::FORBIDDEN,
@@ -110,11 +127,40 @@ impl ApiError {
             Self::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             Self::Sqlx(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Reqwest(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::LLM(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::LLM(LLMError::RateLimited { .. }) => StatusCode::TOO_MANY_REQUESTS,
+            Self::LLM(LLMError::Blocked { .. }) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::LLM(LLMError::Timeout) => StatusCode::GATEWAY_TIMEOUT,
+            Self::LLM(_) => StatusCode::BAD_GATEWAY,
             Self::Graph(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::Multipart(_) => StatusCode::BAD_REQUEST,
+            Self::Multipart(err) => err.status(),
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::BadRequest(_) =>  StatusCode::BAD_REQUEST
+            Self::BadRequest(_) =>  StatusCode::BAD_REQUEST,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS
+        }
+    }
+
+    /// A message describing this error that's safe to show the client outside of a response (e.g. in a job's state).
+    ///
+    /// Unlike `to_string`, this leaves out the details of internal and upstream failures (which could include
+    /// queries or request details), and lists the fields of a `422 Unprocessable Entity`.
+    pub fn client_message(&self) -> String {
+        match self {
+            Self::UnprocessableEntity { errors } => {
+                let mut messages: Vec<_> = errors
+                    .iter()
+                    .flat_map(|(key, messages)| messages.iter().map(move |message| format!("{key}: {message}")))
+                    .collect();
+                messages.sort();
+                messages.join("; ")
+            }
+            Self::Sqlx(_) | Self::Graph(_) | Self::Reqwest(_) | Self::Internal(_) => {
+                "An unexpected internal error occurred".into()
+            }
+            Self::LLM(LLMError::RateLimited { .. } | LLMError::Blocked { .. } | LLMError::Timeout) => self.to_string(),
+            Self::LLM(_) => "An error occurred interfacing with the LLM for event generation".into(),
+            _ => self.to_string()
         }
     }
 }
@@ -139,6 +185,24 @@ impl IntoResponse for ApiError {
                 )
                     .into_response();
             }
+            Self::LLM(LLMError::Blocked { .. }) => {
+                // shaped like any other `422 Unprocessable Entity`
+                let errors = HashMap::from([("input", vec![self.to_string()])]);
+                return (self.status_code(), Json(errors)).into_response();
+            }
+            Self::LLM(LLMError::RateLimited { retry_after: Some(retry_after) })
+            | Self::QuotaExceeded { retry_after, .. } => {
+                // round up, so clients don't retry too early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                return (
+                    self.status_code(),
+                    [(RETRY_AFTER, HeaderValue::from(seconds))]
+                        .into_iter()
+                        .collect::<HeaderMap>(),
+                    self.to_string(),
+                )
+                    .into_response();
+            }
             _ => (),
         }
 
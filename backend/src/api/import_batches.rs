@@ -0,0 +1,40 @@
+use axum::{extract::{Path, Query, State}, routing::{get, post}, Json, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    api::{error::ApiResult, AppState}, 
+    auth::types::AuthUser, 
+    models::import_batch::{ImportBatch, UndoneBatch}
+};
+
+/// The query params for listing batches; `limit` defaults to 20 (and is at most 100).
+#[derive(Deserialize)]
+struct BatchesQuery {
+    limit: Option<i64>
+}
+
+/// Build the router for import batch routes.
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_batches))
+        .route("/{batch_id}/undo", post(undo_batch))
+}
+
+async fn get_batches(
+    State(app_state): State<AppState>,
+    Query(params): Query<BatchesQuery>,
+    user: AuthUser
+) -> ApiResult<Json<Vec<ImportBatch>>> {
+    let batches = app_state.services.import_batches.recent_batches(user.id, params.limit).await?;
+    Ok(Json(batches))
+}
+
+/// Handler for deleting everything created in a batch.
+async fn undo_batch(
+    State(app_state): State<AppState>,
+    Path(batch_id): Path<Uuid>,
+    user: AuthUser
+) -> ApiResult<Json<UndoneBatch>> {
+    let undone = app_state.services.import_batches.undo_batch(user.id, batch_id).await?;
+    Ok(Json(undone))
+}
@@ -10,6 +10,10 @@ mod calendar_events;
 mod recurring_event_groups;
 mod recurring_events;
 mod ai_add_events;
+mod import_batches;
+mod briefings;
+mod categorisation;
+pub(super) mod ai_upload;
 mod azure;
 
 /// State for the app.
@@ -34,7 +38,10 @@ fn build_app_router(state: AppState) -> Router {
         .nest("/recurring_event_groups", recurring_event_groups::router())
         .nest("/recurring_events", recurring_events::router())
         .nest("/calendar_events", calendar_events::router())
-        .nest("/ai_add_event", ai_add_events::router())
+        .nest("/ai_add_event", ai_add_events::router(&state.config.upload_limits))
+        .nest("/import_batches", import_batches::router())
+        .nest("/briefings", briefings::router())
+        .nest("/categorisation", categorisation::router())
         .nest("/azure", azure::router())
         .with_state(state)
         .layer(TraceLayer::new_for_http())
@@ -1,8 +1,9 @@
 use axum::{
-    extract::{Path, State}, 
+    extract::{Path, Query, State}, 
     routing::{delete, get, post, put}, 
     Json, Router,
 };
+use serde::Deserialize;
 use uuid::Uuid;
 use crate::{
     api::{error::ApiResult, AppState}, 
@@ -13,6 +14,13 @@ use crate::{
     }, services::recurring_event_groups_service::{GroupWithEvents, RecurringEventGroupResponse}
 };
 
+/// The query params for creating groups with events.
+#[derive(Deserialize)]
+struct AddWithEventsQuery {
+    /// The AI extraction the events were generated by, if any.
+    extraction_id: Option<Uuid>
+}
+
 /// Build the router for recurring event groups routes.
 pub(super) fn router() -> Router<AppState> {
     Router::new()
@@ -78,11 +86,12 @@ async fn delete_group(
 
 async fn add_with_events(
     State(app_state): State<AppState>,
+    Query(params): Query<AddWithEventsQuery>,
     user: AuthUser,
     Json(events): Json<GroupWithEvents>
 ) -> ApiResult<()> {
     let service = app_state.services.recurring_event_groups;
-    service.add_with_events(user.id, events).await?;
+    service.add_with_events(user.id, events, params.extraction_id).await?;
     Ok(())
 }
 
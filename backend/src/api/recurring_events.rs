@@ -3,6 +3,7 @@ use axum::{
     routing::{delete, get, post, put}, 
     Json, Router
 };
+use serde::Deserialize;
 use uuid::Uuid;
 use crate::{
     auth::types::AuthUser,
@@ -15,6 +16,13 @@ use crate::{
     api::{error::ApiResult, AppState}
 };
 
+/// The query params for creating events.
+#[derive(Deserialize)]
+struct CreateEventsQuery {
+    /// The AI extraction the events were generated by, if any.
+    extraction_id: Option<Uuid>
+}
+
 pub(super) fn router() -> Router<AppState> {
     Router::new()
         .route("/", post(create_events))
@@ -27,11 +35,12 @@ pub(super) fn router() -> Router<AppState> {
 
 async fn create_events(
     State(app_state): State<AppState>,
+    Query(params): Query<CreateEventsQuery>,
     user: AuthUser,
     Json(events): Json<Vec<NewRecurringEvent>>
 ) -> ApiResult<()> {
     let service = app_state.services.recurring_events;
-    service.create_events(user.id, events).await?;
+    service.create_events(user.id, events, params.extraction_id).await?;
     Ok(())
 }
 
@@ -0,0 +1,155 @@
+//! Evaluates event extraction against a corpus (see `backend::eval`), printing a report.
+//!
+//! The cases are run through the same service as the API's requests, so this needs `DATABASE_URL`, and the id of a user
+//! kept for evaluations (`--user-id` or `EVAL_USER_ID`); their AI quota (`AI_DAILY_TOKEN_QUOTA` and 
+//! `AI_MONTHLY_TOKEN_QUOTA`) should be high enough for the whole corpus.
+//!
+//! By default, Gemini's responses are replayed from `<corpus>/recordings/<model>/`, and cases whose responses weren't
+//! recorded are skipped (and listed in the report); use `--mode record` (with `GEMINI_KEY` set) to record them for a
+//! new model or prompt version. Cases which never reach Gemini (those quick-added, or read from a QR code or calendar
+//! attachment) don't need any. For example:
+//! ```sh
+//! cargo run --bin eval -- --corpus eval --model gemini-2.5-flash --mode record --save-report before.json
+//! # ...change the prompts...
+//! cargo run --bin eval -- --corpus eval --model gemini-2.5-flash --mode record --compare before.json
+//! ```
+
+use std::{env, fs, path::PathBuf, process::ExitCode};
+use clap::{Parser, ValueEnum};
+use dotenv::dotenv;
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+use backend::{
+    config::{AIQuotaConfig, GeminiClientConfig, UploadLimitsConfig},
+    eval::{load_corpus, report::EvalReport, scoring::Tolerance, Evaluator},
+    llm::{ResponseSource, LLM},
+    repositories::Repositories
+};
+
+#[derive(Parser)]
+struct EvalArgs {
+    /// The corpus directory.
+    #[clap(long)]
+    corpus: PathBuf,
+    /// The model to evaluate; defaults to `GEMINI_MODEL`.
+    #[clap(long)]
+    model: Option<String>,
+    /// The user to run the cases as; defaults to `EVAL_USER_ID`.
+    #[clap(long)]
+    user_id: Option<Uuid>,
+    #[clap(long, value_enum, default_value_t = Mode::Replay)]
+    mode: Mode,
+    /// A directory of prompt templates to use instead of the built-in ones; defaults to `AI_PROMPTS_DIR`.
+    #[clap(long)]
+    prompts_dir: Option<String>,
+    /// Only run the cases whose names contain this.
+    #[clap(long)]
+    filter: Option<String>,
+    /// How many minutes a datetime can be off by while still counting as correct.
+    #[clap(long, default_value_t = Tolerance::default().datetime_minutes)]
+    datetime_tolerance_minutes: i64,
+    /// How similar (from 0 to 1) titles must be to count as the same.
+    #[clap(long, default_value_t = Tolerance::default().title_similarity)]
+    title_similarity: f64,
+    /// Save the report as JSON, to compare later runs against.
+    #[clap(long)]
+    save_report: Option<PathBuf>,
+    /// Compare against a report saved by an earlier run.
+    #[clap(long)]
+    compare: Option<PathBuf>
+}
+
+/// Where Gemini's responses come from.
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    /// Replay recorded responses, skipping any case without them.
+    Replay,
+    /// Request Gemini and record the responses.
+    Record,
+    /// Request Gemini without recording anything.
+    Live
+}
+
+fn main() -> ExitCode {
+    dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()))
+        .init();
+
+    match run(EvalArgs::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: EvalArgs) -> Result<(), String> {
+    let model = args.model
+        .or_else(|| env::var("GEMINI_MODEL").ok())
+        .ok_or("Either `--model` or `GEMINI_MODEL` is required")?;
+    let recordings = args.corpus.join("recordings").join(&model);
+    let (responses, api_key) = match args.mode {
+        // replaying never requests Gemini, so doesn't need a key
+        Mode::Replay => (ResponseSource::Replay(recordings), env::var("GEMINI_KEY").unwrap_or_default()),
+        Mode::Record => (ResponseSource::Record(recordings), gemini_key()?),
+        Mode::Live => (ResponseSource::Live, gemini_key()?)
+    };
+    let prompts_dir = args.prompts_dir.or_else(|| env::var("AI_PROMPTS_DIR").ok().filter(|dir| !dir.is_empty()));
+    let client_config = GeminiClientConfig::from_lookup(|key| env::var(key).ok())?;
+    let llm = LLM::build(&api_key, &model, &client_config, prompts_dir.as_deref())?.with_response_source(responses);
+    let limits = UploadLimitsConfig::from_lookup(|key| env::var(key).ok())?;
+    let quotas = AIQuotaConfig::from_lookup(|key| env::var(key).ok())?;
+    let user_id = match args.user_id {
+        Some(user_id) => user_id,
+        None => env::var("EVAL_USER_ID")
+            .map_err(|_| "Either `--user-id` or `EVAL_USER_ID` is required")?
+            .parse()
+            .map_err(|err| format!("`EVAL_USER_ID` is not a valid UUID: {err}"))?
+    };
+    let database_url = env::var("DATABASE_URL").map_err(|_| "`DATABASE_URL` is required")?;
+    let tolerance = Tolerance {
+        datetime_minutes: args.datetime_tolerance_minutes,
+        title_similarity: args.title_similarity
+    };
+
+    let mut cases = load_corpus(&args.corpus)?;
+    if let Some(filter) = &args.filter {
+        cases.retain(|case| case.name.contains(filter.as_str()));
+    }
+    if cases.is_empty() {
+        return Err("No cases to evaluate".into());
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| format!("Failed to start the runtime: {err}"))?;
+    let report = runtime.block_on(async {
+        let db = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&database_url)
+            .await
+            .map_err(|err| format!("Failed to connect to the database: {err}"))?;
+        let evaluator = Evaluator::new(llm, Repositories::new(db), user_id, &quotas, limits, tolerance);
+        Ok::<_, String>(evaluator.run(&cases).await)
+    })?;
+    println!("{report}");
+
+    if let Some(path) = &args.compare {
+        let baseline = fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let baseline: EvalReport = serde_json::from_str(&baseline)
+            .map_err(|err| format!("Failed to parse {}: {err}", path.display()))?;
+        println!("{}", report.compared_to(&baseline));
+    }
+    if let Some(path) = &args.save_report {
+        let json = serde_json::to_string_pretty(&report).map_err(|err| format!("Failed to serialize the report: {err}"))?;
+        fs::write(path, json).map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn gemini_key() -> Result<String, String> {
+    env::var("GEMINI_KEY").map_err(|_| "`GEMINI_KEY` is required to request Gemini".into())
+}
@@ -1,4 +1,4 @@
-use std::env;
+use std::{env, str::FromStr};
 
 /// Startup config for the app.
 /// 
@@ -43,6 +43,15 @@ impl StartupConfig {
                         azure_client_id: self.azure_client_id.ok_or("`azure_client_id` missing from CLI args")?,
                         azure_client_secret: self.azure_client_secret.ok_or("`azure_client_secret` missing from CLI args")?,
                         azure_encryption_key: self.azure_encryption_key.ok_or("`azure_encryption_key` missing from CLI args")?,
+                        upload_limits: UploadLimitsConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_jobs: AIJobsConfig::from_lookup(|key| env::var(key).ok())?,
+                        gemini_client: GeminiClientConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_quotas: AIQuotaConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_cache: AICacheConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_provenance: AIProvenanceConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_briefings: AIBriefingsConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_categorisation: AICategorisationConfig::from_lookup(|key| env::var(key).ok())?,
+                        ai_prompts_dir: env::var("AI_PROMPTS_DIR").ok().filter(|dir| !dir.is_empty()),
                     }
                 );
             }
@@ -57,12 +66,24 @@ impl StartupConfig {
                 azure_client_id: env::var("AZURE_CLIENT_ID").map_err(|_| "`AZURE_CLIENT_ID` missing from env vars")?,
                 azure_client_secret: env::var("AZURE_CLIENT_SECRET").map_err(|_| "`AZURE_CLIENT_SECRET` missing from env vars")?,
                 azure_encryption_key: env::var("AZURE_ENCRYPTION_KEY").map_err(|_| "`AZURE_ENCRYPTION_KEY` missing from env vars")?,
+                upload_limits: UploadLimitsConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_jobs: AIJobsConfig::from_lookup(|key| env::var(key).ok())?,
+                gemini_client: GeminiClientConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_quotas: AIQuotaConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_cache: AICacheConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_provenance: AIProvenanceConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_briefings: AIBriefingsConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_categorisation: AICategorisationConfig::from_lookup(|key| env::var(key).ok())?,
+                ai_prompts_dir: env::var("AI_PROMPTS_DIR").ok().filter(|dir| !dir.is_empty()),
             }
         )
     }
 }
 
 /// Environment configs for the app.
+/// 
+/// The AI settings (from `upload_limits` on) are all optional: each section reads its own keys with `from_lookup`,
+/// falling back to its `Default` for any that aren't set, and failing on any that are set but invalid.
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
@@ -71,7 +92,17 @@ pub struct Config {
     pub azure_tenant_id: String,
     pub azure_client_id: String,
     pub azure_client_secret: String,
-    pub azure_encryption_key: String
+    pub azure_encryption_key: String,
+    pub upload_limits: UploadLimitsConfig,
+    pub ai_jobs: AIJobsConfig,
+    pub gemini_client: GeminiClientConfig,
+    pub ai_quotas: AIQuotaConfig,
+    pub ai_cache: AICacheConfig,
+    pub ai_provenance: AIProvenanceConfig,
+    pub ai_briefings: AIBriefingsConfig,
+    pub ai_categorisation: AICategorisationConfig,
+    /// A directory of prompt templates overriding the built-in ones (`AI_PROMPTS_DIR`, optional).
+    pub ai_prompts_dir: Option<String>
 }
 
 impl Config {
@@ -85,6 +116,310 @@ impl Config {
                 azure_client_id: secrets.get("AZURE_CLIENT_ID").ok_or("`AZURE_CLIENT_ID` missing from env vars")?,
                 azure_client_secret: secrets.get("AZURE_CLIENT_SECRET").ok_or("`AZURE_CLIENT_SECRET` missing from env vars")?,
                 azure_encryption_key: secrets.get("AZURE_ENCRYPTION_KEY").ok_or("`AZURE_ENCRYPTION_KEY` missing from env vars")?,
+                upload_limits: UploadLimitsConfig::from_lookup(|key| secrets.get(key))?,
+                ai_jobs: AIJobsConfig::from_lookup(|key| secrets.get(key))?,
+                gemini_client: GeminiClientConfig::from_lookup(|key| secrets.get(key))?,
+                ai_quotas: AIQuotaConfig::from_lookup(|key| secrets.get(key))?,
+                ai_cache: AICacheConfig::from_lookup(|key| secrets.get(key))?,
+                ai_provenance: AIProvenanceConfig::from_lookup(|key| secrets.get(key))?,
+                ai_briefings: AIBriefingsConfig::from_lookup(|key| secrets.get(key))?,
+                ai_categorisation: AICategorisationConfig::from_lookup(|key| secrets.get(key))?,
+                ai_prompts_dir: secrets.get("AI_PROMPTS_DIR").filter(|dir| !dir.is_empty()),
+        })
+    }
+}
+
+/// Limits for requests to the AI routes.
+#[derive(Debug, Clone)]
+pub struct UploadLimitsConfig {
+    /// Max size of an uploaded image (`AI_MAX_IMAGE_BYTES`).
+    pub max_image_bytes: usize,
+    /// Max size of an uploaded audio file (`AI_MAX_AUDIO_BYTES`).
+    pub max_audio_bytes: usize,
+    /// Max size of an uploaded email, including its attachments (`AI_MAX_EMAIL_BYTES`).
+    pub max_email_bytes: usize,
+    /// Max size of a text request's body (`AI_MAX_TEXT_BYTES`).
+    pub max_text_bytes: usize,
+    /// Uploads larger than this are spooled to a temp file instead of being kept in memory (`AI_UPLOAD_MEMORY_BYTES`).
+    pub max_in_memory_bytes: usize,
+    /// Max size of data we inline into an LLM request, after any processing (`AI_MAX_INLINE_BYTES`).
+    /// 
+    /// Gemini caps inline requests at 20MB, and base64 adds a third on top.
+    pub max_inline_bytes: usize,
+    /// Images are downscaled so their longest side is at most this (`AI_MAX_IMAGE_DIMENSION`).
+    pub max_image_dimension: u32,
+    /// The JPEG quality images are re-encoded with (`AI_IMAGE_JPEG_QUALITY`).
+    pub image_jpeg_quality: u8
+}
+
+impl Default for UploadLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_image_bytes: 25 * 1024 * 1024,
+            max_audio_bytes: 50 * 1024 * 1024,
+            max_email_bytes: 35 * 1024 * 1024,
+            max_text_bytes: 1024 * 1024,
+            max_in_memory_bytes: 4 * 1024 * 1024,
+            max_inline_bytes: 14 * 1024 * 1024,
+            max_image_dimension: 3072,
+            image_jpeg_quality: 85
+        }
+    }
+}
+
+impl UploadLimitsConfig {
+    /// Read the limits; the JPEG quality must be between 1 and 100.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            max_image_bytes: parse_or(lookup("AI_MAX_IMAGE_BYTES"), default.max_image_bytes)
+                .ok_or("`AI_MAX_IMAGE_BYTES` is not a valid number")?,
+            max_audio_bytes: parse_or(lookup("AI_MAX_AUDIO_BYTES"), default.max_audio_bytes)
+                .ok_or("`AI_MAX_AUDIO_BYTES` is not a valid number")?,
+            max_email_bytes: parse_or(lookup("AI_MAX_EMAIL_BYTES"), default.max_email_bytes)
+                .ok_or("`AI_MAX_EMAIL_BYTES` is not a valid number")?,
+            max_text_bytes: parse_or(lookup("AI_MAX_TEXT_BYTES"), default.max_text_bytes)
+                .ok_or("`AI_MAX_TEXT_BYTES` is not a valid number")?,
+            max_in_memory_bytes: parse_or(lookup("AI_UPLOAD_MEMORY_BYTES"), default.max_in_memory_bytes)
+                .ok_or("`AI_UPLOAD_MEMORY_BYTES` is not a valid number")?,
+            max_inline_bytes: parse_or(lookup("AI_MAX_INLINE_BYTES"), default.max_inline_bytes)
+                .ok_or("`AI_MAX_INLINE_BYTES` is not a valid number")?,
+            max_image_dimension: parse_or(lookup("AI_MAX_IMAGE_DIMENSION"), default.max_image_dimension)
+                .ok_or("`AI_MAX_IMAGE_DIMENSION` is not a valid number")?,
+            image_jpeg_quality: parse_or(lookup("AI_IMAGE_JPEG_QUALITY"), default.image_jpeg_quality)
+                .filter(|q| (1..=100).contains(q))
+                .ok_or("`AI_IMAGE_JPEG_QUALITY` must be between 1 and 100")?,
+        })
+    }
+}
+
+/// Config for AI extraction jobs.
+#[derive(Debug, Clone)]
+pub struct AIJobsConfig {
+    /// Max number of jobs extracting at once; the rest wait in the queue (`AI_MAX_CONCURRENT_JOBS`).
+    pub max_concurrent_jobs: usize,
+    /// Max number of unfinished (queued or extracting) jobs a single user can have (`AI_MAX_QUEUED_JOBS_PER_USER`).
+    pub max_queued_jobs_per_user: usize,
+    /// How long a finished job's result is kept for (`AI_JOB_RESULT_TTL_SECONDS`).
+    pub result_ttl_seconds: u64
+}
+
+impl Default for AIJobsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_jobs: 4,
+            max_queued_jobs_per_user: 3,
+            result_ttl_seconds: 60 * 60
+        }
+    }
+}
+
+impl AIJobsConfig {
+    /// Read the job settings; at least one job must be able to run at a time, and each user must be able to queue one.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            max_concurrent_jobs: parse_or(lookup("AI_MAX_CONCURRENT_JOBS"), default.max_concurrent_jobs)
+                .filter(|n| *n > 0)
+                .ok_or("`AI_MAX_CONCURRENT_JOBS` must be a positive number")?,
+            max_queued_jobs_per_user: parse_or(lookup("AI_MAX_QUEUED_JOBS_PER_USER"), default.max_queued_jobs_per_user)
+                .filter(|n| *n > 0)
+                .ok_or("`AI_MAX_QUEUED_JOBS_PER_USER` must be a positive number")?,
+            result_ttl_seconds: parse_or(lookup("AI_JOB_RESULT_TTL_SECONDS"), default.result_ttl_seconds)
+                .ok_or("`AI_JOB_RESULT_TTL_SECONDS` is not a valid number")?,
         })
     }
+}
+
+/// Config for how we request the Gemini API.
+#[derive(Debug, Clone)]
+pub struct GeminiClientConfig {
+    /// How long a single request can take, including reading the response (`GEMINI_TIMEOUT_SECONDS`).
+    pub timeout_seconds: u64,
+    /// How many times a rate limited or failed (5xx) request is retried (`GEMINI_MAX_RETRIES`).
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles for each retry after (`GEMINI_RETRY_BASE_DELAY_MS`).
+    pub retry_base_delay_ms: u64
+}
+
+impl Default for GeminiClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 120,
+            max_retries: 3,
+            retry_base_delay_ms: 1000
+        }
+    }
+}
+
+impl GeminiClientConfig {
+    /// Read the client settings; the timeout must be positive, while `0` retries disables retrying.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            timeout_seconds: parse_or(lookup("GEMINI_TIMEOUT_SECONDS"), default.timeout_seconds)
+                .filter(|t| *t > 0)
+                .ok_or("`GEMINI_TIMEOUT_SECONDS` must be a positive number")?,
+            max_retries: parse_or(lookup("GEMINI_MAX_RETRIES"), default.max_retries)
+                .ok_or("`GEMINI_MAX_RETRIES` is not a valid number")?,
+            retry_base_delay_ms: parse_or(lookup("GEMINI_RETRY_BASE_DELAY_MS"), default.retry_base_delay_ms)
+                .ok_or("`GEMINI_RETRY_BASE_DELAY_MS` is not a valid number")?,
+        })
+    }
+}
+
+/// Per-user quotas for the AI features, in tokens.
+#[derive(Debug, Clone)]
+pub struct AIQuotaConfig {
+    /// Tokens a user can use per (UTC) day (`AI_DAILY_TOKEN_QUOTA`).
+    pub daily_tokens: i64,
+    /// Tokens a user can use per (UTC) calendar month (`AI_MONTHLY_TOKEN_QUOTA`).
+    pub monthly_tokens: i64
+}
+
+impl Default for AIQuotaConfig {
+    fn default() -> Self {
+        Self {
+            daily_tokens: 200_000,
+            monthly_tokens: 3_000_000
+        }
+    }
+}
+
+impl AIQuotaConfig {
+    /// Read the quotas, which must be positive (there's no way to turn them off).
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            daily_tokens: parse_or(lookup("AI_DAILY_TOKEN_QUOTA"), default.daily_tokens)
+                .filter(|t| *t > 0)
+                .ok_or("`AI_DAILY_TOKEN_QUOTA` must be a positive number")?,
+            monthly_tokens: parse_or(lookup("AI_MONTHLY_TOKEN_QUOTA"), default.monthly_tokens)
+                .filter(|t| *t > 0)
+                .ok_or("`AI_MONTHLY_TOKEN_QUOTA` must be a positive number")?,
+        })
+    }
+}
+
+/// Config for the cache of AI extractions.
+#[derive(Debug, Clone)]
+pub struct AICacheConfig {
+    /// How long an extraction is cached for (`AI_CACHE_TTL_SECONDS`).
+    pub ttl_seconds: u64
+}
+
+impl Default for AICacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: 6 * 60 * 60
+        }
+    }
+}
+
+impl AICacheConfig {
+    /// Read the cache settings; a TTL of `0` effectively turns the cache off.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            ttl_seconds: parse_or(lookup("AI_CACHE_TTL_SECONDS"), default.ttl_seconds)
+                .ok_or("`AI_CACHE_TTL_SECONDS` is not a valid number")?,
+        })
+    }
+}
+
+/// Config for recording where AI extractions came from.
+#[derive(Debug, Clone)]
+pub struct AIProvenanceConfig {
+    /// The largest input (as given to the LLM) which is kept along with an extraction, so it can be re-run 
+    /// (`AI_MAX_STORED_INPUT_BYTES`); larger inputs only have their hash kept, and `0` (the default) keeps no inputs
+    /// at all, as they're often personal.
+    pub max_stored_input_bytes: usize,
+    /// How long the input of an extraction no events were saved from is kept for
+    /// (`AI_UNSAVED_INPUT_RETENTION_DAYS`); inputs which had events saved from them are kept to build eval cases from.
+    pub unsaved_input_retention_days: u64
+}
+
+impl Default for AIProvenanceConfig {
+    fn default() -> Self {
+        Self {
+            max_stored_input_bytes: 0,
+            unsaved_input_retention_days: 7
+        }
+    }
+}
+
+impl AIProvenanceConfig {
+    /// Read the provenance settings.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            max_stored_input_bytes: parse_or(lookup("AI_MAX_STORED_INPUT_BYTES"), default.max_stored_input_bytes)
+                .ok_or("`AI_MAX_STORED_INPUT_BYTES` is not a valid number")?,
+            unsaved_input_retention_days: parse_or(
+                lookup("AI_UNSAVED_INPUT_RETENTION_DAYS"),
+                default.unsaved_input_retention_days
+            ).ok_or("`AI_UNSAVED_INPUT_RETENTION_DAYS` is not a valid number")?,
+        })
+    }
+}
+
+/// Config for agenda briefings.
+#[derive(Debug, Clone)]
+pub struct AIBriefingsConfig {
+    /// Whether briefings are summarized by the LLM (`AI_BRIEFINGS_USE_LLM`); if not, or the LLM fails, they're 
+    /// summarized from a template instead.
+    pub use_llm: bool
+}
+
+impl Default for AIBriefingsConfig {
+    fn default() -> Self {
+        Self {
+            use_llm: true
+        }
+    }
+}
+
+impl AIBriefingsConfig {
+    /// Read the briefing settings.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            use_llm: parse_or(lookup("AI_BRIEFINGS_USE_LLM"), default.use_llm)
+                .ok_or("`AI_BRIEFINGS_USE_LLM` must be `true` or `false`")?,
+        })
+    }
+}
+
+/// Settings for categorising imported events.
+#[derive(Debug, Clone)]
+pub struct AICategorisationConfig {
+    /// Whether the LLM can be asked to categorise events the rules and keywords don't (`AI_CATEGORISATION_USE_LLM`).
+    pub use_llm: bool
+}
+
+impl Default for AICategorisationConfig {
+    fn default() -> Self {
+        Self {
+            use_llm: true
+        }
+    }
+}
+
+impl AICategorisationConfig {
+    /// Read the categorisation settings.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let default = Self::default();
+        Ok(Self {
+            use_llm: parse_or(lookup("AI_CATEGORISATION_USE_LLM"), default.use_llm)
+                .ok_or("`AI_CATEGORISATION_USE_LLM` must be `true` or `false`")?,
+        })
+    }
+}
+
+/// Parses an optional value, returning `default` if it isn't present or `None` if it's invalid.
+fn parse_or<T: FromStr>(value: Option<String>, default: T) -> Option<T> {
+    match value {
+        Some(value) => value.trim().parse().ok(),
+        None => Some(default)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+//! An offline evaluation of how accurately events are extracted.
+//!
+//! A corpus is a directory of cases (`cases/*.json`), each an input along with the events expected from it, e.g.
+//! ```json
+//! {
+//!     "input": { "type": "text", "text": "Dentist tomorrow 3-4pm" },
+//!     "timezone": "Europe/London",
+//!     "now": "2026-09-01T09:00:00Z",
+//!     "expected": { "events": [{ "title": "Dentist", "start_time": "2026-09-02T14:00:00Z", "end_time": "2026-09-02T15:00:00Z" }] }
+//! }
+//! ```
+//! Image, audio and email inputs are given as `{ "type": "image", "file": "timetable.jpg", "context": null }`, with
+//! the file relative to the case. The expected events take the same shape as `GeneratedEvents` (see `ExpectedEvents`).
+//! A case can also give the events already on the calendar (`"calendar": [...]`, shaped like `NewCalendarEvent`), in
+//! which case the calendar context is used, and which extracted events are marked as likely duplicates is scored too.
+//! Cases can also be built from the events users saved from their extractions (exported from
+//! `GET /ai_add_event/feedback/export`), by adding the input they were extracted from.
+//!
+//! Cases are run through `AIAddEventsService`, as the API's requests are, so the shortcuts which skip the LLM (quick-add,
+//! and events read from QR codes and calendar attachments) and the duplicate marking are measured along with it. This
+//! needs a database, and a user to run the cases as (see `Evaluator::new`). Gemini's responses are usually replayed
+//! from those recorded under `recordings/<model>/`, so runs are repeatable and free; a case needing a response that
+//! wasn't recorded is skipped (and reported as such), rather than failed.
+//! See `src/bin/eval.rs` for running it.
+
+use std::{fs, path::{Path, PathBuf}};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    api::{ai_upload::{UploadBody, UploadedFile}, error::ApiError},
+    config::{AICacheConfig, AIProvenanceConfig, AIQuotaConfig, UploadLimitsConfig},
+    llm::{error::LLMError, GeneratedEvents, LLM},
+    models::{calendar_event::NewCalendarEvent, import_batch::ImportSource, language::LanguageTag},
+    repositories::Repositories,
+    services::{
+        ai_add_events_service::{AIAddEventsService, ExtractionOptions, ExtractionProgress},
+        ai_usage_service::AIUsageService,
+        extractions_service::ExtractionsService,
+        import_batches_service::ImportBatchesService,
+        recurring_events_service::RecurringEventsService
+    }
+};
+use report::{CaseOutcome, CaseReport, EvalReport};
+use scoring::{ExpectedEvents, Tolerance};
+
+pub mod report;
+pub mod scoring;
+
+/// A case in the corpus.
+#[derive(Deserialize, Debug)]
+pub struct EvalCase {
+    /// Defaults to the case's file name.
+    #[serde(default)]
+    pub name: String,
+    pub input: CaseInput,
+    /// An IANA timezone name, or a UTC offset like `UTC+05:30`.
+    pub timezone: String,
+    /// What's taken to be the current time, so relative dates (such as "tomorrow") always resolve the same way.
+    pub now: DateTime<Utc>,
+    /// A BCP 47 tag.
+    pub locale: Option<String>,
+    /// A BCP 47 tag.
+    pub output_language: Option<String>,
+    /// The events already on the calendar, which are added for the case (and removed after).
+    #[serde(default)]
+    pub calendar: Vec<NewCalendarEvent>,
+    pub expected: ExpectedEvents,
+    /// The directory the case is in, which its input file is relative to.
+    #[serde(skip)]
+    dir: PathBuf
+}
+
+/// The input of a case.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaseInput {
+    Text { text: String },
+    Image { file: PathBuf, context: Option<String> },
+    /// `content_type` is only needed if the format can't be detected from the file itself.
+    Audio { file: PathBuf, content_type: Option<String>, context: Option<String> },
+    /// An `.eml` or `.msg` file.
+    Email { file: PathBuf, context: Option<String> }
+}
+
+/// Load the cases in the corpus, sorted by name.
+pub fn load_corpus(dir: &Path) -> Result<Vec<EvalCase>, String> {
+    let cases_dir = dir.join("cases");
+    let entries = fs::read_dir(&cases_dir).map_err(|err| format!("Failed to read {}: {err}", cases_dir.display()))?;
+    let mut cases = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|err| format!("Failed to read {}: {err}", cases_dir.display()))?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let mut case: EvalCase = serde_json::from_str(&source)
+            .map_err(|err| format!("Failed to parse {}: {err}", path.display()))?;
+        if case.name.is_empty() {
+            case.name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        }
+        case.dir = cases_dir.clone();
+        cases.push(case);
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Runs cases through the extraction pipeline and scores them.
+pub struct Evaluator {
+    llm: LLM,
+    ai_add_events: AIAddEventsService,
+    import_batches: ImportBatchesService,
+    repositories: Repositories,
+    user_id: Uuid,
+    limits: UploadLimitsConfig,
+    tolerance: Tolerance
+}
+
+impl Evaluator {
+    /// Run the cases as the user `user_id`, which should be an account kept for evaluations, with nothing on its
+    /// calendar: the cases use up its quota, and their extractions are recorded under it.
+    pub fn new(
+        llm: LLM,
+        repositories: Repositories,
+        user_id: Uuid,
+        quotas: &AIQuotaConfig,
+        limits: UploadLimitsConfig,
+        tolerance: Tolerance
+    ) -> Self {
+        let extractions = ExtractionsService::new(repositories.clone(), llm.model(), &AIProvenanceConfig::default());
+        let ai_add_events = AIAddEventsService::new(
+            llm.clone(),
+            repositories.clone(),
+            RecurringEventsService::new(repositories.clone(), extractions.clone()),
+            AIUsageService::new(repositories.clone(), quotas),
+            extractions,
+            &AICacheConfig::default()
+        );
+        Self {
+            llm,
+            ai_add_events,
+            import_batches: ImportBatchesService::new(repositories.clone()),
+            repositories,
+            user_id,
+            limits,
+            tolerance
+        }
+    }
+
+    /// Run the cases one at a time (so a live run doesn't get rate limited), and report how they did.
+    ///
+    /// A case which fails to extract is reported as failed (or skipped, if its response wasn't recorded), rather than
+    /// stopping the run.
+    pub async fn run(&self, cases: &[EvalCase]) -> EvalReport {
+        let mut report = EvalReport::new(self.llm.model(), &self.llm.prompt_version(), self.tolerance);
+        for case in cases {
+            tracing::info!("Evaluating {}", case.name);
+            let usage_before = self.llm.total_usage();
+            let outcome = match self.extract(case).await {
+                Ok(extracted) => CaseOutcome::Scored {
+                    score: scoring::score(&case.expected, &extracted, &self.tolerance, !case.calendar.is_empty())
+                },
+                Err(CaseError::NotRecorded(reason)) => CaseOutcome::Skipped { reason },
+                Err(CaseError::Failed(error)) => CaseOutcome::Failed { error }
+            };
+            let tokens = self.llm.total_usage() - usage_before;
+            report.add_case(CaseReport { name: case.name.clone(), tokens, outcome });
+        }
+        report
+    }
+
+    /// Extract the events from a case's input, as the API would, with its calendar's events added for the duration.
+    async fn extract(&self, case: &EvalCase) -> Result<GeneratedEvents, CaseError> {
+        let calendar_batch = self.add_calendar(case).await?;
+        let extracted = self.generate(case).await;
+        if let Some(batch_id) = calendar_batch
+        && let Err(err) = self.import_batches.undo_batch(self.user_id, batch_id).await
+        {
+            tracing::warn!("Failed to remove the calendar events added for {}: {err}", case.name);
+        }
+        extracted
+    }
+
+    async fn generate(&self, case: &EvalCase) -> Result<GeneratedEvents, CaseError> {
+        let options = ExtractionOptions {
+            timezone: case.timezone.parse()?,
+            now: case.now,
+            use_calendar_context: !case.calendar.is_empty(),
+            include_extraction_text: false,
+            bypass_cache: true,
+            locale: parse_language_tag(case.locale.as_deref())?,
+            output_language: parse_language_tag(case.output_language.as_deref())?,
+            progress: ExtractionProgress::default()
+        };
+
+        let service = &self.ai_add_events;
+        match &case.input {
+            CaseInput::Text { text } => service.generate_from_text(self.user_id, text.clone(), options).await,
+            CaseInput::Image { file, context } => {
+                let image = self.read_input(case, file)?;
+                service.generate_from_image(self.user_id, image, context.clone(), options, &self.limits).await
+            },
+            CaseInput::Audio { file, content_type, context } => {
+                let audio = UploadedFile { body: self.read_input(case, file)?, content_type: content_type.clone() };
+                service.generate_from_audio(self.user_id, audio, context.clone(), options, &self.limits).await
+            },
+            CaseInput::Email { file, context } => {
+                let email = self.read_input(case, file)?;
+                service.generate_from_email(self.user_id, email, context.clone(), options, &self.limits).await
+            }
+        }.map_err(|err| match err {
+            ApiError::LLM(err @ LLMError::NotRecorded { .. }) => CaseError::NotRecorded(err.to_string()),
+            err => CaseError::Failed(err.to_string())
+        })
+    }
+
+    /// Add the case's calendar events (if it has any) in an import batch, returning the batch to undo afterwards.
+    async fn add_calendar(&self, case: &EvalCase) -> Result<Option<Uuid>, String> {
+        if case.calendar.is_empty() {
+            return Ok(None);
+        }
+        let database_error = |err: sqlx::Error| format!("Failed to add the calendar events: {err}");
+        let mut tx = self.repositories.begin().await.map_err(database_error)?;
+        let batch_id = self.repositories
+            .import_batches
+            .create_batch_in(&mut tx, self.user_id, ImportSource::Events, None)
+            .await
+            .map_err(database_error)?;
+        self.repositories
+            .calendar_events
+            .create_events_in(&mut tx, self.user_id, case.calendar.clone(), None, batch_id)
+            .await
+            .map_err(database_error)?;
+        tx.commit().await.map_err(database_error)?;
+        Ok(Some(batch_id))
+    }
+
+    /// Read a case's input file.
+    fn read_input(&self, case: &EvalCase, file: &Path) -> Result<UploadBody, String> {
+        let path = case.dir.join(file);
+        let bytes = fs::read(&path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        Ok(UploadBody::Memory(bytes.into()))
+    }
+}
+
+/// Why a case couldn't be scored.
+enum CaseError {
+    /// The LLM's response for the case wasn't recorded, so couldn't be replayed.
+    NotRecorded(String),
+    Failed(String)
+}
+
+impl From<String> for CaseError {
+    fn from(error: String) -> Self {
+        Self::Failed(error)
+    }
+}
+
+fn parse_language_tag(tag: Option<&str>) -> Result<Option<LanguageTag>, String> {
+    tag.map(|tag| LanguageTag::parse(tag).ok_or_else(|| format!("`{tag}` is not a valid BCP 47 language tag")))
+        .transpose()
+}
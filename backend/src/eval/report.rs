@@ -0,0 +1,157 @@
+//! The report of an evaluation run, printed as a table or saved as JSON to compare later runs against.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::{eval::scoring::{Score, Tolerance}, models::ai_usage::TokenUsage};
+
+/// The results of an evaluation run.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EvalReport {
+    pub model: String,
+    pub prompt_version: String,
+    pub tolerance: Tolerance,
+    pub cases: Vec<CaseReport>,
+    /// The scores of every case which was scored, added together.
+    pub total: Score,
+    pub failed_cases: usize,
+    #[serde(default)]
+    pub skipped_cases: usize,
+    pub tokens: TokenUsage
+}
+
+/// The results of a single case.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaseReport {
+    pub name: String,
+    pub tokens: TokenUsage,
+    #[serde(flatten)]
+    pub outcome: CaseOutcome
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum CaseOutcome {
+    Scored { score: Score },
+    /// The extraction itself failed (e.g. the response couldn't be parsed).
+    Failed { error: String },
+    /// The case needed a response which wasn't recorded, so couldn't be replayed.
+    Skipped { reason: String }
+}
+
+impl EvalReport {
+    pub fn new(model: &str, prompt_version: &str, tolerance: Tolerance) -> Self {
+        Self {
+            model: model.into(),
+            prompt_version: prompt_version.into(),
+            tolerance,
+            cases: Vec::new(),
+            total: Score::default(),
+            failed_cases: 0,
+            skipped_cases: 0,
+            tokens: TokenUsage::default()
+        }
+    }
+
+    pub fn add_case(&mut self, case: CaseReport) {
+        match &case.outcome {
+            CaseOutcome::Scored { score } => self.total.add(score),
+            CaseOutcome::Failed { .. } => self.failed_cases += 1,
+            CaseOutcome::Skipped { .. } => self.skipped_cases += 1
+        }
+        self.tokens += case.tokens;
+        self.cases.push(case);
+    }
+
+    /// Compare this run against an earlier one.
+    pub fn compared_to<'a>(&'a self, baseline: &'a EvalReport) -> ReportComparison<'a> {
+        ReportComparison { report: self, baseline }
+    }
+}
+
+/// Gets a metric from a score, if there's anything to measure.
+type Metric = fn(&Score) -> Option<f64>;
+
+/// The metrics shown for each score, as (heading, metric).
+const METRICS: [(&str, Metric); 6] = [
+    ("recall", Score::recall),
+    ("precision", Score::precision),
+    ("datetimes", Score::datetime_accuracy),
+    ("titles", Score::title_accuracy),
+    ("rrules", Score::rrule_accuracy),
+    ("duplicates", Score::duplicate_accuracy)
+];
+
+fn percentage(value: Option<f64>) -> String {
+    value.map_or("-".into(), |value| format!("{:.1}%", value * 100.0))
+}
+
+fn write_score_row(f: &mut fmt::Formatter<'_>, name: &str, score: &Score) -> fmt::Result {
+    write!(f, "{name:<32} {:>9}", format!("{}/{}", score.matched, score.expected))?;
+    for (_, metric) in METRICS {
+        write!(f, " {:>10}", percentage(metric(score)))?;
+    }
+    writeln!(f)
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Model {}, prompts {} (datetime tolerance {} min, title similarity {})",
+            self.model, self.prompt_version, self.tolerance.datetime_minutes, self.tolerance.title_similarity
+        )?;
+        writeln!(f)?;
+        write!(f, "{:<32} {:>9}", "case", "matched")?;
+        for (heading, _) in METRICS {
+            write!(f, " {heading:>10}")?;
+        }
+        writeln!(f)?;
+
+        for case in &self.cases {
+            match &case.outcome {
+                CaseOutcome::Scored { score } => {
+                    write_score_row(f, &case.name, score)?;
+                    for mismatch in &score.mismatches {
+                        writeln!(f, "    - {mismatch}")?;
+                    }
+                },
+                CaseOutcome::Failed { error } => writeln!(f, "{:<32} FAILED: {error}", case.name)?,
+                CaseOutcome::Skipped { reason } => writeln!(f, "{:<32} SKIPPED: {reason}", case.name)?
+            }
+        }
+
+        writeln!(f)?;
+        let total = format!(
+            "total ({} cases, {} failed, {} skipped)",
+            self.cases.len(), self.failed_cases, self.skipped_cases
+        );
+        write_score_row(f, &total, &self.total)?;
+        writeln!(f, "{} tokens used", self.tokens.total_tokens)
+    }
+}
+
+/// A run compared against an earlier one, shown as the change in each total metric.
+pub struct ReportComparison<'a> {
+    report: &'a EvalReport,
+    baseline: &'a EvalReport
+}
+
+impl fmt::Display for ReportComparison<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Compared to model {}, prompts {}:",
+            self.baseline.model, self.baseline.prompt_version
+        )?;
+        for (heading, metric) in METRICS {
+            let (before, after) = (metric(&self.baseline.total), metric(&self.report.total));
+            let change = match (before, after) {
+                (Some(before), Some(after)) => format!(" ({:+.1})", (after - before) * 100.0),
+                _ => String::new()
+            };
+            writeln!(f, "  {heading:<10} {:>7} -> {:>7}{change}", percentage(before), percentage(after))?;
+        }
+        writeln!(f, "  {:<10} {:>7} -> {:>7}", "failed", self.baseline.failed_cases, self.report.failed_cases)?;
+        writeln!(f, "  {:<10} {:>7} -> {:>7}", "skipped", self.baseline.skipped_cases, self.report.skipped_cases)
+    }
+}
@@ -0,0 +1,419 @@
+//! Scoring extracted events against the expected ones.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{
+    llm::GeneratedEvents,
+    models::{calendar_event::NewCalendarEvent, recurring_event::{NewRecurringEvent, NewRecurringEventWithExceptions}},
+    utils::matching::{occurrences, pair_by_title_and_start, same_occurrences, title_similarity}
+};
+
+/// The events expected from a case.
+///
+/// This takes the same shape as `GeneratedEvents` (so a response from the API can be used as-is), but the fields only
+/// the LLM fills in (such as `confidence`) can be left out, and groups only matter for their recurring events.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExpectedEvents {
+    #[serde(default)]
+    pub events: Vec<NewCalendarEvent>,
+    #[serde(default)]
+    pub recurring_events: Vec<NewRecurringEventWithExceptions>,
+    #[serde(default)]
+    pub recurring_event_groups: Vec<ExpectedGroup>,
+    /// The titles of the expected items which duplicate one of the case's existing events, so should be marked as
+    /// likely duplicates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub likely_duplicates: Vec<String>
+}
+
+/// A group of expected recurring events.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExpectedGroup {
+    #[serde(default)]
+    pub recurring_events: Vec<NewRecurringEventWithExceptions>
+}
+
+impl ExpectedEvents {
+    /// All the recurring events, whether or not they're under a group.
+    fn all_recurring_events(&self) -> impl Iterator<Item = &NewRecurringEvent> {
+        self.recurring_events
+            .iter()
+            .chain(self.recurring_event_groups.iter().flat_map(|g| &g.recurring_events))
+            .map(|e| &e.event)
+    }
+}
+
+/// How lenient the scoring is.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// How many minutes a datetime can be off by while still counting as correct.
+    pub datetime_minutes: i64,
+    /// How similar two titles must be (from 0 to 1, by the share of words they have in common) to count as the same.
+    pub title_similarity: f64
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            datetime_minutes: 1,
+            title_similarity: 0.5
+        }
+    }
+}
+
+impl Tolerance {
+    fn datetimes_match(&self, a: DateTime<Utc>, b: DateTime<Utc>) -> bool {
+        (a - b).abs() <= Duration::minutes(self.datetime_minutes)
+    }
+}
+
+/// How well the extracted events of a case (or of a whole run) match the expected ones.
+///
+/// One-off and recurring events are counted together as "items"; every accuracy is out of the expected items,
+/// so a missing item counts against all of them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Score {
+    pub expected: usize,
+    pub extracted: usize,
+    /// Extracted items paired up with an expected one.
+    pub matched: usize,
+    pub datetimes_correct: usize,
+    pub titles_correct: usize,
+    pub expected_rrules: usize,
+    /// Recurring events whose rule expands to the same occurrences as expected.
+    pub rrules_correct: usize,
+    /// Matched items whose likely duplicate mark was checked (only in cases with existing events).
+    #[serde(default)]
+    pub duplicates_checked: usize,
+    /// Matched items marked as likely duplicates exactly when expected to be.
+    #[serde(default)]
+    pub duplicates_correct: usize,
+    /// What was wrong, for the report.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mismatches: Vec<String>
+}
+
+impl Score {
+    /// Add another score's counts to this one (without its mismatches).
+    pub fn add(&mut self, other: &Score) {
+        self.expected += other.expected;
+        self.extracted += other.extracted;
+        self.matched += other.matched;
+        self.datetimes_correct += other.datetimes_correct;
+        self.titles_correct += other.titles_correct;
+        self.expected_rrules += other.expected_rrules;
+        self.rrules_correct += other.rrules_correct;
+        self.duplicates_checked += other.duplicates_checked;
+        self.duplicates_correct += other.duplicates_correct;
+    }
+
+    /// The share of expected items which were extracted.
+    pub fn recall(&self) -> Option<f64> {
+        ratio(self.matched, self.expected)
+    }
+
+    /// The share of extracted items which were expected.
+    pub fn precision(&self) -> Option<f64> {
+        ratio(self.matched, self.extracted)
+    }
+
+    pub fn datetime_accuracy(&self) -> Option<f64> {
+        ratio(self.datetimes_correct, self.expected)
+    }
+
+    pub fn title_accuracy(&self) -> Option<f64> {
+        ratio(self.titles_correct, self.expected)
+    }
+
+    pub fn rrule_accuracy(&self) -> Option<f64> {
+        ratio(self.rrules_correct, self.expected_rrules)
+    }
+
+    pub fn duplicate_accuracy(&self) -> Option<f64> {
+        ratio(self.duplicates_correct, self.duplicates_checked)
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+/// Score the extracted events against the expected ones.
+///
+/// Items are paired up by their titles and starts (see `pair_by_title_and_start`), then each pair's datetimes, titles and
+/// (for recurring events) rules are compared; rules are compared by the occurrences they expand to, so different
+/// ways of writing the same rule still match. If `check_duplicates` is set (as the case had existing events), each
+/// pair's likely duplicate mark is compared too.
+pub fn score(expected: &ExpectedEvents, extracted: &GeneratedEvents, tolerance: &Tolerance, check_duplicates: bool) -> Score {
+    let mut score = Score::default();
+    let likely_duplicates = &expected.likely_duplicates;
+
+    let expected_events: Vec<_> = expected.events.iter().collect();
+    let extracted_marks: Vec<_> = extracted.events.iter().map(|e| e.likely_duplicate_of.is_some()).collect();
+    let extracted_events: Vec<_> = extracted.events.iter().map(|e| &e.item).collect();
+    let key = |e: &NewCalendarEvent| (e.title.clone(), e.start_time);
+    for pair in pair_items(&expected_events, &extracted_events, key, tolerance, &mut score) {
+        let (expected, extracted) = (expected_events[pair.0], extracted_events[pair.1]);
+        if tolerance.datetimes_match(expected.start_time, extracted.start_time)
+        && tolerance.datetimes_match(expected.end_time, extracted.end_time)
+        {
+            score.datetimes_correct += 1;
+        } else {
+            score.mismatches.push(format!(
+                "\"{}\": {} to {}, expected {} to {}",
+                expected.title, extracted.start_time, extracted.end_time, expected.start_time, expected.end_time
+            ));
+        }
+        score_title(&expected.title, &extracted.title, tolerance, &mut score);
+        if check_duplicates {
+            score_duplicate(&expected.title, extracted_marks[pair.1], likely_duplicates, &mut score);
+        }
+    }
+
+    let expected_recurring: Vec<_> = expected.all_recurring_events().collect();
+    let extracted_recurring_marks: Vec<_> = extracted.all_recurring_events().map(|e| e.likely_duplicate_of.is_some()).collect();
+    let extracted_recurring: Vec<_> = extracted.all_recurring_events().map(|e| &e.item.event).collect();
+    score.expected_rrules = expected_recurring.len();
+    let key = |e: &NewRecurringEvent| (e.title.clone(), e.recurrence_start);
+    for pair in pair_items(&expected_recurring, &extracted_recurring, key, tolerance, &mut score) {
+        let (expected, extracted) = (expected_recurring[pair.0], extracted_recurring[pair.1]);
+        let duration_difference = i64::from(expected.event_duration_seconds.0) - i64::from(extracted.event_duration_seconds.0);
+        if tolerance.datetimes_match(expected.recurrence_start, extracted.recurrence_start)
+        && duration_difference.abs() <= tolerance.datetime_minutes * 60
+        {
+            score.datetimes_correct += 1;
+        } else {
+            score.mismatches.push(format!(
+                "\"{}\": starts {} lasting {}s, expected {} lasting {}s",
+                expected.title, extracted.recurrence_start, extracted.event_duration_seconds.0,
+                expected.recurrence_start, expected.event_duration_seconds.0
+            ));
+        }
+        score_title(&expected.title, &extracted.title, tolerance, &mut score);
+        if check_duplicates {
+            score_duplicate(&expected.title, extracted_recurring_marks[pair.1], likely_duplicates, &mut score);
+        }
+
+        let (expected_occurrences, extracted_occurrences) = (occurrences(expected), occurrences(extracted));
+        if same_occurrences(&expected_occurrences, &extracted_occurrences, Duration::minutes(tolerance.datetime_minutes)) {
+            score.rrules_correct += 1;
+        } else {
+            score.mismatches.push(format!(
+                "\"{}\": `{}` gives {} occurrences from {}, expected `{}` giving {} from {}",
+                expected.title,
+                extracted.rrule.rule_string(), extracted_occurrences.len(),
+                extracted_occurrences.first().map_or("-".into(), ToString::to_string),
+                expected.rrule.rule_string(), expected_occurrences.len(),
+                expected_occurrences.first().map_or("-".into(), ToString::to_string)
+            ));
+        }
+    }
+
+    score
+}
+
+/// Count the title as correct if it's similar enough, otherwise note it.
+fn score_title(expected: &str, extracted: &str, tolerance: &Tolerance, score: &mut Score) {
+    if title_similarity(expected, extracted) >= tolerance.title_similarity {
+        score.titles_correct += 1;
+    } else {
+        score.mismatches.push(format!("\"{expected}\": titled \"{extracted}\""));
+    }
+}
+
+/// Count the extracted item's likely duplicate mark as correct if it's marked exactly when expected, otherwise note it.
+fn score_duplicate(expected_title: &str, marked: bool, likely_duplicates: &[String], score: &mut Score) {
+    score.duplicates_checked += 1;
+    let duplicate = likely_duplicates.iter().any(|title| title == expected_title);
+    if marked == duplicate {
+        score.duplicates_correct += 1;
+    } else if duplicate {
+        score.mismatches.push(format!("\"{expected_title}\": not marked as a likely duplicate"));
+    } else {
+        score.mismatches.push(format!("\"{expected_title}\": marked as a likely duplicate"));
+    }
+}
+
+/// Pair up expected and extracted items (see `pair_by_title_and_start`), returning their indices, and count them
+/// towards the score.
+fn pair_items<T>(
+    expected: &[&T],
+    extracted: &[&T],
+    key: impl Fn(&T) -> (String, DateTime<Utc>),
+    tolerance: &Tolerance,
+    score: &mut Score
+) -> Vec<(usize, usize)> {
+    let expected_keys: Vec<_> = expected.iter().map(|item| key(item)).collect();
+    let extracted_keys: Vec<_> = extracted.iter().map(|item| key(item)).collect();
+    let pairs = pair_by_title_and_start(
+        &expected_keys,
+        &extracted_keys,
+        Duration::minutes(tolerance.datetime_minutes),
+        tolerance.title_similarity
+    );
+
+    let (mut expected_paired, mut extracted_paired) = (vec![false; expected.len()], vec![false; extracted.len()]);
+    for (i, j) in &pairs {
+        expected_paired[*i] = true;
+        extracted_paired[*j] = true;
+    }
+
+    score.expected += expected.len();
+    score.extracted += extracted.len();
+    score.matched += pairs.len();
+    for ((title, start), _) in expected_keys.iter().zip(&expected_paired).filter(|(_, paired)| !**paired) {
+        score.mismatches.push(format!("missing \"{title}\" at {start}"));
+    }
+    for ((title, start), _) in extracted_keys.iter().zip(&extracted_paired).filter(|(_, paired)| !**paired) {
+        score.mismatches.push(format!("extra \"{title}\" at {start}"));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn expected(events: Value, recurring_events: Value, likely_duplicates: &[&str]) -> ExpectedEvents {
+        serde_json::from_value(json!({
+            "events": events,
+            "recurring_events": recurring_events,
+            "likely_duplicates": likely_duplicates
+        })).unwrap()
+    }
+
+    /// Extracted events, with each item given the LLM's fields (and marked as a likely duplicate if it has `duplicate`).
+    fn extracted(events: Value, recurring_events: Value) -> GeneratedEvents {
+        let generated = |items: Value| -> Vec<Value> {
+            items.as_array().unwrap().iter().map(|item| {
+                let mut item = item.clone();
+                item["confidence"] = json!(0.9);
+                item["source_snippet"] = json!("");
+                if item.as_object_mut().unwrap().remove("duplicate").is_some() {
+                    item["likely_duplicate_of"] = json!({
+                        "kind": "calendar_event",
+                        "event_id": "00000000-0000-4000-8000-000000000001",
+                        "title": item["title"],
+                        "start_time": "2026-09-02T14:00:00Z"
+                    });
+                }
+                item
+            }).collect()
+        };
+        serde_json::from_value(json!({
+            "events": generated(events),
+            "recurring_events": generated(recurring_events),
+            "recurring_event_groups": []
+        })).unwrap()
+    }
+
+    fn event(title: &str, start_time: &str, end_time: &str) -> Value {
+        json!({ "title": title, "description": null, "location": null, "start_time": start_time, "end_time": end_time })
+    }
+
+    fn recurring(rrule: &str, recurrence_end: Option<&str>) -> Value {
+        json!({
+            "group_id": null,
+            "is_active": true,
+            "title": "COMP1511 Lecture",
+            "description": null,
+            "location": null,
+            "event_duration_seconds": 7200,
+            "recurrence_start": "2026-09-14T00:00:00Z",
+            "recurrence_end": recurrence_end,
+            "rrule": rrule
+        })
+    }
+
+    #[test]
+    fn scores_matching_events() {
+        let expected = expected(json!([event("Dentist", "2026-09-02T14:00:00Z", "2026-09-02T15:00:00Z")]), json!([]), &[]);
+        let extracted = extracted(
+            json!([event("Dentist appointment", "2026-09-02T14:00:00Z", "2026-09-02T15:00:00Z")]),
+            json!([])
+        );
+        let score = score(&expected, &extracted, &Tolerance::default(), false);
+        assert_eq!((score.expected, score.extracted, score.matched), (1, 1, 1));
+        assert_eq!((score.datetimes_correct, score.titles_correct), (1, 1));
+        assert_eq!(score.recall(), Some(1.0));
+        assert_eq!(score.rrule_accuracy(), None);
+        assert!(score.mismatches.is_empty());
+    }
+
+    #[test]
+    fn counts_datetimes_within_the_tolerance() {
+        let expected = expected(json!([event("Dentist", "2026-09-02T14:00:00Z", "2026-09-02T15:00:00Z")]), json!([]), &[]);
+        let slightly_off = extracted(json!([event("Dentist", "2026-09-02T14:01:00Z", "2026-09-02T15:00:00Z")]), json!([]));
+        let off = extracted(json!([event("Dentist", "2026-09-02T14:30:00Z", "2026-09-02T15:30:00Z")]), json!([]));
+
+        assert_eq!(score(&expected, &slightly_off, &Tolerance::default(), false).datetimes_correct, 1);
+        let strict = Tolerance { datetime_minutes: 0, ..Tolerance::default() };
+        assert_eq!(score(&expected, &slightly_off, &strict, false).datetimes_correct, 0);
+
+        // still paired up by its title, but with the wrong time
+        let score = score(&expected, &off, &Tolerance::default(), false);
+        assert_eq!((score.matched, score.datetimes_correct, score.titles_correct), (1, 0, 1));
+        assert_eq!(score.mismatches.len(), 1);
+    }
+
+    #[test]
+    fn counts_missing_and_extra_events() {
+        let expected = expected(json!([event("Dentist", "2026-09-02T14:00:00Z", "2026-09-02T15:00:00Z")]), json!([]), &[]);
+        let extracted = extracted(json!([event("Haircut", "2026-09-05T10:00:00Z", "2026-09-05T10:30:00Z")]), json!([]));
+        let score = score(&expected, &extracted, &Tolerance::default(), false);
+        assert_eq!((score.expected, score.extracted, score.matched), (1, 1, 0));
+        assert_eq!((score.recall(), score.precision(), score.datetime_accuracy()), (Some(0.0), Some(0.0), Some(0.0)));
+        assert_eq!(score.mismatches, [
+            "missing \"Dentist\" at 2026-09-02 14:00:00 UTC",
+            "extra \"Haircut\" at 2026-09-05 10:00:00 UTC"
+        ]);
+    }
+
+    #[test]
+    fn compares_rules_by_their_occurrences() {
+        let expected = expected(json!([]), json!([recurring("FREQ=WEEKLY;COUNT=20;BYDAY=MO,WE", None)]), &[]);
+        // the same rule, written with an UNTIL rather than a COUNT
+        let until = extracted(json!([]), json!([recurring("FREQ=WEEKLY;BYDAY=WE,MO", Some("2026-11-18T00:00:00Z"))]));
+        let score_until = score(&expected, &until, &Tolerance::default(), false);
+        assert_eq!((score_until.matched, score_until.rrules_correct, score_until.expected_rrules), (1, 1, 1));
+        assert_eq!(score_until.rrule_accuracy(), Some(1.0));
+
+        // a week short
+        let short = extracted(json!([]), json!([recurring("FREQ=WEEKLY;COUNT=18;BYDAY=MO,WE", None)]));
+        let score_short = score(&expected, &short, &Tolerance::default(), false);
+        assert_eq!((score_short.matched, score_short.datetimes_correct, score_short.rrules_correct), (1, 1, 0));
+        assert_eq!(score_short.mismatches.len(), 1);
+    }
+
+    #[test]
+    fn checks_likely_duplicate_marks() {
+        let expected = expected(
+            json!([
+                event("Haircut", "2026-09-02T14:00:00Z", "2026-09-02T14:30:00Z"),
+                event("Dentist", "2026-09-03T09:00:00Z", "2026-09-03T10:00:00Z")
+            ]),
+            json!([]),
+            &["Haircut"]
+        );
+        let mut duplicate = event("Haircut", "2026-09-02T14:00:00Z", "2026-09-02T14:30:00Z");
+        duplicate["duplicate"] = json!(true);
+        let extracted = extracted(
+            json!([duplicate, event("Dentist", "2026-09-03T09:00:00Z", "2026-09-03T10:00:00Z")]),
+            json!([])
+        );
+
+        let checked = score(&expected, &extracted, &Tolerance::default(), true);
+        assert_eq!((checked.duplicates_checked, checked.duplicates_correct), (2, 2));
+        let unchecked = score(&expected, &extracted, &Tolerance::default(), false);
+        assert_eq!((unchecked.duplicates_checked, unchecked.duplicate_accuracy()), (0, None));
+    }
+
+    #[test]
+    fn adds_scores() {
+        let mut total = Score { expected: 2, matched: 1, rrules_correct: 1, expected_rrules: 1, ..Score::default() };
+        total.add(&Score { expected: 3, matched: 3, mismatches: vec!["extra".into()], ..Score::default() });
+        assert_eq!((total.expected, total.matched, total.rrules_correct), (5, 4, 1));
+        assert!(total.mismatches.is_empty());
+    }
+}
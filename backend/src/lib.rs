@@ -0,0 +1,10 @@
+mod telemetry;
+pub mod config;
+pub mod api;
+mod models;
+pub mod llm;
+pub mod services;
+pub mod repositories;
+mod auth;
+mod utils;
+pub mod eval;
@@ -0,0 +1,302 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{
+    llm::{DuplicateHint, ExistingEventKind},
+    models::{
+        calendar_event::CalendarEvent, 
+        calendar_query::{CalendarSearch, MatchedEvent},
+        recurring_event::RecurringEvent, 
+        recurring_event_group::RecurringEventGroup, 
+        time::UserTimezone
+    }
+};
+
+/// The most existing events we'll put in a prompt, so a busy calendar doesn't blow up the prompt size.
+const MAX_PROMPT_EVENTS: usize = 100;
+
+/// What we tell the LLM about the user's existing calendar, so it can fit extracted events into it.
+#[derive(Clone, Debug, Default)]
+pub struct CalendarContext {
+    pub groups: Vec<RecurringEventGroup>,
+    /// Events (and recurring event instances) already on the user's calendar, sorted by start time.
+    pub events: Vec<ExistingEvent>
+}
+
+/// An event (or a single instance of a recurring event) already on the user's calendar.
+#[derive(Clone, Debug)]
+pub struct ExistingEvent {
+    pub kind: ExistingEventKind,
+    /// The ID of the `CalendarEvent` or `RecurringEvent`.
+    pub event_id: Uuid,
+    pub title: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>
+}
+
+impl ExistingEvent {
+    /// A hint pointing at this event.
+    pub fn to_duplicate_hint(&self) -> DuplicateHint {
+        DuplicateHint {
+            kind: self.kind,
+            event_id: self.event_id,
+            title: self.title.clone(),
+            start_time: self.start_time
+        }
+    }
+}
+
+impl CalendarContext {
+    /// Whether `group_id` is one of the user's existing groups.
+    pub fn has_group(&self, group_id: Uuid) -> bool {
+        self.groups.iter().any(|g| g.id == group_id)
+    }
+
+    /// The section appended to the system instructions.
+    pub fn prompt_section(&self, timezone: &UserTimezone) -> String {
+        let groups_section = if self.groups.is_empty() {
+            "The user has no existing groups, so always leave `group_id` empty.".to_string()
+        } else {
+            let groups = self.groups
+                .iter()
+                .map(|g| match &g.description {
+                    Some(description) => format!("- {}: \"{}\" ({description})", g.id, g.name),
+                    None => format!("- {}: \"{}\"", g.id, g.name)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(r#"
+                The user already has these groups of recurring events (as `id: "name" (description)`):
+                {groups}
+
+                If an extracted recurring event clearly belongs to one of these groups, put it in the top-level `recurring_events` with its `group_id`
+                set to that group's id, and DO NOT create a new group for it. Otherwise, leave `group_id` empty. Never make up an id.
+            "#)
+        };
+        if self.events.is_empty() {
+            return groups_section;
+        }
+
+        let events = self.events
+            .iter()
+            .take(MAX_PROMPT_EVENTS)
+            .map(|e| format!(
+                "- \"{}\": {} to {}",
+                e.title,
+                timezone.utc_to_local(e.start_time).format("%a %d/%m/%Y %H:%M"),
+                timezone.utc_to_local(e.end_time).format("%a %d/%m/%Y %H:%M")
+            ))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(r#"
+            {groups_section}
+
+            These events are already on the user's calendar (in the user's local time):
+            {events}
+
+            Still extract every event found in the input, even if it's already on the calendar. If an extracted event is the same
+            as one of these, reuse its exact title.
+        "#)
+    }
+}
+
+/// What we tell the LLM about the user's calendar, so it can edit it.
+#[derive(Clone, Debug, Default)]
+pub struct EditContext {
+    pub groups: Vec<RecurringEventGroup>,
+    pub events: Vec<CalendarEvent>,
+    pub recurring_events: Vec<RecurringEvent>
+}
+
+impl EditContext {
+    /// The section describing the calendar in the system instructions.
+    pub fn prompt_section(&self, timezone: &UserTimezone) -> String {
+        let format = |datetime: DateTime<Utc>| timezone.utc_to_local(datetime).format("%a %d/%m/%Y %H:%M").to_string();
+        let details = |description: &Option<String>, location: &Option<String>| {
+            let mut details = String::new();
+            if let Some(location) = location {
+                details.push_str(&format!(" at {location}"));
+            }
+            if let Some(description) = description {
+                details.push_str(&format!(" ({description})"));
+            }
+            details
+        };
+
+        let events = self.events
+            .iter()
+            .take(MAX_PROMPT_EVENTS)
+            .map(|e| format!(
+                "- id {}: \"{}\" from {} to {}{}",
+                e.id, e.title, format(e.start_time), format(e.end_time), details(&e.description, &e.location)
+            ))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let recurring_events = self.recurring_events
+            .iter()
+            .take(MAX_PROMPT_EVENTS)
+            .map(|e| {
+                let group = e.group_id
+                    .and_then(|id| self.groups.iter().find(|g| g.id == id))
+                    .map(|g| format!(" in group \"{}\"", g.name))
+                    .unwrap_or_default();
+                let end = e.recurrence_end
+                    .map(|end| format!(" until {}", format(end)))
+                    .unwrap_or_default();
+                format!(
+                    "- id {}: \"{}\"{group}, `{}` starting {}{end}, lasting {} seconds{}",
+                    e.id, e.title, e.rrule.rule_string(), format(e.recurrence_start), 
+                    e.event_duration_seconds.0, details(&e.description, &e.location)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        format!(r#"
+            The user's one-off events are (in the user's local time):
+            {events}
+
+            The user's recurring events are (in the user's local time):
+            {recurring_events}
+        "#)
+    }
+}
+
+/// What we tell the LLM about the user's calendar, so it can turn a question into a search over it.
+#[derive(Clone, Debug, Default)]
+pub struct SearchContext {
+    pub groups: Vec<RecurringEventGroup>
+}
+
+impl SearchContext {
+    /// The section describing the user's groups in the system instructions.
+    pub fn prompt_section(&self, timezone: &UserTimezone) -> String {
+        if self.groups.is_empty() {
+            return "The user has no groups of recurring events, so always leave `group_id` empty.".to_string();
+        }
+        let format = |datetime: DateTime<Utc>| timezone.utc_to_local(datetime).format("%a %d/%m/%Y").to_string();
+        let groups = self.groups
+            .iter()
+            .map(|g| {
+                let mut group = format!("- {}: \"{}\"", g.id, g.name);
+                if let Some(description) = &g.description {
+                    group.push_str(&format!(" ({description})"));
+                }
+                match (g.group_recurrence_start, g.group_recurrence_end) {
+                    (Some(start), Some(end)) => group.push_str(&format!(", running {} to {}", format(start), format(end))),
+                    (Some(start), None) => group.push_str(&format!(", running from {}", format(start))),
+                    (None, Some(end)) => group.push_str(&format!(", running until {}", format(end))),
+                    (None, None) => {}
+                }
+                group
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(r#"
+            The user has these groups of recurring events (as `id: "name" (description)`, with the dates they run 
+            between, if known):
+            {groups}
+
+            If the question is about one of these groups (e.g. a course, or "this term"), set `group_id` to its id, and use its 
+            dates for the period where the question implies them. Otherwise, leave `group_id` empty. Never make up an id.
+        "#)
+    }
+}
+
+/// The events matching a search, which the LLM answers the question from.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResults<'a> {
+    pub search: &'a CalendarSearch,
+    /// The first matching events, in the search's order.
+    pub events: &'a [MatchedEvent],
+    pub total_matches: usize
+}
+
+impl SearchResults<'_> {
+    /// The section describing the search and its results in the system instructions.
+    pub fn prompt_section(&self, timezone: &UserTimezone) -> String {
+        let format = |datetime: DateTime<Utc>| timezone.utc_to_local(datetime).format("%a %d/%m/%Y %H:%M").to_string();
+        let period = format!("{} to {}", format(self.search.start_time), format(self.search.end_time));
+        if self.events.is_empty() {
+            return format!("No events on the user's calendar from {period} match the question.");
+        }
+        let events = self.events
+            .iter()
+            .map(|e| {
+                let mut event = format!("- \"{}\": {} to {}", e.title, format(e.start_time), format(e.end_time));
+                if let Some(location) = &e.location {
+                    event.push_str(&format!(" at {location}"));
+                }
+                if let Some(group_name) = &e.group_name {
+                    event.push_str(&format!(" (in group \"{group_name}\")"));
+                }
+                event
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let shown = if self.total_matches > self.events.len() {
+            format!("These are the first {} of the {} matching events", self.events.len(), self.total_matches)
+        } else {
+            format!("These are all {} matching events", self.total_matches)
+        };
+        format!(r#"
+            {shown} on the user's calendar from {period} (in the user's local time):
+            {events}
+        "#)
+    }
+}
+
+/// What we tell the LLM about the user's groups and ungrouped imported events, so it can categorise them.
+#[derive(Clone, Debug, Default)]
+pub struct CategorisationContext {
+    pub groups: Vec<RecurringEventGroup>,
+    /// The events to categorise, referred to by their index.
+    pub events: Vec<CalendarEvent>
+}
+
+impl CategorisationContext {
+    /// Whether `group_id` is one of the user's existing groups.
+    pub fn has_group(&self, group_id: Uuid) -> bool {
+        self.groups.iter().any(|g| g.id == group_id)
+    }
+
+    /// The section describing the groups and events in the system instructions.
+    pub fn prompt_section(&self) -> String {
+        let groups = if self.groups.is_empty() {
+            "The user has no groups yet, so always leave `group_id` empty.".to_string()
+        } else {
+            let groups = self.groups
+                .iter()
+                .map(|g| match &g.description {
+                    Some(description) => format!("- {}: \"{}\" ({description})", g.id, g.name),
+                    None => format!("- {}: \"{}\"", g.id, g.name)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(r#"
+                The user has these groups (as `id: "name" (description)`):
+                {groups}
+            "#)
+        };
+        let events = self.events
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let mut event = format!("- {i}: \"{}\"", e.title);
+                if let Some(location) = &e.location {
+                    event.push_str(&format!(" at {location}"));
+                }
+                if let Some(organiser) = &e.organiser {
+                    event.push_str(&format!(", organised by {organiser}"));
+                }
+                event
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(r#"
+            {groups}
+
+            The events to categorise are (as `number: "title" at location, organised by organiser`):
+            {events}
+        "#)
+    }
+}
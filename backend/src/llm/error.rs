@@ -1,3 +1,4 @@
+use std::{path::PathBuf, time::Duration};
 use thiserror::Error;
 
 /// Errors that may arise from the Gemini API.
@@ -12,5 +13,45 @@ pub enum LLMError {
     #[error("Got a bad status error from request: {0}")]
     BadStatus(reqwest::Error),
     #[error("Failed to request Gemini API: {0}")]
-    FailedRequest(reqwest::Error)
-}
\ No newline at end of file
+    FailedRequest(reqwest::Error),
+    /// Gemini is rate limiting us; `retry_after` is how long it asked us to wait, if it said.
+    #[error("The LLM is currently overloaded; try again later")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The input (or what the LLM generated from it) was blocked, e.g. by Gemini's safety filters.
+    #[error("The input was blocked by the LLM's content filters ({reason})")]
+    Blocked { reason: String },
+    #[error("The LLM took too long to respond")]
+    Timeout,
+    /// A response couldn't be recorded or replayed (see `ResponseSource`).
+    #[error("Failed to record or replay the response at {}: {reason}", path.display())]
+    Recording { path: PathBuf, reason: String },
+    /// There's no recorded response to replay for a request (see `ResponseSource`).
+    #[error(
+        "No response was recorded at {} (the prompts, inputs or dates may have changed since it was recorded)",
+        path.display()
+    )]
+    NotRecorded { path: PathBuf }
+}
+
+impl LLMError {
+    /// Convert a `reqwest` error from sending a request or reading its response.
+    pub fn from_request_error(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_decode() {
+            Self::ParseIntoGeminiResponse(err)
+        } else {
+            Self::FailedRequest(err)
+        }
+    }
+
+    /// Whether the request might succeed if we try it again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } => true,
+            Self::BadStatus(err) => err.status().is_some_and(|status| status.is_server_error()),
+            Self::FailedRequest(err) => err.is_connect(),
+            _ => false
+        }
+    }
+}
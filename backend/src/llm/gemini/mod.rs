@@ -1,53 +1,102 @@
-use reqwest::Client;
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+use reqwest::{header::{HeaderMap, RETRY_AFTER}, Client, StatusCode};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use base64::prelude::*;
+use sha2::{Digest, Sha256};
 use types::{Content, GenerationConfig, InlineData, LLMRequest, LLMResponse, Part, PartData};
 use schema::GeminiSchema;
-use crate::llm::error::LLMError;
+use crate::{config::GeminiClientConfig, llm::error::LLMError, models::ai_usage::TokenUsage};
 
 pub(crate) mod types;
 mod schema;
 
 static API_PREFIX: &str = "https://generativelanguage.googleapis.com/v1beta";
 
+/// Finish reasons meaning the response was cut off by Gemini's content filters.
+/// See https://ai.google.dev/api/generate-content#FinishReason
+const BLOCKED_FINISH_REASONS: [&str; 6] = ["SAFETY", "RECITATION", "BLOCKLIST", "PROHIBITED_CONTENT", "SPII", "IMAGE_SAFETY"];
+
+/// If Gemini asks us to wait longer than this, we give up instead of retrying.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Where Gemini's responses come from.
+/// 
+/// Recorded responses are saved as `<hash of the request>.json`, so replaying only works for the exact same requests
+/// (same prompts, inputs and dates); responses from different models should be kept in different directories.
+#[derive(Clone, Debug, Default)]
+pub enum ResponseSource {
+    /// Request Gemini.
+    #[default]
+    Live,
+    /// Request Gemini, saving each response into the directory.
+    Record(PathBuf),
+    /// Only use the responses saved into the directory by `Record`, never requesting Gemini.
+    Replay(PathBuf)
+}
+
 /// Used for requesting the Gemini API.
 #[derive(Clone, Debug)]
 pub struct GeminiLLM {
     endpoint: String,
-    client: Client
+    client: Client,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    responses: ResponseSource,
+    /// The tokens used by every request made through this client (and its clones).
+    total_usage: Arc<Mutex<TokenUsage>>
 }
 
 impl GeminiLLM {
     /// Instantiate the struct.
-    pub fn new(api_key: &String, model: &String) -> Self {
+    pub fn new(api_key: &String, model: &String, config: &GeminiClientConfig) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()
+            .expect("Failed to create client");
         Self {
             endpoint: Self::build_api_string(model, api_key),
-            client: Client::new()
+            client,
+            max_retries: config.max_retries,
+            retry_base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            responses: ResponseSource::Live,
+            total_usage: Arc::default()
         }
     }
 
+    /// Use a different source for the responses.
+    pub fn with_response_source(self, responses: ResponseSource) -> Self {
+        Self { responses, ..self }
+    }
+
+    /// The tokens used by every request made through this client (and its clones) so far.
+    pub fn total_usage(&self) -> TokenUsage {
+        *self.total_usage.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
     /// Send a simple text query, decoding the response as `Res`.
     pub async fn request_text<Res>(
         &self, 
         text: String, 
-        system_instruction: Option<String>
+        system_instruction: Option<String>,
+        usage: &mut TokenUsage
     ) -> Result<Res, LLMError>
     where Res: GeminiSchema 
     {
         let request = self.build_text_request::<Res>(text, system_instruction);
-        self.handle_request(request).await
+        self.handle_request(request, usage).await
     }
 
     /// Send a simple text query, decoding the response as a string.
     pub async fn request_text_string_res(
         &self, 
         text: String, 
-        system_instruction: Option<String>
+        system_instruction: Option<String>,
+        usage: &mut TokenUsage
     ) -> Result<String, LLMError>
     {
         let request = self.build_text_request_without_gen_config(text, system_instruction);
-        self.handle_request_string(request).await
+        self.handle_request_string(request, usage).await
     }
 
     /// Send a JPEG image and a text query, decoding the response as `Res`.
@@ -55,11 +104,12 @@ impl GeminiLLM {
         &self, 
         image_bytes: &[u8], 
         system_instruction: Option<String>,
-        request_text: String
+        request_text: String,
+        usage: &mut TokenUsage
     ) -> Result<Res, LLMError>
     where Res: GeminiSchema
     {
-        self.request_inline_data(image_bytes, "image/jpeg", request_text, system_instruction).await
+        self.request_inline_data(image_bytes, "image/jpeg", request_text, system_instruction, usage).await
     }
 
     /// Send a JPEG image and a text query, decoding the response as a string.
@@ -67,33 +117,50 @@ impl GeminiLLM {
         &self, 
         image_bytes: &[u8], 
         system_instruction: Option<String>,
-        request_text: String
+        request_text: String,
+        usage: &mut TokenUsage
     ) -> Result<String, LLMError>
     {
-        self.request_inline_data_string_res(image_bytes, "image/jpeg", request_text, system_instruction).await
+        self.request_inline_data_string_res(image_bytes, "image/jpeg", request_text, system_instruction, usage).await
     }
 
-    /// Send an MP3 audio and a text query, decoding the response as `Res`.
+    /// Send audio of the given MIME type and a text query, decoding the response as `Res`.
     pub async fn request_audio<Res>(
         &self, 
         audio_bytes: &[u8], 
+        mime_type: &'static str,
         system_instruction: Option<String>,
-        request_text: String
+        request_text: String,
+        usage: &mut TokenUsage
     ) -> Result<Res, LLMError>
     where Res: GeminiSchema
     {
-        self.request_inline_data(audio_bytes, "audio/mp3", request_text, system_instruction).await
+        self.request_inline_data(audio_bytes, mime_type, request_text, system_instruction, usage).await
     }
 
-    /// Send an MP3 audio and a text query, decoding the response as a string.
+    /// Send audio of the given MIME type and a text query, decoding the response as a string.
     pub async fn request_audio_string_res(
         &self, 
         audio_bytes: &[u8], 
+        mime_type: &'static str,
         system_instruction: Option<String>,
-        request_text: String
+        request_text: String,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError>
+    {
+        self.request_inline_data_string_res(audio_bytes, mime_type, request_text, system_instruction, usage).await
+    }
+
+    /// Send a PDF and a text query, decoding the response as a string.
+    pub async fn request_pdf_string_res(
+        &self, 
+        pdf_bytes: &[u8], 
+        system_instruction: Option<String>,
+        request_text: String,
+        usage: &mut TokenUsage
     ) -> Result<String, LLMError>
     {
-        self.request_inline_data_string_res(audio_bytes, "audio/mp3", request_text, system_instruction).await
+        self.request_inline_data_string_res(pdf_bytes, "application/pdf", request_text, system_instruction, usage).await
     }
 
     /// Send inline data and a text query, decoding the response as `Res`.
@@ -102,7 +169,8 @@ impl GeminiLLM {
         inline_bytes: &[u8], 
         mime_type: &'static str, 
         request_text: String,
-        system_instruction: Option<String>
+        system_instruction: Option<String>,
+        usage: &mut TokenUsage
     ) -> Result<Res, LLMError>
     where Res: GeminiSchema
     {
@@ -112,7 +180,7 @@ impl GeminiLLM {
             request_text, 
             system_instruction
         );
-        self.handle_request(request).await
+        self.handle_request(request, usage).await
     }
 
     /// Send inline data and a text query, decoding the response as a string.
@@ -121,7 +189,8 @@ impl GeminiLLM {
         inline_bytes: &[u8], 
         mime_type: &'static str, 
         request_text: String,
-        system_instruction: Option<String>
+        system_instruction: Option<String>,
+        usage: &mut TokenUsage
     ) -> Result<String, LLMError>
     {
         let request = self.build_inline_data_request_without_gen_config(
@@ -130,55 +199,150 @@ impl GeminiLLM {
             request_text, 
             system_instruction
         );
-        self.handle_request_string(request).await
+        self.handle_request_string(request, usage).await
     }
     
     /// Handles running a query to the LLM and just returning the string content.
-    async fn handle_request_string(&self, request: LLMRequest) -> Result<String, LLMError> {
-        let response = self.handle_request_inner(request).await?;
-
-        match &response.candidates[0].content.parts[0].data {
-            PartData::Text { text } => Ok(text.clone()),
-            other => return Err(LLMError::NoOrWrongContent { reason: format!("Received unexpected PartData: {other:?}") })
-        }
+    async fn handle_request_string(&self, request: LLMRequest, usage: &mut TokenUsage) -> Result<String, LLMError> {
+        self.handle_request_inner(request, usage).await
     }
 
     /// Handles running and decoding a query to the LLM.
-    async fn handle_request<Res>(&self, request: LLMRequest) -> Result<Res, LLMError>
+    async fn handle_request<Res>(&self, request: LLMRequest, usage: &mut TokenUsage) -> Result<Res, LLMError>
     where Res: GeminiSchema
     {
-        let response = self.handle_request_inner(request).await?;
+        let text = self.handle_request_inner(request, usage).await?;
+        Ok(serde_json::from_str::<Res>(&text)?)
+    }
 
-        match &response.candidates[0].content.parts[0].data {
-            PartData::Text { text } =>  {
-                let res = serde_json::from_str::<Res>(&text)?;
-                return Ok(res);
+    /// The general handling of an LLM request, returning the text of the response and adding the tokens it used to `usage`.
+    /// 
+    /// The response comes from `self.responses`; live requests which are rate limited or fail (5xx) are retried with exponential backoff.
+    async fn handle_request_inner(&self, request: LLMRequest, usage: &mut TokenUsage) -> Result<String, LLMError> {
+        let response = match &self.responses {
+            ResponseSource::Live => self.send_request_with_retries(&request).await?,
+            ResponseSource::Record(dir) => {
+                let response = self.send_request_with_retries(&request).await?;
+                Self::record_response(dir, &request, &response).await?;
+                response
             },
-            other => return Err(LLMError::NoOrWrongContent { reason: format!("Received unexpected PartData: {other:?}") })
+            ResponseSource::Replay(dir) => Self::replay_response(dir, &request).await?
+        };
+
+        if let Some(usage_metadata) = response.usage_metadata {
+            let request_usage: TokenUsage = usage_metadata.into();
+            *usage += request_usage;
+            *self.total_usage.lock().unwrap_or_else(|err| err.into_inner()) += request_usage;
+        }
+        if let Some(reason) = response.prompt_feedback.and_then(|f| f.block_reason) {
+            return Err(LLMError::Blocked { reason });
+        }
+        let Some(candidate) = response.candidates.into_iter().next() else {
+            return Err(LLMError::NoOrWrongContent { reason: "No candidates".into() });
+        };
+        if let Some(reason) = candidate.finish_reason.filter(|r| BLOCKED_FINISH_REASONS.contains(&r.as_str())) {
+            return Err(LLMError::Blocked { reason });
+        }
+        match candidate.content.and_then(|c| c.parts.into_iter().next()).map(|p| p.data) {
+            Some(PartData::Text { text }) => Ok(text),
+            Some(other) => Err(LLMError::NoOrWrongContent { reason: format!("Received unexpected PartData: {other:?}") }),
+            None => Err(LLMError::NoOrWrongContent { reason: "Candidate had no part".into() })
+        }
+    }
+
+    /// Send a request, retrying it with exponential backoff if it's rate limited or fails (5xx).
+    async fn send_request_with_retries(&self, request: &LLMRequest) -> Result<LLMResponse, LLMError> {
+        let mut attempt = 0;
+        loop {
+            match self.send_request(request).await {
+                Err(err) if attempt < self.max_retries && err.is_retryable() => {
+                    let delay = match &err {
+                        LLMError::RateLimited { retry_after: Some(retry_after) } => *retry_after,
+                        _ => self.retry_base_delay * 2u32.pow(attempt)
+                    };
+                    if delay > MAX_RETRY_DELAY {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    tracing::debug!("Gemini request failed ({err}); retrying in {delay:?} (attempt {attempt}/{})", self.max_retries);
+                    tokio::time::sleep(delay).await;
+                },
+                result => return result
+            }
         }
     }
 
-    /// The general handling of an LLM request.
-    async fn handle_request_inner(&self, request: LLMRequest) -> Result<LLMResponse, LLMError> {
+    /// The file a request's response is recorded in.
+    fn recording_path(dir: &Path, request: &LLMRequest) -> Result<PathBuf, LLMError> {
+        let hash = Sha256::digest(serde_json::to_vec(request)?);
+        Ok(dir.join(format!("{hash:x}.json")))
+    }
+
+    /// Save a response so it can be replayed.
+    async fn record_response(dir: &Path, request: &LLMRequest, response: &LLMResponse) -> Result<(), LLMError> {
+        let path = Self::recording_path(dir, request)?;
+        let recording = serde_json::to_vec_pretty(response)?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|err| LLMError::Recording { path: dir.to_path_buf(), reason: err.to_string() })?;
+        tokio::fs::write(&path, recording)
+            .await
+            .map_err(|err| LLMError::Recording { path, reason: err.to_string() })
+    }
+
+    /// Load the recorded response to a request.
+    async fn replay_response(dir: &Path, request: &LLMRequest) -> Result<LLMResponse, LLMError> {
+        let path = Self::recording_path(dir, request)?;
+        let recording = tokio::fs::read(&path)
+            .await
+            .map_err(|err| if err.kind() == std::io::ErrorKind::NotFound {
+                LLMError::NotRecorded { path: path.clone() }
+            } else {
+                LLMError::Recording { path: path.clone(), reason: err.to_string() }
+            })?;
+        serde_json::from_slice(&recording).map_err(|err| LLMError::Recording { path, reason: err.to_string() })
+    }
+
+    /// Send a single request, mapping rate limits and bad statuses to errors.
+    async fn send_request(&self, request: &LLMRequest) -> Result<LLMResponse, LLMError> {
         let response = self.client
             .post(&self.endpoint)
-            .json(&request)
+            .json(request)
             .send()
             .await
-            .map_err(|err| LLMError::FailedRequest(err))?
-            .error_for_status()
-            .map_err(|err| LLMError::BadStatus(err))?;
+            .map_err(LLMError::from_request_error)?;
 
-        let res_data = match response.json::<LLMResponse>().await {
-            Ok(res) => res,
-            Err(err) => return Err(LLMError::ParseIntoGeminiResponse(err))
-        };
-
-        if res_data.candidates.len() == 0 || res_data.candidates[0].content.parts.len() == 0 {
-            return Err(LLMError::NoOrWrongContent { reason: "No candidates, or candidate had no part".into() });
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = Self::retry_after_header(response.headers());
+            let retry_after = match retry_after {
+                Some(retry_after) => Some(retry_after),
+                None => response.text().await.ok().and_then(|body| Self::retry_delay_from_body(&body))
+            };
+            return Err(LLMError::RateLimited { retry_after });
         }
+        response
+            .error_for_status()
+            .map_err(LLMError::BadStatus)?
+            .json::<LLMResponse>()
+            .await
+            .map_err(LLMError::from_request_error)
+    }
+
+    /// Parses a `Retry-After` header given in seconds.
+    fn retry_after_header(headers: &HeaderMap) -> Option<Duration> {
+        let seconds = headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+        Some(Duration::from_secs(seconds))
+    }
 
-        return Ok(res_data)
+    /// Gemini gives how long to wait in the error body's `RetryInfo`, e.g. `"retryDelay": "37s"`.
+    fn retry_delay_from_body(body: &str) -> Option<Duration> {
+        let body: serde_json::Value = serde_json::from_str(body).ok()?;
+        body["error"]["details"]
+            .as_array()?
+            .iter()
+            .find_map(|detail| detail["retryDelay"].as_str())
+            .and_then(|delay| delay.strip_suffix('s')?.parse::<f64>().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
     }
 
     /// Builds a text query's request.
@@ -33,6 +33,7 @@ impl Transform for GeminiTransform {
         //self.remove_ref(schema);
         self.convert_unsigned(schema);
         self.remove_uuid_format(schema);
+        self.remove_default(schema);
         self.type_array_to_anyof(schema);
         transform_subschemas(self, schema);
     }
@@ -58,6 +59,11 @@ impl GeminiTransform {
         }
     }
 
+    /// Remove `default`s (from `#[serde(default)]`), as the LLM should always output the field anyway.
+    fn remove_default(&self, schema: &mut Schema) {
+        schema.remove("default");
+    }
+
     /// If a type array is present, convert it to `anyOf`.
     fn type_array_to_anyof(&self, schema: &mut Schema) {  
     if let Some(type_value) = schema.get("type") {
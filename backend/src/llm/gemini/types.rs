@@ -5,6 +5,7 @@
 use schemars::Schema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use crate::models::ai_usage::TokenUsage;
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -16,9 +17,43 @@ pub struct LLMRequest {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 /// A response from Gemini API.
 pub struct LLMResponse {
-    pub candidates: Vec<Candidate>
+    /// Empty if the prompt was blocked.
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub prompt_feedback: Option<PromptFeedback>,
+    pub usage_metadata: Option<UsageMetadata>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+/// The tokens used by a request; the total also includes any thinking tokens.
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: i64,
+    #[serde(default)]
+    pub candidates_token_count: i64,
+    #[serde(default)]
+    pub total_token_count: i64
+}
+
+impl From<UsageMetadata> for TokenUsage {
+    fn from(usage: UsageMetadata) -> Self {
+        Self {
+            prompt_tokens: usage.prompt_token_count,
+            candidates_tokens: usage.candidates_token_count,
+            total_tokens: usage.total_token_count
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+/// Why the prompt was blocked, if it was.
+pub struct PromptFeedback {
+    pub block_reason: Option<String>
 }
 
 #[skip_serializing_none]
@@ -29,8 +64,11 @@ pub struct GenerationConfig {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Candidate {
-    pub content: Content
+    /// Missing if generation stopped before producing anything (e.g. for safety).
+    pub content: Option<Content>,
+    pub finish_reason: Option<String>
 }
 
 #[skip_serializing_none]
@@ -1,163 +1,697 @@
-use chrono::{Duration, FixedOffset, Utc};
+use std::path::Path;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use crate::config::Config;
+use uuid::Uuid;
+use crate::config::{Config, GeminiClientConfig};
+use crate::llm::context::{CalendarContext, CategorisationContext, EditContext, SearchContext, SearchResults};
 use crate::llm::error::LLMError;
 use crate::llm::gemini::GeminiLLM;
+use crate::llm::prompts::{PromptContext, PromptTemplates};
+use crate::models::ai_usage::TokenUsage;
 use crate::models::calendar_event::NewCalendarEvent;
-use crate::models::recurring_event::{NewRecurringEvent, RecurringEvent};
+use crate::models::categorisation::NewCategorisationRule;
+use crate::models::recurring_event::NewRecurringEventWithExceptions;
 use crate::models::recurring_event_group::NewRecurringEventGroup;
+use crate::models::rrule::ValidatedRRule;
+use crate::models::time::{Second, UserTimezone};
 
 mod gemini;
+pub mod context;
 pub mod error;
+pub mod prompts;
+
+pub use gemini::ResponseSource;
 
 /// Events generated from the LLM.
-#[derive(Serialize, Deserialize, JsonSchema)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct GeneratedEvents {
-    pub events: Vec<NewCalendarEvent>,
-    pub recurring_events: Vec<NewRecurringEvent>,
-    pub recurring_event_group: Option<NewRecurringEventGroup>
+    pub events: Vec<Generated<NewCalendarEvent>>,
+    /// Recurring events which aren't under one of the new groups (they can still reference an existing group).
+    pub recurring_events: Vec<Generated<NewRecurringEventWithExceptions>>,
+    pub recurring_event_groups: Vec<GeneratedGroup>,
+    /// The LLM's intermediate, free-text extraction (for audio, effectively a transcript), if it was asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub extraction_text: Option<String>,
+    /// The version of the prompts the events were extracted with (see `PromptTemplates::extraction_version`).
+    #[serde(default)]
+    #[schemars(skip)]
+    pub prompt_version: String,
+    /// The id the extraction was recorded under, which the events can be saved against (see `ExtractionsService`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub extraction_id: Option<Uuid>
+}
+
+/// A new group of recurring events generated from the LLM.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct GeneratedGroup {
+    #[serde(flatten)]
+    pub group: NewRecurringEventGroup,
+    pub recurring_events: Vec<Generated<NewRecurringEventWithExceptions>>
+}
+
+/// A generated item, along with where it came from and hints about how it relates to the user's existing calendar.
+/// 
+/// The hints are filled in by us after generation, so they're hidden from the LLM's response schema.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Generated<T> {
+    #[serde(flatten)]
+    pub item: T,
+    /// How confident the LLM is that the item (especially its dates and times) is correct, from 0 to 1.
+    pub confidence: f32,
+    /// The part of the input the item came from: a quote of the text or speech, or a description of the image region.
+    pub source_snippet: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub likely_duplicate_of: Option<DuplicateHint>
+}
+
+/// Points at an existing event that a generated item likely duplicates.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DuplicateHint {
+    pub kind: ExistingEventKind,
+    /// The ID of the existing `CalendarEvent` or `RecurringEvent`.
+    pub event_id: Uuid,
+    pub title: String,
+    /// The start of the matching (instance of the) existing event.
+    pub start_time: DateTime<Utc>
+}
+
+/// The kind of event a `DuplicateHint` points at.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExistingEventKind {
+    CalendarEvent,
+    RecurringEvent
 }
 
 impl GeneratedEvents {
-    /// Since the backend deals in UTC, but the user's data is likely in their own timezone,
-    /// we offset all datetimes in the generated events before returning.
+    /// The LLM extracts datetimes as they appear in the input (ie in the user's local time), 
+    /// but the backend deals in UTC, so we convert all of them before returning.
     /// 
-    /// **NOTE**: If any more datetimes are added to any of these types, they should be offset as well.
-    pub fn offset_timezones(&mut self, timezone_offset_minutes: i32) {
-        let offset = -Duration::minutes(timezone_offset_minutes.into());
+    /// This is done per datetime, so events on either side of a DST change each get the right offset.
+    /// 
+    /// **NOTE**: If any more datetimes are added to any of these types, they should be converted as well.
+    pub fn local_to_utc(&mut self, timezone: &UserTimezone) {
+        let convert = |datetime: &mut DateTime<Utc>| *datetime = timezone.local_to_utc(datetime.naive_utc());
         for event in &mut self.events {
-            event.start_time += offset;
-            event.end_time += offset;
+            convert(&mut event.item.start_time);
+            convert(&mut event.item.end_time);
         }
-        for event in &mut self.recurring_events {
-            event.recurrence_start += offset;
-            if let Some(recurrence_end) = &mut event.recurrence_end {
-                *recurrence_end += offset;
+        for event in self.all_recurring_events_mut() {
+            convert(&mut event.item.event.recurrence_start);
+            if let Some(recurrence_end) = &mut event.item.event.recurrence_end {
+                convert(recurrence_end);
+            }
+            for exception in &mut event.item.exceptions {
+                convert(&mut exception.exception_date);
+                if let Some(start) = &mut exception.modified_start_time {
+                    convert(start);
+                }
+                if let Some(end) = &mut exception.modified_end_time {
+                    convert(end);
+                }
             }
         }
-        if let Some(group) = &mut self.recurring_event_group {
+        for GeneratedGroup { group, .. } in &mut self.recurring_event_groups {
             if let Some(start) = &mut group.group_recurrence_start {
-                *start += offset;
+                convert(start);
             }
             if let Some(end) = &mut group.group_recurrence_end {
-                *end += offset;
+                convert(end);
+            }
+        }
+    }
+
+    /// All the recurring events, whether or not they're under a new group.
+    pub fn all_recurring_events(&self) -> impl Iterator<Item = &Generated<NewRecurringEventWithExceptions>> {
+        self.recurring_events
+            .iter()
+            .chain(self.recurring_event_groups.iter().flat_map(|g| &g.recurring_events))
+    }
+
+    /// All the recurring events, whether or not they're under a new group.
+    pub fn all_recurring_events_mut(&mut self) -> impl Iterator<Item = &mut Generated<NewRecurringEventWithExceptions>> {
+        self.recurring_events
+            .iter_mut()
+            .chain(self.recurring_event_groups.iter_mut().flat_map(|g| &mut g.recurring_events))
+    }
+
+    /// Clears any `group_id` that isn't one of the user's existing groups (ie the LLM made it up).
+    /// 
+    /// Events under a new group never keep one, as they'll be put under that group.
+    pub fn drop_unknown_group_ids(&mut self, calendar_context: Option<&CalendarContext>) {
+        for event in self.recurring_event_groups.iter_mut().flat_map(|g| &mut g.recurring_events) {
+            event.item.event.group_id = None;
+        }
+        for event in &mut self.recurring_events {
+            if let Some(group_id) = event.item.event.group_id 
+            && !calendar_context.is_some_and(|c| c.has_group(group_id)) 
+            {
+                tracing::debug!("Dropping unknown group ID {group_id} from generated event");
+                event.item.event.group_id = None;
+            }
+        }
+    }
+
+    /// Snaps exceptions to the instances of their recurring event, dropping any that don't fall on one.
+    pub fn snap_exceptions(&mut self) {
+        for event in self.all_recurring_events_mut() {
+            let mut rrule = event.item.event.rrule.clone();
+            rrule.set_start(event.item.event.recurrence_start);
+            rrule.set_end(event.item.event.recurrence_end);
+            event.item.exceptions.retain_mut(|exception| {
+                match rrule.instance_near(exception.exception_date, Duration::minutes(1)) {
+                    Some(instance) => {
+                        exception.exception_date = instance;
+                        true
+                    },
+                    None => {
+                        tracing::debug!("Dropping generated exception on {}, as it isn't an instance", exception.exception_date);
+                        false
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Edits to the user's calendar generated from the LLM.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GeneratedEdits {
+    pub edits: Vec<GeneratedEdit>
+}
+
+/// A single edit generated from the LLM.
+/// 
+/// This is kept flat (rather than as a tagged enum) as it's much easier for the LLM to follow;
+/// which of the optional fields are used depends on `op`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GeneratedEdit {
+    pub op: GeneratedEditOp,
+    /// The ID of the event or recurring event to edit.
+    pub target_id: Uuid,
+    /// A short explanation of the edit, shown to the user.
+    pub reason: String,
+    /// The new title, if it changes.
+    pub title: Option<String>,
+    /// The new description, if it changes.
+    pub description: Option<String>,
+    /// The new location, if it changes.
+    pub location: Option<String>,
+    /// The new start of an event or recurring event instance.
+    pub start_time: Option<DateTime<Utc>>,
+    /// The new end of an event or recurring event instance.
+    pub end_time: Option<DateTime<Utc>>,
+    /// The (original) start of the recurring event instance being cancelled, modified, or split at.
+    pub instance_start: Option<DateTime<Utc>>,
+    /// The new start of a recurring event (or of the new series when splitting).
+    pub recurrence_start: Option<DateTime<Utc>>,
+    /// The new end of a recurring event (or of the new series when splitting).
+    pub recurrence_end: Option<DateTime<Utc>>,
+    /// The new recurrence rule of a recurring event (or of the new series when splitting).
+    pub rrule: Option<ValidatedRRule>,
+    /// The new duration of a recurring event's instances.
+    pub event_duration_seconds: Option<Second>
+}
+
+/// A recurrence generated from the LLM, from a phrase like "every other Tuesday until June".
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GeneratedRecurrence {
+    /// The RFC 5545 rule, like `FREQ=WEEKLY;INTERVAL=2;BYDAY=TU`, without `DTSTART`, `UNTIL` or `COUNT`.
+    pub rrule: String,
+    /// The last (local) date instances can fall on, if the recurrence ends at a date.
+    pub until_date: Option<NaiveDate>,
+    /// How many instances there are, if the recurrence ends after a number of them.
+    pub count: Option<u32>,
+    /// Parts of the phrase which can't be expressed in the rule (such as exceptions), each explained in a sentence.
+    pub notes: Vec<String>
+}
+
+/// A search over the user's calendar generated from the LLM, from a question like "when is my next dentist appointment?".
+///
+/// This is all the LLM decides; the search itself is run by us, over the user's own events.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct GeneratedSearch {
+    /// The start of the period to search, if the question implies one.
+    pub start_time: Option<DateTime<Utc>>,
+    /// The end of the period to search, if the question implies one.
+    pub end_time: Option<DateTime<Utc>>,
+    /// Words, any of which a matching event's title, description, location or group name must contain; empty to 
+    /// match every event in the period.
+    pub keywords: Vec<String>,
+    /// The ID of the group matching events must be in, if the question is about one.
+    pub group_id: Option<Uuid>,
+    /// Whether the earliest or latest matching events come first.
+    pub order: SearchOrder,
+    /// How many matching events the answer needs, if only the first few (e.g. 1 for "when is my next ...").
+    pub limit: Option<u32>
+}
+
+/// Which matching events a `GeneratedSearch` puts first.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchOrder {
+    Earliest,
+    Latest
+}
+
+impl GeneratedSearch {
+    /// See `GeneratedEvents::local_to_utc`.
+    pub fn local_to_utc(&mut self, timezone: &UserTimezone) {
+        for datetime in [&mut self.start_time, &mut self.end_time].into_iter().flatten() {
+            *datetime = timezone.local_to_utc(datetime.naive_utc());
+        }
+    }
+}
+
+/// Categories for the user's ungrouped imported events, generated from the LLM.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct GeneratedCategories {
+    pub categories: Vec<GeneratedCategory>
+}
+
+/// Events which belong in the same (existing or new) group.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct GeneratedCategory {
+    /// The numbers of the events, as listed.
+    pub events: Vec<u32>,
+    /// The ID of the existing group the events belong in, if any.
+    pub group_id: Option<Uuid>,
+    /// The name of a new group for the events, if they don't belong in an existing one.
+    pub new_group_name: Option<String>,
+    /// A rule putting events like these into the group in future, if a simple one fits them all.
+    pub rule: Option<NewCategorisationRule>
+}
+
+/// The kind of a `GeneratedEdit`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratedEditOp {
+    UpdateEvent,
+    DeleteEvent,
+    UpdateRecurringEvent,
+    DeleteRecurringEvent,
+    CancelInstance,
+    ModifyInstance,
+    SplitSeries
+}
+
+impl GeneratedEdits {
+    /// See `GeneratedEvents::local_to_utc`.
+    pub fn local_to_utc(&mut self, timezone: &UserTimezone) {
+        for edit in &mut self.edits {
+            for datetime in [
+                &mut edit.start_time, 
+                &mut edit.end_time, 
+                &mut edit.instance_start, 
+                &mut edit.recurrence_start, 
+                &mut edit.recurrence_end
+            ].into_iter().flatten() {
+                *datetime = timezone.local_to_utc(datetime.naive_utc());
             }
         }
     }
 }
 
 /// Used for multimodally generating events.
+/// 
+/// Every request adds the tokens Gemini reports it used to the given `TokenUsage`, even if its response then fails to parse.
 #[derive(Clone, Debug)]
 pub struct LLM {
-    gemini: GeminiLLM
+    gemini: GeminiLLM,
+    model: String,
+    prompts: PromptTemplates
 }
 
 impl LLM {
     /// Instantiate the struct.
     pub fn new(config: &Config) -> Self {
-        let gemini = GeminiLLM::new(&config.gemini_key, &config.gemini_model);
+        Self::build(&config.gemini_key, &config.gemini_model, &config.gemini_client, config.ai_prompts_dir.as_deref())
+            .expect("Failed to load prompt templates")
+    }
+
+    /// Instantiate the struct without the rest of the app's config (e.g. for evaluations), 
+    /// failing if the prompt templates can't be loaded.
+    pub fn build(
+        api_key: &String, 
+        model: &String, 
+        client_config: &GeminiClientConfig, 
+        prompts_dir: Option<&str>
+    ) -> Result<Self, String> {
+        Ok(Self {
+            gemini: GeminiLLM::new(api_key, model, client_config),
+            model: model.clone(),
+            prompts: PromptTemplates::load(prompts_dir.map(Path::new))?
+        })
+    }
+
+    /// Use a different source for the LLM's responses, e.g. to replay recorded ones.
+    pub fn with_response_source(self, responses: ResponseSource) -> Self {
         Self {
-            gemini
+            gemini: self.gemini.with_response_source(responses),
+            ..self
         }
     }
+
+    /// The name of the model used.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// The tokens used by every request made through this `LLM` (and its clones) so far.
+    pub fn total_usage(&self) -> TokenUsage {
+        self.gemini.total_usage()
+    }
+
+    /// The version of the extraction prompts.
+    pub fn prompt_version(&self) -> String {
+        self.prompts.extraction_version()
+    }
     
-    /// Generate events from text.
-    pub async fn events_from_text(&self, text: String, timezone_offset_minutes: i32) -> Result<GeneratedEvents, LLMError> {
-        let generated_events_string = self.gemini
-            .request_text_string_res(text, Some(self.event_extraction_system_instruction().into()))
-            .await?;
-        let mut generated_events: GeneratedEvents = self.gemini
-            .request_text(
-                generated_events_string,
-                Some(self.extracted_string_parsing_system_instruction().into())
-            )
-            .await?;
-        generated_events.offset_timezones(timezone_offset_minutes);
-        Ok(generated_events)
+    /// Extract the events in text into a string, to be parsed by `parse_extracted_string`.
+    pub async fn extract_from_text(
+        &self, 
+        text: String, 
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        self.gemini
+            .request_text_string_res(text, Some(self.prompts.event_extraction(prompt_context)), usage)
+            .await
     }
 
-    /// Generate events from an mp3 audio.
-    pub async fn events_from_audio(&self, audio_bytes: &[u8], timezone_offset_minutes: i32) -> Result<GeneratedEvents, LLMError> {
-        let generated_events_string = self.gemini
+    /// Extract the events in audio of the given MIME type into a string, to be parsed by `parse_extracted_string`.
+    pub async fn extract_from_audio(
+        &self, 
+        audio_bytes: &[u8], 
+        mime_type: &'static str, 
+        context: Option<String>, 
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        self.gemini
             .request_audio_string_res(
                 audio_bytes, 
-                Some(self.event_extraction_system_instruction().into()), 
-                "Parse the data from this audio.".into()
+                mime_type,
+                Some(self.prompts.event_extraction(prompt_context)), 
+                self.inline_data_request_text("audio", context),
+                usage
             )
-            .await?;
-        let mut generated_events: GeneratedEvents = self.gemini
-            .request_text(
-                generated_events_string,
-                Some(self.extracted_string_parsing_system_instruction().into())
-            )
-            .await?;
-        generated_events.offset_timezones(timezone_offset_minutes);
-        Ok(generated_events)
+            .await
     }
 
-    /// Generate events from a JPG image.
-    pub async fn events_from_image(&self, image_bytes: &[u8], timezone_offset_minutes: i32) -> Result<GeneratedEvents, LLMError> {
-        let generated_events_string = self.gemini
+    /// Extract the events in a JPG image into a string, to be parsed by `parse_extracted_string`.
+    /// 
+    /// `codes` are the payloads of the QR codes and barcodes found in the image (like a URL, contact details or a
+    /// booking reference), which the LLM can't reliably read itself.
+    pub async fn extract_from_image(
+        &self, 
+        image_bytes: &[u8], 
+        context: Option<String>, 
+        codes: &[String],
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        let mut request_text = self.inline_data_request_text("image", context);
+        if !codes.is_empty() {
+            let codes: Vec<String> = codes.iter().map(|code| format!("- {code}")).collect();
+            request_text.push_str(&format!(
+                "\nQR codes and barcodes in the image decode to the following; use them for details like links, but take the events from the image:\n{}",
+                codes.join("\n")
+            ));
+        }
+        self.gemini
             .request_image_string_res(
                 image_bytes, 
-                Some(self.event_extraction_system_instruction().into()), 
-                "Parse the data from this audio.".into()
+                Some(self.prompts.event_extraction(prompt_context)), 
+                request_text,
+                usage
             )
-            .await?;
+            .await
+    }
+
+    /// Extract the events in a PDF into a string, to be parsed by `parse_extracted_string`.
+    pub async fn extract_from_pdf(
+        &self, 
+        pdf_bytes: &[u8], 
+        context: Option<String>, 
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        self.gemini
+            .request_pdf_string_res(
+                pdf_bytes, 
+                Some(self.prompts.event_extraction(prompt_context)), 
+                self.inline_data_request_text("document", context),
+                usage
+            )
+            .await
+    }
+
+    /// Extract the events in an email's headers and body (as text) into a string, to be parsed by 
+    /// `parse_extracted_string`.
+    /// 
+    /// The headers include when the email was sent, which relative dates in the body (like "tomorrow") are relative to.
+    pub async fn extract_from_email(
+        &self, 
+        email_text: String, 
+        context: Option<String>, 
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        let request_text = format!(
+            "{} Resolve relative dates against when it was sent, if given.\n\n{email_text}",
+            self.inline_data_request_text("email", context)
+        );
+        self.gemini
+            .request_text_string_res(request_text, Some(self.prompts.event_extraction(prompt_context)), usage)
+            .await
+    }
+
+    /// Parse the string from one of the `extract_from_*` requests into `GeneratedEvents`.
+    pub async fn parse_extracted_string(
+        &self,
+        generated_events_string: String,
+        prompt_context: &PromptContext<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<GeneratedEvents, LLMError> {
         let mut generated_events: GeneratedEvents = self.gemini
             .request_text(
                 generated_events_string,
-                Some(self.extracted_string_parsing_system_instruction().into())
+                Some(self.prompts.extracted_string_parsing(prompt_context)),
+                usage
             )
             .await?;
-        generated_events.offset_timezones(timezone_offset_minutes);
-        return Ok(generated_events)
+        generated_events.local_to_utc(prompt_context.timezone);
+        generated_events.drop_unknown_group_ids(prompt_context.calendar_context);
+        generated_events.snap_exceptions();
+        generated_events.prompt_version = self.prompt_version();
+        Ok(generated_events)
     }
 
-    fn event_extraction_system_instruction(&self) -> String {
-        let now_string = Utc::now().format("%d/%m/%Y %H:%M");
-        format!(r#"
-            Today's date and time in UTC is {now_string}.
+    /// Generate edits to the user's calendar from a natural language instruction, resolving relative dates against `now`.
+    pub async fn edits_from_text(
+        &self,
+        instruction: String,
+        now: DateTime<Utc>,
+        timezone: UserTimezone,
+        edit_context: &EditContext,
+        usage: &mut TokenUsage
+    ) -> Result<GeneratedEdits, LLMError> {
+        let system_instruction = self.calendar_editing_system_instruction(now, &timezone, edit_context);
+        let mut generated_edits: GeneratedEdits = self.gemini
+            .request_text(instruction, Some(system_instruction), usage)
+            .await?;
+        generated_edits.local_to_utc(&timezone);
+        Ok(generated_edits)
+    }
 
-            You are a calendar event-generating AI. You take an input of text/image/audio, and output events that are present within the input.
+    /// Generate a recurrence rule from a phrase, for a recurrence starting at the given local datetime.
+    pub async fn recurrence_from_text(
+        &self,
+        phrase: String,
+        start: NaiveDateTime,
+        usage: &mut TokenUsage
+    ) -> Result<GeneratedRecurrence, LLMError> {
+        let system_instruction = format!(r#"
+            You turn a description of how often an event repeats into an RFC 5545 recurrence rule.
+            The first instance is on {}, and every date and time is in the user's local time.
 
-            The output data should consists of these fields:
-            - `events`: This is a list of normal, one-off calendar events. For `start_time` and `end_time`, ENSURE THAT THE DATE AND TIME ALIGNS PERFECTLY with what is present in the input.
-            - `recurring_events`: This is a list of recurring events. These are similar to normal events, but can occur periodically between a start and (optional) end datetime.
-            Its periodicity is described by a recurrence rule string, `rrule`, such as "FREQ=WEEKLY;COUNT=5;BYDAY=MO,TU". `recurrence_start` denotes the starting date and time 
-            for the recurring event. If no date is given, used the current date. ALWAYS USE THE EVENT'S EXACT GIVEN STARTING TIME. `recurrence_end` denotes the (optional) end date
-            for the recurrence. It only needs to be date-accurate. 
-            - `recurring_event_group`: This is an (optional) organized group of *recurring* events. If the extracted RECURRING events follow a sensible pattern, or one is obvious from
-            the input, you can create a group. `group_recurrence_start` and `group_recurrence_end` should be set IF AND ONLY IF all the recurring events have the same start and end date
-            respectively. DO NOT SET THIS IF THERE ARE NO RECURRING EVENTS.
+            Output the rule's parts (such as `FREQ`, `INTERVAL`, `BYDAY`, `BYMONTHDAY`, `BYMONTH` and `BYSETPOS`) in `rrule`, 
+            without `DTSTART`. Leave `UNTIL` and `COUNT` out of it: if the recurrence ends at a date, set `until_date` to the 
+            last date an instance can fall on, and if it ends after a number of instances, set `count`.
 
-            AIM FOR 100% ACCURACY IN EXTRACTING DATETIMES. Having absolute correctness in all extracted events' datetimes is the top priority. DO NOT PERFORM ANY TIMEZONE CONVERSIONS;
-            extract datetimes exactly as they are in the input.
+            A rule can't express exceptions (e.g. "except public holidays") or anything else the rule can't represent; 
+            leave those out of the rule and explain each in `notes`, so the user can handle them themselves.
+        "#, start.format("%A %d/%m/%Y %H:%M"));
+        self.gemini
+            .request_text(phrase, Some(system_instruction), usage)
+            .await
+    }
 
-            For metadata such as title/description/location, summarize as succinctly as possible.
-        "#)
+    /// Generate a search over the user's calendar from a question about it, resolving relative dates against `now`.
+    pub async fn search_from_question(
+        &self,
+        question: String,
+        now: DateTime<Utc>,
+        timezone: UserTimezone,
+        search_context: &SearchContext,
+        usage: &mut TokenUsage
+    ) -> Result<GeneratedSearch, LLMError> {
+        let now_string = self.user_now_description(now, &timezone);
+        let groups_string = search_context.prompt_section(&timezone);
+        let system_instruction = format!(r#"
+            The user's local date and time is {now_string}. Resolve relative dates (such as "next week" or "this month") 
+            against the user's local date, not UTC.
+
+            You turn a question about the user's calendar into a search over it. You don't answer the question yourself; 
+            the search is run, and the question is answered from the events it finds.
+
+            {groups_string}
+
+            Set `start_time` and `end_time` to the period the question is about. A question about what's next or left 
+            (e.g. "when is my next ...") starts now; a question about what's past (e.g. "when did I last ...") ends now.
+            Leave either empty if the question doesn't imply it.
+
+            Set `keywords` to a few words an event about the subject of the question would contain in its title, description, 
+            location or group name, including likely variations (e.g. "dentist" and "dental"). Leave it empty if the question 
+            is about every event in the period (e.g. "what am I doing on friday?").
+
+            Set `order` to `latest` if the question is about the most recent events (e.g. "when did I last ..."), and 
+            `earliest` otherwise. Set `limit` only if the question needs just the first few events.
+
+            DO NOT PERFORM ANY TIMEZONE CONVERSIONS; all datetimes are in the user's local time.
+        "#);
+        let mut generated_search: GeneratedSearch = self.gemini
+            .request_text(question, Some(system_instruction), usage)
+            .await?;
+        generated_search.local_to_utc(&timezone);
+        Ok(generated_search)
+    }
+
+    /// Answer a question about the user's calendar from the results of the search generated for it.
+    pub async fn answer_from_results(
+        &self,
+        question: String,
+        now: DateTime<Utc>,
+        timezone: UserTimezone,
+        results: SearchResults<'_>,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        let now_string = self.user_now_description(now, &timezone);
+        let results_string = results.prompt_section(&timezone);
+        let system_instruction = format!(r#"
+            The user's local date and time is {now_string}.
+
+            You answer a question about the user's calendar in one or two short sentences, using only the events below. 
+            If they don't answer the question, say so; never guess at events which aren't listed.
+
+            {results_string}
+        "#);
+        let answer = self.gemini
+            .request_text_string_res(question, Some(system_instruction), usage)
+            .await?;
+        Ok(answer.trim().to_string())
+    }
+
+    /// Summarize the user's agenda for a day or week, given as plain text along with a template summary of it.
+    pub async fn briefing_summary(
+        &self,
+        agenda: String,
+        now: DateTime<Utc>,
+        timezone: UserTimezone,
+        usage: &mut TokenUsage
+    ) -> Result<String, LLMError> {
+        let now_string = self.user_now_description(now, &timezone);
+        let system_instruction = format!(r#"
+            The user's local date and time is {now_string}.
+
+            You write a short briefing of the user's agenda, in two to four sentences of plain text, addressed to the user. 
+            The agenda starts with a plain summary of it, then lists the events day by day (in the user's local time); events 
+            marked `(conflict)` overlap another, and `(new)` marks the first event of a new recurring series.
+
+            Say how busy the period is, and point out any conflicts, back-to-back events, long free stretches, and new series, 
+            most important first. Only use what's in the agenda; never make up events or details.
+        "#);
+        let summary = self.gemini
+            .request_text_string_res(agenda, Some(system_instruction), usage)
+            .await?;
+        Ok(summary.trim().to_string())
+    }
+
+    /// Sort the user's ungrouped imported events into their existing groups, or new ones.
+    pub async fn categories_from_events(
+        &self,
+        categorisation_context: &CategorisationContext,
+        usage: &mut TokenUsage
+    ) -> Result<GeneratedCategories, LLMError> {
+        let context_string = categorisation_context.prompt_section();
+        let system_instruction = format!(r#"
+            You sort events imported into the user's calendar (e.g. from Outlook) into groups, like a course, a project, 
+            a team or a hobby, so they can be coloured and filtered together.
+
+            {context_string}
+
+            Output a category for each set of events which belong together. If they clearly belong in one of the user's 
+            groups, set `group_id` to its id and leave `new_group_name` empty; never make up an id. Otherwise, if several 
+            events clearly belong together, set `new_group_name` to a short name for a new group. Leave out events which 
+            don't clearly belong anywhere, and never put an event in more than one category.
+
+            If a simple rule would put events like these into the group in future, set `rule`: the `field` (`title`, 
+            `location` or `organiser`) and a short, distinctive `pattern` which that field of every event in the category 
+            contains (e.g. a course code, project name or organiser's address). Leave it empty if no such rule fits.
+        "#);
+        let categories = self.gemini
+            .request_text("Categorise these events.".to_string(), Some(system_instruction), usage)
+            .await?;
+        Ok(categories)
+    }
+
+    /// The text sent alongside inline data, including any extra context the user gave.
+    fn inline_data_request_text(&self, source: &str, context: Option<String>) -> String {
+        match context {
+            Some(context) => format!("Parse the data from this {source}. Additional context from the user: {context}"),
+            None => format!("Parse the data from this {source}.")
+        }
+    }
+
+    /// Describes the user's local date and time at `now`, for the system instructions.
+    fn user_now_description(&self, now: DateTime<Utc>, timezone: &UserTimezone) -> String {
+        let now = timezone.utc_to_local(now);
+        format!("{} (timezone: {timezone}, UTC{})", now.format("%A %d/%m/%Y %H:%M"), now.offset())
     }
 
-    fn extracted_string_parsing_system_instruction(&self) -> String {
-        let now_string = Utc::now().format("%d/%m/%Y %H:%M");
+    fn calendar_editing_system_instruction(&self, now: DateTime<Utc>, timezone: &UserTimezone, edit_context: &EditContext) -> String {
+        let now_string = self.user_now_description(now, timezone);
+        let calendar_string = edit_context.prompt_section(timezone);
         format!(r#"
-            Today's date and time in UTC is {now_string}.
-
-            The input string contains calendar events, recurring calendar events, and an optional recurring calendar event group, which has not been parsed yet.
-            You must parse them into the given format.
-
-            An explanation of the output fields:
-            - `events`: This is a list of normal, one-off calendar events. For `start_time` and `end_time`, ENSURE THAT THE DATE AND TIME ALIGNS PERFECTLY with what is present in the input.
-            - `recurring_events`: This is a list of recurring events. These are similar to normal events, but can occur periodically between a start and (optional) end datetime.
-            Its periodicity is described by a recurrence rule string, `rrule`, such as "FREQ=WEEKLY;COUNT=5;BYDAY=MO,TU". `recurrence_start` denotes the starting date and time 
-            for the recurring event. If no date is given, used the current date. ALWAYS USE THE EVENT'S EXACT GIVEN STARTING TIME. `recurrence_end` denotes the (optional) end date
-            for the recurrence. It only needs to be date-accurate. 
-            - `recurring_event_group`: This is an (optional) organized group of *recurring* events. If the extracted RECURRING events follow a sensible pattern, or one is obvious from
-            the input, you can create a group. `group_recurrence_start` and `group_recurrence_end` should be set IF AND ONLY IF all the recurring events have the same start and end date
-            respectively. DO NOT SET THIS IF THERE ARE NO RECURRING EVENTS.
-
-            Ensure that the output fields do not contain ANY changes from what's found in the input data.
+            The user's local date and time is {now_string}. Resolve relative dates (such as "tomorrow" or "from next week") 
+            against the user's local date, not UTC.
+
+            You are a calendar-editing AI. The user gives an instruction to change their calendar, and you output the edits needed to carry it out.
+            
+            {calendar_string}
+
+            Each edit has an `op`, the `target_id` of the event or recurring event it applies to (which MUST be one of the ids above), 
+            and a short `reason` explaining it. Only set the other fields that the `op` uses, and only if they change:
+            - `update_event`: changes a one-off event. Uses `title`, `description`, `location`, `start_time` and `end_time`.
+            - `delete_event`: deletes a one-off event.
+            - `update_recurring_event`: changes EVERY instance of a recurring event. Uses `title`, `description`, `location`, 
+            `recurrence_start`, `recurrence_end`, `rrule` and `event_duration_seconds`.
+            - `delete_recurring_event`: deletes a recurring event and all its instances.
+            - `cancel_instance`: cancels a single instance of a recurring event, given by its original `instance_start`.
+            - `modify_instance`: changes a single instance of a recurring event, given by its original `instance_start`. Uses `title`, 
+            `description`, `location`, `start_time` and `end_time`.
+            - `split_series`: changes a recurring event from one of its instances onwards (e.g. "from next week"), leaving earlier instances as they are.
+            `instance_start` is the original start of the first instance to change. Uses `title`, `description`, `location`, `recurrence_start`, 
+            `recurrence_end`, `rrule` and `event_duration_seconds` for the series going forward; `recurrence_start` is the start of its first instance.
+
+            Prefer the smallest edit that does what the user asked. If the instruction doesn't match anything on the calendar, output no edits.
+            
+            DO NOT PERFORM ANY TIMEZONE CONVERSIONS; all datetimes are in the user's local time.
         "#)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,178 @@
+//! Prompt templates, loaded from text files.
+//!
+//! A template starts with a `version: <version>` line and a `---` line, followed by the prompt itself, which can use
+//! `{{variable}}`s. The built-in templates live in `backend/prompts`; any of them can be overridden by putting a file
+//! with the same name in the directory given by `AI_PROMPTS_DIR`.
+//!
+//! Only the extraction prompts are templates, as they're the ones the eval (see `crate::eval`) measures, and that
+//! extractions record the version of. The other prompts (for edits, recurrences, searches, briefings and
+//! categorisation) are written inline in `LLM`; like these, any which need the current time are given it by their
+//! callers, rather than reading the clock themselves.
+
+use std::{fs, path::Path};
+use chrono::{DateTime, Utc};
+use crate::{llm::context::CalendarContext, models::{language::LanguageTag, time::UserTimezone}};
+
+/// The variables the extraction templates can use.
+const EXTRACTION_VARIABLES: [&str; 5] = ["now", "timezone", "locale", "calendar_context", "output_language"];
+
+/// What the extraction prompts are filled in with.
+pub struct PromptContext<'a> {
+    /// What's taken to be the current time, which relative dates are resolved against.
+    pub now: DateTime<Utc>,
+    pub timezone: &'a UserTimezone,
+    pub calendar_context: Option<&'a CalendarContext>,
+    /// Used to interpret ambiguous dates.
+    pub locale: Option<&'a LanguageTag>,
+    /// The language to write titles, descriptions and locations in; if not given, they're kept in the input's language.
+    pub output_language: Option<&'a LanguageTag>
+}
+
+impl PromptContext<'_> {
+    /// The values of `EXTRACTION_VARIABLES`.
+    fn extraction_variables(&self) -> [(&'static str, String); 5] {
+        let now = self.timezone.utc_to_local(self.now);
+        let calendar_context = match self.calendar_context {
+            Some(calendar_context) => calendar_context.prompt_section(self.timezone),
+            None => "Always leave `group_id` empty.".into()
+        };
+        [
+            ("now", format!("{} (UTC{})", now.format("%A %d/%m/%Y %H:%M"), now.offset())),
+            ("timezone", self.timezone.to_string()),
+            ("locale", self.locale.map_or_else(|| "not known".into(), |l| format!("\"{l}\""))),
+            ("calendar_context", calendar_context),
+            (
+                "output_language",
+                self.output_language.map_or_else(|| "the same language as the input".into(), |l| format!("the language with BCP 47 tag \"{l}\""))
+            )
+        ]
+    }
+}
+
+/// A prompt template.
+#[derive(Clone, Debug)]
+pub struct PromptTemplate {
+    version: String,
+    body: String
+}
+
+impl PromptTemplate {
+    /// Parse a template, checking that it only uses the given variables.
+    fn parse(name: &str, source: &str, variables: &[&str]) -> Result<Self, String> {
+        let source = source.replace("\r\n", "\n");
+        let (header, body) = source
+            .split_once("\n---\n")
+            .ok_or_else(|| format!("Prompt template `{name}` is missing the `---` after its version"))?;
+        let version = header
+            .trim()
+            .strip_prefix("version:")
+            .map(str::trim)
+            .filter(|version| !version.is_empty())
+            .ok_or_else(|| format!("Prompt template `{name}` must start with `version: <version>`"))?;
+
+        let mut rest = body;
+        while let Some(start) = rest.find("{{") {
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| format!("Prompt template `{name}` has an unclosed `{{{{`"))?;
+            let variable = &after[..end];
+            if !variables.contains(&variable) {
+                return Err(format!("Prompt template `{name}` uses unknown variable `{variable}`"));
+            }
+            rest = &after[end + 2..];
+        }
+
+        Ok(Self {
+            version: version.into(),
+            body: body.trim().into()
+        })
+    }
+
+    /// Render the template, replacing each `{{variable}}` with its value.
+    ///
+    /// This is done in a single pass over the template, so a value which itself contains a `{{variable}}` (e.g. from
+    /// the user's input) is left as it is.
+    fn render(&self, values: &[(&str, String)]) -> String {
+        let mut prompt = String::with_capacity(self.body.len());
+        let mut rest = self.body.as_str();
+        while let Some(start) = rest.find("{{") {
+            let after = &rest[start + 2..];
+            // `parse` checked every `{{` is closed and uses a known variable
+            let Some(end) = after.find("}}") else { break };
+            prompt.push_str(&rest[..start]);
+            let variable = &after[..end];
+            match values.iter().find(|(name, _)| *name == variable) {
+                Some((_, value)) => prompt.push_str(value),
+                None => prompt.push_str(&rest[start..start + end + 4])
+            }
+            rest = &after[end + 2..];
+        }
+        prompt.push_str(rest);
+        prompt
+    }
+}
+
+/// All of the prompt templates.
+#[derive(Clone, Debug)]
+pub struct PromptTemplates {
+    event_extraction: PromptTemplate,
+    extracted_string_parsing: PromptTemplate
+}
+
+impl PromptTemplates {
+    /// Load the templates, preferring any in `dir` over the built-in ones.
+    pub fn load(dir: Option<&Path>) -> Result<Self, String> {
+        Ok(Self {
+            event_extraction: load_template(
+                dir,
+                "event_extraction",
+                include_str!("../../prompts/event_extraction.txt"),
+                &EXTRACTION_VARIABLES
+            )?,
+            extracted_string_parsing: load_template(
+                dir,
+                "extracted_string_parsing",
+                include_str!("../../prompts/extracted_string_parsing.txt"),
+                &EXTRACTION_VARIABLES
+            )?
+        })
+    }
+
+    /// The version of the extraction prompts together, which is recorded with each extraction.
+    pub fn extraction_version(&self) -> String {
+        format!("extraction-{}+parsing-{}", self.event_extraction.version, self.extracted_string_parsing.version)
+    }
+
+    pub fn event_extraction(&self, context: &PromptContext) -> String {
+        self.event_extraction.render(&context.extraction_variables())
+    }
+
+    pub fn extracted_string_parsing(&self, context: &PromptContext) -> String {
+        self.extracted_string_parsing.render(&context.extraction_variables())
+    }
+}
+
+/// Load the template `name` from `dir` if it's there, otherwise use the built-in one.
+fn load_template(dir: Option<&Path>, name: &str, builtin: &str, variables: &[&str]) -> Result<PromptTemplate, String> {
+    let path = dir.map(|dir| dir.join(format!("{name}.txt"))).filter(|path| path.exists());
+    let template = match path {
+        Some(path) => {
+            let source = fs::read_to_string(&path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+            PromptTemplate::parse(name, &source, variables)?
+        },
+        None => PromptTemplate::parse(name, builtin, variables)?
+    };
+    tracing::info!("Loaded prompt template `{name}` (version {})", template.version);
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_does_not_substitute_inside_values() {
+        let template = PromptTemplate::parse("test", "version: 1\n---\n{{input}} in {{timezone}}", &["input", "timezone"]).unwrap();
+        let prompt = template.render(&[("input", "lunch {{timezone}}".into()), ("timezone", "Europe/London".into())]);
+        assert_eq!(prompt, "lunch {{timezone}} in Europe/London");
+    }
+}
@@ -1,17 +1,7 @@
 use clap::Parser;
 use dotenv::dotenv;
 use sqlx::postgres::PgPoolOptions;
-use crate::{config::{Config, StartupConfig}, llm::LLM, repositories::Repositories, services::Services};
-
-mod telemetry;
-mod config;
-mod api;
-mod models;
-mod llm;
-mod services;
-mod repositories;
-mod auth;
-mod utils;
+use backend::{api, config::{Config, StartupConfig}, llm::LLM, repositories::Repositories, services::Services};
 
 #[shuttle_runtime::main]
 async fn main(
@@ -46,7 +36,7 @@ async fn main(
 
     let repos = Repositories::new(db.clone());
     let llm = LLM::new(&config);
-    let services = Services::new(repos, llm);
+    let services = Services::new(repos, llm, &config);
     let router = api::router(config, services).await;
     Ok(router.into())
 }
@@ -0,0 +1,91 @@
+use std::{fmt, ops::{AddAssign, Sub}};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Tokens used by one or more LLM requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: i64,
+    pub candidates_tokens: i64,
+    pub total_tokens: i64
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.candidates_tokens += other.candidates_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+impl Sub for TokenUsage {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens - other.prompt_tokens,
+            candidates_tokens: self.candidates_tokens - other.candidates_tokens,
+            total_tokens: self.total_tokens - other.total_tokens
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Which AI feature tokens were used by.
+pub enum AIUsageKind {
+    Text,
+    Audio,
+    Image,
+    Email,
+    Edit,
+    Recurrence,
+    Question,
+    Briefing,
+    Categorisation
+}
+
+impl fmt::Display for AIUsageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Text => "text",
+            Self::Audio => "audio",
+            Self::Image => "image",
+            Self::Email => "email",
+            Self::Edit => "edit",
+            Self::Recurrence => "recurrence",
+            Self::Question => "question",
+            Self::Briefing => "briefing",
+            Self::Categorisation => "categorisation"
+        };
+        f.write_str(kind)
+    }
+}
+
+/// How much of their AI quotas a user has left.
+#[derive(Debug, Clone, Serialize)]
+pub struct AIAllowance {
+    pub daily: QuotaAllowance,
+    pub monthly: QuotaAllowance
+}
+
+/// How much of a single quota (in tokens) a user has left.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaAllowance {
+    pub limit: i64,
+    pub used: i64,
+    pub remaining: i64,
+    /// When the quota's period (a UTC day or month) ends.
+    pub resets_at: DateTime<Utc>
+}
+
+impl QuotaAllowance {
+    pub fn new(limit: i64, used: i64, resets_at: DateTime<Utc>) -> Self {
+        Self {
+            limit,
+            used,
+            remaining: (limit - used).max(0),
+            resets_at
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::llm::ExistingEventKind;
+
+/// The period a briefing covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BriefingPeriod {
+    /// A single (local) day.
+    Day,
+    /// The (local) week from Monday to Sunday.
+    Week
+}
+
+/// An agenda of the user's events over a day or week, with what's worth pointing out about it and a short summary.
+#[derive(Debug, Clone, Serialize)]
+pub struct Briefing {
+    pub period: BriefingPeriod,
+    /// The first (local) date covered.
+    pub start_date: NaiveDate,
+    /// The last (local) date covered.
+    pub end_date: NaiveDate,
+    /// The timezone the briefing is in (as shown by `UserTimezone`).
+    pub timezone: String,
+    /// The one-off events and recurring event instances (with exceptions applied), sorted by start time.
+    pub items: Vec<BriefingItem>,
+    /// Items which overlap each other.
+    pub conflicts: Vec<Conflict>,
+    /// Items which start right as another ends.
+    pub back_to_back: Vec<ItemPair>,
+    /// Long stretches between items on the same day.
+    pub gaps: Vec<Gap>,
+    pub summary: String,
+    pub summary_source: SummarySource
+}
+
+/// An event, or an instance of a recurring event, in a briefing.
+#[derive(Debug, Clone, Serialize)]
+pub struct BriefingItem {
+    pub kind: ExistingEventKind,
+    /// The ID of the `CalendarEvent` or `RecurringEvent`.
+    pub event_id: Uuid,
+    pub title: String,
+    pub location: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub group_name: Option<String>,
+    /// Whether this is the first instance of a recurring event (i.e. a new series starts).
+    pub first_occurrence: bool
+}
+
+/// Two items in a briefing, by their index in `items`; the first starts first.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ItemPair {
+    pub first: usize,
+    pub second: usize
+}
+
+/// Two items in a briefing which overlap.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Conflict {
+    #[serde(flatten)]
+    pub items: ItemPair,
+    pub overlap_minutes: i64
+}
+
+/// A long stretch with nothing on, between two items on the same day.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Gap {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub minutes: i64
+}
+
+/// What wrote a briefing's summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummarySource {
+    Llm,
+    /// A template, as the LLM was disabled, unavailable, or out of quota.
+    Template
+}
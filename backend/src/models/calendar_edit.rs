@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::models::{
+    calendar_event::UpdatedCalendarEvent,
+    recurring_event::{NewRecurringEvent, UpdatedRecurringEvent},
+    recurring_event_exception::NewRecurringEventException
+};
+
+/// A single change to the user's calendar.
+/// 
+/// These are proposed from a natural language instruction, and applied once the user confirms them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum CalendarEdit {
+    UpdateEvent { event: UpdatedCalendarEvent },
+    DeleteEvent { event_id: Uuid },
+    UpdateRecurringEvent { event: UpdatedRecurringEvent },
+    DeleteRecurringEvent { event_id: Uuid },
+    /// Cancel or modify a single instance of a recurring event.
+    AddException { exception: NewRecurringEventException },
+    /// End a recurring event just before `split_at`, and continue it as `new_event` from then on.
+    SplitSeries { event_id: Uuid, split_at: DateTime<Utc>, new_event: NewRecurringEvent }
+}
+
+impl CalendarEdit {
+    /// The ID of the `CalendarEvent` or `RecurringEvent` being edited.
+    pub fn target_id(&self) -> Uuid {
+        match self {
+            Self::UpdateEvent { event } => event.id,
+            Self::UpdateRecurringEvent { event } => event.id,
+            Self::AddException { exception } => exception.recurring_event_id,
+            Self::DeleteEvent { event_id } 
+            | Self::DeleteRecurringEvent { event_id } 
+            | Self::SplitSeries { event_id, .. } => *event_id
+        }
+    }
+}
+
+/// The edits proposed for an instruction, for the user to review.
+#[derive(Debug, Clone, Serialize)]
+pub struct EditProposal {
+    pub edits: Vec<ProposedEdit>,
+    /// Edits the LLM came up with that couldn't be used (e.g. they pointed at an event that doesn't exist).
+    pub rejected: Vec<RejectedEdit>
+}
+
+/// A proposed edit, along with a diff of what it changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProposedEdit {
+    pub edit: CalendarEdit,
+    /// The (current) title of the event being edited.
+    pub target_title: String,
+    /// A short explanation of the edit.
+    pub reason: String,
+    /// The fields that change; empty for deletions.
+    pub changes: Vec<FieldChange>
+}
+
+/// A change to a single field, formatted for display.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: Option<String>,
+    pub after: Option<String>
+}
+
+/// An edit that was generated but couldn't be proposed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedEdit {
+    pub reason: String,
+    pub error: String
+}
@@ -13,6 +13,12 @@ pub struct CalendarEvent {
     pub location: Option<String>,
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
+    /// The group the event was categorised into, if any.
+    pub group_id: Option<Uuid>,
+    /// Who organised the event, for events synced from Outlook (as `name <address>`).
+    pub organiser: Option<String>,
+    /// The AI extraction the event was saved from, if any.
+    pub extraction_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>
 }
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::llm::{ExistingEventKind, SearchOrder};
+
+/// A search over the user's calendar, as run.
+///
+/// This is built from the LLM's `GeneratedSearch`, with its period filled in and limited, and any made-up group dropped.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarSearch {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// Lowercase words, any of which a matching event's title, description, location or group name contains; empty
+    /// to match every event.
+    pub keywords: Vec<String>,
+    pub group_id: Option<Uuid>,
+    pub order: SearchOrder,
+    /// The most matching events returned.
+    pub limit: usize
+}
+
+/// An event (or a single instance of a recurring event) matching a search.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchedEvent {
+    pub kind: ExistingEventKind,
+    /// The ID of the `CalendarEvent` or `RecurringEvent`.
+    pub event_id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub group_id: Option<Uuid>,
+    pub group_name: Option<String>
+}
+
+/// The answer to a question about the user's calendar, along with the search it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarAnswer {
+    /// A short answer to the question, generated from the matching events.
+    pub answer: String,
+    pub search: CalendarSearch,
+    /// The matching events, in the search's order, up to its limit.
+    pub events: Vec<MatchedEvent>,
+    /// How many events matched in total, which can be more than were returned.
+    pub total_matches: usize
+}
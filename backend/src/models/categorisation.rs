@@ -0,0 +1,138 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::models::calendar_event::CalendarEvent;
+
+/// A rule putting imported events into a group, saved when the user accepts a categorisation suggestion.
+#[derive(Debug, Clone, Serialize)]
+pub struct CategorisationRule {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    pub field: RuleField,
+    /// Lowercase text the field contains.
+    pub pattern: String,
+    pub created_at: DateTime<Utc>
+}
+
+impl CategorisationRule {
+    /// Whether the rule puts the event into its group.
+    pub fn matches(&self, event: &CalendarEvent) -> bool {
+        self.field.matches(&self.pattern, event)
+    }
+}
+
+/// A rule to save along with an accepted categorisation suggestion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NewCategorisationRule {
+    pub field: RuleField,
+    /// Text the field contains (case-insensitively).
+    pub pattern: String
+}
+
+impl NewCategorisationRule {
+    /// Whether the rule would put the event into its group.
+    pub fn matches(&self, event: &CalendarEvent) -> bool {
+        self.field.matches(&self.pattern.trim().to_lowercase(), event)
+    }
+}
+
+/// The part of an event a categorisation rule looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum RuleField {
+    Title,
+    Location,
+    Organiser
+}
+
+impl RuleField {
+    /// Whether the event's field contains the (lowercase) pattern.
+    fn matches(self, pattern: &str, event: &CalendarEvent) -> bool {
+        let value = match self {
+            Self::Title => Some(&event.title),
+            Self::Location => event.location.as_ref(),
+            Self::Organiser => event.organiser.as_ref()
+        };
+        !pattern.is_empty() && value.is_some_and(|value| value.to_lowercase().contains(pattern))
+    }
+}
+
+impl fmt::Display for RuleField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self {
+            Self::Title => "title",
+            Self::Location => "location",
+            Self::Organiser => "organiser"
+        };
+        f.write_str(field)
+    }
+}
+
+/// The group a categorisation suggestion puts events into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CategoryTarget {
+    /// One of the user's existing groups.
+    Group { group_id: Uuid },
+    /// A new group, created when the suggestion is accepted.
+    New { name: String, color: i64 }
+}
+
+/// How a categorisation suggestion was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionSource {
+    /// One of the user's saved rules matched (though it wasn't applied when the events were imported).
+    Rule,
+    /// The events share words (or a location) with the group's name and events.
+    Keyword,
+    /// Several events have the same title, so are suggested a new group of their own.
+    Repeated,
+    Llm
+}
+
+/// A suggestion to put some imported events into a group.
+#[derive(Debug, Clone, Serialize)]
+pub struct CategorySuggestion {
+    pub target: CategoryTarget,
+    /// The name of the (existing or new) group.
+    pub name: String,
+    /// The `CalendarEvent`s to put into the group.
+    pub event_ids: Vec<Uuid>,
+    /// A rule to save, so future imports like these events are put into the group automatically.
+    pub rule: Option<NewCategorisationRule>,
+    pub source: SuggestionSource
+}
+
+/// Suggestions for categorising the user's ungrouped imported events.
+#[derive(Debug, Clone, Serialize)]
+pub struct CategorySuggestions {
+    pub suggestions: Vec<CategorySuggestion>,
+    /// The events nothing was suggested for.
+    pub uncategorised: Vec<Uuid>
+}
+
+/// A categorisation suggestion the user accepted (possibly after changing it).
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcceptedCategory {
+    pub target: CategoryTarget,
+    pub event_ids: Vec<Uuid>,
+    /// A rule to save, if any.
+    pub rule: Option<NewCategorisationRule>
+}
+
+/// What accepting categorisation suggestions did.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AcceptedCategories {
+    /// How many events were put into a group.
+    pub events: u64,
+    /// The IDs of the groups created for new categories.
+    pub created_groups: Vec<Uuid>,
+    /// How many rules were saved.
+    pub rules: u64,
+    /// The import batch the new groups were created in (to undo them), if any were.
+    pub import_batch_id: Option<Uuid>
+}
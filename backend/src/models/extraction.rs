@@ -0,0 +1,247 @@
+use std::fmt;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::models::{calendar_event::NewCalendarEvent, recurring_event::NewRecurringEventWithExceptions};
+
+/// An AI extraction: the events it returned, and where they came from.
+///
+/// Events saved from an extraction reference it, so the user can see where they came from (and re-run it), and what
+/// was saved can be compared against what was generated.
+#[derive(Debug, Clone, Serialize)]
+pub struct Extraction {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    /// The timezone the events were extracted in (as shown by `UserTimezone`).
+    pub timezone: String,
+    /// A BCP 47 tag.
+    pub locale: Option<String>,
+    /// A BCP 47 tag.
+    pub output_language: Option<String>,
+    /// The provenance fields are `None` for extractions recorded before provenance was.
+    pub modality: Option<ExtractionModality>,
+    /// The SHA-256 (in hex) of the input, as given to the LLM.
+    pub input_hash: Option<String>,
+    pub input_content_type: Option<String>,
+    /// The context given along with an audio, image or email input.
+    pub input_context: Option<String>,
+    /// Whether the input itself was kept (see `AIProvenanceConfig`), so the extraction can be re-run.
+    pub input_stored: bool,
+    pub model: Option<String>,
+    pub prompt_version: Option<String>,
+    /// The LLM's intermediate, free-text extraction.
+    pub extraction_text: Option<String>,
+    /// The serialized `GeneratedEvents`, as returned.
+    pub events: serde_json::Value,
+    pub created_at: DateTime<Utc>
+}
+
+/// The kind of input events were extracted from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum ExtractionModality {
+    Text,
+    Audio,
+    Image,
+    Email
+}
+
+impl fmt::Display for ExtractionModality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modality = match self {
+            Self::Text => "text",
+            Self::Audio => "audio",
+            Self::Image => "image",
+            Self::Email => "email"
+        };
+        f.write_str(modality)
+    }
+}
+
+/// The input of an extraction, as it was given to the LLM (i.e. after any downscaling or transcoding).
+#[derive(Debug, Clone)]
+pub struct ExtractionSource {
+    pub modality: ExtractionModality,
+    pub content_type: String,
+    pub bytes: Bytes,
+    pub context: Option<String>
+}
+
+impl ExtractionSource {
+    pub fn text(text: &str) -> Self {
+        Self {
+            modality: ExtractionModality::Text,
+            content_type: "text/plain; charset=utf-8".into(),
+            bytes: Bytes::copy_from_slice(text.as_bytes()),
+            context: None
+        }
+    }
+
+    /// The SHA-256 of the input, in hex.
+    pub fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(&self.bytes))
+    }
+}
+
+/// An extraction to record.
+#[derive(Debug, Clone)]
+pub struct NewExtraction {
+    pub user_id: Uuid,
+    pub timezone: String,
+    pub locale: Option<String>,
+    pub output_language: Option<String>,
+    pub modality: ExtractionModality,
+    pub input_hash: String,
+    pub input_content_type: String,
+    pub input_context: Option<String>,
+    /// `None` if the input is too large to keep.
+    pub input: Option<Vec<u8>>,
+    pub model: String,
+    pub prompt_version: String,
+    pub extraction_text: Option<String>,
+    pub events: serde_json::Value
+}
+
+/// The input of an extraction which was kept.
+#[derive(Debug, Clone)]
+pub struct StoredInput {
+    pub content_type: String,
+    pub context: Option<String>,
+    pub bytes: Vec<u8>
+}
+
+/// An extraction, along with the events the user saved from it.
+#[derive(Debug, Clone)]
+pub struct ExtractionWithFeedback {
+    pub id: Uuid,
+    /// The input, if it was text and was kept.
+    pub input_text: Option<String>,
+    pub timezone: String,
+    pub locale: Option<String>,
+    pub output_language: Option<String>,
+    /// The serialized `GeneratedEvents`.
+    pub events: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    /// The serialized `SavedEvents`.
+    pub saved: serde_json::Value,
+    /// The serialized `ExtractionDiff`.
+    pub diff: serde_json::Value
+}
+
+/// The events the user saved from an extraction (across however many requests they were saved in).
+///
+/// This takes the same shape as `GeneratedEvents`, though recurring events are kept together whichever group they
+/// were saved under.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedEvents {
+    #[serde(default)]
+    pub events: Vec<NewCalendarEvent>,
+    #[serde(default)]
+    pub recurring_events: Vec<NewRecurringEventWithExceptions>
+}
+
+impl SavedEvents {
+    /// Add events saved in a later request.
+    pub fn extend(&mut self, other: SavedEvents) {
+        self.events.extend(other.events);
+        self.recurring_events.extend(other.recurring_events);
+    }
+}
+
+/// How the events the user saved differ from those generated by an extraction.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtractionDiff {
+    /// How many generated items were saved as-is.
+    pub unchanged: usize,
+    /// Generated items which were saved with changes.
+    pub corrected: Vec<ItemCorrection>,
+    /// Generated items which weren't saved.
+    pub dropped: Vec<ItemSummary>,
+    /// Saved items which weren't generated.
+    pub added: Vec<ItemSummary>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Event,
+    RecurringEvent
+}
+
+/// An event or recurring event, as identified in a diff.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemSummary {
+    pub kind: ItemKind,
+    pub title: String,
+    /// The start of the event, or of the recurrence.
+    pub start: DateTime<Utc>
+}
+
+/// The changes the user made to a generated item before saving it; unchanged fields are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemCorrection {
+    /// The item as it was generated.
+    pub generated: ItemSummary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<FieldChange<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<FieldChange<Option<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<FieldChange<Option<String>>>,
+    /// How far the start was moved (later if positive).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_shift_seconds: Option<i64>,
+    /// How much longer the event was made (shorter if negative).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_change_seconds: Option<i64>,
+    /// For recurring events, a rule giving different occurrences.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<FieldChange<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence_end: Option<FieldChange<Option<DateTime<Utc>>>>
+}
+
+impl ItemCorrection {
+    /// A correction to the generated item, with no changes (yet).
+    pub fn new(generated: ItemSummary) -> Self {
+        Self {
+            generated,
+            title: None,
+            description: None,
+            location: None,
+            start_shift_seconds: None,
+            duration_change_seconds: None,
+            rrule: None,
+            recurrence_end: None
+        }
+    }
+
+    /// Whether the item was saved as it was generated.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+        && self.description.is_none()
+        && self.location.is_none()
+        && self.start_shift_seconds.is_none()
+        && self.duration_change_seconds.is_none()
+        && self.rrule.is_none()
+        && self.recurrence_end.is_none()
+    }
+}
+
+/// A field's generated and saved values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldChange<T> {
+    pub from: T,
+    pub to: T
+}
+
+impl<T: PartialEq> FieldChange<T> {
+    /// The change from `from` to `to`, if there is one.
+    pub fn between(from: T, to: T) -> Option<Self> {
+        (from != to).then_some(Self { from, to })
+    }
+}
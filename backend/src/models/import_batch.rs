@@ -0,0 +1,58 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Events, recurring events and groups created in one go, which can be undone in one go.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportBatch {
+    pub id: Uuid,
+    pub source: ImportSource,
+    /// The AI extraction the batch was saved from, if any.
+    pub extraction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    /// When the batch was undone, if it has been.
+    pub undone_at: Option<DateTime<Utc>>,
+    /// How many of the batch's events (and so on) haven't since been deleted.
+    pub event_count: i64,
+    pub recurring_event_count: i64,
+    pub group_count: i64
+}
+
+/// How a batch was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum ImportSource {
+    /// Creating calendar events.
+    Events,
+    /// Creating recurring events.
+    RecurringEvents,
+    /// Creating recurring event groups along with their events.
+    Groups,
+    /// Syncing with Outlook.
+    Outlook,
+    /// Creating groups for events accepted into new categories.
+    Categories
+}
+
+impl fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            Self::Events => "events",
+            Self::RecurringEvents => "recurring_events",
+            Self::Groups => "groups",
+            Self::Outlook => "outlook",
+            Self::Categories => "categories"
+        };
+        f.write_str(source)
+    }
+}
+
+/// What undoing a batch removed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UndoneBatch {
+    pub events: u64,
+    pub recurring_events: u64,
+    pub groups: u64
+}
@@ -0,0 +1,29 @@
+use std::fmt;
+
+/// A BCP 47 language tag, such as "en" or "en-GB".
+/// 
+/// Only the shape of the tag is checked (subtags of letters and digits, starting with a 2-8 letter language), 
+/// not that the language actually exists.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LanguageTag(String);
+
+impl LanguageTag {
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim();
+        let mut subtags = tag.split(['-', '_']);
+        let language = subtags.next()?;
+        let valid_language = (2..=8).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+        let valid_subtags = subtags.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()));
+        (valid_language && valid_subtags).then(|| LanguageTag(tag.replace('_', "-")))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for LanguageTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -1,4 +1,12 @@
+pub mod ai_usage;
+pub mod briefing;
+pub mod calendar_edit;
 pub mod calendar_event;
+pub mod calendar_query;
+pub mod categorisation;
+pub mod extraction;
+pub mod import_batch;
+pub mod language;
 pub mod recurring_event;
 pub mod recurring_event_exception;
 pub mod recurring_event_group;
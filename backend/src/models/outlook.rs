@@ -61,11 +61,38 @@ pub struct OutlookCalendarEvent {
     pub location: Option<OutlookLocation>,
     pub start: OutlookDateTimeTimeZone,
     pub end: OutlookDateTimeTimeZone,
+    pub organizer: Option<OutlookRecipient>,
     //pub recurrence: Option<OutlookRecurrence>,
     //pub series_master_id: Option<String>,
     //pub r#type: OutlookEventType,
 }
 
+impl OutlookCalendarEvent {
+    /// Who organised the event, as `name <address>` (or whichever of them is known).
+    pub fn organiser(&self) -> Option<String> {
+        let email_address = self.organizer.as_ref()?.email_address.as_ref()?;
+        match (&email_address.name, &email_address.address) {
+            (Some(name), Some(address)) => Some(format!("{name} <{address}>")),
+            (Some(name), None) => Some(name.clone()),
+            (None, Some(address)) => Some(address.clone()),
+            (None, None) => None
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlookRecipient {
+    pub email_address: Option<OutlookEmailAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlookEmailAddress {
+    pub name: Option<String>,
+    pub address: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ItemBody {
@@ -3,7 +3,12 @@ use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::models::{recurring_event_group::RecurringEventGroup, rrule::ValidatedRRule, time::Second};
+use crate::models::{
+    recurring_event_exception::NewSeriesException, 
+    recurring_event_group::RecurringEventGroup, 
+    rrule::ValidatedRRule, 
+    time::Second
+};
 
 /// A single instance of a `RecurringEvent`, to be used on the calendar.
 /// 
@@ -42,6 +47,8 @@ pub struct RecurringEvent {
     pub recurrence_start: DateTime<Utc>,
     pub recurrence_end: Option<DateTime<Utc>>,
     pub rrule: ValidatedRRule,
+    /// The AI extraction the event was saved from, if any.
+    pub extraction_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>
 }
@@ -59,6 +66,16 @@ pub struct NewRecurringEvent {
     pub rrule: ValidatedRRule
 }
 
+/// A new recurring event, along with any exceptions to create with it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewRecurringEventWithExceptions {
+    #[serde(flatten)]
+    pub event: NewRecurringEvent,
+    /// Instances that are cancelled or modified (e.g. "no class on 3 Oct").
+    #[serde(default)]
+    pub exceptions: Vec<NewSeriesException>
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdatedRecurringEvent {
     pub id: Uuid,
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -37,7 +38,39 @@ pub struct NewRecurringEventException {
     pub modified_end_time: Option<DateTime<Utc>>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// An exception to create along with its (new) recurring event, e.g. "no lecture in week 6".
+/// 
+/// **Note**: as with `RecurringEventException`, the `modified` members are only used if `ExceptionType::Modified`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewSeriesException {
+    /// The original start of the affected instance.
+    pub exception_date: DateTime<Utc>,
+    pub exception_type: ExceptionType,
+    pub modified_title: Option<String>,
+    pub modified_description: Option<String>,
+    pub modified_location: Option<String>,
+    pub modified_start_time: Option<DateTime<Utc>>,
+    pub modified_end_time: Option<DateTime<Utc>>
+}
+
+impl NewSeriesException {
+    /// Attach the exception to its (now created) recurring event.
+    pub fn for_event(self, recurring_event_id: Uuid) -> NewRecurringEventException {
+        let is_modified = self.exception_type == ExceptionType::Modified;
+        NewRecurringEventException {
+            recurring_event_id,
+            exception_date: self.exception_date,
+            exception_type: self.exception_type,
+            modified_title: self.modified_title.filter(|_| is_modified),
+            modified_description: self.modified_description.filter(|_| is_modified),
+            modified_location: self.modified_location.filter(|_| is_modified).map(Some),
+            modified_start_time: self.modified_start_time.filter(|_| is_modified),
+            modified_end_time: self.modified_end_time.filter(|_| is_modified)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 #[derive(sqlx::Type)]
 #[sqlx(type_name = "varchar", rename_all = "lowercase")]
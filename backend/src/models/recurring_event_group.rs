@@ -21,6 +21,8 @@ pub struct RecurringEventGroup {
     pub group_recurrence_start: Option<DateTime<Utc>>,
     /// A default end date for the group's events.
     pub group_recurrence_end: Option<DateTime<Utc>>,
+    /// The AI extraction the group was saved from, if any.
+    pub extraction_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
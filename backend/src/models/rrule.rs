@@ -1,6 +1,6 @@
 use std::str::FromStr;
-use chrono::{DateTime, TimeZone, Utc};
-use rrule::{RRule, RRuleError, RRuleResult, RRuleSet, Tz, Unvalidated};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use rrule::{Frequency, NWeekday, RRule, RRuleError, RRuleResult, RRuleSet, Tz, Unvalidated};
 use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize, Serializer};
 use sqlx::{postgres::PgHasArrayType, Database, Decode, Encode, Type};
@@ -17,10 +17,69 @@ pub struct ValidatedRRule {
 }
 
 impl ValidatedRRule {
+    /// Validate a rule, starting at `start`.
+    pub fn new(rrule: RRule<Unvalidated>, start: DateTime<Utc>) -> Result<Self, RRuleError> {
+        let start = start.with_timezone(&Tz::UTC);
+        let rrule = rrule.validate(start)?;
+        Ok(Self { rrule: RRuleSet::new(start).rrule(rrule) })
+    }
+
     /// Set a new start datetime.
+    /// 
+    /// Validating a rule fills in the parts it doesn't give (like `BYHOUR`, or `BYDAY` for a weekly rule) from its start,
+    /// which for a deserialized rule is the placeholder start; those parts are moved to the new start, so the rule
+    /// recurs at the new start's time (and day). A part given explicitly, but the same as the old start's, is moved too.
     pub fn set_start(&mut self, start: DateTime<Utc>) {
-        let rrule = self.rrule.get_rrule().clone();
-        self.rrule = RRuleSet::new(start.with_timezone(&Tz::UTC)).set_rrules(rrule);
+        let start = start.with_timezone(&Tz::UTC);
+        let old_start = *self.rrule.get_dt_start();
+        let rrules = self.rrule
+            .get_rrule()
+            .iter()
+            .map(|rrule| {
+                let rrule = Self::without_start_parts(rrule, &old_start);
+                rrule.validate(start).expect("Moving a valid RRule's start should not make it invalid")
+            })
+            .collect();
+        self.rrule = RRuleSet::new(start).set_rrules(rrules);
+    }
+
+    /// The rule without the parts which were filled in from `start` when it was validated.
+    fn without_start_parts(rrule: &RRule, start: &DateTime<Tz>) -> RRule<Unvalidated> {
+        let mut unvalidated = <RRule<Unvalidated>>::from_str(&rrule.to_string())
+            .expect("We parse directly from a validated RRule, so it should be valid");
+        let only = |values: &[u8], value: u32| values == [value as u8];
+        if only(rrule.get_by_hour(), start.hour()) {
+            unvalidated = unvalidated.by_hour(Vec::new());
+        }
+        if only(rrule.get_by_minute(), start.minute()) {
+            unvalidated = unvalidated.by_minute(Vec::new());
+        }
+        if only(rrule.get_by_second(), start.second()) {
+            unvalidated = unvalidated.by_second(Vec::new());
+        }
+
+        // the day is only filled in when the rule gives no days at all
+        let other_days = !rrule.get_by_week_no().is_empty() || !rrule.get_by_year_day().is_empty();
+        let by_start_day = rrule.get_by_month_day() == [start.day() as i8];
+        match rrule.get_freq() {
+            Frequency::Weekly if !other_days
+                && rrule.get_by_month_day().is_empty()
+                && rrule.get_by_weekday() == [NWeekday::Every(start.weekday())] =>
+            {
+                unvalidated = unvalidated.by_weekday(Vec::new());
+            },
+            Frequency::Monthly if !other_days && by_start_day && rrule.get_by_weekday().is_empty() => {
+                unvalidated = unvalidated.by_month_day(Vec::new());
+            },
+            Frequency::Yearly if !other_days && by_start_day && rrule.get_by_weekday().is_empty() => {
+                unvalidated = unvalidated.by_month_day(Vec::new());
+                if only(rrule.get_by_month(), start.month()) {
+                    unvalidated = unvalidated.by_month(&[]);
+                }
+            },
+            _ => ()
+        }
+        unvalidated
     }
 
     /// Set a new end datetime.
@@ -50,6 +109,36 @@ impl ValidatedRRule {
         restricted_rrule.all(INSTANCE_LIMIT)
     }
 
+    /// The first `limit` instances (capped at the same maximum as `all_within_period`).
+    pub fn first_instances(&self, limit: u16) -> Vec<DateTime<Utc>> {
+        self.rrule
+            .clone()
+            .all(limit.min(INSTANCE_LIMIT))
+            .dates
+            .iter()
+            .map(|date| date.to_utc())
+            .collect()
+    }
+
+    /// The rule itself (e.g. `FREQ=WEEKLY;BYDAY=MO`), without the start date.
+    pub fn rule_string(&self) -> String {
+        self.rrule.get_rrule()[0].to_string()
+    }
+
+    /// Whether the rule ends after a fixed number of instances (`COUNT`), rather than at a date.
+    pub fn has_count(&self) -> bool {
+        self.rrule.get_rrule()[0].get_count().is_some()
+    }
+
+    /// Find the instance starting closest to `datetime`, within `tolerance` either side.
+    pub fn instance_near(&self, datetime: DateTime<Utc>, tolerance: Duration) -> Option<DateTime<Utc>> {
+        self.all_within_period(datetime - tolerance, datetime + tolerance)
+            .dates
+            .iter()
+            .map(|date| date.to_utc())
+            .min_by_key(|date| (*date - datetime).abs())
+    }
+
     /// Add EXDATEs for deleted instances for this event.
     pub fn set_exdates(&mut self, datetimes: &[DateTime<Utc>]) {
         let mut rrule = self.rrule.clone();
@@ -164,4 +253,45 @@ impl JsonSchema for ValidatedRRule {
         generator.subschema_for::<String>()
     }
 }
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deserialize(rule: &str) -> ValidatedRRule {
+        serde_json::from_value(serde_json::Value::String(rule.into())).unwrap()
+    }
+
+    fn at(datetime: &str) -> DateTime<Utc> {
+        datetime.parse().unwrap()
+    }
+
+    #[test]
+    fn recurs_from_the_start() {
+        let mut rrule = deserialize("FREQ=WEEKLY;COUNT=2");
+        rrule.set_start(at("2026-09-14T10:30:00Z"));
+        assert_eq!(rrule.first_instances(5), [at("2026-09-14T10:30:00Z"), at("2026-09-21T10:30:00Z")]);
+
+        // and from the new start, after moving it
+        rrule.set_start(at("2026-09-16T09:00:00Z"));
+        assert_eq!(rrule.first_instances(5), [at("2026-09-16T09:00:00Z"), at("2026-09-23T09:00:00Z")]);
+
+        let mut rrule = deserialize("FREQ=YEARLY;COUNT=2");
+        rrule.set_start(at("2026-03-05T08:00:00Z"));
+        assert_eq!(rrule.first_instances(5), [at("2026-03-05T08:00:00Z"), at("2027-03-05T08:00:00Z")]);
+    }
+
+    #[test]
+    fn keeps_parts_given_explicitly() {
+        let mut rrule = deserialize("FREQ=WEEKLY;BYDAY=MO,WE;BYHOUR=18;COUNT=3");
+        rrule.set_start(at("2026-09-14T10:00:00Z"));
+        assert_eq!(
+            rrule.first_instances(5),
+            [at("2026-09-14T18:00:00Z"), at("2026-09-16T18:00:00Z"), at("2026-09-21T18:00:00Z")]
+        );
+
+        let mut rrule = deserialize("FREQ=MONTHLY;BYMONTHDAY=1;COUNT=2");
+        rrule.set_start(at("2026-09-01T12:00:00Z"));
+        assert_eq!(rrule.first_instances(5), [at("2026-09-01T12:00:00Z"), at("2026-10-01T12:00:00Z")]);
+    }
+}
@@ -1,3 +1,6 @@
+use std::{fmt, str::FromStr};
+use chrono::{offset::LocalResult, DateTime, Duration, FixedOffset, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::{postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef}, Decode, Encode, Postgres};
@@ -43,6 +46,77 @@ impl Minute {
 #[schemars(transparent, inline)]
 pub struct Second(pub u32);
 
+/// The timezone a user is in.
+/// 
+/// Clients can either give us an IANA name (preferred, as it knows about DST) or a fixed UTC offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserTimezone {
+    Named(Tz),
+    Offset(FixedOffset)
+}
+
+impl UserTimezone {
+    /// Create from a UTC offset in minutes (positive is east of UTC), returning `None` if it's out of range.
+    pub fn from_offset_minutes(offset_minutes: i32) -> Option<Self> {
+        FixedOffset::east_opt(offset_minutes.checked_mul(60)?).map(Self::Offset)
+    }
+
+    /// Create from an IANA timezone name, like `Asia/Singapore`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        name.trim().parse::<Tz>().ok().map(Self::Named)
+    }
+
+    /// The UTC offset in effect at the given instant.
+    pub fn offset_at(&self, datetime: DateTime<Utc>) -> FixedOffset {
+        match self {
+            Self::Named(tz) => tz.offset_from_utc_datetime(&datetime.naive_utc()).fix(),
+            Self::Offset(offset) => *offset
+        }
+    }
+
+    /// Convert a UTC datetime to the user's local time.
+    pub fn utc_to_local(&self, datetime: DateTime<Utc>) -> DateTime<FixedOffset> {
+        datetime.with_timezone(&self.offset_at(datetime))
+    }
+
+    /// Interpret a wall-clock datetime in this timezone, returning it in UTC.
+    /// 
+    /// Ambiguous times (when the clocks go back) resolve to the earlier instant, 
+    /// and times that don't exist (when the clocks go forward) use the offset from before the change.
+    pub fn local_to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let offset = match self {
+            Self::Named(tz) => match tz.from_local_datetime(&local) {
+                LocalResult::Single(datetime) | LocalResult::Ambiguous(datetime, _) => return datetime.to_utc(),
+                LocalResult::None => tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix()
+            },
+            Self::Offset(offset) => *offset
+        };
+        (local - Duration::seconds(offset.local_minus_utc().into())).and_utc()
+    }
+}
+
+impl fmt::Display for UserTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Named(tz) => write!(f, "{}", tz.name()),
+            Self::Offset(offset) => write!(f, "UTC{offset}")
+        }
+    }
+}
+
+impl FromStr for UserTimezone {
+    type Err = String;
+
+    /// Parse a timezone as it's shown: an IANA name, or a UTC offset like `UTC+05:30`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` is not an IANA timezone name or a UTC offset");
+        match s.trim().strip_prefix("UTC") {
+            Some(offset) if !offset.is_empty() => offset.parse().map(Self::Offset).map_err(|_| invalid()),
+            _ => Self::from_name(s).ok_or_else(invalid)
+        }
+    }
+}
+
 //
 // deserialization
 //
@@ -142,4 +216,66 @@ impl PgHasArrayType for Second {
     fn array_type_info() -> PgTypeInfo {
         <i32 as sqlx::Type<Postgres>>::type_info()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn london() -> UserTimezone {
+        "Europe/London".parse().unwrap()
+    }
+
+    #[test]
+    fn converts_local_times() {
+        assert_eq!(london().local_to_utc(at(2026, 1, 15, 9, 0)), at(2026, 1, 15, 9, 0).and_utc());
+        assert_eq!(london().local_to_utc(at(2026, 7, 15, 9, 0)), at(2026, 7, 15, 8, 0).and_utc());
+        let offset = UserTimezone::from_offset_minutes(330).unwrap();
+        assert_eq!(offset.local_to_utc(at(2026, 7, 15, 10, 0)), at(2026, 7, 15, 4, 30).and_utc());
+    }
+
+    #[test]
+    fn converts_times_skipped_when_the_clocks_go_forward() {
+        // the clocks go from 01:00 to 02:00, so 01:30 never happens; it's taken as GMT, from before the change
+        let skipped = at(2026, 3, 29, 1, 30);
+        assert!(matches!(Tz::Europe__London.from_local_datetime(&skipped), LocalResult::None));
+        assert_eq!(london().local_to_utc(skipped), at(2026, 3, 29, 1, 30).and_utc());
+    }
+
+    #[test]
+    fn converts_times_repeated_when_the_clocks_go_back() {
+        // the clocks go from 02:00 back to 01:00, so 01:30 happens twice; the first (in BST) is taken
+        let repeated = at(2026, 10, 25, 1, 30);
+        assert!(matches!(Tz::Europe__London.from_local_datetime(&repeated), LocalResult::Ambiguous(..)));
+        assert_eq!(london().local_to_utc(repeated), at(2026, 10, 25, 0, 30).and_utc());
+    }
+
+    #[test]
+    fn creates_from_offset_minutes() {
+        assert_eq!(UserTimezone::from_offset_minutes(-180).unwrap().to_string(), "UTC-03:00");
+        assert_eq!(UserTimezone::from_offset_minutes(24 * 60), None);
+        assert_eq!(UserTimezone::from_offset_minutes(i32::MAX), None);
+    }
+
+    #[test]
+    fn parses_timezones() {
+        assert_eq!("Asia/Singapore".parse(), Ok(UserTimezone::Named(Tz::Asia__Singapore)));
+        assert_eq!("UTC+05:30".parse(), Ok(UserTimezone::from_offset_minutes(330).unwrap()));
+        assert_eq!(" UTC-03:00 ".parse(), Ok(UserTimezone::from_offset_minutes(-180).unwrap()));
+        assert!("Mars/Olympus_Mons".parse::<UserTimezone>().is_err());
+        assert!("UTC+25:00".parse::<UserTimezone>().is_err());
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        for timezone in ["Europe/London", "UTC", "UTC+05:30", "UTC-03:00", "UTC+00:00"] {
+            let parsed: UserTimezone = timezone.parse().unwrap();
+            assert_eq!(parsed.to_string(), timezone);
+            assert_eq!(parsed.to_string().parse(), Ok(parsed));
+        }
+    }
+}
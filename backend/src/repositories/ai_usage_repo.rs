@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{models::ai_usage::{AIUsageKind, TokenUsage}, repositories::RepoResult};
+
+/// Abstraction for interacting with the `ai_usage` table.
+#[derive(Clone, Debug)]
+pub struct AIUsageRepository {
+    db: PgPool
+}
+
+impl AIUsageRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Record the tokens used by one of the user's AI requests.
+    pub async fn record_usage(&self, user_id: Uuid, kind: AIUsageKind, usage: TokenUsage) -> RepoResult<()> {
+        sqlx::query!(
+            r#"
+                INSERT INTO ai_usage
+                (user_id, kind, prompt_tokens, candidates_tokens, total_tokens)
+                VALUES
+                ($1, $2, $3, $4, $5)
+            "#,
+            user_id,
+            kind.to_string(),
+            usage.prompt_tokens,
+            usage.candidates_tokens,
+            usage.total_tokens
+        )
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the total tokens the user has used since `day_start` and since `month_start` respectively.
+    pub async fn tokens_used_since(
+        &self, 
+        user_id: Uuid, 
+        day_start: DateTime<Utc>, 
+        month_start: DateTime<Utc>
+    ) -> RepoResult<(i64, i64)> {
+        let used = sqlx::query!(
+            r#"
+                SELECT
+                    COALESCE(SUM(total_tokens) FILTER (WHERE created_at >= $2), 0)::BIGINT AS "day!",
+                    COALESCE(SUM(total_tokens), 0)::BIGINT AS "month!"
+                FROM ai_usage
+                WHERE user_id = $1 AND created_at >= $3
+            "#,
+            user_id,
+            day_start,
+            month_start
+        )
+            .fetch_one(&self.db)
+            .await?;
+        Ok((used.day, used.month))
+    }
+}
@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
-use crate::{models::calendar_event::{CalendarEvent, NewCalendarEvent, UpdatedCalendarEvent}, repositories::RepoResult};
+use crate::{models::{calendar_event::{CalendarEvent, NewCalendarEvent, UpdatedCalendarEvent}, import_batch::ImportSource}, repositories::RepoResult};
 
 /// Abstraction for interacting with the `calendar_events` table.
 #[derive(Clone, Debug)]
@@ -14,7 +14,27 @@ impl CalendarEventsRepository {
         Self { db }
     }
 
-    pub async fn create_events(&self, user_id: Uuid, events: Vec<NewCalendarEvent>) -> RepoResult<Vec<Uuid>> {
+    /// Create events in an import batch, optionally referencing the AI extraction they were saved from.
+    pub async fn create_events(
+        &self, 
+        user_id: Uuid, 
+        events: Vec<NewCalendarEvent>, 
+        extraction_id: Option<Uuid>,
+        import_batch_id: Uuid
+    ) -> RepoResult<Vec<Uuid>> {
+        let mut conn = self.db.acquire().await?;
+        self.create_events_in(&mut conn, user_id, events, extraction_id, import_batch_id).await
+    }
+
+    /// Same as `create_events`, but on the given connection (e.g. within a transaction).
+    pub async fn create_events_in(
+        &self, 
+        conn: &mut PgConnection,
+        user_id: Uuid, 
+        events: Vec<NewCalendarEvent>, 
+        extraction_id: Option<Uuid>,
+        import_batch_id: Uuid
+    ) -> RepoResult<Vec<Uuid>> {
         let user_ids = vec![user_id; events.len()];
         let mut titles = Vec::with_capacity(events.len());
         let mut descriptions = Vec::with_capacity(events.len());
@@ -33,8 +53,8 @@ impl CalendarEventsRepository {
         let event_ids = sqlx::query_scalar!(
             r#"
                 insert into calendar_events
-                (user_id, title, description, start_time, end_time, location)
-                select * from unnest
+                (user_id, title, description, start_time, end_time, location, extraction_id, import_batch_id)
+                select *, $7, $8 from unnest
                 ($1::uuid[], $2::varchar[], $3::varchar[], $4::timestamptz[], $5::timestamptz[], $6::varchar[])
                 returning id
             "#,
@@ -43,9 +63,11 @@ impl CalendarEventsRepository {
             &descriptions[..] as &[Option<String>],
             &start_times[..],
             &end_times[..],
-            &locations[..] as &[Option<String>]
+            &locations[..] as &[Option<String>],
+            extraction_id,
+            import_batch_id
         )
-            .fetch_all(&self.db)
+            .fetch_all(conn)
             .await?;
 
         Ok(event_ids)
@@ -60,7 +82,7 @@ impl CalendarEventsRepository {
         let events = sqlx::query_as!(
             CalendarEvent,
             r#"
-                select id, user_id, title, description, location, start_time, end_time, created_at, last_modified
+                select id, user_id, title, description, location, start_time, end_time, group_id, organiser, extraction_id, created_at, last_modified
                 from calendar_events 
                 where user_id = $1 
                 and start_time >= $2 
@@ -78,6 +100,104 @@ impl CalendarEventsRepository {
         Ok(events)
     }
 
+    /// Get one of the user's (non-deleted) events by its ID.
+    pub async fn get_event(&self, user_id: Uuid, event_id: Uuid) -> RepoResult<Option<CalendarEvent>> {
+        let event = sqlx::query_as!(
+            CalendarEvent,
+            r#"
+                select id, user_id, title, description, location, start_time, end_time, group_id, organiser, extraction_id, created_at, last_modified
+                from calendar_events 
+                where id = $1
+                and user_id = $2 
+                and is_deleted = false
+            "#,
+            event_id,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(event)
+    }
+
+    /// Get the user's (non-deleted) events which aren't in a group, from one import batch or, if not given, from all
+    /// their Outlook syncs; oldest first, up to `limit`.
+    pub async fn get_ungrouped_imported_events(
+        &self,
+        user_id: Uuid,
+        import_batch_id: Option<Uuid>,
+        limit: i64
+    ) -> RepoResult<Vec<CalendarEvent>> {
+        let events = sqlx::query_as!(
+            CalendarEvent,
+            r#"
+                select e.id, e.user_id, e.title, e.description, e.location, e.start_time, e.end_time, e.group_id,
+                    e.organiser, e.extraction_id, e.created_at, e.last_modified
+                from calendar_events e
+                join import_batches b on b.id = e.import_batch_id
+                where e.user_id = $1
+                and e.group_id is null
+                and e.is_deleted = false
+                and (b.id = $2 or ($2 is null and b.source = $3))
+                order by e.start_time
+                limit $4
+            "#,
+            user_id,
+            import_batch_id,
+            ImportSource::Outlook.to_string(),
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Put some of the user's events into a group, returning how many were.
+    pub async fn set_group(&self, user_id: Uuid, event_ids: &[Uuid], group_id: Uuid) -> RepoResult<u64> {
+        let mut conn = self.db.acquire().await?;
+        self.set_group_in(&mut conn, user_id, event_ids, group_id).await
+    }
+
+    /// Same as `set_group`, but on the given connection (e.g. within a transaction).
+    pub async fn set_group_in(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        event_ids: &[Uuid],
+        group_id: Uuid
+    ) -> RepoResult<u64> {
+        let updated = sqlx::query!(
+            r#"
+                update calendar_events
+                set group_id = $1, last_modified = NOW()
+                where id = any($2)
+                and user_id = $3
+                and is_deleted = false
+            "#,
+            group_id,
+            event_ids,
+            user_id
+        )
+        .execute(conn)
+        .await?
+        .rows_affected();
+
+        Ok(updated)
+    }
+
+    pub async fn set_organiser(&self, event_id: Uuid, organiser: Option<String>) -> RepoResult<()> {
+        sqlx::query!(
+            r#"update calendar_events set organiser = $1 where id = $2"#,
+            organiser,
+            event_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn get_event_owner(&self, event_id: Uuid) -> RepoResult<Uuid> {
         let event_record = sqlx::query!(
             r#"select user_id from calendar_events where id = $1"#,
@@ -90,6 +210,12 @@ impl CalendarEventsRepository {
     }
 
     pub async fn update_event(&self, updated_event: UpdatedCalendarEvent) -> RepoResult<()> {
+        let mut conn = self.db.acquire().await?;
+        self.update_event_in(&mut conn, updated_event).await
+    }
+
+    /// Same as `update_event`, but on the given connection (e.g. within a transaction).
+    pub async fn update_event_in(&self, conn: &mut PgConnection, updated_event: UpdatedCalendarEvent) -> RepoResult<()> {
         sqlx::query!(
             r#"
                 update calendar_events
@@ -109,18 +235,24 @@ impl CalendarEventsRepository {
             updated_event.end_time,
             updated_event.id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
         
         Ok(())
     }
 
     pub async fn delete_event(&self, event_id: Uuid) -> RepoResult<()> {
+        let mut conn = self.db.acquire().await?;
+        self.delete_event_in(&mut conn, event_id).await
+    }
+
+    /// Same as `delete_event`, but on the given connection (e.g. within a transaction).
+    pub async fn delete_event_in(&self, conn: &mut PgConnection, event_id: Uuid) -> RepoResult<()> {
         sqlx::query!(
             r#"update calendar_events set is_deleted = true where id = $1"#,
             event_id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
         
         Ok(())
@@ -0,0 +1,76 @@
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    models::categorisation::{CategorisationRule, NewCategorisationRule},
+    repositories::RepoResult
+};
+
+/// Abstraction for interacting with the `categorisation_rules` table.
+#[derive(Clone, Debug)]
+pub struct CategorisationRulesRepository {
+    db: PgPool
+}
+
+impl CategorisationRulesRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Get the user's rules, newest first (so a newer rule wins when several match an event).
+    pub async fn fetch_rules(&self, user_id: Uuid) -> RepoResult<Vec<CategorisationRule>> {
+        sqlx::query_as!(
+            CategorisationRule,
+            r#"
+                SELECT id, group_id, field as "field: _", pattern, created_at
+                FROM categorisation_rules
+                WHERE user_id = $1
+                ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+            .fetch_all(&self.db)
+            .await
+    }
+
+    /// Save a rule on the given connection (e.g. within a transaction), replacing the group of any rule with the same
+    /// field and pattern.
+    pub async fn save_rule_in(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        group_id: Uuid,
+        rule: &NewCategorisationRule
+    ) -> RepoResult<Uuid> {
+        sqlx::query_scalar!(
+            r#"
+                INSERT INTO categorisation_rules
+                (user_id, group_id, field, pattern)
+                VALUES
+                ($1, $2, $3, $4)
+                ON CONFLICT (user_id, field, pattern)
+                DO UPDATE SET group_id = EXCLUDED.group_id, created_at = NOW()
+                RETURNING id
+            "#,
+            user_id,
+            group_id,
+            rule.field.to_string(),
+            rule.pattern
+        )
+            .fetch_one(conn)
+            .await
+    }
+
+    /// Delete one of the user's rules, returning whether it existed.
+    pub async fn delete_rule(&self, user_id: Uuid, rule_id: Uuid) -> RepoResult<bool> {
+        let deleted = sqlx::query!(
+            r#"DELETE FROM categorisation_rules WHERE id = $1 AND user_id = $2"#,
+            rule_id,
+            user_id
+        )
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+}
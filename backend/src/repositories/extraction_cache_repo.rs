@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::repositories::RepoResult;
+
+/// Abstraction for interacting with the `extraction_cache` table.
+#[derive(Clone, Debug)]
+pub struct ExtractionCacheRepository {
+    db: PgPool
+}
+
+impl ExtractionCacheRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Get the (serialized) cached extraction under `cache_key`, if it hasn't expired.
+    pub async fn get_extraction(&self, cache_key: &str) -> RepoResult<Option<serde_json::Value>> {
+        sqlx::query_scalar!(
+            r#"select events from extraction_cache where cache_key = $1 and expires_at > NOW()"#,
+            cache_key
+        )
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    /// Cache a (serialized) extraction under `cache_key`, replacing any already there, and clear out expired ones.
+    pub async fn put_extraction(
+        &self, 
+        cache_key: &str, 
+        user_id: Uuid, 
+        events: serde_json::Value, 
+        expires_at: DateTime<Utc>
+    ) -> RepoResult<()> {
+        sqlx::query!(
+            r#"
+                INSERT INTO extraction_cache
+                (cache_key, user_id, events, expires_at)
+                VALUES
+                ($1, $2, $3, $4)
+                ON CONFLICT (cache_key) DO UPDATE
+                SET events = EXCLUDED.events,
+                created_at = NOW(),
+                expires_at = EXCLUDED.expires_at
+            "#,
+            cache_key,
+            user_id,
+            events,
+            expires_at
+        )
+            .execute(&self.db)
+            .await?;
+        sqlx::query!(r#"DELETE FROM extraction_cache WHERE expires_at <= NOW()"#)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    models::extraction::{Extraction, ExtractionWithFeedback, NewExtraction, StoredInput},
+    repositories::RepoResult
+};
+
+/// Abstraction for interacting with the `extractions` and `extraction_feedback` tables.
+#[derive(Clone, Debug)]
+pub struct ExtractionsRepository {
+    db: PgPool
+}
+
+impl ExtractionsRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Record an extraction, returning its id.
+    pub async fn create_extraction(&self, extraction: NewExtraction) -> RepoResult<Uuid> {
+        sqlx::query_scalar!(
+            r#"
+                INSERT INTO extractions
+                (
+                    user_id, timezone, locale, output_language, modality, input_hash, input_content_type, input_context,
+                    input, model, prompt_version, extraction_text, events
+                )
+                VALUES
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                RETURNING id
+            "#,
+            extraction.user_id,
+            extraction.timezone,
+            extraction.locale,
+            extraction.output_language,
+            extraction.modality.to_string(),
+            extraction.input_hash,
+            extraction.input_content_type,
+            extraction.input_context,
+            extraction.input,
+            extraction.model,
+            extraction.prompt_version,
+            extraction.extraction_text,
+            extraction.events
+        )
+            .fetch_one(&self.db)
+            .await
+    }
+
+    /// Drop the inputs of extractions made before `before` which no events were saved from.
+    pub async fn clear_unsaved_inputs(&self, before: DateTime<Utc>) -> RepoResult<()> {
+        sqlx::query!(
+            r#"
+                UPDATE extractions e
+                SET input = NULL
+                WHERE e.input IS NOT NULL
+                AND e.created_at < $1
+                AND NOT EXISTS (SELECT 1 FROM extraction_feedback f WHERE f.extraction_id = e.id)
+            "#,
+            before
+        )
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the owner of an extraction, if it exists.
+    pub async fn get_extraction_owner(&self, extraction_id: Uuid) -> RepoResult<Option<Uuid>> {
+        sqlx::query_scalar!(r#"SELECT user_id FROM extractions WHERE id = $1"#, extraction_id)
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    /// Get one of the user's extractions (without its input).
+    pub async fn get_extraction(&self, user_id: Uuid, extraction_id: Uuid) -> RepoResult<Option<Extraction>> {
+        sqlx::query_as!(
+            Extraction,
+            r#"
+                SELECT
+                    id, user_id, timezone, locale, output_language, modality as "modality: _", input_hash,
+                    input_content_type, input_context, input IS NOT NULL AS "input_stored!", model, prompt_version,
+                    extraction_text, events, created_at
+                FROM extractions
+                WHERE id = $1 AND user_id = $2
+            "#,
+            extraction_id,
+            user_id
+        )
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    /// Get the input of one of the user's extractions, if it was kept.
+    pub async fn get_input(&self, user_id: Uuid, extraction_id: Uuid) -> RepoResult<Option<StoredInput>> {
+        sqlx::query_as!(
+            StoredInput,
+            r#"
+                SELECT input_content_type AS "content_type!", input_context AS context, input AS "bytes!"
+                FROM extractions
+                WHERE id = $1 AND user_id = $2 AND input IS NOT NULL AND input_content_type IS NOT NULL
+            "#,
+            extraction_id,
+            user_id
+        )
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    /// Get an extraction (without its input), locking it until the transaction ends (so feedback on it is recorded 
+    /// one save at a time).
+    pub async fn lock_extraction_in(&self, conn: &mut PgConnection, extraction_id: Uuid) -> RepoResult<Extraction> {
+        sqlx::query_as!(
+            Extraction,
+            r#"
+                SELECT
+                    id, user_id, timezone, locale, output_language, modality as "modality: _", input_hash,
+                    input_content_type, input_context, input IS NOT NULL AS "input_stored!", model, prompt_version,
+                    extraction_text, events, created_at
+                FROM extractions
+                WHERE id = $1
+                FOR UPDATE
+            "#,
+            extraction_id
+        )
+            .fetch_one(conn)
+            .await
+    }
+
+    /// Get the (serialized) events saved from an extraction so far, if any.
+    pub async fn get_saved_events_in(&self, conn: &mut PgConnection, extraction_id: Uuid) -> RepoResult<Option<serde_json::Value>> {
+        sqlx::query_scalar!(
+            r#"SELECT saved FROM extraction_feedback WHERE extraction_id = $1"#,
+            extraction_id
+        )
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Set the (serialized) events saved from an extraction, and their diff against what was generated.
+    pub async fn put_feedback_in(
+        &self,
+        conn: &mut PgConnection,
+        extraction_id: Uuid,
+        user_id: Uuid,
+        saved: serde_json::Value,
+        diff: serde_json::Value
+    ) -> RepoResult<()> {
+        sqlx::query!(
+            r#"
+                INSERT INTO extraction_feedback
+                (extraction_id, user_id, saved, diff)
+                VALUES
+                ($1, $2, $3, $4)
+                ON CONFLICT (extraction_id) DO UPDATE
+                SET saved = EXCLUDED.saved,
+                diff = EXCLUDED.diff,
+                updated_at = NOW()
+            "#,
+            extraction_id,
+            user_id,
+            saved,
+            diff
+        )
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the user's extractions which have feedback, oldest first, optionally only those with feedback since `since`.
+    pub async fn fetch_with_feedback(&self, user_id: Uuid, since: Option<DateTime<Utc>>) -> RepoResult<Vec<ExtractionWithFeedback>> {
+        sqlx::query_as!(
+            ExtractionWithFeedback,
+            r#"
+                SELECT
+                    e.id,
+                    CASE WHEN e.modality = 'text' THEN convert_from(e.input, 'UTF8') END AS input_text,
+                    e.timezone, e.locale, e.output_language, e.events, e.created_at,
+                    f.saved, f.diff
+                FROM extractions e
+                JOIN extraction_feedback f ON f.extraction_id = e.id
+                WHERE e.user_id = $1 AND ($2::TIMESTAMPTZ IS NULL OR f.updated_at >= $2)
+                ORDER BY e.created_at
+            "#,
+            user_id,
+            since
+        )
+            .fetch_all(&self.db)
+            .await
+    }
+}
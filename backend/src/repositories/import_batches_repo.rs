@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    models::import_batch::{ImportBatch, ImportSource, UndoneBatch},
+    repositories::RepoResult
+};
+
+/// Abstraction for interacting with the `import_batches` table (and undoing them).
+#[derive(Clone, Debug)]
+pub struct ImportBatchesRepository {
+    db: PgPool
+}
+
+impl ImportBatchesRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Start a batch, returning its id for tagging what's created in it.
+    pub async fn create_batch(&self, user_id: Uuid, source: ImportSource, extraction_id: Option<Uuid>) -> RepoResult<Uuid> {
+        let mut conn = self.db.acquire().await?;
+        self.create_batch_in(&mut conn, user_id, source, extraction_id).await
+    }
+
+    /// Same as `create_batch`, but on the given connection (e.g. within a transaction).
+    pub async fn create_batch_in(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        source: ImportSource,
+        extraction_id: Option<Uuid>
+    ) -> RepoResult<Uuid> {
+        sqlx::query_scalar!(
+            r#"
+                INSERT INTO import_batches
+                (user_id, source, extraction_id)
+                VALUES
+                ($1, $2, $3)
+                RETURNING id
+            "#,
+            user_id,
+            source.to_string(),
+            extraction_id
+        )
+            .fetch_one(conn)
+            .await
+    }
+
+    /// Delete a batch if nothing was created in it.
+    pub async fn delete_batch_if_empty(&self, batch_id: Uuid) -> RepoResult<()> {
+        sqlx::query!(
+            r#"
+                DELETE FROM import_batches b
+                WHERE id = $1
+                AND NOT EXISTS (SELECT 1 FROM calendar_events WHERE import_batch_id = b.id)
+                AND NOT EXISTS (SELECT 1 FROM recurring_events WHERE import_batch_id = b.id)
+                AND NOT EXISTS (SELECT 1 FROM recurring_event_groups WHERE import_batch_id = b.id)
+            "#,
+            batch_id
+        )
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the user's most recent batches, newest first.
+    pub async fn fetch_recent_batches(&self, user_id: Uuid, limit: i64) -> RepoResult<Vec<ImportBatch>> {
+        sqlx::query_as!(
+            ImportBatch,
+            r#"
+                SELECT
+                    b.id,
+                    b.source as "source: _",
+                    b.extraction_id,
+                    b.created_at,
+                    b.undone_at,
+                    (
+                        SELECT COUNT(*) FROM calendar_events
+                        WHERE import_batch_id = b.id AND is_deleted = false
+                    ) AS "event_count!",
+                    (
+                        SELECT COUNT(*) FROM recurring_events
+                        WHERE import_batch_id = b.id AND is_deleted = false
+                    ) AS "recurring_event_count!",
+                    (
+                        SELECT COUNT(*) FROM recurring_event_groups
+                        WHERE import_batch_id = b.id
+                    ) AS "group_count!"
+                FROM import_batches b
+                WHERE b.user_id = $1
+                ORDER BY b.created_at DESC
+                LIMIT $2
+            "#,
+            user_id,
+            limit
+        )
+            .fetch_all(&self.db)
+            .await
+    }
+
+    /// Get when one of the user's batches was undone (`Some(None)` if it hasn't been), locking it until the 
+    /// transaction ends (so it's only undone once).
+    pub async fn lock_batch_in(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        batch_id: Uuid
+    ) -> RepoResult<Option<Option<DateTime<Utc>>>> {
+        sqlx::query_scalar!(
+            r#"SELECT undone_at FROM import_batches WHERE id = $1 AND user_id = $2 FOR UPDATE"#,
+            batch_id,
+            user_id
+        )
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Delete everything created in a batch, and mark it as undone.
+    /// 
+    /// Groups created in the batch are deleted along with all their events, including any added since.
+    pub async fn undo_batch_in(&self, conn: &mut PgConnection, batch_id: Uuid) -> RepoResult<UndoneBatch> {
+        let events = sqlx::query!(
+            r#"
+                UPDATE calendar_events
+                SET is_deleted = true, last_modified = NOW()
+                WHERE import_batch_id = $1 AND is_deleted = false
+            "#,
+            batch_id
+        )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+
+        let recurring_events = sqlx::query!(
+            r#"
+                UPDATE recurring_events
+                SET is_deleted = true, last_modified = NOW()
+                WHERE is_deleted = false
+                AND (
+                    import_batch_id = $1
+                    OR group_id IN (SELECT id FROM recurring_event_groups WHERE import_batch_id = $1)
+                )
+            "#,
+            batch_id
+        )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+
+        let groups = sqlx::query!(
+            r#"DELETE FROM recurring_event_groups WHERE import_batch_id = $1"#,
+            batch_id
+        )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+
+        sqlx::query!(
+            r#"UPDATE import_batches SET undone_at = NOW() WHERE id = $1"#,
+            batch_id
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(UndoneBatch { events, recurring_events, groups })
+    }
+}
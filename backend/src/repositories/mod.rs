@@ -1,11 +1,16 @@
-use sqlx::PgPool;
-use crate::repositories::{azure_token_repo::AzureTokensRepository, calendar_events_repo::CalendarEventsRepository, outlook_calendar_repo::OutlookCalendarRepository, recurring_event_groups_repo::RecurringEventGroupsRepository, recurring_events_repo::RecurringEventsRepository};
+use sqlx::{PgPool, Postgres, Transaction};
+use crate::repositories::{ai_usage_repo::AIUsageRepository, azure_token_repo::AzureTokensRepository, calendar_events_repo::CalendarEventsRepository, categorisation_rules_repo::CategorisationRulesRepository, extraction_cache_repo::ExtractionCacheRepository, extractions_repo::ExtractionsRepository, import_batches_repo::ImportBatchesRepository, outlook_calendar_repo::OutlookCalendarRepository, recurring_event_groups_repo::RecurringEventGroupsRepository, recurring_events_repo::RecurringEventsRepository};
 
+pub mod ai_usage_repo;
 pub mod calendar_events_repo;
+pub mod categorisation_rules_repo;
 pub mod recurring_event_groups_repo;
 pub mod recurring_events_repo;
 pub mod azure_token_repo;
 pub mod outlook_calendar_repo;
+pub mod extraction_cache_repo;
+pub mod extractions_repo;
+pub mod import_batches_repo;
 
 /// Repositories, or abstractions over the database.
 #[derive(Clone, Debug)]
@@ -14,7 +19,13 @@ pub struct Repositories {
     pub recurring_event_groups: RecurringEventGroupsRepository,
     pub recurring_events: RecurringEventsRepository,
     pub azure_tokens: AzureTokensRepository,
-    pub outlook_calendar: OutlookCalendarRepository
+    pub outlook_calendar: OutlookCalendarRepository,
+    pub ai_usage: AIUsageRepository,
+    pub extraction_cache: ExtractionCacheRepository,
+    pub extractions: ExtractionsRepository,
+    pub import_batches: ImportBatchesRepository,
+    pub categorisation_rules: CategorisationRulesRepository,
+    db: PgPool
 }
 
 impl Repositories {
@@ -25,9 +36,20 @@ impl Repositories {
             recurring_event_groups: RecurringEventGroupsRepository::new(db.clone()),
             recurring_events: RecurringEventsRepository::new(db.clone()),
             azure_tokens: AzureTokensRepository::new(db.clone()),
-            outlook_calendar: OutlookCalendarRepository::new(calendar_events.clone(), db.clone())
+            outlook_calendar: OutlookCalendarRepository::new(calendar_events.clone(), db.clone()),
+            ai_usage: AIUsageRepository::new(db.clone()),
+            extraction_cache: ExtractionCacheRepository::new(db.clone()),
+            extractions: ExtractionsRepository::new(db.clone()),
+            import_batches: ImportBatchesRepository::new(db.clone()),
+            categorisation_rules: CategorisationRulesRepository::new(db.clone()),
+            db
         }
     }
+
+    /// Begin a transaction, for repository methods which take a connection.
+    pub async fn begin(&self) -> RepoResult<Transaction<'static, Postgres>> {
+        self.db.begin().await
+    }
 }
 
 /// The result returned from a repository.
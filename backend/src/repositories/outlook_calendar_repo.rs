@@ -75,7 +75,9 @@ impl OutlookCalendarRepository {
         Ok(())
     }
 
-    pub async fn add_or_update_outlook_event(&self, user_id: Uuid, event: OutlookCalendarEvent) -> RepoResult<()> {
+    /// Update the event mapped to an Outlook event (and its organiser), or create it (in the given import batch) if there
+    /// isn't one.
+    pub async fn add_or_update_outlook_event(&self, user_id: Uuid, event: OutlookCalendarEvent, import_batch_id: Uuid) -> RepoResult<()> {
         let res = sqlx::query!(
             r#"SELECT local_event_id FROM outlook_event_mappings WHERE outlook_event_id = $1"#,
             event.id
@@ -83,7 +85,8 @@ impl OutlookCalendarRepository {
             .fetch_optional(&self.db)
             .await?;
         
-        if let Some(res) = res {
+        let organiser = event.organiser();
+        let local_event_id = if let Some(res) = res {
             let local_event_id = res.local_event_id;
             let updated_event = UpdatedCalendarEvent {
                 id: local_event_id,
@@ -96,6 +99,7 @@ impl OutlookCalendarRepository {
             self.calendar_repo
                 .update_event(updated_event)
                 .await?;
+            local_event_id
         } 
         else {
             let local_event = NewCalendarEvent {
@@ -106,7 +110,7 @@ impl OutlookCalendarRepository {
                 end_time: event.end.to_utc(),
             };
             let local_event_id = self.calendar_repo
-                .create_events(user_id, vec![local_event])
+                .create_events(user_id, vec![local_event], None, import_batch_id)
                 .await?[0];
             sqlx::query!(
                 r#"
@@ -120,7 +124,11 @@ impl OutlookCalendarRepository {
             )
                 .execute(&self.db)
                 .await?;
-        } 
+            local_event_id
+        };
+        self.calendar_repo
+            .set_organiser(local_event_id, organiser)
+            .await?;
         
         Ok(())
     }
@@ -1,4 +1,4 @@
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 use crate::{models::{
     recurring_event::RecurringEvent,
@@ -27,11 +27,12 @@ impl RecurringEventGroupsRepository {
                     g.group_is_active,
                     g.group_recurrence_start,
                     g.group_recurrence_end,
+                    g.extraction_id,
                     COALESCE(COUNT(e.id), 0) as event_count
                 FROM recurring_event_groups g
                 LEFT JOIN recurring_events e ON g.id = e.group_id
                 WHERE g.user_id = $1
-                GROUP BY g.id, g.user_id, g.name, g.description, g.color, g.group_is_active, g.group_recurrence_start, g.group_recurrence_end
+                GROUP BY g.id, g.user_id, g.name, g.description, g.color, g.group_is_active, g.group_recurrence_start, g.group_recurrence_end, g.extraction_id
                 ORDER BY g.name
             "#,
             user_id
@@ -51,6 +52,7 @@ impl RecurringEventGroupsRepository {
                     group_is_active: row.group_is_active,
                     group_recurrence_start: row.group_recurrence_start,
                     group_recurrence_end: row.group_recurrence_end,
+                    extraction_id: row.extraction_id,
                 },
                 event_count: row.event_count.unwrap_or(0) as usize,
             })
@@ -87,11 +89,12 @@ impl RecurringEventGroupsRepository {
                     g.group_is_active,
                     g.group_recurrence_start,
                     g.group_recurrence_end,
+                    g.extraction_id,
                     COALESCE(COUNT(e.id), 0) as event_count
                 FROM recurring_event_groups g
                 LEFT JOIN recurring_events e ON g.id = e.group_id
                 WHERE g.user_id = $1 AND g.id = $2 AND is_deleted = false
-                GROUP BY g.id, g.user_id, g.name, g.description, g.color, g.group_is_active, g.group_recurrence_start, g.group_recurrence_end
+                GROUP BY g.id, g.user_id, g.name, g.description, g.color, g.group_is_active, g.group_recurrence_start, g.group_recurrence_end, g.extraction_id
             "#,
             user_id,
             group_id
@@ -109,6 +112,7 @@ impl RecurringEventGroupsRepository {
                 group_is_active: row.group_is_active,
                 group_recurrence_start: row.group_recurrence_start,
                 group_recurrence_end: row.group_recurrence_end,
+                extraction_id: row.extraction_id,
             },
             event_count: row.event_count.unwrap_or(0) as usize,
         })
@@ -136,13 +140,26 @@ impl RecurringEventGroupsRepository {
         Ok(())
     }
 
-    pub async fn create_group_returning_id(&self, user_id: Uuid, new_group: &NewRecurringEventGroup) -> RepoResult<Uuid> {
+    /// Create a group on the given connection (e.g. within a transaction), returning its ID.
+    /// 
+    /// It can optionally reference the AI extraction it was saved from.
+    pub async fn create_group_returning_id(
+        &self, 
+        conn: &mut PgConnection, 
+        user_id: Uuid, 
+        new_group: &NewRecurringEventGroup,
+        extraction_id: Option<Uuid>,
+        import_batch_id: Uuid
+    ) -> RepoResult<Uuid> {
         let row = sqlx::query!(
             r#"
                 INSERT INTO recurring_event_groups 
-                (user_id, name, description, color, group_is_active, group_recurrence_start, group_recurrence_end)
+                (
+                    user_id, name, description, color, group_is_active, group_recurrence_start, group_recurrence_end,
+                    extraction_id, import_batch_id
+                )
                 VALUES 
-                ($1, $2, $3, $4, $5, $6, $7)
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9)
                 RETURNING id
             "#,
             user_id,
@@ -151,9 +168,11 @@ impl RecurringEventGroupsRepository {
             new_group.color as i64,
             new_group.group_is_active,
             new_group.group_recurrence_start,
-            new_group.group_recurrence_end
+            new_group.group_recurrence_end,
+            extraction_id,
+            import_batch_id
         )
-        .fetch_one(&self.db)
+        .fetch_one(conn)
         .await?;
 
         Ok(row.id)
@@ -238,6 +257,7 @@ impl RecurringEventGroupsRepository {
                     recurrence_start, 
                     recurrence_end, 
                     rrule as "rrule: _",
+                    extraction_id,
                     created_at,
                     last_modified
                 FROM recurring_events
@@ -251,6 +271,22 @@ impl RecurringEventGroupsRepository {
         Ok(events)
     }
 
+    /// Fetch the titles and locations of the (non-deleted) events in all of the user's groups.
+    pub async fn fetch_grouped_event_summaries(&self, user_id: Uuid) -> RepoResult<Vec<GroupedEventSummary>> {
+        sqlx::query_as!(
+            GroupedEventSummary,
+            r#"
+                SELECT e.group_id AS "group_id!", e.title, e.location
+                FROM recurring_events e
+                JOIN recurring_event_groups g ON e.group_id = g.id
+                WHERE g.user_id = $1 AND e.is_deleted = false
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
     pub async fn fetch_ungrouped_events(&self, user_id: Uuid) -> RepoResult<Vec<RecurringEvent>> {
         let events = sqlx::query_as!(
             RecurringEvent,
@@ -267,6 +303,7 @@ impl RecurringEventGroupsRepository {
                     recurrence_start, 
                     recurrence_end, 
                     rrule as "rrule: _",
+                    extraction_id,
                     created_at,
                     last_modified
                 FROM recurring_events
@@ -322,6 +359,14 @@ pub struct GroupWithCount {
     pub event_count: usize,
 }
 
+/// The title and location of an event in a group.
+#[derive(Debug)]
+pub struct GroupedEventSummary {
+    pub group_id: Uuid,
+    pub title: String,
+    pub location: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct EventInfo {
     pub id: Uuid,
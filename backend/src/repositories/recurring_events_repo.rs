@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use crate::{models::{
@@ -17,10 +17,13 @@ impl RecurringEventsRepository {
     }
 
     pub async fn validate_group_ownership(&self, user_id: Uuid, group_ids: &[Option<Uuid>]) -> RepoResult<bool> {
+        // dedupe, as many events can share a group
         let requested_group_ids: Vec<_> = group_ids
             .iter()
             .filter(|&g| g.is_some())
             .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
             .collect();
 
         if requested_group_ids.is_empty() {
@@ -41,7 +44,18 @@ impl RecurringEventsRepository {
         Ok(authorized_groups.len() == requested_group_ids.len())
     }
 
-    pub async fn bulk_create_events(&self, events: &[NewRecurringEvent], user_id: Uuid) -> RepoResult<()> {
+    /// Create events, optionally in an import batch and referencing the AI extraction they were saved from, returning
+    /// their ids in the same order as `events`.
+    pub async fn bulk_create_events_in(
+        &self, 
+        conn: &mut PgConnection, 
+        events: &[NewRecurringEvent], 
+        user_id: Uuid,
+        extraction_id: Option<Uuid>,
+        import_batch_id: Option<Uuid>
+    ) -> RepoResult<Vec<Uuid>> {
+        // the ids are made here, as `returning` doesn't guarantee the rows come back in the order they were given
+        let event_ids: Vec<_> = events.iter().map(|_| Uuid::new_v4()).collect();
         let mut group_ids = Vec::with_capacity(events.len());
         let user_ids = vec![user_id; events.len()];
         let mut titles = Vec::with_capacity(events.len());
@@ -66,10 +80,14 @@ impl RecurringEventsRepository {
         sqlx::query!(
             r#"
                 insert into recurring_events
-                (group_id, user_id, title, description, location, event_duration_seconds, recurrence_start, recurrence_end, rrule)
-                select * from unnest
-                ($1::uuid[], $2::uuid[], $3::varchar[], $4::varchar[], $5::varchar[], $6::int[], $7::timestamptz[], $8::timestamptz[], $9::varchar[])
+                (
+                    id, group_id, user_id, title, description, location, event_duration_seconds, recurrence_start, recurrence_end, rrule,
+                    extraction_id, import_batch_id
+                )
+                select *, $11, $12 from unnest
+                ($1::uuid[], $2::uuid[], $3::uuid[], $4::varchar[], $5::varchar[], $6::varchar[], $7::int[], $8::timestamptz[], $9::timestamptz[], $10::varchar[])
             "#,
+            &event_ids[..],
             &group_ids[..] as &[Option<Uuid>],
             &user_ids[..],
             &titles[..],
@@ -78,12 +96,14 @@ impl RecurringEventsRepository {
             &durations[..],
             &recurrence_starts[..],
             &recurrence_ends[..] as &[Option<DateTime<Utc>>],
-            &rrules[..]
+            &rrules[..],
+            extraction_id,
+            import_batch_id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
 
-        Ok(())
+        Ok(event_ids)
     }
 
     pub async fn fetch_active_events_in_period(
@@ -107,6 +127,7 @@ impl RecurringEventsRepository {
                     re.recurrence_end, 
                     re.event_duration_seconds as "event_duration_seconds: _", 
                     re.rrule as "rrule: _",
+                    re.extraction_id,
                     re.created_at,
                     re.last_modified
                 FROM recurring_events re
@@ -127,6 +148,38 @@ impl RecurringEventsRepository {
         Ok(events)
     }
 
+    /// Fetch one of the user's (non-deleted) events by its ID.
+    pub async fn fetch_event(&self, user_id: Uuid, event_id: Uuid) -> RepoResult<Option<RecurringEvent>> {
+        let event = sqlx::query_as!(
+            RecurringEvent,
+            r#"
+                SELECT 
+                    id, 
+                    group_id, 
+                    user_id,
+                    is_active, 
+                    title, 
+                    description, 
+                    location, 
+                    recurrence_start, 
+                    recurrence_end, 
+                    event_duration_seconds as "event_duration_seconds: _", 
+                    rrule as "rrule: _",
+                    extraction_id,
+                    created_at,
+                    last_modified
+                FROM recurring_events
+                WHERE id = $1 AND user_id = $2 AND is_deleted = false
+            "#,
+            event_id,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(event)
+    }
+
     pub async fn fetch_exceptions_for_events(&self, event_ids: &[Uuid]) -> RepoResult<Vec<RecurringEventException>> {
         let exceptions = sqlx::query_as!(
             RecurringEventException,
@@ -158,7 +211,9 @@ impl RecurringEventsRepository {
         let groups = sqlx::query_as!(
             RecurringEventGroup,
             r#"
-                SELECT *
+                SELECT
+                    id, user_id, name, description, color, group_is_active, group_recurrence_start,
+                    group_recurrence_end, extraction_id
                 FROM recurring_event_groups
                 WHERE id = ANY($1)
             "#,
@@ -182,6 +237,12 @@ impl RecurringEventsRepository {
     }
 
     pub async fn update_event(&self, updated_event: UpdatedRecurringEvent) -> RepoResult<()> {
+        let mut conn = self.db.acquire().await?;
+        self.update_event_in(&mut conn, updated_event).await
+    }
+
+    /// Same as `update_event`, but on the given connection (e.g. within a transaction).
+    pub async fn update_event_in(&self, conn: &mut PgConnection, updated_event: UpdatedRecurringEvent) -> RepoResult<()> {
         sqlx::query!(
             r#"
                 update recurring_events
@@ -207,7 +268,7 @@ impl RecurringEventsRepository {
             updated_event.rrule as ValidatedRRule,
             updated_event.id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
 
         Ok(())
@@ -230,11 +291,17 @@ impl RecurringEventsRepository {
     }
 
     pub async fn delete_event(&self, event_id: Uuid) -> RepoResult<()> {
+        let mut conn = self.db.acquire().await?;
+        self.delete_event_in(&mut conn, event_id).await
+    }
+
+    /// Same as `delete_event`, but on the given connection (e.g. within a transaction).
+    pub async fn delete_event_in(&self, conn: &mut PgConnection, event_id: Uuid) -> RepoResult<()> {
         sqlx::query!(
             r#"UPDATE recurring_events SET is_deleted = true where id = $1"#,
             event_id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
 
         Ok(())
@@ -258,6 +325,16 @@ impl RecurringEventsRepository {
     }
 
     pub async fn create_event_exception(&self, exception: NewRecurringEventException) -> RepoResult<()> {
+        let mut conn = self.db.acquire().await?;
+        self.create_event_exception_in(&mut conn, exception).await
+    }
+
+    /// Same as `create_event_exception`, but on the given connection (e.g. within a transaction).
+    pub async fn create_event_exception_in(
+        &self, 
+        conn: &mut PgConnection, 
+        exception: NewRecurringEventException
+    ) -> RepoResult<()> {
         sqlx::query!(
             r#"
                 INSERT INTO recurring_event_exceptions (
@@ -281,7 +358,55 @@ impl RecurringEventsRepository {
             exception.modified_start_time,
             exception.modified_end_time
         )
-        .execute(&self.db)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create exceptions on the given connection (e.g. within a transaction).
+    pub async fn bulk_create_event_exceptions(
+        &self, 
+        conn: &mut PgConnection, 
+        exceptions: &[NewRecurringEventException]
+    ) -> RepoResult<()> {
+        let mut event_ids = Vec::with_capacity(exceptions.len());
+        let mut exception_dates = Vec::with_capacity(exceptions.len());
+        let mut exception_types = Vec::with_capacity(exceptions.len());
+        let mut titles = Vec::with_capacity(exceptions.len());
+        let mut descriptions = Vec::with_capacity(exceptions.len());
+        let mut locations = Vec::with_capacity(exceptions.len());
+        let mut start_times = Vec::with_capacity(exceptions.len());
+        let mut end_times = Vec::with_capacity(exceptions.len());
+
+        for exception in exceptions {
+            event_ids.push(exception.recurring_event_id);
+            exception_dates.push(exception.exception_date);
+            exception_types.push(exception.exception_type.to_string());
+            titles.push(exception.modified_title.clone());
+            descriptions.push(exception.modified_description.clone());
+            locations.push(exception.modified_location.clone().flatten());
+            start_times.push(exception.modified_start_time);
+            end_times.push(exception.modified_end_time);
+        }
+
+        sqlx::query!(
+            r#"
+                insert into recurring_event_exceptions
+                (recurring_event_id, exception_date, exception_type, modified_title, modified_description, modified_location, modified_start_time, modified_end_time)
+                select * from unnest
+                ($1::uuid[], $2::timestamptz[], $3::varchar[], $4::varchar[], $5::varchar[], $6::varchar[], $7::timestamptz[], $8::timestamptz[])
+            "#,
+            &event_ids[..],
+            &exception_dates[..],
+            &exception_types[..],
+            &titles[..] as &[Option<String>],
+            &descriptions[..] as &[Option<String>],
+            &locations[..] as &[Option<String>],
+            &start_times[..] as &[Option<DateTime<Utc>>],
+            &end_times[..] as &[Option<DateTime<Utc>>]
+        )
+        .execute(conn)
         .await?;
 
         Ok(())
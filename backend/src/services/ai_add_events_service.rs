@@ -1,40 +1,840 @@
-use std::io::Cursor;
-
+use std::{collections::HashSet, sync::Arc};
 use axum::body::Bytes;
-use image::{ImageFormat, ImageReader};
+use chrono::{DateTime, Duration, Utc};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageReader};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    api::{ai_upload::{UploadBody, UploadedFile}, error::{ApiError, ApiResult}},
+    config::{AICacheConfig, UploadLimitsConfig},
+    llm::{context::{CalendarContext, ExistingEvent}, prompts::PromptContext, ExistingEventKind, Generated, GeneratedEvents, LLM},
+    models::{
+        ai_usage::{AIUsageKind, TokenUsage},
+        calendar_event::NewCalendarEvent,
+        extraction::{ExtractionModality, ExtractionSource},
+        language::LanguageTag,
+        recurring_event::{NewRecurringEvent, NewRecurringEventWithExceptions},
+        recurring_event_exception::{ExceptionType, NewSeriesException},
+        time::{Second, UserTimezone}
+    },
+    repositories::Repositories,
+    services::{
+        ai_usage_service::AIUsageService,
+        extractions_service::ExtractionsService,
+        recurrence_builder_service::resolve_recurrence,
+        recurring_events_service::{EventsQuery, RecurringEventsService}
+    },
+    utils::{
+        audio::{transcode_to_wav, AudioFormat},
+        email::{is_msg, parse_email, AttachmentKind, ParsedEmail},
+        ical::{is_icalendar, parse_icalendar, ICalendarEvent, ICALENDAR_VERSION},
+        barcode::decode_barcodes,
+        quick_add::{parse_quick_add, QuickAddEvent, QUICK_ADD_VERSION}
+    }
+};
+
+/// We won't downscale images past this to make them fit; the text would become unreadable.
+const MIN_IMAGE_DIMENSION: u32 = 768;
+
+/// How far ahead we look for existing events to give the LLM as context.
+const CONTEXT_EVENTS_WEEKS: i64 = 8;
+
+/// How far past its start we expand a generated recurring event when looking for duplicates.
+const DUPLICATE_RECURRENCE_WEEKS: i64 = 5;
+
+/// How far apart two events' starts can be while still being considered the same event.
+const DUPLICATE_START_TOLERANCE_MINUTES: i64 = 30;
+
+/// The confidence given to events from the quick-add parser, which only answers when it understood every word.
+const QUICK_ADD_CONFIDENCE: f32 = 0.95;
+
+/// The confidence given to events read from iCalendar data, which says exactly what they are.
+const ICALENDAR_CONFIDENCE: f32 = 1.0;
+
+/// Images attached to an email smaller than this on either side (like logos, icons and tracking pixels) are skipped.
+const MIN_EMAIL_IMAGE_DIMENSION: u32 = 200;
+
+/// The most image and PDF attachments of an email we extract events from.
+const MAX_EMAIL_ATTACHMENTS: usize = 5;
+
+/// How to run an extraction, regardless of the input's modality.
+pub struct ExtractionOptions {
+    pub timezone: UserTimezone,
+    /// What's taken to be the current time, which relative dates (like "tomorrow") are resolved against; this is
+    /// usually when the request was made.
+    pub now: DateTime<Utc>,
+    /// Whether to give the LLM the user's existing groups and upcoming events, and mark likely duplicates.
+    pub use_calendar_context: bool,
+    /// Whether to return the LLM's intermediate extraction text alongside the events.
+    pub include_extraction_text: bool,
+    /// Whether to skip looking in the cache; the fresh result is still cached.
+    pub bypass_cache: bool,
+    /// Used to interpret ambiguous dates.
+    pub locale: Option<LanguageTag>,
+    /// The language to write titles, descriptions and locations in; by default, the input's language is kept.
+    pub output_language: Option<LanguageTag>,
+    pub progress: ExtractionProgress
+}
+
+impl ExtractionOptions {
+    /// What to fill the prompts in with.
+    fn prompt_context<'a>(&'a self, calendar_context: Option<&'a CalendarContext>) -> PromptContext<'a> {
+        PromptContext {
+            now: self.now,
+            timezone: &self.timezone,
+            calendar_context,
+            locale: self.locale.as_ref(),
+            output_language: self.output_language.as_ref()
+        }
+    }
+}
+
+/// The stages of an extraction, in the order they happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtractionStage {
+    /// Preparing the input and extracting the events from it as a string.
+    Extracting,
+    /// Parsing the extracted string into events.
+    Parsing
+}
 
-use crate::{api::error::{ApiError, ApiResult}, llm::{GeneratedEvents, LLM}};
+/// Somewhere to report an extraction's progress to; by default, it goes nowhere.
+#[derive(Clone, Default)]
+pub struct ExtractionProgress(Option<Arc<dyn Fn(ExtractionStage) + Send + Sync>>);
+
+impl ExtractionProgress {
+    pub fn new(report: impl Fn(ExtractionStage) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(report)))
+    }
+
+    /// Report that the extraction has reached `stage`.
+    pub fn report(&self, stage: ExtractionStage) {
+        if let Some(report) = &self.0 {
+            report(stage);
+        }
+    }
+}
+
+/// An email's attachment, prepared to extract events from.
+enum PreparedAttachment {
+    Image { name: String, jpg_bytes: Vec<u8> },
+    Pdf { name: String, pdf_bytes: Vec<u8> }
+}
 
 /// Handles business logic for generating events using AI/LLMs.
 #[derive(Clone, Debug)]
 pub struct AIAddEventsService {
-    llm: LLM
+    llm: LLM,
+    repositories: Repositories,
+    recurring_events: RecurringEventsService,
+    usage: AIUsageService,
+    extractions: ExtractionsService,
+    /// How long extractions are cached for.
+    cache_ttl: Duration
 }
 
 impl AIAddEventsService {
-    pub fn new(llm: LLM) -> Self {
+    pub fn new(
+        llm: LLM, 
+        repositories: Repositories, 
+        recurring_events: RecurringEventsService, 
+        usage: AIUsageService,
+        extractions: ExtractionsService,
+        cache_config: &AICacheConfig
+    ) -> Self {
         Self {
-            llm
+            llm,
+            repositories,
+            recurring_events,
+            usage,
+            extractions,
+            cache_ttl: Duration::seconds(cache_config.ttl_seconds.try_into().unwrap_or(i64::MAX))
         }
     }
 
-    pub async fn generate_from_text(&self, text: String, timezone_offset_minutes: i32) -> ApiResult<GeneratedEvents> {
-        let events = self.llm
-            .events_from_text(text, timezone_offset_minutes)
+    /// Generate events from text.
+    /// 
+    /// Simple single events (e.g. "Dentist tomorrow 3pm for 45 min at Main St") are parsed deterministically where
+    /// possible (see `parse_quick_add`), without the LLM; they don't count towards the user's quota, and still work
+    /// while the LLM is unavailable.
+    /// 
+    /// If `options.use_calendar_context` is set, the user's existing groups and upcoming events are given to the LLM,
+    /// and any generated event matching an existing one is marked as a likely duplicate.
+    /// 
+    /// Results are cached by their input (see `cache_key`), unless `options.bypass_cache` is set.
+    pub async fn generate_from_text(
+        &self, 
+        user_id: Uuid,
+        text: String, 
+        options: ExtractionOptions
+    ) -> ApiResult<GeneratedEvents> {
+        options.progress.report(ExtractionStage::Extracting);
+        let source = ExtractionSource::text(&text);
+        if let Some(events) = Self::quick_add(&text, &options) {
+            tracing::debug!("Quick-added events from text, without the LLM");
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let calendar_context = self.calendar_context(user_id, &options).await?;
+        let cache_key = self.cache_key(user_id, &[b"text", normalize_text(&text).as_bytes()], &options, calendar_context.as_ref());
+        if let Some(events) = self.cached_extraction(&cache_key, &options).await {
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let mut meter = self.usage.start(user_id, AIUsageKind::Text).await?;
+        let extracted = self.llm
+            .extract_from_text(text, &options.prompt_context(calendar_context.as_ref()), &mut meter.tokens)
             .await?;
-        Ok(events)
+        let events = self.parse_extraction(user_id, extracted, &options, calendar_context.as_ref(), &mut meter.tokens, &cache_key).await?;
+        self.finish_extraction(user_id, events, &options, &source).await
+    }
+
+    pub async fn generate_from_audio(
+        &self,
+        user_id: Uuid,
+        audio: UploadedFile,
+        context: Option<String>,
+        options: ExtractionOptions,
+        limits: &UploadLimitsConfig
+    ) -> ApiResult<GeneratedEvents> {
+        options.progress.report(ExtractionStage::Extracting);
+        // reading/transcoding is blocking, so do it off the async runtime
+        let max_inline_bytes = limits.max_inline_bytes;
+        let (audio_bytes, mime_type) = tokio::task::spawn_blocking(move || {
+            Self::prepare_audio(audio.body, audio.content_type, max_inline_bytes)
+        })
+            .await
+            .map_err(|err| ApiError::Internal(format!("Audio processing task failed: {err}")))??;
+
+        let calendar_context = self.calendar_context(user_id, &options).await?;
+        let cache_key = self.cache_key(
+            user_id, 
+            &[b"audio", mime_type.as_bytes(), &audio_bytes, context.as_deref().unwrap_or_default().as_bytes()], 
+            &options, 
+            calendar_context.as_ref()
+        );
+        let source = ExtractionSource {
+            modality: ExtractionModality::Audio,
+            content_type: mime_type.into(),
+            bytes: audio_bytes.clone(),
+            context: context.clone()
+        };
+        if let Some(events) = self.cached_extraction(&cache_key, &options).await {
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let mut meter = self.usage.start(user_id, AIUsageKind::Audio).await?;
+        let extracted = self.llm
+            .extract_from_audio(&audio_bytes, mime_type, context, &options.prompt_context(calendar_context.as_ref()), &mut meter.tokens)
+            .await?;
+        let events = self.parse_extraction(user_id, extracted, &options, calendar_context.as_ref(), &mut meter.tokens, &cache_key).await?;
+        self.finish_extraction(user_id, events, &options, &source).await
     }
 
-    pub async fn generate_from_audio(&self, audio_bytes: Bytes, timezone_offset_minutes: i32) -> ApiResult<GeneratedEvents> {
-        let events = self.llm
-            .events_from_audio(&audio_bytes, timezone_offset_minutes)
+    /// Generate events from an image.
+    /// 
+    /// The image is first scanned for QR codes and barcodes. Events encoded in them (as iCalendar data, like on many
+    /// posters and tickets) are read directly, without the LLM or counting towards the user's quota; other codes (like a
+    /// link, contact details or a booking reference) are given to the LLM along with the image.
+    pub async fn generate_from_image(
+        &self,
+        user_id: Uuid,
+        image: UploadBody,
+        context: Option<String>,
+        options: ExtractionOptions,
+        limits: &UploadLimitsConfig
+    ) -> ApiResult<GeneratedEvents> {
+        options.progress.report(ExtractionStage::Extracting);
+        // decoding/scanning/re-encoding is blocking, so do it off the async runtime
+        let limits = limits.clone();
+        let (jpg_bytes, codes) = tokio::task::spawn_blocking(move || {
+            let image = Self::decode_image(image)?;
+            let codes = decode_barcodes(&image.to_luma8());
+            Ok::<_, ApiError>((Self::encode_image(&image, &limits)?, codes))
+        })
+            .await
+            .map_err(|err| ApiError::Internal(format!("Image processing task failed: {err}")))??;
+        let source = ExtractionSource {
+            modality: ExtractionModality::Image,
+            content_type: "image/jpeg".into(),
+            bytes: jpg_bytes.clone().into(),
+            context: context.clone()
+        };
+
+        let (events, codes): (Vec<String>, Vec<String>) = codes.into_iter().partition(|code| is_icalendar(code));
+        if let Some(events) = Self::icalendar_events(events.iter().map(String::as_str), &options, "QR code or barcode") {
+            tracing::debug!("Read events from a code in the image, without the LLM");
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+        // Wi-Fi credentials are no use to the LLM, and shouldn't be sent to it
+        let codes: Vec<String> = codes.into_iter().filter(|code| !code.starts_with("WIFI:")).collect();
+
+        // then request the LLM
+        let calendar_context = self.calendar_context(user_id, &options).await?;
+        let cache_key = self.cache_key(
+            user_id, 
+            &[b"image", &jpg_bytes, context.as_deref().unwrap_or_default().as_bytes()], 
+            &options, 
+            calendar_context.as_ref()
+        );
+        if let Some(events) = self.cached_extraction(&cache_key, &options).await {
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let mut meter = self.usage.start(user_id, AIUsageKind::Image).await?;
+        let extracted = self.llm
+            .extract_from_image(&jpg_bytes, context, &codes, &options.prompt_context(calendar_context.as_ref()), &mut meter.tokens)
+            .await?;
+        let events = self.parse_extraction(user_id, extracted, &options, calendar_context.as_ref(), &mut meter.tokens, &cache_key).await?;
+        self.finish_extraction(user_id, events, &options, &source).await
+    }
+
+    /// Generate events from an email, either a raw RFC 822 message (`.eml`) or an Outlook `.msg` file.
+    /// 
+    /// Events in calendar attachments (like a meeting invite's) are read directly, without the LLM or counting towards
+    /// the user's quota. Otherwise, events are extracted from the headers and body, and any image or PDF attachments,
+    /// then parsed together, so an event both described in the body and on an attached flyer is only returned once.
+    pub async fn generate_from_email(
+        &self,
+        user_id: Uuid,
+        email: UploadBody,
+        context: Option<String>,
+        options: ExtractionOptions,
+        limits: &UploadLimitsConfig
+    ) -> ApiResult<GeneratedEvents> {
+        options.progress.report(ExtractionStage::Extracting);
+        // parsing and preparing the attachments is blocking, so do it off the async runtime
+        let attachment_limits = limits.clone();
+        let (email_bytes, email, content_type, attachments) = tokio::task::spawn_blocking(move || {
+            let email_bytes = email.into_bytes().map_err(Self::read_error)?;
+            let parsed = parse_email(&email_bytes)
+                .ok_or_else(|| ApiError::UnsupportedMediaType("Unable to read the email".into()))?;
+            let content_type = if is_msg(&email_bytes) {
+                "application/vnd.ms-outlook"
+            } else {
+                "message/rfc822"
+            };
+            let attachments = Self::prepare_email_attachments(&parsed, &attachment_limits);
+            Ok::<_, ApiError>((email_bytes, parsed, content_type, attachments))
+        })
+            .await
+            .map_err(|err| ApiError::Internal(format!("Email processing task failed: {err}")))??;
+        let source = ExtractionSource {
+            modality: ExtractionModality::Email,
+            content_type: content_type.into(),
+            bytes: email_bytes.clone(),
+            context: context.clone()
+        };
+
+        if let Some(events) = Self::email_calendar_events(&email, &options) {
+            tracing::debug!("Read events from an email's calendar attachment, without the LLM");
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let calendar_context = self.calendar_context(user_id, &options).await?;
+        let cache_key = self.cache_key(
+            user_id, 
+            &[b"email", &email_bytes, context.as_deref().unwrap_or_default().as_bytes()], 
+            &options, 
+            calendar_context.as_ref()
+        );
+        if let Some(events) = self.cached_extraction(&cache_key, &options).await {
+            return self.finish_extraction(user_id, events, &options, &source).await;
+        }
+
+        let mut meter = self.usage.start(user_id, AIUsageKind::Email).await?;
+        let prompt_context = options.prompt_context(calendar_context.as_ref());
+        let mut email_text = email.text();
+        truncate_to_char_boundary(&mut email_text, limits.max_text_bytes);
+        let mut extracted = vec![format!(
+            "From the email:\n{}",
+            self.llm.extract_from_email(email_text, context.clone(), &prompt_context, &mut meter.tokens).await?
+        )];
+        for attachment in attachments {
+            let (name, attachment_extracted) = match attachment {
+                PreparedAttachment::Image { name, jpg_bytes } => (name, self.llm
+                    .extract_from_image(&jpg_bytes, context.clone(), &[], &prompt_context, &mut meter.tokens)
+                    .await?),
+                PreparedAttachment::Pdf { name, pdf_bytes } => (name, self.llm
+                    .extract_from_pdf(&pdf_bytes, context.clone(), &prompt_context, &mut meter.tokens)
+                    .await?)
+            };
+            extracted.push(format!("From the attachment `{name}`:\n{attachment_extracted}"));
+        }
+        let events = self.parse_extraction(
+            user_id, 
+            extracted.join("\n\n"), 
+            &options, 
+            calendar_context.as_ref(), 
+            &mut meter.tokens, 
+            &cache_key
+        ).await?;
+        self.finish_extraction(user_id, events, &options, &source).await
+    }
+
+    /// Re-run one of the user's extractions from its kept input, with the current model and prompts.
+    /// 
+    /// The extraction is run with the same timezone, locale and output language, is never served from the cache, and
+    /// is recorded as a new extraction.
+    pub async fn rerun_extraction(
+        &self,
+        user_id: Uuid,
+        extraction_id: Uuid,
+        use_calendar_context: bool,
+        include_extraction_text: bool,
+        limits: &UploadLimitsConfig
+    ) -> ApiResult<GeneratedEvents> {
+        let extraction = self.extractions.get_extraction(user_id, extraction_id).await?;
+        let Some(modality) = extraction.modality else {
+            return Err(ApiError::BadRequest("This extraction was made before its input was recorded".into()));
+        };
+        let input = self.extractions.get_input(user_id, extraction_id).await?;
+
+        let options = ExtractionOptions {
+            timezone: extraction.timezone
+                .parse()
+                .map_err(|err| ApiError::Internal(format!("Extraction {extraction_id} has an invalid timezone: {err}")))?,
+            now: Utc::now(),
+            use_calendar_context,
+            include_extraction_text,
+            bypass_cache: true,
+            locale: extraction.locale.as_deref().and_then(LanguageTag::parse),
+            output_language: extraction.output_language.as_deref().and_then(LanguageTag::parse),
+            progress: ExtractionProgress::default()
+        };
+        match modality {
+            ExtractionModality::Text => {
+                let text = String::from_utf8(input.bytes)
+                    .map_err(|_| ApiError::Internal(format!("Extraction {extraction_id} has a non-UTF-8 text input")))?;
+                self.generate_from_text(user_id, text, options).await
+            },
+            ExtractionModality::Audio => {
+                let audio = UploadedFile { body: UploadBody::Memory(input.bytes.into()), content_type: Some(input.content_type) };
+                self.generate_from_audio(user_id, audio, input.context, options, limits).await
+            },
+            ExtractionModality::Image => {
+                let image = UploadBody::Memory(input.bytes.into());
+                self.generate_from_image(user_id, image, input.context, options, limits).await
+            },
+            ExtractionModality::Email => {
+                let email = UploadBody::Memory(input.bytes.into());
+                self.generate_from_email(user_id, email, input.context, options, limits).await
+            }
+        }
+    }
+
+    /// Parse the text as a single simple event, returning `None` unless the parser is confident of it.
+    /// 
+    /// The parser only understands English, and can't translate, so it isn't tried when an output language is asked for.
+    fn quick_add(text: &str, options: &ExtractionOptions) -> Option<GeneratedEvents> {
+        if options.output_language.is_some() {
+            return None;
+        }
+        let timezone = options.timezone;
+        let QuickAddEvent { title, location, start, end, recurrence } = parse_quick_add(text, timezone.utc_to_local(options.now).naive_local())?;
+        fn generated<T>(item: T, text: &str) -> Generated<T> {
+            Generated { item, confidence: QUICK_ADD_CONFIDENCE, source_snippet: text.trim().into(), likely_duplicate_of: None }
+        }
+
+        let mut events = GeneratedEvents {
+            events: Vec::new(),
+            recurring_events: Vec::new(),
+            recurring_event_groups: Vec::new(),
+            extraction_text: None,
+            prompt_version: QUICK_ADD_VERSION.into(),
+            extraction_id: None
+        };
+        match recurrence {
+            None => events.events.push(generated(NewCalendarEvent {
+                title,
+                description: None,
+                location,
+                start_time: timezone.local_to_utc(start),
+                end_time: timezone.local_to_utc(end)
+            }, text)),
+            Some(recurrence) => {
+                // any rule the resolver has caveats about is better left to the LLM
+                let resolved = resolve_recurrence(recurrence, start, timezone)
+                    .inspect_err(|err| tracing::debug!("Couldn't resolve a quick-added recurrence: {err}"))
+                    .ok()
+                    .filter(|resolved| resolved.notes.is_empty())?;
+                let event = NewRecurringEvent {
+                    group_id: None,
+                    is_active: true,
+                    title,
+                    description: None,
+                    location,
+                    event_duration_seconds: Second((end - start).num_seconds().try_into().ok()?),
+                    recurrence_start: resolved.recurrence_start,
+                    recurrence_end: resolved.recurrence_end,
+                    rrule: resolved.rrule
+                };
+                events.recurring_events.push(generated(NewRecurringEventWithExceptions { event, exceptions: Vec::new() }, text));
+            }
+        }
+        Some(events)
+    }
+
+    /// Read the events from iCalendar data (see `parse_icalendar`), returning `None` if there are none.
+    /// 
+    /// `source` describes where the data came from (like "QR code"), for the events' source snippets.
+    fn icalendar_events<'a>(
+        texts: impl IntoIterator<Item = &'a str>,
+        options: &ExtractionOptions,
+        source: &str
+    ) -> Option<GeneratedEvents> {
+        fn generated<T>(item: T, snippet: String) -> Generated<T> {
+            Generated { item, confidence: ICALENDAR_CONFIDENCE, source_snippet: snippet, likely_duplicate_of: None }
+        }
+
+        let mut events = GeneratedEvents {
+            events: Vec::new(),
+            recurring_events: Vec::new(),
+            recurring_event_groups: Vec::new(),
+            extraction_text: None,
+            prompt_version: ICALENDAR_VERSION.into(),
+            extraction_id: None
+        };
+        for event in texts.into_iter().flat_map(parse_icalendar) {
+            let ICalendarEvent { title, description, location, start, end, timezone, recurrence, cancelled } = event;
+            // floating times are in whatever timezone the user's in
+            let timezone = timezone.map_or(options.timezone, UserTimezone::Named);
+            let snippet = format!("{source}: {title}");
+            let resolved = recurrence.and_then(|recurrence| {
+                resolve_recurrence(recurrence, start, timezone)
+                    .inspect_err(|err| tracing::debug!("Couldn't resolve an iCalendar recurrence, so adding its first instance: {err}"))
+                    .ok()
+            });
+            let Some(resolved) = resolved else {
+                events.events.push(generated(NewCalendarEvent {
+                    title,
+                    description,
+                    location,
+                    start_time: timezone.local_to_utc(start),
+                    end_time: timezone.local_to_utc(end)
+                }, snippet));
+                continue;
+            };
+
+            let exceptions = cancelled
+                .into_iter()
+                .map(|cancelled| NewSeriesException {
+                    exception_date: timezone.local_to_utc(cancelled),
+                    exception_type: ExceptionType::Cancelled,
+                    modified_title: None,
+                    modified_description: None,
+                    modified_location: None,
+                    modified_start_time: None,
+                    modified_end_time: None
+                })
+                .collect();
+            let event = NewRecurringEvent {
+                group_id: None,
+                is_active: true,
+                title,
+                description,
+                location,
+                event_duration_seconds: Second((end - start).num_seconds().try_into().ok()?),
+                recurrence_start: resolved.recurrence_start,
+                recurrence_end: resolved.recurrence_end,
+                rrule: resolved.rrule
+            };
+            events.recurring_events.push(generated(NewRecurringEventWithExceptions { event, exceptions }, snippet));
+        }
+        (!events.events.is_empty() || !events.recurring_events.is_empty()).then_some(events)
+    }
+
+    /// Read the events from an email's calendar attachments, returning `None` if it has none.
+    /// 
+    /// Calendar attachments say exactly what the events are, so the rest of the email isn't needed; an attachment
+    /// without any events (like a cancellation) means there are none to add, rather than that the LLM should look.
+    fn email_calendar_events(email: &ParsedEmail, options: &ExtractionOptions) -> Option<GeneratedEvents> {
+        let calendars: Vec<String> = email.attachments
+            .iter()
+            .filter(|attachment| attachment.kind() == AttachmentKind::Calendar)
+            .map(|attachment| String::from_utf8_lossy(&attachment.bytes).into_owned())
+            .collect();
+        if calendars.is_empty() {
+            return None;
+        }
+        Self::icalendar_events(calendars.iter().map(String::as_str), options, "Calendar invite")
+            .or_else(|| Some(GeneratedEvents {
+                events: Vec::new(),
+                recurring_events: Vec::new(),
+                recurring_event_groups: Vec::new(),
+                extraction_text: None,
+                prompt_version: ICALENDAR_VERSION.into(),
+                extraction_id: None
+            }))
+    }
+
+    /// The second half of every (uncached) extraction: parse the LLM's extracted string and cache the result.
+    async fn parse_extraction(
+        &self,
+        user_id: Uuid,
+        extracted: String,
+        options: &ExtractionOptions,
+        calendar_context: Option<&CalendarContext>,
+        tokens: &mut TokenUsage,
+        cache_key: &str
+    ) -> ApiResult<GeneratedEvents> {
+        options.progress.report(ExtractionStage::Parsing);
+        let extraction_text = extracted.clone();
+        let mut events = self.llm
+            .parse_extracted_string(extracted, &options.prompt_context(calendar_context), tokens)
             .await?;
+        // always cache the extraction text, as a later request may want it
+        events.extraction_text = Some(extraction_text);
+        self.cache_extraction(user_id, cache_key, &events).await;
         Ok(events)
     }
 
-    pub async fn generate_from_image(&self, image_bytes: Bytes, timezone_offset_minutes: i32) -> ApiResult<GeneratedEvents> {
-        // parse/validate the image and convert it to JPG
-        let img = ImageReader::new(Cursor::new(image_bytes.clone()))
+    /// Tailor freshly generated or cached events to the request, and record them (and where they came from).
+    async fn finish_extraction(
+        &self, 
+        user_id: Uuid, 
+        mut events: GeneratedEvents, 
+        options: &ExtractionOptions, 
+        source: &ExtractionSource
+    ) -> ApiResult<GeneratedEvents> {
+        if options.use_calendar_context {
+            self.mark_likely_duplicates(user_id, &mut events).await?;
+        }
+        let extraction_text = events.extraction_text.take();
+        events.extraction_id = self.extractions
+            .record_extraction(user_id, &events, extraction_text.clone(), options, source)
+            .await;
+        if options.include_extraction_text {
+            events.extraction_text = extraction_text;
+        }
+        Ok(events)
+    }
+
+    /// The key an extraction is cached under; a hash of the (normalized) input `parts`, and everything else that 
+    /// goes into the prompts: the user, their timezone, local date, locale and output language, the calendar context, 
+    /// the model and the prompt version.
+    /// 
+    /// Only the user's local date (rather than time) is used, so a retry within the same day hits the cache.
+    fn cache_key(
+        &self, 
+        user_id: Uuid, 
+        parts: &[&[u8]], 
+        options: &ExtractionOptions, 
+        calendar_context: Option<&CalendarContext>
+    ) -> String {
+        let local_date = options.timezone.utc_to_local(options.now).date_naive().to_string();
+        let calendar_context = calendar_context
+            .map(|c| c.prompt_section(&options.timezone))
+            .unwrap_or_default();
+        let prompt_version = self.llm.prompt_version();
+        let mut hasher = Sha256::new();
+        for part in [
+            user_id.as_bytes().as_slice(),
+            self.llm.model().as_bytes(),
+            prompt_version.as_bytes(),
+            options.timezone.to_string().as_bytes(),
+            local_date.as_bytes(),
+            options.locale.as_ref().map_or("", LanguageTag::as_str).as_bytes(),
+            options.output_language.as_ref().map_or("", LanguageTag::as_str).as_bytes(),
+            calendar_context.as_bytes()
+        ].into_iter().chain(parts.iter().copied()) {
+            // length-prefix each part, so different splits of the same bytes don't collide
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Get a cached extraction, if there's one and the request doesn't bypass the cache.
+    /// 
+    /// The cache is only an optimization, so any error is treated as a miss.
+    async fn cached_extraction(&self, cache_key: &str, options: &ExtractionOptions) -> Option<GeneratedEvents> {
+        if options.bypass_cache {
+            return None;
+        }
+        let cached = self.repositories
+            .extraction_cache
+            .get_extraction(cache_key)
+            .await
+            .inspect_err(|err| tracing::warn!("Failed to read the extraction cache: {err}"))
+            .ok()??;
+        match serde_json::from_value(cached) {
+            Ok(events) => {
+                tracing::trace!("Extraction cache hit for {cache_key}");
+                Some(events)
+            },
+            Err(err) => {
+                tracing::warn!("Failed to parse cached extraction {cache_key}: {err}");
+                None
+            }
+        }
+    }
+
+    /// Cache an extraction; like reading it, failing to do so is only logged.
+    async fn cache_extraction(&self, user_id: Uuid, cache_key: &str, events: &GeneratedEvents) {
+        let events = match serde_json::to_value(events) {
+            Ok(events) => events,
+            Err(err) => {
+                tracing::warn!("Failed to serialize an extraction for caching: {err}");
+                return;
+            }
+        };
+        let expires_at = Utc::now() + self.cache_ttl;
+        if let Err(err) = self.repositories.extraction_cache.put_extraction(cache_key, user_id, events, expires_at).await {
+            tracing::warn!("Failed to write to the extraction cache: {err}");
+        }
+    }
+
+    /// Build the context about the user's calendar to give to the LLM, if it was asked for.
+    async fn calendar_context(&self, user_id: Uuid, options: &ExtractionOptions) -> ApiResult<Option<CalendarContext>> {
+        if !options.use_calendar_context {
+            return Ok(None);
+        }
+        let groups = self.repositories
+            .recurring_event_groups
+            .fetch_all_groups_with_counts(user_id)
+            .await?
+            .into_iter()
+            .map(|g| g.group)
+            .collect();
+        let now = options.now;
+        let events = self.existing_events(user_id, now - Duration::days(1), now + Duration::weeks(CONTEXT_EVENTS_WEEKS)).await?;
+        tracing::trace!("Built calendar context with {} existing events", events.len());
+
+        Ok(Some(CalendarContext { groups, events }))
+    }
+
+    /// Get the user's events and recurring event instances within the period, sorted by start time.
+    async fn existing_events(&self, user_id: Uuid, start: DateTime<Utc>, end: DateTime<Utc>) -> ApiResult<Vec<ExistingEvent>> {
+        let calendar_events = self.repositories
+            .calendar_events
+            .get_events_by_user_and_date_range(user_id, start, end)
+            .await?
+            .into_iter()
+            .map(|e| ExistingEvent {
+                kind: ExistingEventKind::CalendarEvent,
+                event_id: e.id,
+                title: e.title,
+                start_time: e.start_time,
+                end_time: e.end_time
+            });
+        let recurring_instances = self.recurring_events
+            .get_events(user_id, EventsQuery { start, end })
+            .await?
+            .into_iter()
+            .map(|e| ExistingEvent {
+                kind: ExistingEventKind::RecurringEvent,
+                event_id: e.recurring_event_id,
+                title: e.title,
+                start_time: e.start_time,
+                end_time: e.end_time
+            });
+
+        let mut events: Vec<_> = calendar_events.chain(recurring_instances).collect();
+        events.sort_by_key(|e| e.start_time);
+        Ok(events)
+    }
+
+    /// Mark generated events that match an event already on the user's calendar.
+    /// 
+    /// Recurring events are checked by their occurrences over the first few weeks.
+    async fn mark_likely_duplicates(&self, user_id: Uuid, generated: &mut GeneratedEvents) -> ApiResult<()> {
+        let event_occurrences: Vec<Vec<DateTime<Utc>>> = generated.events
+            .iter()
+            .map(|e| vec![e.item.start_time])
+            .collect();
+        let recurring_occurrences: Vec<Vec<DateTime<Utc>>> = generated
+            .all_recurring_events()
+            .map(|e| {
+                let mut rrule = e.item.event.rrule.clone();
+                rrule.set_start(e.item.event.recurrence_start);
+                rrule.set_end(e.item.event.recurrence_end);
+                rrule
+                    .all_within_period(e.item.event.recurrence_start, e.item.event.recurrence_start + Duration::weeks(DUPLICATE_RECURRENCE_WEEKS))
+                    .dates
+                    .iter()
+                    .map(|d| d.to_utc())
+                    .collect()
+            })
+            .collect();
+
+        // only look at the existing events around what was generated
+        let all_starts: Vec<_> = event_occurrences.iter().chain(&recurring_occurrences).flatten().copied().collect();
+        let (Some(start), Some(end)) = (all_starts.iter().min(), all_starts.iter().max()) else {
+            return Ok(());
+        };
+        let padding = Duration::days(1);
+        let (start, end) = (*start - padding, *end + padding);
+        let existing = self.existing_events(user_id, start, end).await?;
+        if existing.is_empty() {
+            return Ok(());
+        }
+
+        let find_duplicate = |title: &str, occurrences: &[DateTime<Utc>]| {
+            existing
+                .iter()
+                .find(|e| {
+                    occurrences.iter().any(|o| (e.start_time - *o).num_minutes().abs() <= DUPLICATE_START_TOLERANCE_MINUTES)
+                    && titles_match(&e.title, title)
+                })
+                .map(ExistingEvent::to_duplicate_hint)
+        };
+        for (event, occurrences) in generated.events.iter_mut().zip(&event_occurrences) {
+            event.likely_duplicate_of = find_duplicate(&event.item.title, occurrences);
+        }
+        for (event, occurrences) in generated.all_recurring_events_mut().zip(&recurring_occurrences) {
+            event.likely_duplicate_of = find_duplicate(&event.item.event.title, occurrences);
+        }
+
+        Ok(())
+    }
+
+    /// Detects the audio's format and transcodes it if needed, returning the bytes and MIME type to send to the LLM.
+    fn prepare_audio(
+        audio: UploadBody,
+        content_type: Option<String>,
+        max_inline_bytes: usize
+    ) -> ApiResult<(Bytes, &'static str)> {
+        // figure out the audio format, preferring the bytes over what the client claims
+        let head = audio.head(16).map_err(Self::read_error)?;
+        let format = AudioFormat::detect(&head)
+            .or_else(|| content_type.as_deref().and_then(AudioFormat::from_content_type))
+            .ok_or_else(|| {
+                tracing::debug!("Unable to detect audio format (content type: {content_type:?})");
+                ApiError::UnsupportedMediaType("Unable to determine the audio format".into())
+            })?;
+
+        // transcode it if the LLM can't take it directly
+        let (audio_bytes, mime_type) = match format.llm_mime_type() {
+            Some(mime_type) => {
+                if audio.len() > max_inline_bytes {
+                    return Err(Self::audio_too_large());
+                }
+                (audio.into_bytes().map_err(Self::read_error)?, mime_type)
+            },
+            None if format.can_transcode() => {
+                let reader = audio.into_reader().map_err(Self::read_error)?;
+                let wav_bytes = transcode_to_wav(Box::new(reader))
+                    .map_err(|err| {
+                        tracing::debug!("Failed to transcode {format:?} audio: {err:?}");
+                        ApiError::UnsupportedMediaType(format!("Unable to decode the {format:?} audio"))
+                    })?;
+                (Bytes::from(wav_bytes), "audio/wav")
+            },
+            None => return Err(ApiError::UnsupportedMediaType(format!("{format:?} audio is not supported")))
+        };
+        if audio_bytes.len() > max_inline_bytes {
+            return Err(Self::audio_too_large());
+        }
+        tracing::trace!("Sending {} bytes of {format:?} audio to the LLM as {mime_type}", audio_bytes.len());
+
+        Ok((audio_bytes, mime_type))
+    }
+
+    fn decode_image(image: UploadBody) -> ApiResult<DynamicImage> {
+        let reader = image.into_reader().map_err(Self::read_error)?;
+        ImageReader::new(reader)
             .with_guessed_format()
             .map_err(|err|{
                 tracing::warn!("Got an IO error guessing the image format: {err}");
@@ -43,19 +843,177 @@ impl AIAddEventsService {
             .decode()
             .map_err(|err| {
                 tracing::debug!("Failed to decode image (unsupported format or invalid data): {err:?}");
-                ApiError::BadRequest("Invalid image format or data was requested".into())
-            })?;
-        let mut jpg_bytes = Vec::new();
-        img.write_to(&mut Cursor::new(&mut jpg_bytes), ImageFormat::Jpeg)
-            .map_err(|err| {
-                tracing::debug!("Failed to write JPG image: {err:?}");
-                ApiError::Internal("Failed to process the image".into())
-            })?;
-        
-        // then request the LLM
-        let events = self.llm
-            .events_from_image(&jpg_bytes, timezone_offset_minutes)
-            .await?;
-        Ok(events)
+                ApiError::UnsupportedMediaType("Invalid image format or data was requested".into())
+            })
+    }
+
+    /// Re-encodes the image as a JPG, downscaling it until it fits within the inline limit.
+    fn encode_image(img: &DynamicImage, limits: &UploadLimitsConfig) -> ApiResult<Vec<u8>> {
+        let mut max_dimension = limits.max_image_dimension;
+        loop {
+            // `resize` preserves the aspect ratio; JPG doesn't support alpha, so we also drop that
+            let rgb = if img.width() > max_dimension || img.height() > max_dimension {
+                img.resize(max_dimension, max_dimension, FilterType::Triangle).to_rgb8()
+            } else {
+                img.to_rgb8()
+            };
+            let mut jpg_bytes = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpg_bytes, limits.image_jpeg_quality)
+                .encode_image(&rgb)
+                .map_err(|err| {
+                    tracing::debug!("Failed to write JPG image: {err:?}");
+                    ApiError::Internal("Failed to process the image".into())
+                })?;
+
+            if jpg_bytes.len() <= limits.max_inline_bytes {
+                tracing::trace!("Re-encoded image to {}x{} JPG ({} bytes)", rgb.width(), rgb.height(), jpg_bytes.len());
+                return Ok(jpg_bytes);
+            }
+            max_dimension = max_dimension.min(rgb.width().max(rgb.height())) * 3 / 4;
+            if max_dimension < MIN_IMAGE_DIMENSION {
+                return Err(ApiError::PayloadTooLarge("The image is too large to process, even after downscaling".into()));
+            }
+            tracing::trace!("JPG was {} bytes; downscaling to {max_dimension}px and retrying", jpg_bytes.len());
+        }
     }
-}
\ No newline at end of file
+
+    /// Prepares an email's image and PDF attachments for the LLM.
+    /// 
+    /// Attachments which can't be used (small images, unreadable or oversized files, and any past 
+    /// `MAX_EMAIL_ATTACHMENTS`) are skipped, rather than failing the whole email.
+    fn prepare_email_attachments(email: &ParsedEmail, limits: &UploadLimitsConfig) -> Vec<PreparedAttachment> {
+        let mut prepared = Vec::new();
+        for attachment in &email.attachments {
+            if prepared.len() >= MAX_EMAIL_ATTACHMENTS {
+                tracing::debug!("Skipping the rest of an email's attachments, past the first {MAX_EMAIL_ATTACHMENTS}");
+                break;
+            }
+            let name = attachment.display_name().to_string();
+            match attachment.kind() {
+                AttachmentKind::Image => {
+                    let image = match Self::decode_image(UploadBody::Memory(attachment.bytes.clone().into())) {
+                        Ok(image) => image,
+                        Err(err) => {
+                            tracing::debug!("Skipping an email's unreadable image attachment `{name}`: {err}");
+                            continue;
+                        }
+                    };
+                    if image.width() < MIN_EMAIL_IMAGE_DIMENSION || image.height() < MIN_EMAIL_IMAGE_DIMENSION {
+                        continue;
+                    }
+                    match Self::encode_image(&image, limits) {
+                        Ok(jpg_bytes) => prepared.push(PreparedAttachment::Image { name, jpg_bytes }),
+                        Err(err) => tracing::debug!("Skipping an email's image attachment `{name}`: {err}")
+                    }
+                },
+                AttachmentKind::Pdf if attachment.bytes.len() > limits.max_inline_bytes => {
+                    tracing::debug!("Skipping an email's PDF attachment `{name}`, as it's too large to send to the LLM");
+                },
+                AttachmentKind::Pdf => prepared.push(PreparedAttachment::Pdf { name, pdf_bytes: attachment.bytes.clone() }),
+                AttachmentKind::Calendar | AttachmentKind::Other => {}
+            }
+        }
+        prepared
+    }
+
+    fn audio_too_large() -> ApiError {
+        ApiError::PayloadTooLarge("The audio is too long to process; try a shorter recording".into())
+    }
+
+    fn read_error(err: std::io::Error) -> ApiError {
+        tracing::warn!("Failed to read an uploaded file: {err}");
+        ApiError::Internal("Failed to read the upload".into())
+    }
+}
+
+/// Normalize text for caching, so resubmissions differing only in line endings or trailing whitespace hit the cache.
+fn normalize_text(text: &str) -> String {
+    text
+        .trim()
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Shorten the text to at most `max_bytes`, without splitting a character.
+fn truncate_to_char_boundary(text: &mut String, max_bytes: usize) {
+    if text.len() > max_bytes {
+        let end = (0..=max_bytes).rev().find(|&i| text.is_char_boundary(i)).unwrap_or_default();
+        text.truncate(end);
+    }
+}
+
+/// Whether two titles likely describe the same event, i.e. one's words contain the other's,
+/// or they share at least half of their words.
+fn titles_match(a: &str, b: &str) -> bool {
+    let words = |title: &str| -> HashSet<String> {
+        title
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    let shared = a.intersection(&b).count();
+    shared == a.len().min(b.len()) || shared * 2 >= a.union(&b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ExtractionOptions {
+        ExtractionOptions {
+            timezone: "Europe/London".parse().unwrap(),
+            now: "2026-10-19T09:00:00Z".parse().unwrap(),
+            use_calendar_context: false,
+            include_extraction_text: false,
+            bypass_cache: false,
+            locale: None,
+            output_language: None,
+            progress: ExtractionProgress::default()
+        }
+    }
+
+    /// An email with the iCalendar data attached.
+    fn email_with_calendar(subject: &str, ics: &str) -> ParsedEmail {
+        let email = format!(
+            "From: Priya Shah <priya@example.com>\r\nSubject: {subject}\r\nMIME-Version: 1.0\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nSee the invite.\r\n\
+            --b\r\nContent-Type: text/calendar; method=REQUEST\r\n\r\n{ics}\r\n--b--\r\n"
+        );
+        parse_email(email.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_events_from_email_calendars() {
+        let email = email_with_calendar("Jazz night", include_str!("../../tests/fixtures/ical/poster.ics"));
+        let events = AIAddEventsService::email_calendar_events(&email, &options()).unwrap();
+        assert!(!events.events.is_empty() || !events.recurring_events.is_empty());
+        assert_eq!(events.prompt_version, ICALENDAR_VERSION);
+    }
+
+    #[test]
+    fn email_calendars_without_events_are_still_used() {
+        // a cancellation has no events to add, and mustn't be left to the LLM (which would add the cancelled meeting)
+        let email = email_with_calendar(
+            "Canceled: Weekly planning",
+            include_str!("../../tests/fixtures/ical/cancellation.ics")
+        );
+        let events = AIAddEventsService::email_calendar_events(&email, &options()).unwrap();
+        assert!(events.events.is_empty());
+        assert!(events.recurring_events.is_empty());
+        assert!(events.recurring_event_groups.is_empty());
+    }
+
+    #[test]
+    fn emails_without_calendars_are_left_to_the_llm() {
+        let email = parse_email(b"From: priya@example.com\r\nSubject: Lunch\r\n\r\nLunch on Friday at 1pm?").unwrap();
+        assert!(AIAddEventsService::email_calendar_events(&email, &options()).is_none());
+    }
+}
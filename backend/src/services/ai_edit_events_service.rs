@@ -0,0 +1,425 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    llm::{context::EditContext, GeneratedEdit, GeneratedEditOp, LLM},
+    models::{
+        ai_usage::AIUsageKind,
+        calendar_edit::{CalendarEdit, EditProposal, FieldChange, ProposedEdit, RejectedEdit},
+        calendar_event::{CalendarEvent, UpdatedCalendarEvent},
+        recurring_event::{NewRecurringEvent, RecurringEvent, UpdatedRecurringEvent},
+        recurring_event_exception::{ExceptionType, NewRecurringEventException},
+        time::UserTimezone
+    },
+    repositories::Repositories,
+    services::ai_usage_service::AIUsageService
+};
+
+/// How far back we look for events the user may want to edit.
+const EDIT_CONTEXT_WEEKS_BEFORE: i64 = 4;
+
+/// How far ahead we look for events the user may want to edit.
+const EDIT_CONTEXT_WEEKS_AFTER: i64 = 26;
+
+/// How far a generated `instance_start` can be from an actual instance, and still be snapped to it.
+const INSTANCE_TOLERANCE_MINUTES: i64 = 1;
+
+/// Handles business logic for editing the user's calendar through natural language.
+#[derive(Clone, Debug)]
+pub struct AIEditEventsService {
+    llm: LLM,
+    repositories: Repositories,
+    usage: AIUsageService
+}
+
+impl AIEditEventsService {
+    pub fn new(llm: LLM, repositories: Repositories, usage: AIUsageService) -> Self {
+        Self {
+            llm,
+            repositories,
+            usage
+        }
+    }
+
+    /// Propose edits to the user's calendar from an instruction, without applying them.
+    pub async fn propose_edits(&self, user_id: Uuid, instruction: String, timezone: UserTimezone) -> ApiResult<EditProposal> {
+        let mut meter = self.usage.start(user_id, AIUsageKind::Edit).await?;
+        let now = Utc::now();
+        let edit_context = self.edit_context(user_id, now).await?;
+        let generated = self.llm
+            .edits_from_text(instruction, now, timezone, &edit_context, &mut meter.tokens)
+            .await?;
+
+        let mut proposal = EditProposal { edits: Vec::new(), rejected: Vec::new() };
+        for generated_edit in generated.edits {
+            match self.to_proposed_edit(&generated_edit, &edit_context, &timezone) {
+                Ok(edit) => proposal.edits.push(edit),
+                Err(error) => {
+                    tracing::debug!("Rejected generated {:?} edit: {error}", generated_edit.op);
+                    proposal.rejected.push(RejectedEdit { reason: generated_edit.reason, error });
+                }
+            }
+        }
+        tracing::trace!("Proposing {} edits ({} rejected)", proposal.edits.len(), proposal.rejected.len());
+
+        Ok(proposal)
+    }
+
+    /// Apply edits the user has confirmed.
+    ///
+    /// All edits are checked before any are applied, and are then applied in a single transaction, so a bad edit (or 
+    /// a failure partway through) doesn't leave the calendar half-edited.
+    pub async fn apply_edits(&self, user_id: Uuid, edits: Vec<CalendarEdit>) -> ApiResult<()> {
+        for edit in &edits {
+            self.validate_edit(user_id, edit).await?;
+        }
+
+        let mut tx = self.repositories.begin().await?;
+        for edit in edits {
+            match edit {
+                CalendarEdit::UpdateEvent { event } => {
+                    self.repositories.calendar_events.update_event_in(&mut tx, event).await?;
+                },
+                CalendarEdit::DeleteEvent { event_id } => {
+                    self.repositories.calendar_events.delete_event_in(&mut tx, event_id).await?;
+                },
+                CalendarEdit::UpdateRecurringEvent { mut event } => {
+                    // HACK: as with creating events, ensure the rrule has the right start/end
+                    event.rrule.set_start(event.recurrence_start);
+                    event.rrule.set_end(event.recurrence_end);
+                    self.repositories.recurring_events.update_event_in(&mut tx, event).await?;
+                },
+                CalendarEdit::DeleteRecurringEvent { event_id } => {
+                    self.repositories.recurring_events.delete_event_in(&mut tx, event_id).await?;
+                },
+                CalendarEdit::AddException { exception } => {
+                    match self.repositories.recurring_events.create_event_exception_in(&mut tx, exception).await {
+                        Ok(()) => {},
+                        Err(sqlx::Error::Database(db_err)) if db_err.constraint() == Some("unique_exception_per_instance") => {
+                            return Err(ApiError::unprocessable_entity([("exception_date", "There's already an exception on this exception date")]));
+                        },
+                        Err(err) => return Err(err.into())
+                    }
+                },
+                CalendarEdit::SplitSeries { event_id, split_at, mut new_event } => {
+                    let event = self.fetch_recurring_event(user_id, event_id).await?;
+                    let mut ended_event = Self::to_updated_event(event);
+                    ended_event.recurrence_end = Some(split_at - Duration::seconds(1));
+                    ended_event.rrule.set_start(ended_event.recurrence_start);
+                    ended_event.rrule.set_end(ended_event.recurrence_end);
+                    self.repositories.recurring_events.update_event_in(&mut tx, ended_event).await?;
+
+                    // the continuation is part of an edit rather than an import, so it isn't put in an (undoable) 
+                    // import batch; undoing it would leave the original series cut short
+                    new_event.rrule.set_start(new_event.recurrence_start);
+                    new_event.rrule.set_end(new_event.recurrence_end);
+                    self.repositories
+                        .recurring_events
+                        .bulk_create_events_in(&mut tx, &[new_event], user_id, None, None)
+                        .await?;
+                }
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the user's groups, along with events around now which they're likely to want to edit.
+    async fn edit_context(&self, user_id: Uuid, now: DateTime<Utc>) -> ApiResult<EditContext> {
+        let start = now - Duration::weeks(EDIT_CONTEXT_WEEKS_BEFORE);
+        let end = now + Duration::weeks(EDIT_CONTEXT_WEEKS_AFTER);
+        let groups = self.repositories
+            .recurring_event_groups
+            .fetch_all_groups_with_counts(user_id)
+            .await?
+            .into_iter()
+            .map(|g| g.group)
+            .collect();
+        let events = self.repositories
+            .calendar_events
+            .get_events_by_user_and_date_range(user_id, start, end)
+            .await?;
+        let recurring_events = self.repositories
+            .recurring_events
+            .fetch_active_events_in_period(user_id, start, end)
+            .await?;
+
+        Ok(EditContext { groups, events, recurring_events })
+    }
+
+    /// Turn a generated edit into a typed one, checking it against the calendar it was generated from.
+    fn to_proposed_edit(
+        &self,
+        generated: &GeneratedEdit,
+        edit_context: &EditContext,
+        timezone: &UserTimezone
+    ) -> Result<ProposedEdit, String> {
+        let format = |datetime: DateTime<Utc>| timezone.utc_to_local(datetime).format("%a %d/%m/%Y %H:%M").to_string();
+        let find_event = || edit_context.events
+            .iter()
+            .find(|e| e.id == generated.target_id)
+            .ok_or_else(|| "the event doesn't exist".to_string());
+        let find_recurring_event = || edit_context.recurring_events
+            .iter()
+            .find(|e| e.id == generated.target_id)
+            .ok_or_else(|| "the recurring event doesn't exist".to_string());
+
+        let mut changes = Vec::new();
+        let (edit, target_title) = match generated.op {
+            GeneratedEditOp::UpdateEvent => {
+                let event = find_event()?;
+                let updated = UpdatedCalendarEvent {
+                    id: event.id,
+                    title: generated.title.clone().unwrap_or_else(|| event.title.clone()),
+                    description: generated.description.clone().or_else(|| event.description.clone()),
+                    location: generated.location.clone().or_else(|| event.location.clone()),
+                    start_time: generated.start_time.unwrap_or(event.start_time),
+                    end_time: generated.end_time.unwrap_or(event.end_time)
+                };
+                if updated.end_time <= updated.start_time {
+                    return Err("the event would end before it starts".into());
+                }
+                Self::diff_event(&mut changes, &Self::to_updated_calendar_event(event), &updated, format);
+                (CalendarEdit::UpdateEvent { event: updated }, event.title.clone())
+            },
+            GeneratedEditOp::DeleteEvent => {
+                let event = find_event()?;
+                (CalendarEdit::DeleteEvent { event_id: event.id }, event.title.clone())
+            },
+            GeneratedEditOp::UpdateRecurringEvent => {
+                let event = find_recurring_event()?;
+                let mut updated = Self::to_updated_event(event.clone());
+                Self::apply_series_changes(generated, &mut updated);
+                if updated.recurrence_end.is_some_and(|end| end <= updated.recurrence_start) {
+                    return Err("the recurring event would end before it starts".into());
+                }
+                Self::diff_recurring_event(&mut changes, &Self::to_updated_event(event.clone()), &updated, format);
+                (CalendarEdit::UpdateRecurringEvent { event: updated }, event.title.clone())
+            },
+            GeneratedEditOp::DeleteRecurringEvent => {
+                let event = find_recurring_event()?;
+                (CalendarEdit::DeleteRecurringEvent { event_id: event.id }, event.title.clone())
+            },
+            GeneratedEditOp::CancelInstance => {
+                let event = find_recurring_event()?;
+                let instance_start = Self::find_instance(event, generated.instance_start)?;
+                changes.push(FieldChange { field: "instance", before: Some(format(instance_start)), after: None });
+                let exception = NewRecurringEventException {
+                    recurring_event_id: event.id,
+                    exception_date: instance_start,
+                    exception_type: ExceptionType::Cancelled,
+                    modified_title: None,
+                    modified_description: None,
+                    modified_location: None,
+                    modified_start_time: None,
+                    modified_end_time: None
+                };
+                (CalendarEdit::AddException { exception }, event.title.clone())
+            },
+            GeneratedEditOp::ModifyInstance => {
+                let event = find_recurring_event()?;
+                let instance_start = Self::find_instance(event, generated.instance_start)?;
+                let duration = Duration::seconds(event.event_duration_seconds.0.into());
+                let start_time = generated.start_time.unwrap_or(instance_start);
+                let end_time = generated.end_time.unwrap_or(start_time + duration);
+                if end_time <= start_time {
+                    return Err("the instance would end before it starts".into());
+                }
+                let instance = UpdatedCalendarEvent {
+                    id: event.id,
+                    title: event.title.clone(),
+                    description: event.description.clone(),
+                    location: event.location.clone(),
+                    start_time: instance_start,
+                    end_time: instance_start + duration
+                };
+                let modified = UpdatedCalendarEvent {
+                    id: event.id,
+                    title: generated.title.clone().unwrap_or_else(|| event.title.clone()),
+                    description: generated.description.clone().or_else(|| event.description.clone()),
+                    location: generated.location.clone().or_else(|| event.location.clone()),
+                    start_time,
+                    end_time
+                };
+                Self::diff_event(&mut changes, &instance, &modified, format);
+                let exception = NewRecurringEventException {
+                    recurring_event_id: event.id,
+                    exception_date: instance_start,
+                    exception_type: ExceptionType::Modified,
+                    modified_title: generated.title.clone(),
+                    modified_description: generated.description.clone(),
+                    modified_location: generated.location.clone().map(Some),
+                    modified_start_time: Some(start_time),
+                    modified_end_time: Some(end_time)
+                };
+                (CalendarEdit::AddException { exception }, event.title.clone())
+            },
+            GeneratedEditOp::SplitSeries => {
+                let event = find_recurring_event()?;
+                if event.rrule.has_count() {
+                    return Err("recurring events with a fixed number of instances can't be split".into());
+                }
+                let split_at = Self::find_instance(event, generated.instance_start)?;
+                if split_at <= event.recurrence_start {
+                    return Err("the split must be after the first instance; update the whole recurring event instead".into());
+                }
+                // the new series continues on from the split, with the generated changes
+                let before = Self::to_updated_event(event.clone());
+                let mut continued = before.clone();
+                continued.recurrence_start = split_at;
+                Self::apply_series_changes(generated, &mut continued);
+                if continued.recurrence_start < split_at {
+                    return Err("the new series would start before the split".into());
+                }
+                if continued.recurrence_end.is_some_and(|end| end <= continued.recurrence_start) {
+                    return Err("the new series would end before it starts".into());
+                }
+
+                changes.push(FieldChange { field: "split_at", before: None, after: Some(format(split_at)) });
+                Self::diff_recurring_event(&mut changes, &before, &continued, format);
+                let new_event = NewRecurringEvent {
+                    group_id: continued.group_id,
+                    is_active: continued.is_active,
+                    title: continued.title,
+                    description: continued.description,
+                    location: continued.location,
+                    event_duration_seconds: continued.event_duration_seconds,
+                    recurrence_start: continued.recurrence_start,
+                    recurrence_end: continued.recurrence_end,
+                    rrule: continued.rrule
+                };
+                (CalendarEdit::SplitSeries { event_id: event.id, split_at, new_event }, event.title.clone())
+            }
+        };
+
+        Ok(ProposedEdit { edit, target_title, reason: generated.reason.clone(), changes })
+    }
+
+    /// Check that the edit's target exists and belongs to the user.
+    async fn validate_edit(&self, user_id: Uuid, edit: &CalendarEdit) -> ApiResult<()> {
+        match edit {
+            CalendarEdit::UpdateEvent { .. } | CalendarEdit::DeleteEvent { .. } => {
+                self.repositories
+                    .calendar_events
+                    .get_event(user_id, edit.target_id())
+                    .await?
+                    .ok_or(ApiError::Forbidden)?;
+            },
+            CalendarEdit::SplitSeries { split_at, new_event, .. } => {
+                let event = self.fetch_recurring_event(user_id, edit.target_id()).await?;
+                if event.rrule.has_count() || *split_at <= event.recurrence_start || new_event.recurrence_start < *split_at {
+                    return Err(ApiError::unprocessable_entity([("split_at", "is not a valid point to split the recurring event at")]));
+                }
+                // the new series is created directly, so check its group as `create_events` would
+                if !self.repositories.recurring_events.validate_group_ownership(user_id, &[new_event.group_id]).await? {
+                    return Err(ApiError::Forbidden);
+                }
+            },
+            CalendarEdit::UpdateRecurringEvent { .. }
+            | CalendarEdit::DeleteRecurringEvent { .. }
+            | CalendarEdit::AddException { .. } => {
+                self.fetch_recurring_event(user_id, edit.target_id()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn fetch_recurring_event(&self, user_id: Uuid, event_id: Uuid) -> ApiResult<RecurringEvent> {
+        self.repositories
+            .recurring_events
+            .fetch_event(user_id, event_id)
+            .await?
+            .ok_or(ApiError::Forbidden)
+    }
+
+    /// Snap a generated instance start to an actual instance of the event.
+    fn find_instance(event: &RecurringEvent, instance_start: Option<DateTime<Utc>>) -> Result<DateTime<Utc>, String> {
+        let instance_start = instance_start.ok_or("`instance_start` is missing")?;
+        let tolerance = Duration::minutes(INSTANCE_TOLERANCE_MINUTES);
+        let mut rrule = event.rrule.clone();
+        rrule.set_start(event.recurrence_start);
+        rrule.set_end(event.recurrence_end);
+        rrule
+            .all_within_period(instance_start - tolerance, instance_start + tolerance)
+            .dates
+            .first()
+            .map(|date| date.to_utc())
+            .ok_or_else(|| "the recurring event doesn't occur then".into())
+    }
+
+    /// Apply a generated edit's changes to a recurring event.
+    fn apply_series_changes(generated: &GeneratedEdit, event: &mut UpdatedRecurringEvent) {
+        if let Some(title) = &generated.title { event.title = title.clone(); }
+        if let Some(description) = &generated.description { event.description = Some(description.clone()); }
+        if let Some(location) = &generated.location { event.location = Some(location.clone()); }
+        if let Some(duration) = generated.event_duration_seconds { event.event_duration_seconds = duration; }
+        if let Some(start) = generated.recurrence_start { event.recurrence_start = start; }
+        if let Some(end) = generated.recurrence_end { event.recurrence_end = Some(end); }
+        if let Some(rrule) = &generated.rrule { event.rrule = rrule.clone(); }
+    }
+
+    fn to_updated_calendar_event(event: &CalendarEvent) -> UpdatedCalendarEvent {
+        UpdatedCalendarEvent {
+            id: event.id,
+            title: event.title.clone(),
+            description: event.description.clone(),
+            location: event.location.clone(),
+            start_time: event.start_time,
+            end_time: event.end_time
+        }
+    }
+
+    fn to_updated_event(event: RecurringEvent) -> UpdatedRecurringEvent {
+        UpdatedRecurringEvent {
+            id: event.id,
+            group_id: event.group_id,
+            is_active: event.is_active,
+            title: event.title,
+            description: event.description,
+            location: event.location,
+            event_duration_seconds: event.event_duration_seconds,
+            recurrence_start: event.recurrence_start,
+            recurrence_end: event.recurrence_end,
+            rrule: event.rrule
+        }
+    }
+
+    fn diff_event(
+        changes: &mut Vec<FieldChange>,
+        before: &UpdatedCalendarEvent,
+        after: &UpdatedCalendarEvent,
+        format: impl Fn(DateTime<Utc>) -> String
+    ) {
+        push_change(changes, "title", Some(before.title.clone()), Some(after.title.clone()));
+        push_change(changes, "description", before.description.clone(), after.description.clone());
+        push_change(changes, "location", before.location.clone(), after.location.clone());
+        push_change(changes, "start_time", Some(format(before.start_time)), Some(format(after.start_time)));
+        push_change(changes, "end_time", Some(format(before.end_time)), Some(format(after.end_time)));
+    }
+
+    fn diff_recurring_event(
+        changes: &mut Vec<FieldChange>,
+        before: &UpdatedRecurringEvent,
+        after: &UpdatedRecurringEvent,
+        format: impl Fn(DateTime<Utc>) -> String
+    ) {
+        push_change(changes, "title", Some(before.title.clone()), Some(after.title.clone()));
+        push_change(changes, "description", before.description.clone(), after.description.clone());
+        push_change(changes, "location", before.location.clone(), after.location.clone());
+        push_change(
+            changes, "event_duration_seconds",
+            Some(before.event_duration_seconds.0.to_string()), Some(after.event_duration_seconds.0.to_string())
+        );
+        push_change(changes, "recurrence_start", Some(format(before.recurrence_start)), Some(format(after.recurrence_start)));
+        push_change(changes, "recurrence_end", before.recurrence_end.map(&format), after.recurrence_end.map(&format));
+        push_change(changes, "rrule", Some(before.rrule.rule_string()), Some(after.rrule.rule_string()));
+    }
+}
+
+/// Record a change if the field actually changed.
+fn push_change(changes: &mut Vec<FieldChange>, field: &'static str, before: Option<String>, after: Option<String>) {
+    if before != after {
+        changes.push(FieldChange { field, before, after });
+    }
+}
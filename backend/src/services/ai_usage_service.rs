@@ -0,0 +1,92 @@
+use chrono::{Datelike, Days, Months, NaiveTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    api::error::{ApiError, ApiResult},
+    config::AIQuotaConfig,
+    models::ai_usage::{AIAllowance, AIUsageKind, QuotaAllowance, TokenUsage},
+    repositories::Repositories
+};
+
+/// Handles business logic for metering and limiting the tokens users spend on AI features.
+#[derive(Clone, Debug)]
+pub struct AIUsageService {
+    repositories: Repositories,
+    quotas: AIQuotaConfig
+}
+
+impl AIUsageService {
+    pub fn new(repositories: Repositories, quotas: &AIQuotaConfig) -> Self {
+        Self {
+            repositories,
+            quotas: quotas.clone()
+        }
+    }
+
+    /// Get how much of their daily and monthly quotas the user has left.
+    pub async fn allowance(&self, user_id: Uuid) -> ApiResult<AIAllowance> {
+        let today = Utc::now().date_naive();
+        let day_start = today.and_time(NaiveTime::MIN).and_utc();
+        let month_start = today.with_day(1).unwrap_or(today).and_time(NaiveTime::MIN).and_utc();
+        let (day_used, month_used) = self.repositories
+            .ai_usage
+            .tokens_used_since(user_id, day_start, month_start)
+            .await?;
+
+        Ok(AIAllowance {
+            daily: QuotaAllowance::new(self.quotas.daily_tokens, day_used, day_start + Days::new(1)),
+            monthly: QuotaAllowance::new(self.quotas.monthly_tokens, month_used, month_start + Months::new(1))
+        })
+    }
+
+    /// Fail with `429 Too Many Requests` if the user has used up either of their quotas.
+    pub async fn check_quota(&self, user_id: Uuid) -> ApiResult<()> {
+        let allowance = self.allowance(user_id).await?;
+        // check the monthly quota first, as it'll take longer to reset
+        for (period, quota) in [("monthly", &allowance.monthly), ("daily", &allowance.daily)] {
+            if quota.remaining == 0 {
+                return Err(ApiError::QuotaExceeded {
+                    message: format!("You've used up your {period} AI allowance; it resets at {}", quota.resets_at.to_rfc3339()),
+                    retry_after: (quota.resets_at - Utc::now()).to_std().unwrap_or_default()
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the user's quota, then start metering one of their AI requests.
+    pub async fn start(&self, user_id: Uuid, kind: AIUsageKind) -> ApiResult<UsageMeter> {
+        self.check_quota(user_id).await?;
+        Ok(UsageMeter {
+            repositories: self.repositories.clone(),
+            user_id,
+            kind,
+            tokens: TokenUsage::default()
+        })
+    }
+}
+
+/// Meters the tokens used by one AI request; pass `tokens` to each LLM call.
+/// 
+/// The usage is recorded when the meter is dropped, so tokens spent on requests which then fail (or are cancelled)
+/// are still counted.
+pub struct UsageMeter {
+    repositories: Repositories,
+    user_id: Uuid,
+    kind: AIUsageKind,
+    pub tokens: TokenUsage
+}
+
+impl Drop for UsageMeter {
+    fn drop(&mut self) {
+        if self.tokens == TokenUsage::default() {
+            return;
+        }
+        let (repositories, user_id, kind, tokens) = (self.repositories.clone(), self.user_id, self.kind, self.tokens);
+        tokio::spawn(async move {
+            if let Err(err) = repositories.ai_usage.record_usage(user_id, kind, tokens).await {
+                tracing::warn!("Failed to record {} tokens of {kind} AI usage for user {user_id}: {err}", tokens.total_tokens);
+            }
+        });
+    }
+}
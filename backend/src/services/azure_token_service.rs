@@ -2,7 +2,7 @@ use graph_rs_sdk::{Graph, identity::{ConfidentialClientApplication, Confidential
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::{api::error::{ApiError, ApiResult}, config::Config, llm::{GeneratedEvents, LLM}, utils::{azure::{is_access_token_valid, refresh_azure_tokens}, encrypt::{decrypt_token, encrypt_token}}};
-use crate::Repositories;
+use crate::repositories::Repositories;
 
 /// Service for functionality related to Azure tokens.
 #[derive(Clone, Debug)]
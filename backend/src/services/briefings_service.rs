@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, Days, Duration, NaiveDate, NaiveTime, Utc};
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    config::AIBriefingsConfig,
+    llm::{ExistingEventKind, LLM},
+    models::{
+        ai_usage::AIUsageKind,
+        briefing::{Briefing, BriefingItem, BriefingPeriod, Conflict, Gap, ItemPair, SummarySource},
+        time::UserTimezone
+    },
+    repositories::Repositories,
+    services::{ai_usage_service::AIUsageService, recurring_events_service::{EventsQuery, RecurringEventsService}}
+};
+
+/// How soon after one item another can start and still be back-to-back with it.
+const BACK_TO_BACK_MINUTES: i64 = 5;
+
+/// How long a stretch between items on the same day must be to be pointed out.
+const LONG_GAP_MINUTES: i64 = 3 * 60;
+
+/// The most of each kind of note (conflicts, gaps, etc.) spelled out in a template summary.
+const MAX_TEMPLATE_NOTES: usize = 3;
+
+/// Handles business logic for daily and weekly agenda briefings.
+#[derive(Clone, Debug)]
+pub struct BriefingsService {
+    llm: LLM,
+    repositories: Repositories,
+    recurring_events: RecurringEventsService,
+    usage: AIUsageService,
+    /// Whether summaries are written by the LLM at all (see `AIBriefingsConfig`).
+    use_llm: bool
+}
+
+impl BriefingsService {
+    pub fn new(
+        llm: LLM,
+        repositories: Repositories,
+        recurring_events: RecurringEventsService,
+        usage: AIUsageService,
+        config: &AIBriefingsConfig
+    ) -> Self {
+        Self {
+            llm,
+            repositories,
+            recurring_events,
+            usage,
+            use_llm: config.use_llm
+        }
+    }
+
+    /// Build the user's briefing for the (local) day, or the week containing it.
+    ///
+    /// The summary is written by the LLM if `use_llm` is set (and it isn't disabled), counting towards the user's
+    /// quota; if it isn't, or the LLM fails, the summary comes from a template instead.
+    pub async fn briefing(
+        &self,
+        user_id: Uuid,
+        period: BriefingPeriod,
+        date: NaiveDate,
+        timezone: UserTimezone,
+        use_llm: bool
+    ) -> ApiResult<Briefing> {
+        let (start_date, end_date) = match period {
+            BriefingPeriod::Day => (date, date),
+            BriefingPeriod::Week => {
+                let monday = date - Days::new(date.weekday().num_days_from_monday().into());
+                (monday, monday + Days::new(6))
+            }
+        };
+        let start = timezone.local_to_utc(start_date.and_time(NaiveTime::MIN));
+        let end = timezone.local_to_utc((end_date + Days::new(1)).and_time(NaiveTime::MIN));
+
+        let items = self.items(user_id, start, end).await?;
+        let conflicts = find_conflicts(&items);
+        let (back_to_back, gaps) = find_back_to_back_and_gaps(&items, &timezone);
+        let mut briefing = Briefing {
+            period,
+            start_date,
+            end_date,
+            timezone: timezone.to_string(),
+            items,
+            conflicts,
+            back_to_back,
+            gaps,
+            summary: String::new(),
+            summary_source: SummarySource::Template
+        };
+        briefing.summary = template_summary(&briefing, &timezone);
+
+        if self.use_llm && use_llm {
+            match self.llm_summary(user_id, &briefing, timezone).await {
+                Ok(summary) => {
+                    briefing.summary = summary;
+                    briefing.summary_source = SummarySource::Llm;
+                },
+                Err(err) => tracing::warn!("Falling back to a template briefing summary: {err}")
+            }
+        }
+        Ok(briefing)
+    }
+
+    /// Have the LLM summarize a briefing, from its agenda and template summary.
+    async fn llm_summary(&self, user_id: Uuid, briefing: &Briefing, timezone: UserTimezone) -> ApiResult<String> {
+        let mut meter = self.usage.start(user_id, AIUsageKind::Briefing).await?;
+        let summary = self.llm
+            .briefing_summary(briefing_text(briefing, &timezone), Utc::now(), timezone, &mut meter.tokens)
+            .await?;
+        if summary.is_empty() {
+            return Err(ApiError::Internal("The LLM returned an empty briefing summary".into()));
+        }
+        Ok(summary)
+    }
+
+    /// Get the user's events and recurring event instances within the period, sorted by start time, marking the first
+    /// instance of any recurring event which starts within it.
+    async fn items(&self, user_id: Uuid, start: DateTime<Utc>, end: DateTime<Utc>) -> ApiResult<Vec<BriefingItem>> {
+        let calendar_events = self.repositories
+            .calendar_events
+            .get_events_by_user_and_date_range(user_id, start, end)
+            .await?;
+        let group_ids: Vec<_> = calendar_events.iter().filter_map(|e| e.group_id).collect();
+        let groups = self.repositories
+            .recurring_events
+            .fetch_groups_by_ids(&group_ids)
+            .await?;
+        let calendar_events = calendar_events
+            .into_iter()
+            .map(|e| BriefingItem {
+                kind: ExistingEventKind::CalendarEvent,
+                event_id: e.id,
+                title: e.title,
+                location: e.location,
+                start_time: e.start_time,
+                end_time: e.end_time,
+                group_name: e.group_id
+                    .and_then(|group_id| groups.iter().find(|g| g.id == group_id))
+                    .map(|g| g.name.clone()),
+                first_occurrence: false
+            });
+        let recurring_instances = self.recurring_events
+            .get_events(user_id, EventsQuery { start, end })
+            .await?
+            .into_iter()
+            .map(|e| BriefingItem {
+                kind: ExistingEventKind::RecurringEvent,
+                event_id: e.recurring_event_id,
+                title: e.title,
+                location: e.location,
+                start_time: e.start_time,
+                end_time: e.end_time,
+                group_name: e.group.map(|g| g.name),
+                first_occurrence: false
+            });
+        let mut items: Vec<_> = calendar_events.chain(recurring_instances).collect();
+        items.sort_by_key(|item| item.start_time);
+
+        // the recurring events whose first instance is within the period
+        let new_series: Vec<_> = self.repositories
+            .recurring_events
+            .fetch_active_events_in_period(user_id, start, end)
+            .await?
+            .into_iter()
+            .filter(|event| {
+                let mut rrule = event.rrule.clone();
+                rrule.set_start(event.recurrence_start);
+                rrule.first_instances(1).first().is_some_and(|first| (start..end).contains(first))
+            })
+            .map(|event| event.id)
+            .collect();
+        for event_id in new_series {
+            if let Some(item) = items
+                .iter_mut()
+                .find(|item| item.kind == ExistingEventKind::RecurringEvent && item.event_id == event_id)
+            {
+                item.first_occurrence = true;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Find the items which overlap each other, given items sorted by start time.
+fn find_conflicts(items: &[BriefingItem]) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for (first, a) in items.iter().enumerate() {
+        for (second, b) in items.iter().enumerate().skip(first + 1) {
+            if b.start_time >= a.end_time {
+                break;
+            }
+            let overlap = a.end_time.min(b.end_time) - b.start_time;
+            if overlap > Duration::zero() {
+                conflicts.push(Conflict { items: ItemPair { first, second }, overlap_minutes: overlap.num_minutes() });
+            }
+        }
+    }
+    conflicts
+}
+
+/// Find the items which start right as another ends, and the long stretches between items on the same day, given items
+/// sorted by start time.
+fn find_back_to_back_and_gaps(items: &[BriefingItem], timezone: &UserTimezone) -> (Vec<ItemPair>, Vec<Gap>) {
+    let (mut back_to_back, mut gaps) = (Vec::new(), Vec::new());
+    // the item which ends last of those so far, as a later one can start within an earlier, longer one
+    let mut latest: Option<usize> = None;
+    for (i, item) in items.iter().enumerate() {
+        if let Some(previous) = latest {
+            let previous_end = items[previous].end_time;
+            let between = item.start_time - previous_end;
+            let same_day = timezone.utc_to_local(previous_end).date_naive() == timezone.utc_to_local(item.start_time).date_naive();
+            if between >= Duration::zero() && between <= Duration::minutes(BACK_TO_BACK_MINUTES) {
+                back_to_back.push(ItemPair { first: previous, second: i });
+            } else if between >= Duration::minutes(LONG_GAP_MINUTES) && same_day {
+                gaps.push(Gap { start_time: previous_end, end_time: item.start_time, minutes: between.num_minutes() });
+            }
+        }
+        if latest.is_none_or(|latest| item.end_time > items[latest].end_time) {
+            latest = Some(i);
+        }
+    }
+    (back_to_back, gaps)
+}
+
+/// Summarize a briefing from a template, without the LLM.
+fn template_summary(briefing: &Briefing, timezone: &UserTimezone) -> String {
+    let is_week = briefing.period == BriefingPeriod::Week;
+    // in a week, times are given with their day
+    let time = |datetime: DateTime<Utc>| {
+        let local = timezone.utc_to_local(datetime);
+        if is_week { local.format("%a %H:%M").to_string() } else { local.format("%H:%M").to_string() }
+    };
+    let title = |i: usize| format!("\"{}\"", briefing.items[i].title);
+    let items = &briefing.items;
+
+    let mut sentences = Vec::new();
+    match (items.first(), items.last()) {
+        (Some(first), Some(last)) if is_week => {
+            let mut per_day: HashMap<NaiveDate, usize> = HashMap::new();
+            for item in items {
+                *per_day.entry(timezone.utc_to_local(item.start_time).date_naive()).or_default() += 1;
+            }
+            let (busiest, busiest_count) = per_day
+                .iter()
+                .max_by_key(|(date, count)| (**count, std::cmp::Reverse(**date)))
+                .map(|(date, count)| (*date, *count))
+                .unwrap_or((briefing.start_date, 0));
+            sentences.push(format!(
+                "{} across {} of the week of {}, from {} to {}; the busiest day is {} with {busiest_count}.",
+                count_noun(items.len(), "event"),
+                count_noun(per_day.len(), "day"),
+                briefing.start_date.format("%A %-d %B"),
+                time(first.start_time),
+                time(last.end_time),
+                busiest.format("%A")
+            ));
+        },
+        (Some(first), Some(last)) => sentences.push(format!(
+            "{} on {}, from {} to {}.",
+            count_noun(items.len(), "event"),
+            briefing.start_date.format("%A %-d %B"),
+            time(first.start_time),
+            time(last.end_time)
+        )),
+        _ if is_week => sentences.push(format!("Nothing on in the week of {}.", briefing.start_date.format("%A %-d %B"))),
+        _ => sentences.push(format!("Nothing on for {}.", briefing.start_date.format("%A %-d %B")))
+    }
+
+    let mut notes = |label: &str, descriptions: Vec<String>| {
+        if descriptions.is_empty() {
+            return;
+        }
+        let more = descriptions.len().saturating_sub(MAX_TEMPLATE_NOTES);
+        let mut listed = descriptions.into_iter().take(MAX_TEMPLATE_NOTES).collect::<Vec<_>>().join("; ");
+        if more > 0 {
+            listed.push_str(&format!("; and {more} more"));
+        }
+        sentences.push(format!("{label}: {listed}."));
+    };
+    notes("Conflicts", briefing.conflicts
+        .iter()
+        .map(|c| format!(
+            "{} and {} overlap by {} min at {}",
+            title(c.items.first), title(c.items.second), c.overlap_minutes, time(items[c.items.second].start_time)
+        ))
+        .collect());
+    notes("Back to back", briefing.back_to_back
+        .iter()
+        .map(|pair| format!("{} then {} at {}", title(pair.first), title(pair.second), time(items[pair.second].start_time)))
+        .collect());
+    notes("Free", briefing.gaps
+        .iter()
+        .map(|gap| format!("{} to {}", time(gap.start_time), time(gap.end_time)))
+        .collect());
+    notes("Starting", items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.first_occurrence)
+        .map(|(i, item)| format!("{} at {}", title(i), time(item.start_time)))
+        .collect());
+    sentences.join(" ")
+}
+
+/// Render a briefing as plain text: its summary, then its agenda day by day.
+pub fn briefing_text(briefing: &Briefing, timezone: &UserTimezone) -> String {
+    let mut text = String::new();
+    text.push_str(&briefing.summary);
+    text.push('\n');
+
+    let conflicting: Vec<usize> = briefing.conflicts
+        .iter()
+        .flat_map(|c| [c.items.first, c.items.second])
+        .collect();
+    let mut current_date = None;
+    for (i, item) in briefing.items.iter().enumerate() {
+        let start = timezone.utc_to_local(item.start_time);
+        if current_date != Some(start.date_naive()) {
+            current_date = Some(start.date_naive());
+            text.push_str(&format!("\n{}\n", start.format("%A %-d %B")));
+        }
+        let mut line = format!(
+            "  {}-{}  {}",
+            start.format("%H:%M"),
+            timezone.utc_to_local(item.end_time).format("%H:%M"),
+            item.title
+        );
+        if let Some(location) = &item.location {
+            line.push_str(&format!(" @ {location}"));
+        }
+        if let Some(group_name) = &item.group_name {
+            line.push_str(&format!(" [{group_name}]"));
+        }
+        if item.first_occurrence {
+            line.push_str(" (new)");
+        }
+        if conflicting.contains(&i) {
+            line.push_str(" (conflict)");
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// A count of something, like "1 event" or "3 events".
+fn count_noun(count: usize, noun: &str) -> String {
+    if count == 1 { format!("1 {noun}") } else { format!("{count} {noun}s") }
+}
@@ -2,26 +2,45 @@ use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use crate::{
     api::error::{ApiError, ApiResult}, 
-    models::calendar_event::{CalendarEvent, NewCalendarEvent, UpdatedCalendarEvent},
-    repositories::Repositories
+    models::{calendar_event::{CalendarEvent, NewCalendarEvent, UpdatedCalendarEvent}, extraction::SavedEvents, import_batch::ImportSource},
+    repositories::Repositories,
+    services::extractions_service::ExtractionsService
 };
 
 /// Handles business logic for calendar event routes.
 #[derive(Clone, Debug)]
 pub struct CalendarEventsService {
     repositories: Repositories,
+    extractions: ExtractionsService
 }
 
 impl CalendarEventsService {
-    pub fn new(repositories: Repositories) -> Self {
-        Self { repositories }
+    pub fn new(repositories: Repositories, extractions: ExtractionsService) -> Self {
+        Self { repositories, extractions }
     }
 
-    pub async fn create_events(&self, user_id: Uuid, events: Vec<NewCalendarEvent>) -> ApiResult<()> {
+    /// Create events in an import batch (so they can be undone together), optionally recording them as saved from an extraction (see `ExtractionsService`).
+    pub async fn create_events(&self, user_id: Uuid, events: Vec<NewCalendarEvent>, extraction_id: Option<Uuid>) -> ApiResult<()> {
+        let feedback = match extraction_id {
+            Some(extraction_id) => {
+                self.extractions.check_extraction(user_id, extraction_id).await?;
+                Some((extraction_id, SavedEvents { events: events.clone(), ..Default::default() }))
+            },
+            None => None
+        };
+        let mut tx = self.repositories.begin().await?;
+        let import_batch_id = self.repositories
+            .import_batches
+            .create_batch_in(&mut tx, user_id, ImportSource::Events, extraction_id)
+            .await?;
         self.repositories
             .calendar_events
-            .create_events(user_id, events)
+            .create_events_in(&mut tx, user_id, events, extraction_id, import_batch_id)
             .await?;
+        tx.commit().await?;
+        if let Some((extraction_id, saved)) = feedback {
+            self.extractions.record_saved(user_id, extraction_id, saved).await;
+        }
         Ok(())
     }
 
@@ -0,0 +1,184 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    llm::{context::{SearchContext, SearchResults}, ExistingEventKind, GeneratedSearch, SearchOrder, LLM},
+    models::{
+        ai_usage::AIUsageKind,
+        calendar_query::{CalendarAnswer, CalendarSearch, MatchedEvent},
+        recurring_event_group::RecurringEventGroup,
+        time::UserTimezone
+    },
+    repositories::Repositories,
+    services::{ai_usage_service::AIUsageService, recurring_events_service::{EventsQuery, RecurringEventsService}}
+};
+
+/// How long a search's period is when the question only implies one end of it (or neither).
+const DEFAULT_SEARCH_DAYS: i64 = 365;
+
+/// The longest period a search can cover, so a vague question doesn't expand years of recurring events.
+const MAX_SEARCH_DAYS: i64 = 2 * 365;
+
+/// The most matching events returned (and given to the LLM to answer from).
+const MAX_RESULTS: usize = 50;
+
+/// Handles business logic for answering natural language questions about the user's calendar.
+///
+/// The LLM only ever turns the question into a `GeneratedSearch`; the search is run here, over the user's own events
+/// (through the repositories), and the LLM then answers from the events it found.
+#[derive(Clone, Debug)]
+pub struct CalendarQueryService {
+    llm: LLM,
+    repositories: Repositories,
+    recurring_events: RecurringEventsService,
+    usage: AIUsageService
+}
+
+impl CalendarQueryService {
+    pub fn new(llm: LLM, repositories: Repositories, recurring_events: RecurringEventsService, usage: AIUsageService) -> Self {
+        Self {
+            llm,
+            repositories,
+            recurring_events,
+            usage
+        }
+    }
+
+    /// Answer a question about the user's calendar, like "when is my next dentist appointment?".
+    pub async fn ask(&self, user_id: Uuid, question: String, timezone: UserTimezone) -> ApiResult<CalendarAnswer> {
+        let mut meter = self.usage.start(user_id, AIUsageKind::Question).await?;
+        let groups = self.repositories
+            .recurring_event_groups
+            .fetch_all_groups_with_counts(user_id)
+            .await?
+            .into_iter()
+            .map(|g| g.group)
+            .collect();
+        let search_context = SearchContext { groups };
+        let now = Utc::now();
+        let generated = self.llm
+            .search_from_question(question.clone(), now, timezone, &search_context, &mut meter.tokens)
+            .await?;
+
+        let search = Self::to_search(generated, &search_context, now)?;
+        let (events, total_matches) = self.search(user_id, &search, &search_context.groups).await?;
+        tracing::trace!("Question matched {total_matches} events, returning {}", events.len());
+
+        let results = SearchResults { search: &search, events: &events, total_matches };
+        let answer = self.llm
+            .answer_from_results(question, now, timezone, results, &mut meter.tokens)
+            .await?;
+        Ok(CalendarAnswer { answer, search, events, total_matches })
+    }
+
+    /// Fill in and limit a generated search, dropping any group the LLM made up.
+    fn to_search(generated: GeneratedSearch, search_context: &SearchContext, now: DateTime<Utc>) -> ApiResult<CalendarSearch> {
+        let default_period = Duration::days(DEFAULT_SEARCH_DAYS);
+        let (start_time, end_time) = match (generated.start_time, generated.end_time) {
+            (Some(start), Some(end)) => (start, end),
+            (Some(start), None) => (start, start + default_period),
+            (None, Some(end)) => (end - default_period, end),
+            (None, None) => match generated.order {
+                SearchOrder::Earliest => (now, now + default_period),
+                SearchOrder::Latest => (now - default_period, now)
+            }
+        };
+        if end_time <= start_time {
+            return Err(ApiError::unprocessable_entity([("question", "doesn't give a period to search")]));
+        }
+        // keep the end of the period nearest the events the question is about
+        let max_period = Duration::days(MAX_SEARCH_DAYS);
+        let (start_time, end_time) = match generated.order {
+            SearchOrder::Earliest => (start_time, end_time.min(start_time + max_period)),
+            SearchOrder::Latest => (start_time.max(end_time - max_period), end_time)
+        };
+
+        let group_id = generated.group_id.filter(|&group_id| {
+            let known = search_context.groups.iter().any(|g| g.id == group_id);
+            if !known {
+                tracing::debug!("Dropping unknown group ID {group_id} from generated search");
+            }
+            known
+        });
+        let keywords = generated.keywords
+            .iter()
+            .map(|keyword| keyword.trim().to_lowercase())
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        let limit = generated.limit
+            .and_then(|limit| usize::try_from(limit).ok())
+            .unwrap_or(MAX_RESULTS)
+            .clamp(1, MAX_RESULTS);
+
+        Ok(CalendarSearch { start_time, end_time, keywords, group_id, order: generated.order, limit })
+    }
+
+    /// Run a search over the user's events and recurring event instances, returning the first matches and how many
+    /// there were in total.
+    async fn search(
+        &self,
+        user_id: Uuid,
+        search: &CalendarSearch,
+        groups: &[RecurringEventGroup]
+    ) -> ApiResult<(Vec<MatchedEvent>, usize)> {
+        let calendar_events = self.repositories
+            .calendar_events
+            .get_events_by_user_and_date_range(user_id, search.start_time, search.end_time)
+            .await?;
+        let recurring_instances = self.recurring_events
+            .get_events(user_id, EventsQuery { start: search.start_time, end: search.end_time })
+            .await?;
+
+        let mut matches: Vec<_> = calendar_events
+            .into_iter()
+            .map(|e| MatchedEvent {
+                kind: ExistingEventKind::CalendarEvent,
+                event_id: e.id,
+                title: e.title,
+                description: e.description,
+                location: e.location,
+                start_time: e.start_time,
+                end_time: e.end_time,
+                group_id: e.group_id,
+                group_name: e.group_id
+                    .and_then(|group_id| groups.iter().find(|g| g.id == group_id))
+                    .map(|g| g.name.clone())
+            })
+            .chain(recurring_instances.into_iter().map(|e| MatchedEvent {
+                kind: ExistingEventKind::RecurringEvent,
+                event_id: e.recurring_event_id,
+                title: e.title,
+                description: e.description,
+                location: e.location,
+                start_time: e.start_time,
+                end_time: e.end_time,
+                group_id: e.group.as_ref().map(|g| g.id),
+                group_name: e.group.map(|g| g.name)
+            }))
+            .filter(|e| search.group_id.is_none_or(|group_id| e.group_id == Some(group_id)))
+            .filter(|e| Self::matches_keywords(e, &search.keywords))
+            .collect();
+        match search.order {
+            SearchOrder::Earliest => matches.sort_by_key(|e| e.start_time),
+            SearchOrder::Latest => matches.sort_by_key(|e| std::cmp::Reverse(e.start_time))
+        }
+
+        let total_matches = matches.len();
+        matches.truncate(search.limit);
+        Ok((matches, total_matches))
+    }
+
+    /// Whether any of the (lowercase) keywords is in the event's title, description, location or group name.
+    fn matches_keywords(event: &MatchedEvent, keywords: &[String]) -> bool {
+        if keywords.is_empty() {
+            return true;
+        }
+        let text = [Some(&event.title), event.description.as_ref(), event.location.as_ref(), event.group_name.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|field| field.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+        keywords.iter().any(|keyword| text.contains(keyword.as_str()))
+    }
+}
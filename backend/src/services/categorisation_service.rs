@@ -0,0 +1,473 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    config::AICategorisationConfig,
+    llm::{context::CategorisationContext, GeneratedCategories, LLM},
+    models::{
+        ai_usage::AIUsageKind,
+        calendar_event::CalendarEvent,
+        categorisation::{
+            AcceptedCategories, AcceptedCategory, CategorisationRule, CategorySuggestion, CategorySuggestions,
+            CategoryTarget, NewCategorisationRule, RuleField, SuggestionSource
+        },
+        import_batch::ImportSource,
+        recurring_event_group::{NewRecurringEventGroup, RecurringEventGroup}
+    },
+    repositories::{recurring_event_groups_repo::GroupedEventSummary, Repositories},
+    services::ai_usage_service::AIUsageService
+};
+
+/// The most ungrouped events categorised at once.
+const MAX_EVENTS: i64 = 500;
+
+/// The most events given to the LLM to categorise at once.
+const MAX_LLM_EVENTS: usize = 100;
+
+/// How well an event must match a group (see `keyword_score`) to be suggested it.
+const MIN_KEYWORD_SCORE: usize = 2;
+
+/// How many events must share a title to be suggested a new group of their own.
+const MIN_REPEATED_EVENTS: usize = 2;
+
+/// The colours (as ARGB) given to suggested new groups, in turn.
+const NEW_GROUP_COLORS: [u32; 8] = [
+    0xFF4285F4, 0xFFDB4437, 0xFFF4B400, 0xFF0F9D58, 0xFFAB47BC, 0xFF00ACC1, 0xFFFF7043, 0xFF9E9D24
+];
+
+/// Words too common in event titles to categorise them by.
+const STOP_WORDS: [&str; 16] = [
+    "the", "and", "for", "with", "from", "meeting", "call", "sync", "catch", "chat", "event", "session", "weekly",
+    "daily", "monthly", "online"
+];
+
+/// Handles business logic for categorising imported (e.g. Outlook synced) events into groups.
+///
+/// Suggestions come from the user's saved rules, words shared with their groups, repeated titles and, optionally, the
+/// LLM. Accepting one can save a rule, which then categorises future imports automatically (see `apply_rules`).
+#[derive(Clone, Debug)]
+pub struct CategorisationService {
+    llm: LLM,
+    repositories: Repositories,
+    usage: AIUsageService,
+    /// Whether the LLM can be asked to categorise at all (see `AICategorisationConfig`).
+    use_llm: bool
+}
+
+/// What we know about one of the user's groups, to match events against.
+struct GroupProfile {
+    group: RecurringEventGroup,
+    /// Words in the group's name.
+    name_words: HashSet<String>,
+    /// Words in the titles of the group's events.
+    event_words: HashSet<String>,
+    /// The (lowercase) locations of the group's events.
+    locations: HashSet<String>
+}
+
+impl CategorisationService {
+    pub fn new(llm: LLM, repositories: Repositories, usage: AIUsageService, config: &AICategorisationConfig) -> Self {
+        Self {
+            llm,
+            repositories,
+            usage,
+            use_llm: config.use_llm
+        }
+    }
+
+    /// Suggest groups for the user's ungrouped events from an import batch or, if not given, from all their Outlook
+    /// syncs.
+    ///
+    /// Events nothing else matches are given to the LLM if `use_llm` is set (and it isn't disabled), counting towards
+    /// the user's quota; if the LLM fails, they're left uncategorised.
+    pub async fn suggest(&self, user_id: Uuid, import_batch_id: Option<Uuid>, use_llm: bool) -> ApiResult<CategorySuggestions> {
+        let events = self.repositories
+            .calendar_events
+            .get_ungrouped_imported_events(user_id, import_batch_id, MAX_EVENTS)
+            .await?;
+        let rules = self.repositories.categorisation_rules.fetch_rules(user_id).await?;
+        let profiles = self.group_profiles(user_id).await?;
+
+        let mut suggestions = Vec::new();
+        let mut remaining = Vec::new();
+        for event in events {
+            match suggest_from_rules(&event, &rules, &profiles).or_else(|| suggest_from_keywords(&event, &profiles)) {
+                Some(suggestion) => add_suggestion(&mut suggestions, suggestion),
+                None => remaining.push(event)
+            }
+        }
+        let mut remaining = suggest_from_repeated_titles(remaining, &mut suggestions, profiles.len());
+
+        if self.use_llm && use_llm && !remaining.is_empty() {
+            let groups = profiles.into_iter().map(|p| p.group).collect();
+            match self.suggest_from_llm(user_id, groups, &remaining, &mut suggestions).await {
+                Ok(categorised) => remaining.retain(|e| !categorised.contains(&e.id)),
+                Err(err) => tracing::warn!("Not categorising the remaining events with the LLM: {err}")
+            }
+        }
+
+        let uncategorised = remaining.into_iter().map(|e| e.id).collect();
+        Ok(CategorySuggestions { suggestions, uncategorised })
+    }
+
+    /// Put the user's events into the accepted groups, creating any new ones (in an import batch, so they can be
+    /// undone) and saving any rules.
+    pub async fn accept(&self, user_id: Uuid, accepted: Vec<AcceptedCategory>) -> ApiResult<AcceptedCategories> {
+        validate_accepted(&accepted)?;
+        let group_ids: Vec<_> = accepted
+            .iter()
+            .filter_map(|a| match a.target {
+                CategoryTarget::Group { group_id } => Some(Some(group_id)),
+                CategoryTarget::New { .. } => None
+            })
+            .collect();
+        let all_groups_authorized = self.repositories
+            .recurring_events
+            .validate_group_ownership(user_id, &group_ids)
+            .await?;
+        if !all_groups_authorized {
+            return Err(ApiError::Forbidden);
+        }
+
+        let mut tx = self.repositories.begin().await?;
+        let mut result = AcceptedCategories::default();
+        for category in accepted {
+            let group_id = match category.target {
+                CategoryTarget::Group { group_id } => group_id,
+                CategoryTarget::New { name, color } => {
+                    let import_batch_id = match result.import_batch_id {
+                        Some(import_batch_id) => import_batch_id,
+                        None => {
+                            let import_batch_id = self.repositories
+                                .import_batches
+                                .create_batch_in(&mut tx, user_id, ImportSource::Categories, None)
+                                .await?;
+                            *result.import_batch_id.insert(import_batch_id)
+                        }
+                    };
+                    let new_group = NewRecurringEventGroup {
+                        name: name.trim().to_string(),
+                        description: None,
+                        color,
+                        group_is_active: None,
+                        group_recurrence_start: None,
+                        group_recurrence_end: None
+                    };
+                    let group_id = self.repositories
+                        .recurring_event_groups
+                        .create_group_returning_id(&mut tx, user_id, &new_group, None, import_batch_id)
+                        .await?;
+                    result.created_groups.push(group_id);
+                    group_id
+                }
+            };
+            result.events += self.repositories
+                .calendar_events
+                .set_group_in(&mut tx, user_id, &category.event_ids, group_id)
+                .await?;
+            if let Some(rule) = category.rule {
+                let rule = NewCategorisationRule { field: rule.field, pattern: rule.pattern.trim().to_lowercase() };
+                self.repositories
+                    .categorisation_rules
+                    .save_rule_in(&mut tx, user_id, group_id, &rule)
+                    .await?;
+                result.rules += 1;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(result)
+    }
+
+    pub async fn rules(&self, user_id: Uuid) -> ApiResult<Vec<CategorisationRule>> {
+        let rules = self.repositories.categorisation_rules.fetch_rules(user_id).await?;
+        Ok(rules)
+    }
+
+    pub async fn delete_rule(&self, user_id: Uuid, rule_id: Uuid) -> ApiResult<()> {
+        let deleted = self.repositories.categorisation_rules.delete_rule(user_id, rule_id).await?;
+        if !deleted {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Put the events from an import batch into groups by the user's saved rules, returning how many were.
+    pub async fn apply_rules(&self, user_id: Uuid, import_batch_id: Uuid) -> ApiResult<u64> {
+        let rules = self.repositories.categorisation_rules.fetch_rules(user_id).await?;
+        if rules.is_empty() {
+            return Ok(0);
+        }
+        let events = self.repositories
+            .calendar_events
+            .get_ungrouped_imported_events(user_id, Some(import_batch_id), MAX_EVENTS)
+            .await?;
+
+        let mut event_ids_by_group: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for event in events {
+            if let Some(rule) = rules.iter().find(|rule| rule.matches(&event)) {
+                event_ids_by_group.entry(rule.group_id).or_default().push(event.id);
+            }
+        }
+        let mut categorised = 0;
+        for (group_id, event_ids) in event_ids_by_group {
+            categorised += self.repositories
+                .calendar_events
+                .set_group(user_id, &event_ids, group_id)
+                .await?;
+        }
+        tracing::debug!("Categorised {categorised} events from import batch {import_batch_id} by saved rules");
+        Ok(categorised)
+    }
+
+    /// The user's groups, along with the words and locations of their events.
+    async fn group_profiles(&self, user_id: Uuid) -> ApiResult<Vec<GroupProfile>> {
+        let groups = self.repositories
+            .recurring_event_groups
+            .fetch_all_groups_with_counts(user_id)
+            .await?;
+        let mut events_by_group: HashMap<Uuid, Vec<GroupedEventSummary>> = HashMap::new();
+        for event in self.repositories.recurring_event_groups.fetch_grouped_event_summaries(user_id).await? {
+            events_by_group.entry(event.group_id).or_default().push(event);
+        }
+        let profiles = groups
+            .into_iter()
+            .map(|g| {
+                let events = events_by_group.remove(&g.group.id).unwrap_or_default();
+                GroupProfile {
+                    name_words: words(&g.group.name).collect(),
+                    event_words: events.iter().flat_map(|e| words(&e.title)).collect(),
+                    locations: events.iter().filter_map(|e| e.location.as_deref()).map(normalise).collect(),
+                    group: g.group
+                }
+            })
+            .collect();
+        Ok(profiles)
+    }
+
+    /// Have the LLM categorise the remaining events, adding its (checked) suggestions and returning the IDs of the
+    /// events it categorised.
+    async fn suggest_from_llm(
+        &self,
+        user_id: Uuid,
+        groups: Vec<RecurringEventGroup>,
+        remaining: &[CalendarEvent],
+        suggestions: &mut Vec<CategorySuggestion>
+    ) -> ApiResult<HashSet<Uuid>> {
+        let mut meter = self.usage.start(user_id, AIUsageKind::Categorisation).await?;
+        let context = CategorisationContext {
+            groups,
+            events: remaining.iter().take(MAX_LLM_EVENTS).cloned().collect()
+        };
+        let generated: GeneratedCategories = self.llm
+            .categories_from_events(&context, &mut meter.tokens)
+            .await?;
+
+        let mut categorised = HashSet::new();
+        let mut new_groups = suggestions.iter().filter(|s| matches!(s.target, CategoryTarget::New { .. })).count();
+        for category in generated.categories {
+            let events: Vec<_> = category.events
+                .iter()
+                .filter_map(|&i| context.events.get(i as usize))
+                .filter(|e| categorised.insert(e.id))
+                .collect();
+            if events.is_empty() {
+                continue;
+            }
+            let (target, name) = match (category.group_id, category.new_group_name) {
+                (Some(group_id), _) if context.has_group(group_id) => {
+                    let name = context.groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()).unwrap_or_default();
+                    (CategoryTarget::Group { group_id }, name)
+                },
+                (_, Some(name)) if !name.trim().is_empty() => {
+                    let name = name.trim().to_string();
+                    // reuse the colour of a suggested new group with the same name, so the two are merged
+                    let color = suggestions
+                        .iter()
+                        .find_map(|s| match &s.target {
+                            CategoryTarget::New { name: other, color } if other.eq_ignore_ascii_case(&name) => Some(*color),
+                            _ => None
+                        })
+                        .unwrap_or_else(|| {
+                            new_groups += 1;
+                            new_group_color(context.groups.len() + new_groups - 1)
+                        });
+                    (CategoryTarget::New { name: name.clone(), color }, name)
+                },
+                _ => {
+                    tracing::debug!("Dropping generated category with neither a known group nor a new group name");
+                    events.iter().for_each(|e| { categorised.remove(&e.id); });
+                    continue;
+                }
+            };
+            // only keep a rule that actually matches the events it's for
+            let rule = category.rule
+                .map(|rule| NewCategorisationRule { field: rule.field, pattern: rule.pattern.trim().to_lowercase() })
+                .filter(|rule| events.iter().all(|e| rule.matches(e)));
+            add_suggestion(suggestions, CategorySuggestion {
+                target,
+                name,
+                event_ids: events.iter().map(|e| e.id).collect(),
+                rule,
+                source: SuggestionSource::Llm
+            });
+        }
+        Ok(categorised)
+    }
+}
+
+/// Suggest the group of the first (newest) saved rule matching the event.
+fn suggest_from_rules(event: &CalendarEvent, rules: &[CategorisationRule], profiles: &[GroupProfile]) -> Option<CategorySuggestion> {
+    let rule = rules.iter().find(|rule| rule.matches(event))?;
+    let profile = profiles.iter().find(|p| p.group.id == rule.group_id)?;
+    Some(CategorySuggestion {
+        target: CategoryTarget::Group { group_id: rule.group_id },
+        name: profile.group.name.clone(),
+        event_ids: vec![event.id],
+        rule: None,
+        source: SuggestionSource::Rule
+    })
+}
+
+/// Suggest the group the event best matches by its title's words and its location, if one clearly does.
+///
+/// The suggested rule is on the most distinctive thing matched: a word from the group's name, then the location, then a
+/// word from the group's events' titles.
+fn suggest_from_keywords(event: &CalendarEvent, profiles: &[GroupProfile]) -> Option<CategorySuggestion> {
+    let title_words: HashSet<_> = words(&event.title).collect();
+    let location = event.location.as_deref().map(normalise);
+    let mut scores: Vec<_> = profiles
+        .iter()
+        .map(|p| (keyword_score(p, &title_words, location.as_deref()), p))
+        .filter(|(score, _)| *score >= MIN_KEYWORD_SCORE)
+        .collect();
+    scores.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    let (score, profile) = *scores.first()?;
+    if scores.get(1).is_some_and(|(other, _)| *other == score) {
+        return None;
+    }
+
+    let longest = |words: &HashSet<String>| title_words
+        .intersection(words)
+        .max_by(|a, b| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+        .cloned();
+    let rule = longest(&profile.name_words)
+        .map(|pattern| NewCategorisationRule { field: RuleField::Title, pattern })
+        .or_else(|| location
+            .filter(|location| profile.locations.contains(location))
+            .map(|pattern| NewCategorisationRule { field: RuleField::Location, pattern }))
+        .or_else(|| longest(&profile.event_words).map(|pattern| NewCategorisationRule { field: RuleField::Title, pattern }));
+    Some(CategorySuggestion {
+        target: CategoryTarget::Group { group_id: profile.group.id },
+        name: profile.group.name.clone(),
+        event_ids: vec![event.id],
+        rule,
+        source: SuggestionSource::Keyword
+    })
+}
+
+/// How well an event matches a group: 2 for each title word in the group's name, 2 for being at one of the group's
+/// events' locations, and 1 for each other title word in the group's events' titles.
+fn keyword_score(profile: &GroupProfile, title_words: &HashSet<String>, location: Option<&str>) -> usize {
+    let name_matches = title_words.intersection(&profile.name_words).count();
+    let event_matches = title_words
+        .iter()
+        .filter(|word| !profile.name_words.contains(*word) && profile.event_words.contains(*word))
+        .count();
+    let location_match = location.is_some_and(|location| profile.locations.contains(location));
+    2 * name_matches + event_matches + if location_match { 2 } else { 0 }
+}
+
+/// Suggest new groups for events sharing a title, returning the rest.
+fn suggest_from_repeated_titles(
+    events: Vec<CalendarEvent>,
+    suggestions: &mut Vec<CategorySuggestion>,
+    existing_groups: usize
+) -> Vec<CalendarEvent> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for event in &events {
+        *counts.entry(normalise(&event.title)).or_default() += 1;
+    }
+
+    let mut remaining = Vec::new();
+    let mut new_groups: Vec<String> = Vec::new();
+    for event in events {
+        let title = normalise(&event.title);
+        if title.is_empty() || counts[&title] < MIN_REPEATED_EVENTS {
+            remaining.push(event);
+            continue;
+        }
+        let index = new_groups.iter().position(|t| *t == title).unwrap_or_else(|| {
+            new_groups.push(title.clone());
+            new_groups.len() - 1
+        });
+        let name = event.title.trim().to_string();
+        add_suggestion(suggestions, CategorySuggestion {
+            target: CategoryTarget::New { name: name.clone(), color: new_group_color(existing_groups + index) },
+            name,
+            event_ids: vec![event.id],
+            rule: Some(NewCategorisationRule { field: RuleField::Title, pattern: title }),
+            source: SuggestionSource::Repeated
+        });
+    }
+    remaining
+}
+
+/// Add a suggestion, merging it into an existing one with the same group, rule and source.
+fn add_suggestion(suggestions: &mut Vec<CategorySuggestion>, suggestion: CategorySuggestion) {
+    let existing = suggestions.iter_mut().find(|s| {
+        let same_target = match (&s.target, &suggestion.target) {
+            (CategoryTarget::New { name: a, .. }, CategoryTarget::New { name: b, .. }) => a.eq_ignore_ascii_case(b),
+            (a, b) => a == b
+        };
+        same_target && s.rule == suggestion.rule && s.source == suggestion.source
+    });
+    match existing {
+        Some(existing) => existing.event_ids.extend(suggestion.event_ids),
+        None => suggestions.push(suggestion)
+    }
+}
+
+/// Check accepted suggestions are complete, before anything is saved.
+fn validate_accepted(accepted: &[AcceptedCategory]) -> ApiResult<()> {
+    let mut errors = HashMap::new();
+    for category in accepted {
+        if category.event_ids.is_empty() {
+            errors.insert("event_ids", "is empty");
+        }
+        if let CategoryTarget::New { name, color } = &category.target {
+            if name.trim().is_empty() {
+                errors.insert("name", "is empty");
+            }
+            if *color <= 0 {
+                errors.insert("color", "is less than 0");
+            }
+        }
+        if category.rule.as_ref().is_some_and(|rule| rule.pattern.trim().is_empty()) {
+            errors.insert("pattern", "is empty");
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::unprocessable_entity(errors));
+    }
+    Ok(())
+}
+
+/// The colour for the `index`th group, cycling through `NEW_GROUP_COLORS`.
+fn new_group_color(index: usize) -> i64 {
+    NEW_GROUP_COLORS[index % NEW_GROUP_COLORS.len()].into()
+}
+
+/// The distinctive (lowercase) words in some text, to match events by.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3 && !word.chars().all(|c| c.is_numeric()))
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+}
+
+/// Lowercase text with its whitespace collapsed, to compare titles and locations by.
+fn normalise(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
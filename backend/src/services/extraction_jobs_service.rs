@@ -0,0 +1,221 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, MutexGuard}, time::Duration as StdDuration};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::{watch, Semaphore};
+use uuid::Uuid;
+
+use crate::{
+    api::{ai_upload::{UploadBody, UploadedFile}, error::{ApiError, ApiResult}},
+    config::{AIJobsConfig, UploadLimitsConfig},
+    llm::GeneratedEvents,
+    services::{ai_add_events_service::{AIAddEventsService, ExtractionOptions, ExtractionProgress, ExtractionStage}, ai_usage_service::AIUsageService}
+};
+
+/// The input of an extraction job.
+pub enum ExtractionInput {
+    Text(String),
+    Audio { audio: UploadedFile, context: Option<String> },
+    Image { image: UploadBody, context: Option<String> },
+    Email { email: UploadBody, context: Option<String> }
+}
+
+/// A snapshot of an extraction job.
+#[derive(Serialize, Clone, Debug)]
+pub struct ExtractionJob {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub state: ExtractionJobState,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+/// The state of an extraction job; jobs move through these in order, ending in either `Done` or `Failed`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ExtractionJobState {
+    /// Waiting for one of the workers to be free.
+    Queued,
+    Extracting,
+    Parsing,
+    Done { result: GeneratedEvents },
+    /// `status` is the status code the extraction would've failed with if it weren't run as a job, and `error` is
+    /// the error's client message.
+    Failed { status: u16, error: String }
+}
+
+impl ExtractionJobState {
+    /// Whether the job is done or failed, so won't change again.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done { .. } | Self::Failed { .. })
+    }
+}
+
+impl From<ExtractionStage> for ExtractionJobState {
+    fn from(stage: ExtractionStage) -> Self {
+        match stage {
+            ExtractionStage::Extracting => Self::Extracting,
+            ExtractionStage::Parsing => Self::Parsing
+        }
+    }
+}
+
+/// How long a user whose queue is full is told to wait; roughly how long an extraction takes.
+const QUEUE_FULL_RETRY_AFTER: StdDuration = StdDuration::from_secs(15);
+
+/// A job in the store.
+#[derive(Debug)]
+struct JobEntry {
+    user_id: Uuid,
+    job: watch::Sender<ExtractionJob>
+}
+
+/// Handles business logic for running AI extractions as background jobs.
+///
+/// Jobs are kept in memory, so they're lost on restart; finished jobs are dropped once their result has been kept
+/// for the configured TTL.
+#[derive(Clone, Debug)]
+pub struct ExtractionJobsService {
+    ai_add_events: AIAddEventsService,
+    usage: AIUsageService,
+    jobs: Arc<Mutex<HashMap<Uuid, JobEntry>>>,
+    /// Limits how many jobs run at once.
+    workers: Arc<Semaphore>,
+    max_queued_jobs_per_user: usize,
+    result_ttl: Duration
+}
+
+impl ExtractionJobsService {
+    pub fn new(ai_add_events: AIAddEventsService, usage: AIUsageService, config: &AIJobsConfig) -> Self {
+        Self {
+            ai_add_events,
+            usage,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            workers: Arc::new(Semaphore::new(config.max_concurrent_jobs)),
+            max_queued_jobs_per_user: config.max_queued_jobs_per_user,
+            result_ttl: Duration::seconds(config.result_ttl_seconds.try_into().unwrap_or(i64::MAX))
+        }
+    }
+
+    /// Queue an extraction, returning the new job straight away.
+    /// 
+    /// The user's AI quota is checked up front, so they aren't given a job which is bound to fail, and each user can
+    /// only have so many unfinished jobs, so one user can't fill the queue for everyone else.
+    /// The job reports its own progress, so any in `options` is replaced.
+    pub async fn submit(
+        &self,
+        user_id: Uuid,
+        input: ExtractionInput,
+        options: ExtractionOptions,
+        limits: &UploadLimitsConfig
+    ) -> ApiResult<ExtractionJob> {
+        self.usage.check_quota(user_id).await?;
+        self.purge_expired();
+
+        let now = Utc::now();
+        let job = ExtractionJob {
+            id: Uuid::new_v4(),
+            state: ExtractionJobState::Queued,
+            created_at: now,
+            updated_at: now
+        };
+        let (sender, _) = watch::channel(job.clone());
+        {
+            // checked while holding the lock, so concurrent submissions can't both squeeze in
+            let mut jobs = self.lock_jobs();
+            let unfinished = jobs
+                .values()
+                .filter(|entry| entry.user_id == user_id && !entry.job.borrow().state.is_finished())
+                .count();
+            if unfinished >= self.max_queued_jobs_per_user {
+                return Err(ApiError::QuotaExceeded {
+                    message: format!(
+                        "You already have {unfinished} extractions in progress; wait for one to finish before starting another"
+                    ),
+                    retry_after: QUEUE_FULL_RETRY_AFTER
+                });
+            }
+            jobs.insert(job.id, JobEntry { user_id, job: sender.clone() });
+        }
+
+        let progress_sender = sender.clone();
+        let options = ExtractionOptions {
+            progress: ExtractionProgress::new(move |stage| set_state(&progress_sender, stage.into())),
+            ..options
+        };
+        let ai_add_events = self.ai_add_events.clone();
+        let workers = self.workers.clone();
+        let limits = limits.clone();
+        let job_id = job.id;
+        tokio::spawn(async move {
+            let _permit = workers.acquire_owned().await.expect("The workers semaphore is never closed");
+            // the extraction runs in a task of its own, so the job still finishes (as failed) if it panics
+            let extraction = tokio::spawn(async move {
+                match input {
+                    ExtractionInput::Text(text) => ai_add_events.generate_from_text(user_id, text, options).await,
+                    ExtractionInput::Audio { audio, context } => ai_add_events
+                        .generate_from_audio(user_id, audio, context, options, &limits)
+                        .await,
+                    ExtractionInput::Image { image, context } => ai_add_events
+                        .generate_from_image(user_id, image, context, options, &limits)
+                        .await,
+                    ExtractionInput::Email { email, context } => ai_add_events
+                        .generate_from_email(user_id, email, context, options, &limits)
+                        .await
+                }
+            });
+            let result = extraction
+                .await
+                .unwrap_or_else(|err| Err(ApiError::Internal(format!("The extraction job panicked: {err}"))));
+            let state = match result {
+                Ok(result) => ExtractionJobState::Done { result },
+                Err(err) => {
+                    tracing::debug!("Extraction job {job_id} failed: {err:?}");
+                    ExtractionJobState::Failed { status: err.status_code().as_u16(), error: err.client_message() }
+                }
+            };
+            set_state(&sender, state);
+        });
+
+        Ok(job)
+    }
+
+    /// Get a snapshot of the user's job.
+    pub fn get_job(&self, user_id: Uuid, job_id: Uuid) -> ApiResult<ExtractionJob> {
+        Ok(self.subscribe(user_id, job_id)?.borrow().clone())
+    }
+
+    /// Watch the user's job, which is marked as changed whenever its state does.
+    ///
+    /// The sender is dropped (so `changed` errors) once the job expires.
+    pub fn subscribe(&self, user_id: Uuid, job_id: Uuid) -> ApiResult<watch::Receiver<ExtractionJob>> {
+        self.purge_expired();
+        let jobs = self.lock_jobs();
+        let entry = jobs.get(&job_id).ok_or(ApiError::NotFound)?;
+        if entry.user_id != user_id {
+            return Err(ApiError::Forbidden);
+        }
+        Ok(entry.job.subscribe())
+    }
+
+    /// Drop the finished jobs which have outlived the TTL.
+    fn purge_expired(&self) {
+        let expire_before = Utc::now() - self.result_ttl;
+        self.lock_jobs().retain(|_, entry| {
+            let job = entry.job.borrow();
+            !job.state.is_finished() || job.updated_at > expire_before
+        });
+    }
+
+    fn lock_jobs(&self) -> MutexGuard<'_, HashMap<Uuid, JobEntry>> {
+        // the map is always left consistent, so it's fine to carry on if another thread panicked while holding it
+        self.jobs.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Move the job into a new state.
+fn set_state(job: &watch::Sender<ExtractionJob>, state: ExtractionJobState) {
+    job.send_modify(|job| {
+        job.state = state;
+        job.updated_at = Utc::now();
+    });
+}
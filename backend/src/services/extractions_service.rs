@@ -0,0 +1,318 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    config::AIProvenanceConfig,
+    llm::GeneratedEvents,
+    models::{
+        calendar_event::NewCalendarEvent,
+        extraction::{
+            Extraction, ExtractionDiff, ExtractionSource, FieldChange, ItemCorrection, ItemKind, ItemSummary,
+            NewExtraction, SavedEvents, StoredInput
+        },
+        recurring_event::NewRecurringEvent
+    },
+    repositories::Repositories,
+    services::ai_add_events_service::ExtractionOptions,
+    utils::matching::{occurrences, pair_by_title_and_start, same_occurrences}
+};
+
+/// How far apart a generated and saved item's starts can be for them to pair up regardless of their titles.
+const PAIR_START_TOLERANCE_MINUTES: i64 = 1;
+
+/// How similar a generated and saved item's titles must be for them to pair up regardless of their starts.
+const PAIR_TITLE_SIMILARITY: f64 = 0.5;
+
+/// An extraction the user has saved events from, in the shape of an eval case (see `crate::eval`).
+///
+/// Only text inputs are included; the input of any other case has to be added before it can be run.
+#[derive(Serialize, Debug)]
+pub struct FeedbackFixture {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<FixtureInput>,
+    pub timezone: String,
+    /// When the extraction was made, so relative dates resolve the same way.
+    pub now: DateTime<Utc>,
+    pub locale: Option<String>,
+    pub output_language: Option<String>,
+    pub expected: SavedEvents,
+    /// The serialized `GeneratedEvents`, for reference.
+    pub generated: serde_json::Value,
+    /// The serialized `ExtractionDiff`, for reference.
+    pub diff: serde_json::Value
+}
+
+/// The input of a fixture (as in `CaseInput`).
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FixtureInput {
+    Text { text: String }
+}
+
+/// Records AI extractions (and where they came from), and how the events users save from them differ from what was
+/// extracted, to measure and improve extraction.
+#[derive(Clone, Debug)]
+pub struct ExtractionsService {
+    repositories: Repositories,
+    /// The model extractions are made with.
+    model: String,
+    max_stored_input_bytes: usize,
+    unsaved_input_retention: Duration
+}
+
+impl ExtractionsService {
+    /// `model` is the LLM's model, which extractions are recorded as made with.
+    pub fn new(repositories: Repositories, model: &str, provenance: &AIProvenanceConfig) -> Self {
+        Self {
+            repositories,
+            model: model.into(),
+            max_stored_input_bytes: provenance.max_stored_input_bytes,
+            unsaved_input_retention: Duration::days(provenance.unsaved_input_retention_days.try_into().unwrap_or(i64::MAX))
+        }
+    }
+
+    /// Record an extraction and where it came from, returning its id for saving events against.
+    ///
+    /// `events` should have had their `extraction_text` taken out, as it's kept separately.
+    /// This mustn't fail the extraction, so any error is only logged (and no id returned).
+    /// Inputs kept past their retention, which no events were saved from, are cleared out at the same time.
+    pub async fn record_extraction(
+        &self,
+        user_id: Uuid,
+        events: &GeneratedEvents,
+        extraction_text: Option<String>,
+        options: &ExtractionOptions,
+        source: &ExtractionSource
+    ) -> Option<Uuid> {
+        let serialized_events = serde_json::to_value(events)
+            .inspect_err(|err| tracing::warn!("Failed to serialize an extraction for recording: {err}"))
+            .ok()?;
+        let extraction = NewExtraction {
+            user_id,
+            timezone: options.timezone.to_string(),
+            locale: options.locale.as_ref().map(ToString::to_string),
+            output_language: options.output_language.as_ref().map(ToString::to_string),
+            modality: source.modality,
+            input_hash: source.hash(),
+            input_content_type: source.content_type.clone(),
+            input_context: source.context.clone(),
+            input: (source.bytes.len() <= self.max_stored_input_bytes).then(|| source.bytes.to_vec()),
+            model: self.model.clone(),
+            prompt_version: events.prompt_version.clone(),
+            extraction_text,
+            events: serialized_events
+        };
+        let extraction_id = self.repositories
+            .extractions
+            .create_extraction(extraction)
+            .await
+            .inspect_err(|err| tracing::warn!("Failed to record an extraction: {err}"))
+            .ok()?;
+        if let Err(err) = self.repositories
+            .extractions
+            .clear_unsaved_inputs(Utc::now() - self.unsaved_input_retention)
+            .await
+        {
+            tracing::warn!("Failed to clear out old extraction inputs: {err}");
+        }
+        Some(extraction_id)
+    }
+
+    /// Get one of the user's extractions, to show where events saved from it came from.
+    pub async fn get_extraction(&self, user_id: Uuid, extraction_id: Uuid) -> ApiResult<Extraction> {
+        self.check_extraction(user_id, extraction_id).await?;
+        self.repositories
+            .extractions
+            .get_extraction(user_id, extraction_id)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Get the input of one of the user's extractions, if it was kept.
+    pub async fn get_input(&self, user_id: Uuid, extraction_id: Uuid) -> ApiResult<StoredInput> {
+        self.check_extraction(user_id, extraction_id).await?;
+        self.repositories
+            .extractions
+            .get_input(user_id, extraction_id)
+            .await?
+            .ok_or_else(|| ApiError::BadRequest("The input of this extraction wasn't kept".into()))
+    }
+
+    /// Check the user can save events against the extraction, before saving them.
+    pub async fn check_extraction(&self, user_id: Uuid, extraction_id: Uuid) -> ApiResult<()> {
+        let owner = self.repositories
+            .extractions
+            .get_extraction_owner(extraction_id)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        if owner != user_id {
+            return Err(ApiError::Forbidden);
+        }
+        Ok(())
+    }
+
+    /// Record that events were saved from the extraction, updating its diff.
+    ///
+    /// The events may be saved across several requests (e.g. one-off and recurring events separately), so the diff is
+    /// always between everything generated and everything saved so far.
+    /// The events have already been saved by now, so any error is only logged.
+    pub async fn record_saved(&self, user_id: Uuid, extraction_id: Uuid, saved: SavedEvents) {
+        if let Err(err) = self.try_record_saved(user_id, extraction_id, saved).await {
+            tracing::warn!("Failed to record the events saved from extraction {extraction_id}: {err}");
+        }
+    }
+
+    async fn try_record_saved(&self, user_id: Uuid, extraction_id: Uuid, saved: SavedEvents) -> ApiResult<()> {
+        let mut tx = self.repositories.begin().await?;
+        let extraction = self.repositories.extractions.lock_extraction_in(&mut tx, extraction_id).await?;
+        let generated: GeneratedEvents = serde_json::from_value(extraction.events)
+            .map_err(|err| ApiError::Internal(format!("Failed to parse the recorded extraction: {err}")))?;
+
+        let mut all_saved = match self.repositories.extractions.get_saved_events_in(&mut tx, extraction_id).await? {
+            Some(previously_saved) => serde_json::from_value(previously_saved)
+                .map_err(|err| ApiError::Internal(format!("Failed to parse the previously saved events: {err}")))?,
+            None => SavedEvents::default()
+        };
+        all_saved.extend(saved);
+
+        let diff = diff_extraction(&generated, &all_saved);
+        let serialize_error = |err: serde_json::Error| ApiError::Internal(format!("Failed to serialize extraction feedback: {err}"));
+        let saved = serde_json::to_value(&all_saved).map_err(serialize_error)?;
+        let diff = serde_json::to_value(&diff).map_err(serialize_error)?;
+        self.repositories
+            .extractions
+            .put_feedback_in(&mut tx, extraction_id, user_id, saved, diff)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Export the user's extractions which have feedback as fixtures, oldest first.
+    pub async fn export_fixtures(&self, user_id: Uuid, since: Option<DateTime<Utc>>) -> ApiResult<Vec<FeedbackFixture>> {
+        let extractions = self.repositories.extractions.fetch_with_feedback(user_id, since).await?;
+        extractions
+            .into_iter()
+            .map(|extraction| Ok(FeedbackFixture {
+                name: format!("feedback_{}", extraction.id),
+                input: extraction.input_text.map(|text| FixtureInput::Text { text }),
+                timezone: extraction.timezone,
+                now: extraction.created_at,
+                locale: extraction.locale,
+                output_language: extraction.output_language,
+                expected: serde_json::from_value(extraction.saved)
+                    .map_err(|err| ApiError::Internal(format!("Failed to parse the saved events of extraction {}: {err}", extraction.id)))?,
+                generated: extraction.events,
+                diff: extraction.diff
+            }))
+            .collect()
+    }
+}
+
+/// Compare what was saved from an extraction against what was generated.
+///
+/// Items are paired up by their titles and starts (so a renamed or moved item is still recognized), then each pair's
+/// fields are compared; anything left over was either dropped or added by the user.
+fn diff_extraction(generated: &GeneratedEvents, saved: &SavedEvents) -> ExtractionDiff {
+    let mut diff = ExtractionDiff::default();
+
+    let generated_events: Vec<_> = generated.events.iter().map(|e| &e.item).collect();
+    let saved_events: Vec<_> = saved.events.iter().collect();
+    diff_items(&generated_events, &saved_events, event_summary, event_correction, &mut diff);
+
+    let generated_recurring: Vec<_> = generated.all_recurring_events().map(|e| &e.item.event).collect();
+    let saved_recurring: Vec<_> = saved.recurring_events.iter().map(|e| &e.event).collect();
+    diff_items(&generated_recurring, &saved_recurring, recurring_event_summary, recurring_event_correction, &mut diff);
+
+    diff
+}
+
+/// Pair up generated and saved items of one kind, and add how they differ to the diff.
+fn diff_items<T>(
+    generated: &[&T],
+    saved: &[&T],
+    summarize: impl Fn(&T) -> ItemSummary,
+    correct: impl Fn(&T, &T) -> ItemCorrection,
+    diff: &mut ExtractionDiff
+) {
+    let generated_summaries: Vec<_> = generated.iter().map(|item| summarize(item)).collect();
+    let saved_summaries: Vec<_> = saved.iter().map(|item| summarize(item)).collect();
+    let keys = |summaries: &[ItemSummary]| summaries
+        .iter()
+        .map(|summary| (summary.title.clone(), summary.start))
+        .collect::<Vec<_>>();
+    let pairs = pair_by_title_and_start(
+        &keys(&generated_summaries),
+        &keys(&saved_summaries),
+        Duration::minutes(PAIR_START_TOLERANCE_MINUTES),
+        PAIR_TITLE_SIMILARITY
+    );
+
+    let (mut generated_paired, mut saved_paired) = (vec![false; generated.len()], vec![false; saved.len()]);
+    for (i, j) in pairs {
+        generated_paired[i] = true;
+        saved_paired[j] = true;
+        let correction = correct(generated[i], saved[j]);
+        if correction.is_empty() {
+            diff.unchanged += 1;
+        } else {
+            diff.corrected.push(correction);
+        }
+    }
+
+    let unpaired = |summaries: Vec<ItemSummary>, paired: Vec<bool>| summaries
+        .into_iter()
+        .zip(paired)
+        .filter(|(_, paired)| !paired)
+        .map(|(summary, _)| summary);
+    diff.dropped.extend(unpaired(generated_summaries, generated_paired));
+    diff.added.extend(unpaired(saved_summaries, saved_paired));
+}
+
+fn event_summary(event: &NewCalendarEvent) -> ItemSummary {
+    ItemSummary { kind: ItemKind::Event, title: event.title.clone(), start: event.start_time }
+}
+
+fn recurring_event_summary(event: &NewRecurringEvent) -> ItemSummary {
+    ItemSummary { kind: ItemKind::RecurringEvent, title: event.title.clone(), start: event.recurrence_start }
+}
+
+fn event_correction(generated: &NewCalendarEvent, saved: &NewCalendarEvent) -> ItemCorrection {
+    ItemCorrection {
+        title: FieldChange::between(generated.title.clone(), saved.title.clone()),
+        description: FieldChange::between(generated.description.clone(), saved.description.clone()),
+        location: FieldChange::between(generated.location.clone(), saved.location.clone()),
+        start_shift_seconds: nonzero_seconds(saved.start_time - generated.start_time),
+        duration_change_seconds: nonzero_seconds(
+            (saved.end_time - saved.start_time) - (generated.end_time - generated.start_time)
+        ),
+        ..ItemCorrection::new(event_summary(generated))
+    }
+}
+
+fn recurring_event_correction(generated: &NewRecurringEvent, saved: &NewRecurringEvent) -> ItemCorrection {
+    // only count the rule as changed if it gives different occurrences over the same period,
+    // so moving the recurrence (or writing the same rule differently) doesn't
+    let mut regenerated = generated.clone();
+    regenerated.recurrence_start = saved.recurrence_start;
+    regenerated.recurrence_end = saved.recurrence_end;
+    let rrule_changed = !same_occurrences(&occurrences(&regenerated), &occurrences(saved), Duration::zero());
+
+    ItemCorrection {
+        title: FieldChange::between(generated.title.clone(), saved.title.clone()),
+        description: FieldChange::between(generated.description.clone(), saved.description.clone()),
+        location: FieldChange::between(generated.location.clone(), saved.location.clone()),
+        start_shift_seconds: nonzero_seconds(saved.recurrence_start - generated.recurrence_start),
+        duration_change_seconds: nonzero_seconds(Duration::seconds(
+            i64::from(saved.event_duration_seconds.0) - i64::from(generated.event_duration_seconds.0)
+        )),
+        rrule: rrule_changed.then(|| FieldChange { from: generated.rrule.rule_string(), to: saved.rrule.rule_string() }),
+        recurrence_end: FieldChange::between(generated.recurrence_end, saved.recurrence_end),
+        ..ItemCorrection::new(recurring_event_summary(generated))
+    }
+}
+
+fn nonzero_seconds(duration: Duration) -> Option<i64> {
+    Some(duration.num_seconds()).filter(|seconds| *seconds != 0)
+}
@@ -0,0 +1,59 @@
+use uuid::Uuid;
+
+use crate::{
+    api::error::{ApiError, ApiResult},
+    models::import_batch::{ImportBatch, UndoneBatch},
+    repositories::Repositories
+};
+
+/// How many batches are listed by default.
+const DEFAULT_BATCH_LIMIT: i64 = 20;
+
+/// The most batches which can be listed at once.
+const MAX_BATCH_LIMIT: i64 = 100;
+
+/// Handles business logic for listing and undoing import batches (everything created in one go).
+#[derive(Clone, Debug)]
+pub struct ImportBatchesService {
+    repositories: Repositories
+}
+
+impl ImportBatchesService {
+    pub fn new(repositories: Repositories) -> Self {
+        Self { repositories }
+    }
+
+    /// Get the user's most recent batches, newest first.
+    pub async fn recent_batches(&self, user_id: Uuid, limit: Option<i64>) -> ApiResult<Vec<ImportBatch>> {
+        let limit = limit.unwrap_or(DEFAULT_BATCH_LIMIT).clamp(1, MAX_BATCH_LIMIT);
+        let batches = self.repositories
+            .import_batches
+            .fetch_recent_batches(user_id, limit)
+            .await?;
+        Ok(batches)
+    }
+
+    /// Delete everything created in one of the user's batches, in one transaction.
+    pub async fn undo_batch(&self, user_id: Uuid, batch_id: Uuid) -> ApiResult<UndoneBatch> {
+        let mut tx = self.repositories.begin().await?;
+        let undone_at = self.repositories
+            .import_batches
+            .lock_batch_in(&mut tx, user_id, batch_id)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        if undone_at.is_some() {
+            return Err(ApiError::BadRequest("This import has already been undone".into()));
+        }
+
+        let undone = self.repositories
+            .import_batches
+            .undo_batch_in(&mut tx, batch_id)
+            .await?;
+        tx.commit().await?;
+        tracing::debug!(
+            "Undid import batch {batch_id}: {} events, {} recurring events and {} groups deleted",
+            undone.events, undone.recurring_events, undone.groups
+        );
+        Ok(undone)
+    }
+}
@@ -1,9 +1,18 @@
-use crate::{llm::LLM, repositories::Repositories, services::{ai_add_events_service::AIAddEventsService, azure_token_service::AzureTokenService, calendar_events_service::CalendarEventsService, outlook_calendar_service::OutlookCalendarService, recurring_event_groups_service::RecurringEventGroupsService, recurring_events_service::RecurringEventsService}};
+use crate::{config::Config, llm::LLM, repositories::Repositories, services::{ai_add_events_service::AIAddEventsService, ai_edit_events_service::AIEditEventsService, ai_usage_service::AIUsageService, azure_token_service::AzureTokenService, briefings_service::BriefingsService, calendar_events_service::CalendarEventsService, calendar_query_service::CalendarQueryService, categorisation_service::CategorisationService, extractions_service::ExtractionsService, extraction_jobs_service::ExtractionJobsService, import_batches_service::ImportBatchesService, outlook_calendar_service::OutlookCalendarService, recurrence_builder_service::RecurrenceBuilderService, recurring_event_groups_service::RecurringEventGroupsService, recurring_events_service::RecurringEventsService}};
 
 pub mod ai_add_events_service;
+pub mod ai_edit_events_service;
+pub mod ai_usage_service;
+pub mod briefings_service;
+pub mod extraction_jobs_service;
+pub mod extractions_service;
+pub mod import_batches_service;
 pub mod calendar_events_service;
+pub mod calendar_query_service;
+pub mod categorisation_service;
 pub mod recurring_event_groups_service;
 pub mod recurring_events_service;
+pub mod recurrence_builder_service;
 pub mod azure_token_service;
 pub mod outlook_calendar_service;
 
@@ -14,20 +23,66 @@ pub struct Services {
     pub recurring_event_groups: RecurringEventGroupsService,
     pub recurring_events: RecurringEventsService,
     pub ai_add_events: AIAddEventsService,
+    pub ai_edit_events: AIEditEventsService,
+    pub calendar_query: CalendarQueryService,
+    pub briefings: BriefingsService,
+    pub categorisation: CategorisationService,
+    pub recurrence_builder: RecurrenceBuilderService,
+    pub extraction_jobs: ExtractionJobsService,
+    pub extractions: ExtractionsService,
+    pub import_batches: ImportBatchesService,
+    pub ai_usage: AIUsageService,
     pub azure_token: AzureTokenService,
     pub outlook_calendar: OutlookCalendarService
 }
 
 impl Services {
-    pub fn new(repositories: Repositories, llm: LLM) -> Self {
+    pub fn new(repositories: Repositories, llm: LLM, config: &Config) -> Self {
         let azure_token_service = AzureTokenService::new(llm.clone(), repositories.clone());
+        let extractions_service = ExtractionsService::new(repositories.clone(), &config.gemini_model, &config.ai_provenance);
+        let recurring_events_service = RecurringEventsService::new(repositories.clone(), extractions_service.clone());
+        let ai_usage_service = AIUsageService::new(repositories.clone(), &config.ai_quotas);
+        let ai_add_events_service = AIAddEventsService::new(
+            llm.clone(), 
+            repositories.clone(), 
+            recurring_events_service.clone(), 
+            ai_usage_service.clone(),
+            extractions_service.clone(),
+            &config.ai_cache
+        );
+        let categorisation_service = CategorisationService::new(
+            llm.clone(), 
+            repositories.clone(), 
+            ai_usage_service.clone(), 
+            &config.ai_categorisation
+        );
         Self {
-            calendar_events: CalendarEventsService::new(repositories.clone()),
-            recurring_event_groups: RecurringEventGroupsService::new(repositories.clone()),
-            recurring_events: RecurringEventsService::new(repositories.clone()),
-            ai_add_events: AIAddEventsService::new(llm.clone()),
+            calendar_events: CalendarEventsService::new(repositories.clone(), extractions_service.clone()),
+            recurring_event_groups: RecurringEventGroupsService::new(repositories.clone(), extractions_service.clone()),
+            recurring_events: recurring_events_service.clone(),
+            ai_add_events: ai_add_events_service.clone(),
+            ai_edit_events: AIEditEventsService::new(llm.clone(), repositories.clone(), ai_usage_service.clone()),
+            calendar_query: CalendarQueryService::new(
+                llm.clone(), 
+                repositories.clone(), 
+                recurring_events_service.clone(), 
+                ai_usage_service.clone()
+            ),
+            briefings: BriefingsService::new(
+                llm.clone(), 
+                repositories.clone(), 
+                recurring_events_service, 
+                ai_usage_service.clone(), 
+                &config.ai_briefings
+            ),
+            categorisation: categorisation_service.clone(),
+            recurrence_builder: RecurrenceBuilderService::new(llm.clone(), ai_usage_service.clone()),
+            extraction_jobs: ExtractionJobsService::new(ai_add_events_service, ai_usage_service.clone(), &config.ai_jobs),
+            extractions: extractions_service,
+            import_batches: ImportBatchesService::new(repositories.clone()),
+            ai_usage: ai_usage_service,
             azure_token: azure_token_service.clone(),
-            outlook_calendar: OutlookCalendarService::new(azure_token_service, repositories.clone())
+            outlook_calendar: OutlookCalendarService::new(azure_token_service, repositories.clone(), categorisation_service)
         }
     }
 }
\ No newline at end of file
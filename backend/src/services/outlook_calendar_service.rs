@@ -4,7 +4,7 @@ use chrono::{Datelike, SecondsFormat, TimeZone, Utc};
 use graph_rs_sdk::Graph;
 use icalendar::{Calendar, Component, Event, EventLike};
 use uuid::Uuid;
-use crate::{api::error::ApiResult, config::Config, models::{outlook::{OutlookCalendar, OutlookCalendarResponse, OutlookDeltaEvent}, recurring_event_exception::ExceptionType}, repositories::Repositories, services::azure_token_service::AzureTokenService};
+use crate::{api::error::ApiResult, config::Config, models::{import_batch::ImportSource, outlook::{OutlookCalendarResponse, OutlookDeltaEvent}, recurring_event_exception::ExceptionType}, repositories::Repositories, services::{azure_token_service::AzureTokenService, categorisation_service::CategorisationService}};
 
 static CLIENT: OnceLock<Client> = OnceLock::new();
 
@@ -13,13 +13,15 @@ static CLIENT: OnceLock<Client> = OnceLock::new();
 pub struct OutlookCalendarService {
     azure_token_service: AzureTokenService,
     repositories: Repositories,
+    categorisation: CategorisationService,
 }
 
 impl OutlookCalendarService {
-    pub fn new(azure_token_service: AzureTokenService, repositories: Repositories) -> Self {
+    pub fn new(azure_token_service: AzureTokenService, repositories: Repositories, categorisation: CategorisationService) -> Self {
         Self { 
             azure_token_service,
-            repositories 
+            repositories,
+            categorisation
         }
     }
 
@@ -93,11 +95,32 @@ impl OutlookCalendarService {
             });
         tracing::debug!("Got {total_events} events and {total_deletions} deletions from the Outlook sync");
 
-        for update in total_updates {
+        // new events are created in one batch, so a bad sync can be undone; it's dropped if nothing was new, even if
+        // the sync failed part way through
+        let import_batch_id = self.repositories.import_batches
+            .create_batch(user_id, ImportSource::Outlook, None)
+            .await?;
+        let synced = self.apply_updates(user_id, total_updates, import_batch_id).await;
+        // new events are put into groups by the user's saved categorisation rules; they're still synced if that fails
+        if let Err(err) = self.categorisation.apply_rules(user_id, import_batch_id).await {
+            tracing::warn!("Failed to categorise the new events from user {user_id}'s Outlook sync: {err}");
+        }
+        let cleaned_up = self.repositories.import_batches
+            .delete_batch_if_empty(import_batch_id)
+            .await;
+        synced?;
+        cleaned_up?;
+        
+        Ok(())
+    }
+
+    /// Apply the updates from an Outlook sync, creating any new events in the import batch.
+    async fn apply_updates(&self, user_id: Uuid, updates: Vec<OutlookDeltaEvent>, import_batch_id: Uuid) -> ApiResult<()> {
+        for update in updates {
             match update {
                 OutlookDeltaEvent::Event(event) => {
                     self.repositories.outlook_calendar
-                        .add_or_update_outlook_event(user_id, event)
+                        .add_or_update_outlook_event(user_id, event, import_batch_id)
                         .await?;
                 },
                 OutlookDeltaEvent::Deleted { id, removed } => {
@@ -107,7 +130,6 @@ impl OutlookCalendarService {
                 }
             }
         }
-        
         Ok(())
     }
 
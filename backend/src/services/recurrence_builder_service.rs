@@ -0,0 +1,218 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, NaiveTime, Utc};
+use rrule::{NWeekday, RRule, Tz, Unvalidated, Weekday};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::{
+    api::error::{ApiError, ApiResult},
+    llm::{GeneratedRecurrence, LLM},
+    models::{ai_usage::AIUsageKind, rrule::ValidatedRRule, time::UserTimezone},
+    services::ai_usage_service::AIUsageService,
+    utils::recurrence_phrase::{parse_recurrence_phrase, ParsedRecurrence, RecurrenceEnd}
+};
+
+/// How many instances are previewed by default.
+const DEFAULT_PREVIEW_INSTANCES: u16 = 10;
+
+/// A recurrence rule built from a phrase, ready to use in a `NewRecurringEvent`.
+#[derive(Debug, Serialize)]
+pub struct BuiltRecurrence {
+    pub rrule: ValidatedRRule,
+    /// The start given, in UTC; the first instance is the first in `preview` (which it may not be).
+    pub recurrence_start: DateTime<Utc>,
+    /// The end of the recurrence, if it ends at a date (rather than after a number of instances, or never).
+    pub recurrence_end: Option<DateTime<Utc>>,
+    /// The first instances, in the user's local time.
+    pub preview: Vec<DateTime<FixedOffset>>,
+    /// Parts of the phrase which couldn't be expressed in the rule, and any caveats about it.
+    pub notes: Vec<String>,
+    pub source: RecurrenceSource
+}
+
+/// What understood the phrase.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecurrenceSource {
+    /// The deterministic parser (see `parse_recurrence_phrase`).
+    Parser,
+    Llm
+}
+
+/// A recurrence resolved into a rule in UTC terms, as it's stored.
+#[derive(Debug)]
+pub struct ResolvedRecurrence {
+    pub rrule: ValidatedRRule,
+    pub recurrence_start: DateTime<Utc>,
+    pub recurrence_end: Option<DateTime<Utc>>,
+    /// The parsed recurrence's notes, and any more about resolving it.
+    pub notes: Vec<String>
+}
+
+/// Handles business logic for building recurrence rules from natural language.
+#[derive(Clone, Debug)]
+pub struct RecurrenceBuilderService {
+    llm: LLM,
+    usage: AIUsageService
+}
+
+impl RecurrenceBuilderService {
+    pub fn new(llm: LLM, usage: AIUsageService) -> Self {
+        Self { llm, usage }
+    }
+
+    /// Build a validated rule from a phrase, for a recurrence starting at the given local datetime.
+    ///
+    /// Common phrasings are parsed deterministically, while the LLM handles the rest (counting towards the user's quota).
+    pub async fn build(
+        &self,
+        user_id: Uuid,
+        phrase: String,
+        start: NaiveDateTime,
+        timezone: UserTimezone,
+        preview_instances: Option<u16>
+    ) -> ApiResult<BuiltRecurrence> {
+        let (parsed, source) = match parse_recurrence_phrase(&phrase, start.date()) {
+            Some(parsed) => (parsed, RecurrenceSource::Parser),
+            None => {
+                tracing::debug!("Falling back to the LLM for recurrence phrase {phrase:?}");
+                let mut meter = self.usage.start(user_id, AIUsageKind::Recurrence).await?;
+                let generated = self.llm.recurrence_from_text(phrase, start, &mut meter.tokens).await?;
+                (Self::from_generated(generated)?, RecurrenceSource::Llm)
+            }
+        };
+        let ResolvedRecurrence { rrule, recurrence_start, recurrence_end, mut notes } = resolve_recurrence(parsed, start, timezone)?;
+
+        let preview: Vec<_> = rrule
+            .first_instances(preview_instances.unwrap_or(DEFAULT_PREVIEW_INSTANCES))
+            .into_iter()
+            .map(|instance| timezone.utc_to_local(instance))
+            .collect();
+        if preview.is_empty() {
+            notes.push("The rule has no instances after the start".into());
+        }
+        if preview.iter().any(|instance| instance.time() != start.time()) {
+            notes.push("Instances keep the same time in UTC, so they move when the clocks change for daylight saving".into());
+        }
+
+        Ok(BuiltRecurrence { rrule, recurrence_start, recurrence_end, preview, notes, source })
+    }
+
+    /// Parse the LLM's recurrence into the same shape as the parser's.
+    fn from_generated(generated: GeneratedRecurrence) -> ApiResult<ParsedRecurrence> {
+        let rule_string = generated.rrule.trim();
+        let rule: RRule<Unvalidated> = rule_string
+            .strip_prefix("RRULE:")
+            .unwrap_or(rule_string)
+            .parse()
+            .map_err(|err| ApiError::unprocessable_entity([("text", format!("couldn't be turned into a recurrence rule: {err}"))]))?;
+        let end = match (generated.until_date, generated.count) {
+            (Some(date), _) => RecurrenceEnd::Until(date),
+            (None, Some(count)) => RecurrenceEnd::Count(count),
+            (None, None) => RecurrenceEnd::Never
+        };
+        Ok(ParsedRecurrence { rule, end, notes: generated.notes })
+    }
+}
+
+/// Resolve a recurrence in the user's local terms, starting at the given local datetime, into a validated rule in UTC.
+pub fn resolve_recurrence(parsed: ParsedRecurrence, start: NaiveDateTime, timezone: UserTimezone) -> ApiResult<ResolvedRecurrence> {
+    let ParsedRecurrence { rule, end, mut notes } = parsed;
+
+    // rules are stored (and expanded) in UTC, so any days in them must be shifted to match the start in UTC
+    let recurrence_start = timezone.local_to_utc(start);
+    let day_shift = (recurrence_start.date_naive() - start.date()).num_days();
+    let mut rule = shift_days(rule, day_shift, &mut notes)?;
+    rule = round_trippable(rule)?;
+
+    let mut recurrence_end = None;
+    match end {
+        RecurrenceEnd::Never => {},
+        RecurrenceEnd::Until(date) => {
+            let until = timezone.local_to_utc(date.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default()));
+            rule = rule.until(until.with_timezone(&Tz::UTC));
+            recurrence_end = Some(until);
+        },
+        RecurrenceEnd::Count(count) => rule = rule.count(count)
+    }
+    let rrule = ValidatedRRule::new(rule, recurrence_start)
+        .map_err(|err| ApiError::unprocessable_entity([("text", format!("doesn't give a valid recurrence rule: {err}"))]))?;
+
+    Ok(ResolvedRecurrence { rrule, recurrence_start, recurrence_end, notes })
+}
+
+/// Shift the days a rule falls on by `days` (-1, 0 or 1), e.g. from the user's local terms to UTC.
+fn shift_days(rule: RRule<Unvalidated>, days: i64, notes: &mut Vec<String>) -> ApiResult<RRule<Unvalidated>> {
+    if days == 0 {
+        return Ok(rule);
+    }
+    let shift = |weekday: Weekday| if days > 0 { weekday.succ() } else { weekday.pred() };
+    let mut nth_weekdays = false;
+    let weekdays: Vec<_> = rule
+        .get_by_weekday()
+        .iter()
+        .map(|weekday| match *weekday {
+            NWeekday::Every(weekday) => NWeekday::Every(shift(weekday)),
+            NWeekday::Nth(n, weekday) => {
+                nth_weekdays = true;
+                NWeekday::Nth(n, shift(weekday))
+            }
+        })
+        .collect();
+    let month_days = rule
+        .get_by_month_day()
+        .iter()
+        .map(|&day| match day + days as i8 {
+            // the 1st in local time is the last day of the previous month in UTC, and vice versa
+            0 if days < 0 => Some(-1),
+            0 => Some(1),
+            32 => None,
+            day => Some(day)
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ApiError::unprocessable_entity([("text", "can't be expressed for this start time and timezone")]))?;
+    if nth_weekdays {
+        notes.push("Instances near the start or end of a month may be a day out, as of the start time's timezone".into());
+    }
+    Ok(rule.by_weekday(weekdays).by_month_day(month_days))
+}
+
+/// Rewrite the rule so it's unchanged by serializing and parsing it again.
+///
+/// The `rrule` crate writes the 1st of a weekday (e.g. `1MO`) without its `1`, and drops negative month days (e.g. 
+/// `-1` for the last day) entirely, so both are rewritten using `BYSETPOS`: `BYDAY=MO;BYSETPOS=1` and 
+/// `BYMONTHDAY=1,...,31;BYSETPOS=-1` mean the same and survive the round trip.
+fn round_trippable(rule: RRule<Unvalidated>) -> ApiResult<RRule<Unvalidated>> {
+    let unsupported = || ApiError::unprocessable_entity([("text", "gives a rule which can't be saved; try describing it another way")]);
+    let weekdays = rule.get_by_weekday();
+    let month_days = rule.get_by_month_day();
+    let first_weekday = weekdays.iter().any(|weekday| matches!(weekday, NWeekday::Nth(1, _)));
+    let negative_month_day = month_days.iter().any(|&day| day < 0);
+    if !first_weekday && !negative_month_day {
+        return Ok(rule);
+    }
+    if !rule.get_by_set_pos().is_empty() || (first_weekday && negative_month_day) {
+        return Err(unsupported());
+    }
+
+    if negative_month_day {
+        if !weekdays.is_empty() {
+            return Err(unsupported());
+        }
+        let positions = month_days.iter().map(|&day| day.into()).collect();
+        return Ok(rule.by_month_day((1..=31).collect()).by_set_pos(positions));
+    }
+    let mut positions = Vec::with_capacity(weekdays.len());
+    let mut shared_weekday = None;
+    for weekday in weekdays {
+        let NWeekday::Nth(n, weekday) = *weekday else {
+            return Err(unsupported());
+        };
+        if shared_weekday.replace(weekday).is_some_and(|shared| shared != weekday) {
+            return Err(unsupported());
+        }
+        positions.push(n.into());
+    }
+    match shared_weekday {
+        Some(weekday) => Ok(rule.by_weekday(vec![NWeekday::Every(weekday)]).by_set_pos(positions)),
+        None => Ok(rule)
+    }
+}
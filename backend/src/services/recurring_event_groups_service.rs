@@ -1,19 +1,55 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use chrono::Duration;
 use uuid::Uuid;
 use serde::Serialize;
 use crate::{
     api::error::ApiError,
     models::{
-        recurring_event::{NewRecurringEvent, RecurringEvent},
-        recurring_event_group::{NewRecurringEventGroup, RecurringEventGroup, UpdatedRecurringEventGroup}
-    }, repositories::Repositories
+        extraction::SavedEvents,
+        import_batch::ImportSource,
+        recurring_event::{NewRecurringEventWithExceptions, RecurringEvent},
+        recurring_event_exception::{ExceptionType, NewSeriesException},
+        recurring_event_group::{NewRecurringEventGroup, RecurringEventGroup, UpdatedRecurringEventGroup},
+        rrule::ValidatedRRule
+    }, repositories::Repositories, services::extractions_service::ExtractionsService
 };
 
-/// Used for bulk creating events, optionally under a group.
+/// How far an exception's date can be from an actual instance of its event, and still be snapped to it.
+const EXCEPTION_TOLERANCE_MINUTES: i64 = 1;
+
+/// Used for bulk creating events (and their exceptions), optionally under new groups.
 #[derive(serde::Deserialize)]
 pub struct GroupWithEvents {
-    pub recurring_events: Vec<NewRecurringEvent>,
-    pub recurring_event_group: Option<NewRecurringEventGroup>
+    /// Events which aren't under one of the new groups (they can still reference an existing group).
+    #[serde(default)]
+    pub recurring_events: Vec<NewRecurringEventWithExceptions>,
+    /// A new group to put `recurring_events` under.
+    pub recurring_event_group: Option<NewRecurringEventGroup>,
+    /// New groups, each with their own events.
+    #[serde(default)]
+    pub recurring_event_groups: Vec<NewGroupWithEvents>
+}
+
+impl GroupWithEvents {
+    /// All the events to create, whichever group they're under.
+    fn saved_events(&self) -> SavedEvents {
+        SavedEvents {
+            recurring_events: self.recurring_events
+                .iter()
+                .chain(self.recurring_event_groups.iter().flat_map(|g| &g.recurring_events))
+                .cloned()
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A new group, along with the events to create under it.
+#[derive(serde::Deserialize)]
+pub struct NewGroupWithEvents {
+    #[serde(flatten)]
+    pub group: NewRecurringEventGroup,
+    pub recurring_events: Vec<NewRecurringEventWithExceptions>
 }
 
 /// The response for a group (includes the number of events under the group).
@@ -29,11 +65,12 @@ pub struct RecurringEventGroupResponse {
 #[derive(Clone, Debug)]
 pub struct RecurringEventGroupsService {
     repositories: Repositories,
+    extractions: ExtractionsService
 }
 
 impl RecurringEventGroupsService {
-    pub fn new(repositories: Repositories) -> Self {
-        Self { repositories }
+    pub fn new(repositories: Repositories, extractions: ExtractionsService) -> Self {
+        Self { repositories, extractions }
     }
 
     pub async fn fetch_all_groups(&self, user_id: Uuid) -> Result<Vec<RecurringEventGroupResponse>, ApiError> {
@@ -65,7 +102,8 @@ impl RecurringEventGroupsService {
             color: u32::MAX as i64,
             group_is_active: None,
             group_recurrence_start: None,
-            group_recurrence_end: None
+            group_recurrence_end: None,
+            extraction_id: None
         };
 
         response.push(RecurringEventGroupResponse {
@@ -147,28 +185,132 @@ impl RecurringEventGroupsService {
         Ok(())
     }
 
-    pub async fn add_with_events(&self, user_id: Uuid, mut events: GroupWithEvents) -> Result<(), ApiError> {
-        let group_id = match events.recurring_event_group {
-            Some(new_group) => {
-                let id = self.repositories
-                    .recurring_event_groups
-                    .create_group_returning_id(user_id, &new_group)
-                    .await
-                    .map_err(ApiError::from)?;
-                Some(id)
+    /// Create all the groups and events (and their exceptions) in one transaction and import batch, optionally 
+    /// recording them as saved from an extraction (see `ExtractionsService`).
+    pub async fn add_with_events(&self, user_id: Uuid, events: GroupWithEvents, extraction_id: Option<Uuid>) -> Result<(), ApiError> {
+        let feedback = match extraction_id {
+            Some(extraction_id) => {
+                self.extractions.check_extraction(user_id, extraction_id).await?;
+                Some((extraction_id, events.saved_events()))
             },
             None => None
         };
 
-        if let Some(id) = group_id {
-            events.recurring_events
-                .iter_mut()
-                .for_each(|e| e.group_id = Some(id));
+        // each batch is an (optional) new group, and the events to create (under it)
+        let mut batches: Vec<_> = std::iter::once((events.recurring_event_group, events.recurring_events))
+            .chain(events.recurring_event_groups.into_iter().map(|g| (Some(g.group), g.recurring_events)))
+            .filter(|(group, events)| group.is_some() || !events.is_empty())
+            .map(|(group, events)| {
+                let (new_events, exceptions): (Vec<_>, Vec<_>) = events
+                    .into_iter()
+                    .map(|e| (e.event, e.exceptions))
+                    .unzip();
+                (group, new_events, exceptions)
+            })
+            .collect();
+
+        // Check everything before creating anything
+        for (group, new_events, exceptions) in &mut batches {
+            if let Some(group) = group {
+                self.validate_new_group(group)?;
+            }
+
+            // HACK: as with `RecurringEventsService::create_events`, ensure the rrules have the right start/end
+            for event in new_events.iter_mut() {
+                event.rrule.set_start(event.recurrence_start);
+                event.rrule.set_end(event.recurrence_end);
+            }
+
+            for (event, event_exceptions) in new_events.iter().zip(exceptions.iter_mut()) {
+                let duration = Duration::seconds(event.event_duration_seconds.0.into());
+                let mut exception_dates = HashSet::new();
+                for exception in event_exceptions {
+                    self.validate_series_exception(exception, &event.rrule, duration)?;
+                    if !exception_dates.insert(exception.exception_date) {
+                        return Err(ApiError::unprocessable_entity([("exceptions", "only one exception is allowed per instance")]));
+                    }
+                }
+            }
         }
-        self.repositories.recurring_events
-            .bulk_create_events(&events.recurring_events, user_id)
+
+        // Events not under a new group may reference the user's existing groups instead
+        let existing_group_ids: Vec<_> = batches
+            .iter()
+            .filter(|(group, _, _)| group.is_none())
+            .flat_map(|(_, new_events, _)| new_events.iter().map(|e| e.group_id))
+            .collect();
+        let all_groups_authorized = self.repositories
+            .recurring_events
+            .validate_group_ownership(user_id, &existing_group_ids)
             .await?;
+        if !all_groups_authorized {
+            return Err(ApiError::Forbidden);
+        }
+
+        let mut tx = self.repositories.begin().await?;
+        let import_batch_id = self.repositories
+            .import_batches
+            .create_batch_in(&mut tx, user_id, ImportSource::Groups, extraction_id)
+            .await?;
+        let mut all_exceptions = Vec::new();
+        for (group, mut new_events, exceptions) in batches {
+            if let Some(group) = group {
+                let group_id = self.repositories
+                    .recurring_event_groups
+                    .create_group_returning_id(&mut tx, user_id, &group, extraction_id, import_batch_id)
+                    .await?;
+                new_events
+                    .iter_mut()
+                    .for_each(|e| e.group_id = Some(group_id));
+            }
+            if new_events.is_empty() {
+                continue;
+            }
 
+            let event_ids = self.repositories
+                .recurring_events
+                .bulk_create_events_in(&mut tx, &new_events, user_id, extraction_id, Some(import_batch_id))
+                .await?;
+            all_exceptions.extend(
+                event_ids
+                    .into_iter()
+                    .zip(exceptions)
+                    .flat_map(|(event_id, exceptions)| exceptions.into_iter().map(move |e| e.for_event(event_id)))
+            );
+        }
+        if !all_exceptions.is_empty() {
+            tracing::trace!("Creating {} exceptions with the new events", all_exceptions.len());
+            self.repositories
+                .recurring_events
+                .bulk_create_event_exceptions(&mut tx, &all_exceptions)
+                .await?;
+        }
+        tx.commit().await?;
+
+        if let Some((extraction_id, saved)) = feedback {
+            self.extractions.record_saved(user_id, extraction_id, saved).await;
+        }
+        Ok(())
+    }
+
+    /// Snap an exception to the instance it's for, filling in any missing times for modified instances.
+    fn validate_series_exception(
+        &self,
+        exception: &mut NewSeriesException,
+        rrule: &ValidatedRRule,
+        duration: Duration
+    ) -> Result<(), ApiError> {
+        exception.exception_date = rrule
+            .instance_near(exception.exception_date, Duration::minutes(EXCEPTION_TOLERANCE_MINUTES))
+            .ok_or_else(|| ApiError::unprocessable_entity([("exceptions", "must be on an instance of their recurring event")]))?;
+
+        if exception.exception_type == ExceptionType::Modified {
+            let start = *exception.modified_start_time.get_or_insert(exception.exception_date);
+            let end = *exception.modified_end_time.get_or_insert(start + duration);
+            if end <= start {
+                return Err(ApiError::unprocessable_entity([("exceptions", "modified instances must end after they start")]));
+            }
+        }
         Ok(())
     }
 
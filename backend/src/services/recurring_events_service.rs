@@ -6,9 +6,11 @@ use uuid::Uuid;
 use crate::{
     api::error::ApiError,
     models::{
-        recurring_event::{NewRecurringEvent, RecurringCalendarEvent, RecurringEvent, UpdatedRecurringEvent},
+        extraction::SavedEvents,
+        import_batch::ImportSource,
+        recurring_event::{NewRecurringEvent, NewRecurringEventWithExceptions, RecurringCalendarEvent, RecurringEvent, UpdatedRecurringEvent},
         recurring_event_exception::{ExceptionType, NewRecurringEventException, RecurringEventException},
-    }, repositories::Repositories
+    }, repositories::Repositories, services::extractions_service::ExtractionsService
 };
 
 /// The query params for querying events.
@@ -21,15 +23,34 @@ pub struct EventsQuery {
 /// Handles business logic for recurring events routes.
 #[derive(Clone, Debug)]
 pub struct RecurringEventsService {
-    repositories: Repositories
+    repositories: Repositories,
+    extractions: ExtractionsService
 }
 
 impl RecurringEventsService {
-    pub fn new(repositories: Repositories) -> Self {
-        Self { repositories }
+    pub fn new(repositories: Repositories, extractions: ExtractionsService) -> Self {
+        Self { repositories, extractions }
     }
 
-    pub async fn create_events(&self, user_id: Uuid, mut events: Vec<NewRecurringEvent>) -> Result<(), ApiError> {
+    /// Create events in an import batch, optionally as saved from an AI extraction (see `ExtractionsService`).
+    pub async fn create_events(
+        &self, 
+        user_id: Uuid, 
+        mut events: Vec<NewRecurringEvent>, 
+        extraction_id: Option<Uuid>
+    ) -> Result<(), ApiError> {
+        let feedback = match extraction_id {
+            Some(extraction_id) => {
+                self.extractions.check_extraction(user_id, extraction_id).await?;
+                let recurring_events = events
+                    .iter()
+                    .map(|event| NewRecurringEventWithExceptions { event: event.clone(), exceptions: Vec::new() })
+                    .collect();
+                Some((extraction_id, SavedEvents { recurring_events, ..Default::default() }))
+            },
+            None => None
+        };
+
         // HACK: frontend is unable to set start/end datetimes in the rrule, so we must ensure they're set here
         for event in &mut events {
             event.rrule.set_start(event.recurrence_start);
@@ -50,12 +71,20 @@ impl RecurringEventsService {
             return Err(ApiError::Forbidden);
         }
 
+        let mut tx = self.repositories.begin().await?;
+        let import_batch_id = self.repositories
+            .import_batches
+            .create_batch_in(&mut tx, user_id, ImportSource::RecurringEvents, extraction_id)
+            .await?;
         self.repositories
             .recurring_events
-            .bulk_create_events(&events, user_id)
-            .await
-            .map_err(ApiError::from)?;
+            .bulk_create_events_in(&mut tx, &events, user_id, extraction_id, Some(import_batch_id))
+            .await?;
+        tx.commit().await?;
 
+        if let Some((extraction_id, saved)) = feedback {
+            self.extractions.record_saved(user_id, extraction_id, saved).await;
+        }
         Ok(())
     }
 
@@ -0,0 +1,182 @@
+use std::io::ErrorKind;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint
+};
+
+/// The sample rate transcoded audio is resampled to (the LLM downsamples to this anyway).
+const TRANSCODE_SAMPLE_RATE: u32 = 16_000;
+
+/// Audio container formats we can recognise from uploaded bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Mp3,
+    Wav,
+    Aiff,
+    Aac,
+    Ogg,
+    Flac,
+    /// MP4/M4A/3GP containers (usually AAC, as recorded by Android and iOS).
+    Mp4,
+    WebM,
+    Amr
+}
+
+impl AudioFormat {
+    /// Sniff the container format from the leading bytes of the audio.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', ..] => Some(Self::Aiff),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Ogg),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(Self::Mp4),
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(Self::WebM),
+            [b'#', b'!', b'A', b'M', b'R', ..] => Some(Self::Amr),
+            // both MPEG audio and ADTS start with a frame sync; the layer bits tell them apart
+            [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some(Self::Aac),
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some(Self::Mp3),
+            _ => None
+        }
+    }
+
+    /// Map a (multipart) content type to a format, for when sniffing fails.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "audio/mp3" | "audio/mpeg" => Some(Self::Mp3),
+            "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => Some(Self::Wav),
+            "audio/aiff" | "audio/x-aiff" => Some(Self::Aiff),
+            "audio/aac" | "audio/x-aac" => Some(Self::Aac),
+            "audio/ogg" | "audio/opus" | "application/ogg" => Some(Self::Ogg),
+            "audio/flac" | "audio/x-flac" => Some(Self::Flac),
+            "audio/mp4" | "audio/m4a" | "audio/x-m4a" | "audio/3gpp" | "video/mp4" => Some(Self::Mp4),
+            "audio/webm" | "video/webm" => Some(Self::WebM),
+            "audio/amr" => Some(Self::Amr),
+            _ => None
+        }
+    }
+
+    /// The MIME type to send the audio to the LLM as, or `None` if the LLM doesn't accept this format.
+    pub fn llm_mime_type(&self) -> Option<&'static str> {
+        match self {
+            Self::Mp3 => Some("audio/mp3"),
+            Self::Wav => Some("audio/wav"),
+            Self::Aiff => Some("audio/aiff"),
+            Self::Aac => Some("audio/aac"),
+            Self::Ogg => Some("audio/ogg"),
+            Self::Flac => Some("audio/flac"),
+            Self::Mp4 | Self::WebM | Self::Amr => None
+        }
+    }
+
+    /// Whether `transcode_to_wav` can convert this format.
+    pub fn can_transcode(&self) -> bool {
+        matches!(self, Self::Mp4)
+    }
+}
+
+/// Decode the audio and re-encode it as a mono 16-bit PCM WAV.
+pub fn transcode_to_wav(source: Box<dyn MediaSource>) -> Result<Vec<u8>, SymphoniaError> {
+    let stream = MediaSourceStream::new(source, Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(&Hint::new(), stream, &FormatOptions::default(), &MetadataOptions::default())?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(SymphoniaError::Unsupported("no audio track found"))?;
+    let track_id = track.id;
+    let source_rate = track.codec_params.sample_rate
+        .ok_or(SymphoniaError::Unsupported("audio track has no sample rate"))?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    // decode everything, downmixing to mono as we go
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err)
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(err)) => {
+                tracing::debug!("Skipping undecodable audio packet: {err}");
+                continue;
+            },
+            Err(err) => return Err(err)
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(
+            buffer.samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        );
+    }
+
+    let samples = resample(&samples, source_rate, TRANSCODE_SAMPLE_RATE);
+    Ok(encode_wav(&samples, TRANSCODE_SAMPLE_RATE))
+}
+
+/// Linearly resample mono samples between sample rates.
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from_rate as f64 / to_rate as f64;
+    let output_len = (samples.len() as f64 / ratio) as usize;
+    (0..output_len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).copied().unwrap_or(current);
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+/// Write mono samples out as a 16-bit PCM WAV file.
+fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
@@ -0,0 +1,124 @@
+//! Reads the QR codes and barcodes in photos and scans, like those on posters and tickets (which often encode an event,
+//! a URL or contact details, or a booking reference).
+//!
+//! The decoding itself is done by `rxing`, a port of ZXing, which handles QR and Aztec codes, PDF417 (as on boarding
+//! passes), Data Matrix and the common 1D barcodes.
+
+use std::{borrow::Cow, panic::{self, AssertUnwindSafe}};
+use image::{imageops::{self, FilterType}, GrayImage};
+use rxing::{helpers::detect_multiple_in_luma_with_hints, DecodeHints, Exceptions};
+
+/// Images are scanned at most this large, which keeps scanning quick while codes of a reasonable size stay legible.
+const MAX_SCAN_DIMENSION: u32 = 1600;
+
+/// Decode the QR codes and barcodes in an image, returning each distinct payload, in the order they were found.
+///
+/// An image without any (readable) codes just returns none.
+pub fn decode_barcodes(image: &GrayImage) -> Vec<String> {
+    let (width, height) = image.dimensions();
+    let image = if width > MAX_SCAN_DIMENSION || height > MAX_SCAN_DIMENSION {
+        let scale = MAX_SCAN_DIMENSION as f64 / width.max(height) as f64;
+        let (width, height) = ((width as f64 * scale) as u32, (height as f64 * scale) as u32);
+        Cow::Owned(imageops::resize(image, width.max(1), height.max(1), FilterType::Triangle))
+    } else {
+        Cow::Borrowed(image)
+    };
+    let (width, height) = image.dimensions();
+
+    // a malformed image shouldn't fail the whole extraction, so a panic in the decoder is treated as finding nothing
+    let luma = image.into_owned().into_raw();
+    let results = panic::catch_unwind(AssertUnwindSafe(|| {
+        // this tries harder (e.g. rotating the image for 1D barcodes) unless told otherwise
+        detect_multiple_in_luma_with_hints(luma, width, height, &mut DecodeHints::default())
+    }));
+    let results = match results {
+        Ok(Ok(results)) => results,
+        Ok(Err(Exceptions::NotFoundException(_))) => Vec::new(),
+        Ok(Err(err)) => {
+            tracing::debug!("Failed to decode the codes in an image: {err}");
+            Vec::new()
+        },
+        Err(_) => {
+            tracing::warn!("The barcode decoder panicked on an image");
+            Vec::new()
+        }
+    };
+
+    let mut payloads: Vec<String> = Vec::new();
+    for result in results {
+        let payload = result.getText();
+        if !payload.trim().is_empty() && !payloads.iter().any(|found| found == payload) {
+            payloads.push(payload.to_string());
+        }
+    }
+    payloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(fixture: &[u8]) -> Vec<String> {
+        decode_barcodes(&image::load_from_memory(fixture).unwrap().to_luma8())
+    }
+
+    #[test]
+    fn decodes_qr_codes() {
+        assert_eq!(
+            decode(include_bytes!("../../tests/fixtures/barcodes/url_photo.jpg")),
+            ["https://example.com/events/42?ref=poster"]
+        );
+        let mut codes = decode(include_bytes!("../../tests/fixtures/barcodes/two_codes.png"));
+        codes.sort();
+        assert_eq!(codes, ["MECARD:N:Two;;", "https://a.example"]);
+    }
+
+    #[test]
+    fn decodes_rotated_and_damaged_qr_codes() {
+        // photographed at an angle, with noise
+        let codes = decode(include_bytes!("../../tests/fixtures/barcodes/rotated_vevent.jpg"));
+        let [code] = codes.as_slice() else { panic!("expected one code, got {codes:?}") };
+        assert!(code.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(code.contains("SUMMARY:Jazz night\\, live\r\n"));
+
+        // a few modules wrong, which error correction recovers
+        assert_eq!(
+            decode(include_bytes!("../../tests/fixtures/barcodes/damaged.png")),
+            ["https://example.com/damaged"]
+        );
+    }
+
+    #[test]
+    fn decodes_barcodes() {
+        // on its side, as on many tickets
+        assert_eq!(
+            decode(include_bytes!("../../tests/fixtures/barcodes/ticket_code128_vertical.png")),
+            ["TKT-2026-0042"]
+        );
+        assert_eq!(decode(include_bytes!("../../tests/fixtures/barcodes/ean13.png")), ["5012345678900"]);
+        assert_eq!(
+            decode(include_bytes!("../../tests/fixtures/barcodes/boarding_pass_pdf417.png")),
+            ["M1DOE/JANE            EABC123 LHRJFKBA 0117 293Y012A0042 100"]
+        );
+        assert_eq!(
+            decode(include_bytes!("../../tests/fixtures/barcodes/aztec.png")),
+            ["https://tickets.example/t/0042"]
+        );
+    }
+
+    #[test]
+    fn finds_nothing_without_a_readable_code() {
+        assert!(decode(include_bytes!("../../tests/fixtures/barcodes/no_code.jpg")).is_empty());
+        // too much of the code is missing to correct
+        assert!(decode(include_bytes!("../../tests/fixtures/barcodes/unreadable.png")).is_empty());
+        assert!(decode_barcodes(&GrayImage::new(1, 1)).is_empty());
+        assert!(decode_barcodes(&GrayImage::new(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn scans_large_images() {
+        let code = image::load_from_memory(include_bytes!("../../tests/fixtures/barcodes/damaged.png")).unwrap().to_luma8();
+        let large = imageops::resize(&code, 3000, 3000, FilterType::Nearest);
+        assert_eq!(decode_barcodes(&large), ["https://example.com/damaged"]);
+    }
+}
@@ -0,0 +1,424 @@
+//! Reads emails, either raw RFC 822 messages (`.eml`) or Outlook's `.msg` files, into their headers, body text and
+//! attachments.
+
+use std::{io::{Cursor, Read}, path::Path};
+use cfb::CompoundFile;
+use chrono::{DateTime, FixedOffset};
+use image::ImageFormat;
+use mail_parser::{decoders::html, Message, MessagePart, MessageParser, MimeHeaders, PartType};
+
+/// The magic bytes of an OLE compound file, which `.msg` files are.
+const COMPOUND_FILE_MAGIC: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+/// How deeply we follow emails attached to emails (like a forwarded invite).
+const MAX_ATTACHED_EMAIL_DEPTH: usize = 3;
+
+/// The width HTML bodies are wrapped to when converted to text.
+const HTML_TEXT_WIDTH: usize = 120;
+
+/// An email's headers, body and attachments.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedEmail {
+    pub subject: Option<String>,
+    /// The sender, as `Name <address>` where both are known.
+    pub from: Option<String>,
+    pub sent: Option<DateTime<FixedOffset>>,
+    /// The plain text body (or the HTML body, converted to text), followed by those of any attached emails.
+    pub body: String,
+    /// The attachments, including those of any attached emails.
+    pub attachments: Vec<EmailAttachment>
+}
+
+impl ParsedEmail {
+    /// The headers and body, as text.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        if let Some(subject) = &self.subject {
+            text.push_str(&format!("Subject: {subject}\n"));
+        }
+        if let Some(from) = &self.from {
+            text.push_str(&format!("From: {from}\n"));
+        }
+        if let Some(sent) = &self.sent {
+            text.push_str(&format!("Sent: {}\n", sent.format("%A %d %B %Y %H:%M %:z")));
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(self.body.trim());
+        text
+    }
+
+    /// Whether nothing could be read from the email.
+    fn is_empty(&self) -> bool {
+        self.subject.is_none() && self.from.is_none() && self.body.trim().is_empty() && self.attachments.is_empty()
+    }
+}
+
+/// A file attached to an email.
+#[derive(Debug, Clone)]
+pub struct EmailAttachment {
+    pub filename: Option<String>,
+    /// The (lowercase) MIME type, without parameters, if one was given.
+    pub content_type: Option<String>,
+    pub bytes: Vec<u8>
+}
+
+/// The kinds of attachment we can extract events from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    /// iCalendar data, like a meeting invite.
+    Calendar,
+    Image,
+    Pdf,
+    Other
+}
+
+impl EmailAttachment {
+    /// What kind of file this is, from its content type, then its extension, then its bytes.
+    pub fn kind(&self) -> AttachmentKind {
+        let extension = self.filename
+            .as_deref()
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match (self.content_type.as_deref(), extension.as_deref()) {
+            (Some("text/calendar" | "application/ics"), _) | (_, Some("ics" | "vcs")) => AttachmentKind::Calendar,
+            (Some("application/pdf"), _) | (_, Some("pdf")) => AttachmentKind::Pdf,
+            (Some(content_type), _) if content_type.starts_with("image/") => AttachmentKind::Image,
+            (_, Some(extension)) if ImageFormat::from_extension(extension).is_some() => AttachmentKind::Image,
+            _ if self.bytes.starts_with(b"%PDF-") => AttachmentKind::Pdf,
+            _ if image::guess_format(&self.bytes).is_ok() => AttachmentKind::Image,
+            _ => AttachmentKind::Other
+        }
+    }
+
+    /// A name for the attachment, for describing where events came from.
+    pub fn display_name(&self) -> &str {
+        self.filename.as_deref().unwrap_or("attachment")
+    }
+}
+
+/// Whether the bytes are (or at least look like) an Outlook `.msg` file, rather than an RFC 822 message.
+pub fn is_msg(bytes: &[u8]) -> bool {
+    bytes.starts_with(&COMPOUND_FILE_MAGIC)
+}
+
+/// Read an email, either as an Outlook `.msg` file or (otherwise) an RFC 822 message, returning `None` if nothing
+/// could be read from it.
+pub fn parse_email(bytes: &[u8]) -> Option<ParsedEmail> {
+    let email = if is_msg(bytes) {
+        parse_msg(bytes)?
+    } else {
+        read_message(&MessageParser::default().parse(bytes)?, 0)
+    };
+    (!email.is_empty()).then_some(email)
+}
+
+/// Read a parsed RFC 822 message, along with any emails attached to it (up to `MAX_ATTACHED_EMAIL_DEPTH`).
+fn read_message(message: &Message, depth: usize) -> ParsedEmail {
+    let from = message.from().and_then(|from| from.first()).and_then(|from| match (from.name(), from.address()) {
+        (Some(name), Some(address)) => Some(format!("{name} <{address}>")),
+        (name, address) => name.or(address).map(str::to_string)
+    });
+    let mut email = ParsedEmail {
+        subject: message.subject().map(str::to_string),
+        from,
+        // going via RFC 3339 keeps the sender's offset
+        sent: message.date().and_then(|date| DateTime::parse_from_rfc3339(&date.to_rfc3339()).ok()),
+        body: String::new(),
+        attachments: Vec::new()
+    };
+
+    // the text bodies are the alternatives to the HTML ones, so there's no need to read both
+    let mut bodies = Vec::new();
+    for part in message.text_body.iter().filter_map(|&id| message.parts.get(id as usize)) {
+        let text = match &part.body {
+            _ if attachment(part).kind() == AttachmentKind::Calendar => continue,
+            PartType::Text(text) => text.to_string(),
+            // where there's no plain text alternative
+            PartType::Html(html) => html_to_text(html),
+            _ => continue
+        };
+        if !text.trim().is_empty() {
+            bodies.push(text.trim().to_string());
+        }
+    }
+    email.body = bodies.join("\n\n");
+
+    for (id, part) in message.parts.iter().enumerate() {
+        let is_body = message.text_body.contains(&(id as u32)) || message.html_body.contains(&(id as u32));
+        match &part.body {
+            PartType::Multipart(_) => {},
+            PartType::Message(attached) if depth < MAX_ATTACHED_EMAIL_DEPTH => {
+                let attached = read_message(attached, depth + 1);
+                email.body.push_str(&format!("\n\n---------- Attached email ----------\n{}", attached.text()));
+                email.attachments.extend(attached.attachments);
+            },
+            PartType::Message(_) => tracing::debug!("Skipping an email attached more than {MAX_ATTACHED_EMAIL_DEPTH} deep"),
+            _ => {
+                let attachment = attachment(part);
+                // invites give their calendar data as an alternative to the body, rather than an attachment
+                if !is_body || attachment.kind() == AttachmentKind::Calendar {
+                    email.attachments.push(attachment);
+                }
+            }
+        }
+    }
+    email
+}
+
+/// Read a (non-multipart) message part as an attachment.
+fn attachment(part: &MessagePart) -> EmailAttachment {
+    let content_type = part.content_type().map(|content_type| match content_type.subtype() {
+        Some(subtype) => format!("{}/{subtype}", content_type.ctype()).to_ascii_lowercase(),
+        None => content_type.ctype().to_ascii_lowercase()
+    });
+    EmailAttachment {
+        filename: part.attachment_name().map(str::to_string),
+        content_type,
+        bytes: part.contents().to_vec()
+    }
+}
+
+/// Read an Outlook `.msg` file, returning `None` if it isn't a valid compound file.
+///
+/// Its properties are kept in streams named after their ID and type (see `MS-OXMSG`); meeting requests also keep
+/// their times in named properties, which we don't read, so their bodies are relied on instead.
+fn parse_msg(bytes: &[u8]) -> Option<ParsedEmail> {
+    let mut file = CompoundFile::open(Cursor::new(bytes))
+        .inspect_err(|err| tracing::debug!("Failed to open a .msg file: {err}"))
+        .ok()?;
+
+    let subject = msg_string(&mut file, "/", 0x0037);
+    let sender_name = msg_string(&mut file, "/", 0x0C1A);
+    // Exchange senders have an X.500 address instead of an email address
+    let sender_address = msg_string(&mut file, "/", 0x0C1F).filter(|address| address.contains('@'));
+    let from = match (sender_name, sender_address) {
+        (Some(name), Some(address)) => Some(format!("{name} <{address}>")),
+        (name, address) => name.or(address)
+    };
+    let body = msg_string(&mut file, "/", 0x1000)
+        .or_else(|| {
+            let html = msg_binary(&mut file, "/", 0x1013).map(|html| String::from_utf8_lossy(&html).into_owned());
+            html.or_else(|| msg_string(&mut file, "/", 0x1013)).map(|html| html_to_text(&html))
+        })
+        .unwrap_or_default();
+    // when it was sent, or else when it was received
+    let sent = msg_time(&mut file, 0x0039).or_else(|| msg_time(&mut file, 0x0E06));
+
+    let attachment_storages: Vec<String> = file
+        .read_root_storage()
+        .filter(|entry| entry.is_storage() && entry.name().starts_with("__attach_version1.0_#"))
+        .map(|entry| format!("/{}/", entry.name()))
+        .collect();
+    let attachments = attachment_storages
+        .iter()
+        .filter_map(|storage| {
+            // attached emails are kept as storages rather than data, so are skipped
+            let bytes = msg_binary(&mut file, storage, 0x3701)?;
+            Some(EmailAttachment {
+                filename: msg_string(&mut file, storage, 0x3707).or_else(|| msg_string(&mut file, storage, 0x3704)),
+                content_type: msg_string(&mut file, storage, 0x370E).map(|content_type| content_type.to_ascii_lowercase()),
+                bytes
+            })
+        })
+        .collect();
+
+    Some(ParsedEmail { subject, from, sent, body, attachments })
+}
+
+/// Read a stream of a `.msg` file, returning `None` if there isn't one.
+fn msg_stream(file: &mut CompoundFile<Cursor<&[u8]>>, path: &str) -> Option<Vec<u8>> {
+    let mut stream = file.open_stream(path).ok()?;
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Read a string property of a `.msg` file, either Unicode (UTF-16) or 8-bit, returning `None` if it's missing or
+/// blank.
+fn msg_string(file: &mut CompoundFile<Cursor<&[u8]>>, storage: &str, id: u16) -> Option<String> {
+    let text = match msg_stream(file, &format!("{storage}__substg1.0_{id:04X}001F")) {
+        Some(bytes) => {
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+            String::from_utf16_lossy(&units)
+        },
+        None => String::from_utf8_lossy(&msg_stream(file, &format!("{storage}__substg1.0_{id:04X}001E"))?).into_owned()
+    };
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Read a binary property of a `.msg` file.
+fn msg_binary(file: &mut CompoundFile<Cursor<&[u8]>>, storage: &str, id: u16) -> Option<Vec<u8>> {
+    msg_stream(file, &format!("{storage}__substg1.0_{id:04X}0102"))
+}
+
+/// Read a time property of a `.msg` file.
+///
+/// Fixed-size properties are kept together in the top-level property stream, after a 32-byte header, as 16-byte
+/// entries of the property's type and ID, flags and value; times are in 100ns intervals since 1601.
+fn msg_time(file: &mut CompoundFile<Cursor<&[u8]>>, id: u16) -> Option<DateTime<FixedOffset>> {
+    const TIME_TYPE: u16 = 0x0040;
+    const SECONDS_FROM_1601_TO_1970: i64 = 11_644_473_600;
+
+    let properties = msg_stream(file, "/__properties_version1.0")?;
+    let entry = properties.get(32..)?.chunks_exact(16).find(|entry| {
+        u16::from_le_bytes([entry[0], entry[1]]) == TIME_TYPE && u16::from_le_bytes([entry[2], entry[3]]) == id
+    })?;
+    let intervals = i64::try_from(u64::from_le_bytes(entry[8..16].try_into().ok()?)).ok()?;
+    let time = DateTime::from_timestamp(intervals / 10_000_000 - SECONDS_FROM_1601_TO_1970, 0)?;
+    Some(time.fixed_offset())
+}
+
+/// Convert an HTML body to text, keeping its links (which often say where an event is, or how to join it).
+fn html_to_text(html: &str) -> String {
+    html2text::config::plain()
+        .raw_mode(true)
+        .string_from_read(html.as_bytes(), HTML_TEXT_WIDTH)
+        .unwrap_or_else(|err| {
+            tracing::debug!("Failed to convert an HTML body to text, so falling back to stripping its tags: {err}");
+            html::html_to_text(html)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    fn kinds(email: &ParsedEmail) -> Vec<(&str, AttachmentKind)> {
+        email.attachments.iter().map(|attachment| (attachment.display_name(), attachment.kind())).collect()
+    }
+
+    #[test]
+    fn reads_invites() {
+        let email = parse_email(include_bytes!("../../tests/fixtures/email/invite.eml")).unwrap();
+        assert_eq!(email.subject.as_deref(), Some("Weekly planning – Room 4"));
+        assert_eq!(email.from.as_deref(), Some("Priya Shah <priya@example.com>"));
+        assert_eq!(email.sent.unwrap().to_rfc3339(), "2026-10-19T09:30:00+01:00");
+        // the plain text body, rather than its HTML alternative
+        assert_eq!(email.body, "Hi all, planning is moving to Tuesdays at 9:30 in Room 4 – see the invite.");
+        assert_eq!(
+            kinds(&email),
+            [
+                ("attachment", AttachmentKind::Calendar),
+                ("floor plan.png", AttachmentKind::Image),
+                ("agenda.PDF", AttachmentKind::Pdf)
+            ]
+        );
+        let invite = String::from_utf8(email.attachments[0].bytes.clone()).unwrap();
+        assert!(invite.contains("SUMMARY;LANGUAGE=en-GB:Weekly planning"));
+
+        let text = email.text();
+        assert!(text.starts_with("Subject: Weekly planning – Room 4\nFrom: Priya Shah <priya@example.com>\nSent: Monday 19 October 2026 09:30 +01:00\n\n"));
+    }
+
+    #[test]
+    fn reads_html_bodies() {
+        let email = parse_email(include_bytes!("../../tests/fixtures/email/html_only.eml")).unwrap();
+        assert_eq!(email.from.as_deref(), Some("tickets@venue.example"));
+        assert_eq!(email.sent.unwrap().offset().local_minus_utc(), -5 * 60 * 60);
+        // blocks stay apart, and links are kept
+        assert!(email.body.contains("Summer fete\n"), "{}", email.body);
+        assert!(email.body.contains("Saturday 12 June, 11am"));
+        assert!(email.body.contains("https://venue.example/map"));
+        assert!(!email.body.contains('<'));
+        assert!(email.attachments.is_empty());
+    }
+
+    #[test]
+    fn reads_attached_emails() {
+        let email = parse_email(include_bytes!("../../tests/fixtures/email/forwarded.eml")).unwrap();
+        assert!(email.body.starts_with("See below!\n\n---------- Attached email ----------\nSubject: Book club\nFrom: Jo <jo@example.com>\n"));
+        assert!(email.body.contains("Book club is on the 5th of November at 7pm."));
+        assert_eq!(kinds(&email), [("bookclub.ics", AttachmentKind::Calendar)]);
+    }
+
+    /// Build a `.msg` file with a subject, sender, body, sent time and an attachment.
+    fn build_msg(properties: &[u8]) -> Vec<u8> {
+        let mut file = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        let write = |file: &mut CompoundFile<Cursor<Vec<u8>>>, path: &str, bytes: &[u8]| {
+            file.create_stream(path).unwrap().write_all(bytes).unwrap();
+        };
+        let utf16 = |text: &str| text.encode_utf16().flat_map(u16::to_le_bytes).chain([0, 0]).collect::<Vec<u8>>();
+
+        write(&mut file, "/__substg1.0_0037001F", &utf16("Quarterly review"));
+        write(&mut file, "/__substg1.0_0C1A001E", b"Priya Shah");
+        // an Exchange X.500 address, which isn't worth keeping
+        write(&mut file, "/__substg1.0_0C1F001E", b"/O=EXCHANGELABS/OU=EXCHANGE ADMINISTRATIVE GROUP/CN=RECIPIENTS/CN=PRIYA");
+        write(&mut file, "/__substg1.0_1000001F", &utf16("The review is on Thursday at 2pm.\0"));
+        write(&mut file, "/__properties_version1.0", properties);
+        file.create_storage("/__attach_version1.0_#00000000").unwrap();
+        write(&mut file, "/__attach_version1.0_#00000000/__substg1.0_37010102", b"%PDF-1.4");
+        write(&mut file, "/__attach_version1.0_#00000000/__substg1.0_3707001F", &utf16("slides.pdf"));
+        write(&mut file, "/__attach_version1.0_#00000000/__substg1.0_370E001E", b"Application/PDF");
+        file.flush().unwrap();
+        file.into_inner().into_inner()
+    }
+
+    /// A property stream's entry for a time property.
+    fn time_entry(id: u16, intervals: u64) -> Vec<u8> {
+        [0x0040u16.to_le_bytes(), id.to_le_bytes()].concat().into_iter()
+            .chain([0; 4])
+            .chain(intervals.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn reads_msg_files() {
+        // 2026-10-19T09:30:00Z, as 100ns intervals since 1601, after an unrelated (integer) property
+        let properties = [
+            vec![0; 32],
+            vec![0x03, 0, 0x07, 0x0E, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0],
+            time_entry(0x0039, 134_368_758_000_000_000)
+        ].concat();
+        let bytes = build_msg(&properties);
+        assert!(is_msg(&bytes));
+
+        let email = parse_email(&bytes).unwrap();
+        assert_eq!(email.subject.as_deref(), Some("Quarterly review"));
+        assert_eq!(email.from.as_deref(), Some("Priya Shah"));
+        assert_eq!(email.body, "The review is on Thursday at 2pm.");
+        assert_eq!(email.sent.unwrap().to_rfc3339(), "2026-10-19T09:30:00+00:00");
+        assert_eq!(kinds(&email), [("slides.pdf", AttachmentKind::Pdf)]);
+        assert_eq!(email.attachments[0].content_type.as_deref(), Some("application/pdf"));
+    }
+
+    #[test]
+    fn skips_bad_msg_times() {
+        // a property stream too short to have any entries
+        let email = parse_email(&build_msg(&[0; 20])).unwrap();
+        assert_eq!(email.sent, None);
+        // too far in the future to be a time
+        let properties = [vec![0; 32], time_entry(0x0039, u64::MAX)].concat();
+        assert_eq!(parse_email(&build_msg(&properties)).unwrap().sent, None);
+    }
+
+    #[test]
+    fn rejects_malformed_emails() {
+        assert!(parse_email(b"").is_none());
+        // looks like a .msg file, but isn't one
+        assert!(parse_email(&[COMPOUND_FILE_MAGIC.as_slice(), &[0; 600]].concat()).is_none());
+        // a compound file without any of a message's properties
+        let mut file = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        file.flush().unwrap();
+        assert!(parse_email(&file.into_inner().into_inner()).is_none());
+    }
+
+    #[test]
+    fn detects_attachment_kinds() {
+        let attachment = |filename: Option<&str>, content_type: Option<&str>, bytes: &[u8]| EmailAttachment {
+            filename: filename.map(str::to_string),
+            content_type: content_type.map(str::to_string),
+            bytes: bytes.to_vec()
+        };
+        assert_eq!(attachment(Some("invite.ICS"), Some("application/octet-stream"), b"").kind(), AttachmentKind::Calendar);
+        assert_eq!(attachment(None, Some("application/ics"), b"").kind(), AttachmentKind::Calendar);
+        assert_eq!(attachment(Some("photo.jpeg"), None, b"").kind(), AttachmentKind::Image);
+        assert_eq!(attachment(None, None, b"%PDF-1.7").kind(), AttachmentKind::Pdf);
+        assert_eq!(attachment(None, None, b"\x89PNG\r\n\x1a\n").kind(), AttachmentKind::Image);
+        assert_eq!(attachment(Some("notes.docx"), Some("application/msword"), b"PK").kind(), AttachmentKind::Other);
+    }
+}
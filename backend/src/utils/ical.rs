@@ -0,0 +1,325 @@
+//! Reads events from iCalendar (RFC 5545) data, like that encoded in a QR code on a poster or attached to an invite.
+//!
+//! The data says exactly what the events are, so unlike other inputs it needs no LLM.
+
+use std::{collections::HashMap, str::FromStr};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use icalendar::{Calendar, Component, Event, EventLike, Property};
+use windows_timezones::WindowsTimezone;
+use crate::utils::recurrence_phrase::{ParsedRecurrence, RecurrenceEnd};
+
+/// Recorded as the prompt version of extractions read from iCalendar data, in place of the LLM's.
+pub const ICALENDAR_VERSION: &str = "icalendar-1";
+
+/// How long a timed event is when the data gives neither an end nor a duration (all-day events last the day).
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+
+const DATE_FORMAT: &str = "%Y%m%d";
+const DATETIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// An event read from iCalendar data, in its own timezone.
+#[derive(Debug, Clone)]
+pub struct ICalendarEvent {
+    pub title: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// The timezone of the event's times, or `None` if they're "floating" (in whatever timezone the user's in).
+    pub timezone: Option<Tz>,
+    /// The recurrence, starting at `start`, if the event repeats.
+    pub recurrence: Option<ParsedRecurrence>,
+    /// The original starts of the recurrence's cancelled (or separately given) instances.
+    pub cancelled: Vec<NaiveDateTime>
+}
+
+/// Whether the text is (or contains) iCalendar data with an event.
+pub fn is_icalendar(text: &str) -> bool {
+    text.contains("BEGIN:VEVENT")
+}
+
+/// Read the events from iCalendar data, skipping cancelled ones and any without a title or start.
+///
+/// Instances of a recurring event given separately (with a `RECURRENCE-ID`) are read as events of their own, and
+/// cancelled in the recurrence.
+pub fn parse_icalendar(text: &str) -> Vec<ICalendarEvent> {
+    let calendar = match Calendar::from_str(text.trim()) {
+        Ok(calendar) => calendar,
+        Err(err) => {
+            tracing::debug!("Failed to parse iCalendar data: {err}");
+            return Vec::new();
+        }
+    };
+    // a cancellation of a meeting (rather than an invite to one)
+    if calendar.property_value("METHOD").is_some_and(|method| method.eq_ignore_ascii_case("CANCEL")) {
+        return Vec::new();
+    }
+
+    let mut events = Vec::new();
+    // the original starts of the instances given separately, by the UID of their recurring event
+    let mut instances: HashMap<String, Vec<(NaiveDateTime, Option<Tz>)>> = HashMap::new();
+    let mut recurring = HashMap::new();
+    for event in calendar.events() {
+        if event.property_value("STATUS").is_some_and(|status| status.eq_ignore_ascii_case("CANCELLED")) {
+            continue;
+        }
+        let Some(parsed) = parse_event(event) else {
+            continue;
+        };
+        let uid = event.property_value("UID").map(str::to_string);
+        if let Some(uid) = &uid {
+            if let Some(instance) = event.properties().get("RECURRENCE-ID").and_then(parse_time) {
+                instances.entry(uid.clone()).or_default().push(instance);
+            } else if parsed.recurrence.is_some() {
+                recurring.insert(uid.clone(), events.len());
+            }
+        }
+        events.push(parsed);
+    }
+
+    for (uid, starts) in instances {
+        if let Some(event) = recurring.get(&uid).map(|&i| &mut events[i]) {
+            let (timezone, time) = (event.timezone, event.start.time());
+            event.cancelled.extend(starts.into_iter().map(|start| in_timezone(start, timezone, time)));
+        }
+    }
+    events
+}
+
+/// Read one event, returning `None` if it has no title or start.
+fn parse_event(event: &Event) -> Option<ICalendarEvent> {
+    let text = |value: Option<&str>| value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_string);
+    let title = text(event.get_summary())?;
+    let (start, timezone) = event.properties().get("DTSTART").and_then(parse_time)?;
+    let all_day = is_date(event.properties().get("DTSTART")?);
+
+    let end = match (event.properties().get("DTEND").and_then(parse_time), event.property_value("DURATION")) {
+        (Some(end), _) => in_timezone(end, timezone, NaiveTime::MIN),
+        (None, Some(duration)) => start + parse_duration(duration)?,
+        (None, None) if all_day => start + Duration::days(1),
+        (None, None) => start + Duration::minutes(DEFAULT_DURATION_MINUTES)
+    };
+    if end < start {
+        return None;
+    }
+
+    // a poster's link is worth keeping along with its description
+    let description = match (text(event.get_description()), text(event.get_url())) {
+        (Some(description), Some(url)) if !description.contains(&url) => Some(format!("{description}\n{url}")),
+        (description, url) => description.or(url)
+    };
+    let recurrence = event.property_value("RRULE").and_then(|rule| parse_recurrence(rule, timezone));
+    let cancelled = event
+        .multi_properties()
+        .get("EXDATE")
+        .into_iter()
+        .flatten()
+        .flat_map(|property| {
+            let tzid = property.params().get("TZID").map(|param| param.value().to_string());
+            property
+                .value()
+                .split(',')
+                .filter_map(move |value| parse_time_value(value, tzid.as_deref()))
+                .collect::<Vec<_>>()
+        })
+        .map(|exception| in_timezone(exception, timezone, start.time()))
+        .collect();
+
+    Some(ICalendarEvent {
+        title,
+        description,
+        location: text(event.get_location()),
+        start,
+        end,
+        timezone,
+        recurrence,
+        cancelled
+    })
+}
+
+/// Whether a property's value is a date (rather than a datetime).
+fn is_date(property: &Property) -> bool {
+    NaiveDate::parse_from_str(property.value().trim(), DATE_FORMAT).is_ok()
+}
+
+/// Read a date or datetime property, with its timezone (if it isn't floating).
+fn parse_time(property: &Property) -> Option<(NaiveDateTime, Option<Tz>)> {
+    let tzid = property.params().get("TZID").map(|param| param.value());
+    parse_time_value(property.value(), tzid)
+}
+
+/// Read a date (as its midnight) or datetime, in UTC if it ends with `Z`, else in the given timezone (if known).
+fn parse_time_value(value: &str, tzid: Option<&str>) -> Option<(NaiveDateTime, Option<Tz>)> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, DATE_FORMAT) {
+        return Some((date.and_time(NaiveTime::MIN), None));
+    }
+    match value.strip_suffix('Z') {
+        Some(value) => Some((NaiveDateTime::parse_from_str(value, DATETIME_FORMAT).ok()?, Some(Tz::UTC))),
+        None => Some((NaiveDateTime::parse_from_str(value, DATETIME_FORMAT).ok()?, tzid.and_then(parse_tzid)))
+    }
+}
+
+/// Find the timezone a `TZID` names: an IANA or Windows name (as Outlook uses), possibly prefixed with a vendor path
+/// (like `/mozilla.org/20050126_1/Europe/London`).
+fn parse_tzid(tzid: &str) -> Option<Tz> {
+    let tzid = tzid.trim().trim_matches('"');
+    let parse = |name: &str| name.parse::<Tz>().ok().or_else(|| WindowsTimezone::from_str(name).ok().map(Tz::from));
+    parse(tzid).or_else(|| {
+        let parts: Vec<&str> = tzid.split('/').collect();
+        parts.len().checked_sub(2).and_then(|i| parse(&parts[i..].join("/")))
+    })
+}
+
+/// Convert a time to the given timezone, with a date (read as midnight) taking the given time of day.
+fn in_timezone((time, from): (NaiveDateTime, Option<Tz>), to: Option<Tz>, time_of_day: NaiveTime) -> NaiveDateTime {
+    match (from, to) {
+        (None, _) if time.time() == NaiveTime::MIN => time.date().and_time(time_of_day),
+        (Some(from), Some(to)) if from != to => from
+            .from_local_datetime(&time)
+            .earliest()
+            .map_or(time, |time| time.with_timezone(&to).naive_local()),
+        _ => time
+    }
+}
+
+/// Read an ISO 8601 duration, like `PT1H30M` or `P1D` (negative durations aren't supported).
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let value = value.strip_prefix('+').unwrap_or(value).strip_prefix('P')?;
+    let (mut duration, mut number, mut in_time) = (Duration::zero(), String::new(), false);
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            unit => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                duration += match (unit, in_time) {
+                    ('W', false) => Duration::weeks(n),
+                    ('D', false) => Duration::days(n),
+                    ('H', true) => Duration::hours(n),
+                    ('M', true) => Duration::minutes(n),
+                    ('S', true) => Duration::seconds(n),
+                    _ => return None
+                };
+            }
+        }
+    }
+    number.is_empty().then_some(duration)
+}
+
+/// Read an `RRULE`, with its `UNTIL` or `COUNT` taken out as the end.
+fn parse_recurrence(rule: &str, timezone: Option<Tz>) -> Option<ParsedRecurrence> {
+    let rule = rule.trim().to_uppercase();
+    let mut end = RecurrenceEnd::Never;
+    let mut parts = Vec::new();
+    for part in rule.split(';').filter(|part| !part.is_empty()) {
+        match part.split_once('=') {
+            Some(("UNTIL", until)) => {
+                let until = parse_time_value(until, None)?;
+                end = RecurrenceEnd::Until(in_timezone(until, timezone, NaiveTime::MIN).date());
+            }
+            Some(("COUNT", count)) => end = RecurrenceEnd::Count(count.parse().ok()?),
+            _ => parts.push(part)
+        }
+    }
+    let rule = parts
+        .join(";")
+        .parse()
+        .inspect_err(|err| tracing::debug!("Failed to parse an iCalendar recurrence rule `{rule}`: {err}"))
+        .ok()?;
+    Some(ParsedRecurrence { rule, end, notes: Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use rrule::{Frequency, NWeekday, Weekday};
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn reads_folded_and_escaped_values() {
+        let events = parse_icalendar(include_str!("../../tests/fixtures/ical/poster.ics"));
+        let [event] = events.as_slice() else { panic!("expected one event, got {events:?}") };
+        assert_eq!(event.title, "Summer Fete");
+        assert_eq!(event.location.as_deref(), Some("Village Green, Upper Street"));
+        assert_eq!(
+            event.description.as_deref(),
+            Some("Cake stall, tombola and a dog show. Bring the whole family, and some change for the raffle.\nhttps://villagehall.example/fete")
+        );
+        assert_eq!(event.start, at(2027, 6, 12, 11, 0));
+        assert_eq!(event.end, at(2027, 6, 12, 16, 0));
+        assert_eq!(event.timezone, Some(chrono_tz::Europe::London));
+        assert!(event.recurrence.is_none());
+    }
+
+    #[test]
+    fn reads_outlook_invites() {
+        let text = include_str!("../../tests/fixtures/ical/outlook_invite.ics");
+        assert!(is_icalendar(text));
+        let events = parse_icalendar(text);
+        let [series, moved] = events.as_slice() else { panic!("expected two events, got {events:?}") };
+
+        // a Windows timezone name
+        assert_eq!(series.timezone, Some(chrono_tz::Europe::London));
+        assert_eq!(series.start, at(2026, 10, 20, 9, 30));
+        assert_eq!(series.end, at(2026, 10, 20, 10, 15));
+        let recurrence = series.recurrence.as_ref().unwrap();
+        assert_eq!(recurrence.rule.get_freq(), Frequency::Weekly);
+        assert_eq!(recurrence.rule.get_by_weekday(), [NWeekday::Every(Weekday::Tue)]);
+        assert_eq!(recurrence.end, RecurrenceEnd::Until(NaiveDate::from_ymd_opt(2026, 12, 15).unwrap()));
+        // the excluded dates, then the instance that was moved
+        assert_eq!(series.cancelled, [at(2026, 11, 3, 9, 30), at(2026, 11, 10, 9, 30), at(2026, 11, 17, 9, 30)]);
+
+        assert_eq!(moved.title, "Weekly planning (moved)");
+        assert_eq!(moved.start, at(2026, 11, 18, 14, 0));
+        assert!(moved.recurrence.is_none());
+    }
+
+    #[test]
+    fn reads_dates_durations_and_timezones() {
+        let events = parse_icalendar(include_str!("../../tests/fixtures/ical/mixed.ics"));
+        let titles: Vec<&str> = events.iter().map(|event| event.title.as_str()).collect();
+        // the cancelled event and those missing or with a bad start, end or title are skipped
+        assert_eq!(titles, ["Bank holiday", "Call with New York", "Launch"]);
+
+        // all day, and floating
+        assert_eq!(events[0].start, at(2026, 12, 25, 0, 0));
+        assert_eq!(events[0].end, at(2026, 12, 26, 0, 0));
+        assert_eq!(events[0].timezone, None);
+        // a vendor-prefixed timezone
+        assert_eq!(events[1].timezone, Some(chrono_tz::America::New_York));
+        assert_eq!(events[1].end, at(2026, 10, 21, 10, 30));
+        // UTC, with the default duration
+        assert_eq!(events[2].timezone, Some(Tz::UTC));
+        assert_eq!(events[2].end, at(2026, 10, 22, 18, 0));
+    }
+
+    #[test]
+    fn skips_cancellations_and_malformed_data() {
+        assert!(parse_icalendar(include_str!("../../tests/fixtures/ical/cancellation.ics")).is_empty());
+        assert!(parse_icalendar("").is_empty());
+        assert!(parse_icalendar("Summer fete, Saturday 12th June 11am").is_empty());
+        assert!(!is_icalendar("BEGIN:VCALENDAR\nEND:VCALENDAR"));
+
+        // cut off partway through the event
+        let poster = include_str!("../../tests/fixtures/ical/poster.ics");
+        let truncated = &poster[..poster.find("DTEND").unwrap()];
+        assert!(parse_icalendar(truncated).iter().all(|event| event.start == at(2027, 6, 12, 11, 0)));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1W2D"), Some(Duration::days(9)));
+        assert_eq!(parse_duration("P1DT12H"), Some(Duration::hours(36)));
+        assert_eq!(parse_duration("-PT1H"), None);
+        assert_eq!(parse_duration("PT1"), None);
+        assert_eq!(parse_duration("P1H"), None);
+    }
+}
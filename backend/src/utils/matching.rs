@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use chrono::{DateTime, Duration, Utc};
+use crate::models::recurring_event::NewRecurringEvent;
+
+/// How far past its start we expand a recurring event with no end, when comparing occurrences.
+const OPEN_ENDED_EXPANSION_WEEKS: i64 = 52;
+
+/// Pair up two lists of items, given as (title, start), returning the indices of each pair.
+///
+/// Items can pair if their titles are at least `min_title_similarity` similar (see `title_similarity`) or they start
+/// within `start_tolerance` of each other, so an item with a changed time or title is still paired up.
+/// The best pairs (by title similarity, then how close their starts are) are taken first.
+pub fn pair_by_title_and_start<S: AsRef<str>>(
+    a: &[(S, DateTime<Utc>)],
+    b: &[(S, DateTime<Utc>)],
+    start_tolerance: Duration,
+    min_title_similarity: f64
+) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    for (i, (a_title, a_start)) in a.iter().enumerate() {
+        for (j, (b_title, b_start)) in b.iter().enumerate() {
+            let similarity = title_similarity(a_title.as_ref(), b_title.as_ref());
+            let same_start = (*a_start - *b_start).abs() <= start_tolerance;
+            if similarity >= min_title_similarity || same_start {
+                let hours_apart = (*a_start - *b_start).num_minutes().abs() as f64 / 60.0;
+                candidates.push((similarity + 1.0 / (1.0 + hours_apart), i, j));
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.0.total_cmp(&x.0));
+
+    let (mut a_paired, mut b_paired) = (vec![false; a.len()], vec![false; b.len()]);
+    let mut pairs = Vec::new();
+    for (_, i, j) in candidates {
+        if !a_paired[i] && !b_paired[j] {
+            a_paired[i] = true;
+            b_paired[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+/// How similar two titles are, from 0 to 1, by the share of (case-insensitive) words they have in common.
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    let words = |title: &str| title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<HashSet<_>>();
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+/// The starts of a recurring event's occurrences, up to the rule's instance limit.
+///
+/// Comparing these (rather than the rules themselves) means different ways of writing the same rule still match.
+pub fn occurrences(event: &NewRecurringEvent) -> Vec<DateTime<Utc>> {
+    let mut rrule = event.rrule.clone();
+    rrule.set_start(event.recurrence_start);
+    // a rule can't have both a COUNT and an UNTIL
+    if !rrule.has_count() {
+        rrule.set_end(event.recurrence_end);
+    }
+    let end = event.recurrence_end.unwrap_or(event.recurrence_start + Duration::weeks(OPEN_ENDED_EXPANSION_WEEKS));
+    rrule
+        .all_within_period(event.recurrence_start - Duration::seconds(1), end + Duration::seconds(1))
+        .dates
+        .iter()
+        .map(|date| date.to_utc())
+        .collect()
+}
+
+/// Whether two lists of occurrences are the same, to within `tolerance`.
+pub fn same_occurrences(a: &[DateTime<Utc>], b: &[DateTime<Utc>], tolerance: Duration) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (*a - *b).abs() <= tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(datetime: &str) -> DateTime<Utc> {
+        datetime.parse().unwrap()
+    }
+
+    /// A recurring event starting on Monday 14 September 2026.
+    fn recurring(rrule: &str, recurrence_end: Option<&str>) -> NewRecurringEvent {
+        serde_json::from_value(serde_json::json!({
+            "group_id": null,
+            "is_active": true,
+            "title": "Lecture",
+            "description": null,
+            "location": null,
+            "event_duration_seconds": 3600,
+            "recurrence_start": "2026-09-14T10:00:00Z",
+            "recurrence_end": recurrence_end,
+            "rrule": rrule
+        })).unwrap()
+    }
+
+    #[test]
+    fn compares_titles_by_their_words() {
+        assert_eq!(title_similarity("Team Meeting", "team meeting!"), 1.0);
+        assert_eq!(title_similarity("Team meeting", "Team lunch"), 1.0 / 3.0);
+        assert_eq!(title_similarity("Dentist", "Haircut"), 0.0);
+        assert_eq!(title_similarity("", "--"), 1.0);
+    }
+
+    #[test]
+    fn pairs_by_title_or_start() {
+        let a = [
+            ("Dentist", at("2026-09-02T14:00:00Z")),
+            ("Team meeting", at("2026-09-03T09:00:00Z")),
+            ("Gym", at("2026-09-04T18:00:00Z"))
+        ];
+        let b = [
+            // moved, but still titled the same
+            ("Dentist appointment", at("2026-09-02T15:00:00Z")),
+            // renamed, but still at the same time
+            ("Standup", at("2026-09-03T09:00:00Z")),
+            ("Haircut", at("2026-09-10T12:00:00Z"))
+        ];
+        let mut pairs = pair_by_title_and_start(&a, &b, Duration::minutes(1), 0.5);
+        pairs.sort();
+        assert_eq!(pairs, [(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn pairs_the_best_matches_first() {
+        let a = [("Lecture", at("2026-09-14T10:00:00Z")), ("Lecture", at("2026-09-16T10:00:00Z"))];
+        let b = [("Lecture", at("2026-09-16T10:00:00Z")), ("Lecture", at("2026-09-14T10:00:00Z"))];
+        let mut pairs = pair_by_title_and_start(&a, &b, Duration::minutes(1), 0.5);
+        pairs.sort();
+        assert_eq!(pairs, [(0, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn expands_rules_written_differently_to_the_same_occurrences() {
+        let expected = [
+            at("2026-09-14T10:00:00Z"), at("2026-09-16T10:00:00Z"), at("2026-09-21T10:00:00Z"), at("2026-09-23T10:00:00Z")
+        ];
+        let count = occurrences(&recurring("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4", None));
+        let reordered = occurrences(&recurring("FREQ=WEEKLY;INTERVAL=1;COUNT=4;BYDAY=WE,MO", None));
+        let until = occurrences(&recurring("FREQ=WEEKLY;BYDAY=MO,WE", Some("2026-09-23T10:00:00Z")));
+        assert_eq!(count, expected);
+        assert_eq!(reordered, expected);
+        assert_eq!(until, expected);
+        assert!(same_occurrences(&count, &until, Duration::zero()));
+
+        let longer = occurrences(&recurring("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=5", None));
+        assert!(!same_occurrences(&count, &longer, Duration::minutes(1)));
+    }
+
+    #[test]
+    fn expands_open_ended_rules_for_a_year() {
+        let weekly = occurrences(&recurring("FREQ=WEEKLY", None));
+        assert_eq!(weekly.len(), 53);
+        assert_eq!(weekly.last(), Some(&at("2027-09-13T10:00:00Z")));
+    }
+
+    #[test]
+    fn compares_occurrences_within_the_tolerance() {
+        let a = [at("2026-09-14T10:00:00Z"), at("2026-09-16T10:00:00Z")];
+        let b = [at("2026-09-14T10:01:00Z"), at("2026-09-16T09:59:00Z")];
+        assert!(same_occurrences(&a, &b, Duration::minutes(1)));
+        assert!(!same_occurrences(&a, &b, Duration::seconds(59)));
+        assert!(!same_occurrences(&a, &a[..1], Duration::minutes(1)));
+    }
+}
@@ -1,4 +1,11 @@
 pub mod encrypt;
 pub mod azure;
 pub mod rrule;
-pub mod datetime;
\ No newline at end of file
+pub mod datetime;
+pub mod audio;
+pub mod matching;
+pub mod recurrence_phrase;
+pub mod quick_add;
+pub mod barcode;
+pub mod ical;
+pub mod email;
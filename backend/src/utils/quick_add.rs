@@ -0,0 +1,570 @@
+//! A deterministic parser for simple, single-event inputs in English, like "Dentist tomorrow 3pm for 45 min at Main St"
+//! or "Gym every monday and thursday 7am".
+//!
+//! It only answers when every word of the input is accounted for and nothing is ambiguous (e.g. "at 3", which could be
+//! morning or afternoon, or "next friday"); anything else is left for the LLM.
+
+use chrono::{Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use rrule::{NWeekday, Weekday};
+use crate::utils::recurrence_phrase::{
+    parse_day_month_year, parse_month, parse_number, parse_recurrence_phrase, parse_weekday, ParsedRecurrence
+};
+
+/// Recorded as the prompt version of extractions the parser handled, in place of the LLM's.
+pub const QUICK_ADD_VERSION: &str = "quick-add-1";
+
+/// Inputs longer than this are unlikely to be a single simple event.
+const MAX_WORDS: usize = 24;
+
+/// How long an event is when the input doesn't say.
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+
+/// Words which change the meaning of whatever follows them, so can't end a title (as in "lunch next friday").
+const DANGLING_WORDS: [&str; 14] = [
+    "next", "last", "this", "coming", "on", "at", "by", "before", "after", "from", "until", "the", "every", "in"
+];
+
+/// Words which start a recurrence, like "every monday" or "daily".
+const RECURRENCE_STARTS: [&str; 10] = [
+    "every", "each", "daily", "weekly", "weekdays", "fortnightly", "biweekly", "monthly", "yearly", "annually"
+];
+
+/// An event parsed from an input, in the user's local terms.
+#[derive(Debug, Clone)]
+pub struct QuickAddEvent {
+    pub title: String,
+    pub location: Option<String>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// The recurrence, starting at `start`, if the event repeats.
+    pub recurrence: Option<ParsedRecurrence>
+}
+
+/// Parse a single event from an input, given the user's local time now, returning `None` unless it's fully understood.
+pub fn parse_quick_add(text: &str, now: NaiveDateTime) -> Option<QuickAddEvent> {
+    let text = text.trim();
+    // several lines or clauses are likely several events
+    if text.contains(['\n', ';']) {
+        return None;
+    }
+    let raw: Vec<&str> = text.split_whitespace().collect();
+    if raw.is_empty() || raw.len() > MAX_WORDS {
+        return None;
+    }
+    let lower: Vec<String> = raw
+        .iter()
+        .map(|word| word.trim_end_matches([',', '.', '!']).to_lowercase())
+        .collect();
+    let words: Vec<&str> = lower.iter().map(String::as_str).collect();
+    let today = now.date();
+
+    let mut parts = Parts::default();
+    let mut title_end = None;
+    let mut i = 0;
+    while i < words.len() {
+        if let Some((component, len)) = parse_component(&words[i..], today) {
+            title_end.get_or_insert(i);
+            parts.add(component)?;
+            i += len;
+        } else if i > 0 && matches!(words[i], "at" | "@") && i + 1 < words.len() {
+            // a location runs up to whatever comes next
+            title_end.get_or_insert(i);
+            let start = i + 1;
+            let end = (start + 1..words.len())
+                .find(|&j| parse_component(&words[j..], today).is_some())
+                .unwrap_or(words.len());
+            let location = join_words(&raw[start..end]);
+            if location.is_empty() || parts.location.replace(location).is_some() {
+                return None;
+            }
+            i = end;
+        } else if title_end.is_none() {
+            i += 1;
+        } else {
+            return None;
+        }
+    }
+
+    let title_end = title_end?;
+    if title_end == 0 || DANGLING_WORDS.contains(&words[title_end - 1]) {
+        return None;
+    }
+    // a date we don't understand, like "3/6", may be hiding in the title
+    if words[..title_end].iter().any(|word| word.contains('/') && word.contains(|c: char| c.is_ascii_digit())) {
+        return None;
+    }
+    let title = join_words(&raw[..title_end]);
+    if !title.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    parts.into_event(title, now)
+}
+
+/// A part of an input, other than its title and location.
+enum Component {
+    Date(DateSpec),
+    /// A start time, and possibly an end time (as in "3-4pm").
+    Time(NaiveTime, Option<NaiveTime>),
+    Duration(Duration),
+    Recurrence(Box<ParsedRecurrence>)
+}
+
+/// A date, which for a weekday depends on the time of the event.
+enum DateSpec {
+    Date(NaiveDate),
+    /// The next such weekday, which may be today.
+    Weekday(Weekday)
+}
+
+/// The parts found so far; each can only be given once.
+#[derive(Default)]
+struct Parts {
+    date: Option<DateSpec>,
+    time: Option<(NaiveTime, Option<NaiveTime>)>,
+    duration: Option<Duration>,
+    location: Option<String>,
+    recurrence: Option<ParsedRecurrence>
+}
+
+impl Parts {
+    /// Add a component, returning `None` if it was already given.
+    fn add(&mut self, component: Component) -> Option<()> {
+        let added = match component {
+            Component::Date(date) => self.date.replace(date).is_none(),
+            Component::Time(start, end) => self.time.replace((start, end)).is_none(),
+            Component::Duration(duration) => self.duration.replace(duration).is_none(),
+            Component::Recurrence(recurrence) => self.recurrence.replace(*recurrence).is_none()
+        };
+        added.then_some(())
+    }
+
+    fn into_event(self, title: String, now: NaiveDateTime) -> Option<QuickAddEvent> {
+        // an event without a time is likely all-day, or the time is too vague for us
+        let (time, end_time) = self.time?;
+        let today = now.date();
+        let date = match (self.date, &self.recurrence) {
+            (Some(DateSpec::Date(date)), _) => date,
+            (Some(DateSpec::Weekday(weekday)), _) => next_date(today, now.time(), time, |date| date.weekday() == weekday)?,
+            (None, Some(recurrence)) => {
+                // we can't find the first of a rule like "every first thursday of the month", so leave those out
+                if !recurrence.rule.get_by_month_day().is_empty() {
+                    return None;
+                }
+                let weekdays = recurrence.rule
+                    .get_by_weekday()
+                    .iter()
+                    .map(|weekday| match weekday {
+                        NWeekday::Every(weekday) => Some(*weekday),
+                        NWeekday::Nth(..) => None
+                    })
+                    .collect::<Option<Vec<Weekday>>>()?;
+                next_date(today, now.time(), time, |date| weekdays.is_empty() || weekdays.contains(&date.weekday()))?
+            },
+            // without a date, the time must still be ahead today
+            (None, None) => (time > now.time()).then_some(today)?
+        };
+        // exceptions (e.g. "except holidays") are better left to the LLM, which can create them
+        if self.recurrence.as_ref().is_some_and(|recurrence| !recurrence.notes.is_empty()) {
+            return None;
+        }
+
+        let start = date.and_time(time);
+        let end = match (end_time, self.duration) {
+            (Some(_), Some(_)) => return None,
+            (Some(end_time), None) => {
+                let end = date.and_time(end_time);
+                // "10pm-1am" ends the next day
+                if end > start { end } else { end + Duration::days(1) }
+            },
+            (None, Some(duration)) => start + duration,
+            (None, None) => start + Duration::minutes(DEFAULT_DURATION_MINUTES)
+        };
+        Some(QuickAddEvent { title, location: self.location, start, end, recurrence: self.recurrence })
+    }
+}
+
+/// The first date from today matching `matches`, skipping today if `time` has already passed.
+fn next_date(today: NaiveDate, now: NaiveTime, time: NaiveTime, matches: impl Fn(NaiveDate) -> bool) -> Option<NaiveDate> {
+    (0..8)
+        .filter_map(|days| today.checked_add_days(Days::new(days)))
+        .filter(|&date| date > today || time > now)
+        .find(|&date| matches(date))
+}
+
+/// Join words back together, without any punctuation trailing the last.
+fn join_words(words: &[&str]) -> String {
+    words.join(" ").trim_end_matches([',', '.', '!', '-', ':']).trim().to_string()
+}
+
+/// Parse the component at the start of `words`, returning it and how many words it took.
+fn parse_component(words: &[&str], today: NaiveDate) -> Option<(Component, usize)> {
+    if let Some((recurrence, len)) = parse_recurrence(words, today) {
+        return Some((Component::Recurrence(Box::new(recurrence)), len));
+    }
+    if let Some((date, len)) = parse_date(words, today) {
+        return Some((Component::Date(date), len));
+    }
+    if let Some((start, end, len)) = parse_time_range(words) {
+        return Some((Component::Time(start, end), len));
+    }
+    parse_duration(words).map(|(duration, len)| (Component::Duration(duration), len))
+}
+
+/// Parse the longest recurrence phrase (see `parse_recurrence_phrase`) at the start of `words`.
+fn parse_recurrence(words: &[&str], today: NaiveDate) -> Option<(ParsedRecurrence, usize)> {
+    if !RECURRENCE_STARTS.contains(words.first()?) {
+        return None;
+    }
+    (1..=words.len())
+        .rev()
+        .find_map(|len| parse_recurrence_phrase(&words[..len].join(" "), today).map(|recurrence| (recurrence, len)))
+}
+
+/// Parse a date, like "tomorrow", "on friday", "in 3 days", "june 3rd" or "2026-11-02".
+///
+/// "next friday" and numeric dates like "3/6" are ambiguous, so aren't parsed.
+fn parse_date(words: &[&str], today: NaiveDate) -> Option<(DateSpec, usize)> {
+    if let ["on", rest @ ..] = words {
+        return parse_date(rest, today).map(|(date, len)| (date, len + 1));
+    }
+    let after = |days: u64| today.checked_add_days(Days::new(days)).map(DateSpec::Date);
+    match words {
+        ["today" | "tonight", ..] => Some((DateSpec::Date(today), 1)),
+        ["tomorrow" | "tmrw" | "tmr", ..] => Some((after(1)?, 1)),
+        ["the", "day", "after", "tomorrow", ..] => Some((after(2)?, 4)),
+        ["day", "after", "tomorrow", ..] => Some((after(2)?, 3)),
+        ["this", weekday, ..] => Some((DateSpec::Weekday(parse_weekday(weekday)?), 2)),
+        ["in", count, unit, ..] => {
+            let count = u64::from(match *count {
+                "a" | "an" => 1,
+                count => parse_number(count)?
+            });
+            let days = match *unit {
+                "day" | "days" => count,
+                "week" | "weeks" => count * 7,
+                _ => return None
+            };
+            Some((after(days)?, 3))
+        },
+        [iso, ..] if iso.len() == 10 && iso.contains('-') => {
+            NaiveDate::parse_from_str(iso, "%Y-%m-%d").ok().map(|date| (DateSpec::Date(date), 1))
+        },
+        [first, ..] if parse_weekday(first).is_some() => Some((DateSpec::Weekday(parse_weekday(first)?), 1)),
+        ["the", day, "of", month, rest @ ..] => day_month_year(day, month, rest, today).map(|(date, len)| (date, len + 3)),
+        [day, "of", month, rest @ ..] => day_month_year(day, month, rest, today).map(|(date, len)| (date, len + 2)),
+        [month, day, rest @ ..] if parse_month(month).is_some() => {
+            day_month_year(day, month, rest, today).map(|(date, len)| (date, len + 1))
+        },
+        [day, month, rest @ ..] => day_month_year(day, month, rest, today).map(|(date, len)| (date, len + 1)),
+        _ => None
+    }
+}
+
+/// Parse a day and month, then a year if the next word is one, returning how many of `day`, `month` and the year it took.
+fn day_month_year(day: &str, month: &str, rest: &[&str], today: NaiveDate) -> Option<(DateSpec, usize)> {
+    let year: &[&str] = match rest {
+        [year, ..] if year.len() == 4 && year.parse::<i32>().is_ok() => &rest[..1],
+        _ => &[]
+    };
+    let date = parse_day_month_year(day, month, year, today)?;
+    Some((DateSpec::Date(date), 1 + year.len()))
+}
+
+/// A time as written, which may not say whether it's am or pm.
+#[derive(Clone, Copy)]
+struct WrittenTime {
+    hour: u32,
+    minute: u32,
+    /// Whether it's pm, if it says (or it's a 24-hour time like "15:00").
+    pm: Option<bool>
+}
+
+impl WrittenTime {
+    fn to_time(self, pm: bool) -> Option<NaiveTime> {
+        let hour = match (self.hour, pm) {
+            (hour, _) if hour > 12 || hour == 0 => hour,
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, true) => hour + 12,
+            (hour, false) => hour
+        };
+        NaiveTime::from_hms_opt(hour, self.minute, 0)
+    }
+
+    /// The time, if it's unambiguous.
+    fn resolve(self) -> Option<NaiveTime> {
+        self.to_time(self.pm?)
+    }
+}
+
+/// Parse a time or time range, like "at 3pm", "3:30 pm", "noon", "15:00", "from 3 to 4pm" or "10am-12".
+///
+/// A time without am or pm (e.g. "at 3") is ambiguous, so is only parsed as the start of a range whose end has one.
+fn parse_time_range(words: &[&str]) -> Option<(NaiveTime, Option<NaiveTime>, usize)> {
+    if let ["at" | "@" | "from", rest @ ..] = words {
+        return parse_time_range(rest).map(|(start, end, len)| (start, end, len + 1));
+    }
+    // "3-4pm", as one word
+    let first = words.first()?;
+    if let Some((start, end)) = first.split_once(['-', '–'])
+        && !start.is_empty()
+        && !end.is_empty()
+    {
+        let (start, end) = resolve_range(parse_time_word(start)?, parse_time_word(end)?)?;
+        return Some((start, end, 1));
+    }
+
+    let (start, mut len) = parse_time(words)?;
+    if let Some(["-" | "–" | "to" | "until" | "till", rest @ ..]) = words.get(len..)
+        && let Some((end, end_len)) = parse_time(rest)
+    {
+        let (start, end) = resolve_range(start, end)?;
+        len += 1 + end_len;
+        return Some((start, end, len));
+    }
+    Some((start.resolve()?, None, len))
+}
+
+/// Resolve a range's times, where either one can take am or pm from the other (e.g. "11-1pm" is 11am to 1pm).
+fn resolve_range(start: WrittenTime, end: WrittenTime) -> Option<(NaiveTime, Option<NaiveTime>)> {
+    let (start, end) = match (start.pm, end.pm) {
+        (Some(_), Some(_)) => (start.resolve()?, end.resolve()?),
+        (None, Some(end_pm)) => {
+            let end = end.resolve()?;
+            // take the end's am or pm, unless that puts the start after the end
+            let start = start.to_time(end_pm).filter(|&start| start < end).or_else(|| start.to_time(!end_pm))?;
+            (start, end)
+        },
+        (Some(_), None) => {
+            let start = start.resolve()?;
+            let end = [false, true]
+                .into_iter()
+                .filter_map(|pm| end.to_time(pm))
+                .find(|&end| end > start)?;
+            (start, end)
+        },
+        (None, None) => return None
+    };
+    Some((start, Some(end)))
+}
+
+/// Parse a single time at the start of `words`, which may be split over two (as in "3 pm").
+fn parse_time(words: &[&str]) -> Option<(WrittenTime, usize)> {
+    let first = words.first()?;
+    match words.get(1).copied().and_then(parse_meridiem) {
+        Some(pm) if parse_meridiem(first).is_none() => {
+            let time = parse_time_word(first).filter(|time| time.pm.is_none() && time.hour <= 12)?;
+            Some((WrittenTime { pm: Some(pm), ..time }, 2))
+        },
+        _ => parse_time_word(first).map(|time| (time, 1))
+    }
+}
+
+/// Parse a time in a single word, like "3pm", "3:30", "15.00" or "noon".
+fn parse_time_word(word: &str) -> Option<WrittenTime> {
+    match word {
+        "noon" | "midday" => return Some(WrittenTime { hour: 12, minute: 0, pm: Some(true) }),
+        "midnight" => return Some(WrittenTime { hour: 0, minute: 0, pm: Some(false) }),
+        _ => {}
+    }
+    let (digits, pm) = ["am", "a.m", "a.m."]
+        .iter()
+        .find_map(|suffix| word.strip_suffix(suffix).map(|digits| (digits, Some(false))))
+        .or_else(|| ["pm", "p.m", "p.m."].iter().find_map(|suffix| word.strip_suffix(suffix).map(|digits| (digits, Some(true)))))
+        .unwrap_or((word, None));
+    let (hour, minute) = match digits.split_once([':', '.']) {
+        Some((hour, minute)) if minute.len() == 2 => (hour, minute.parse().ok()?),
+        Some(_) => return None,
+        None => (digits, 0)
+    };
+    if hour.is_empty() || hour.len() > 2 || !hour.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hour: u32 = hour.parse().ok()?;
+    let has_minutes = digits.contains([':', '.']);
+    let pm = match pm {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(pm) => Some(pm),
+        // only a 24-hour time is unambiguous without am or pm, e.g. "15:00" or "09:30"
+        None if has_minutes && (hour > 12 || hour == 0 || digits.starts_with('0')) => Some(hour >= 12),
+        None => None
+    };
+    (hour < 24 && minute < 60).then_some(WrittenTime { hour, minute, pm })
+}
+
+fn parse_meridiem(word: &str) -> Option<bool> {
+    match word {
+        "am" | "a.m" | "a.m." => Some(false),
+        "pm" | "p.m" | "p.m." => Some(true),
+        _ => None
+    }
+}
+
+/// Parse a duration, like "for 45 min", "for an hour", "for 1.5 hours", "90m" or "for 1h30".
+fn parse_duration(words: &[&str]) -> Option<(Duration, usize)> {
+    if let ["for", rest @ ..] = words {
+        return parse_duration(rest).map(|(duration, len)| (duration, len + 1));
+    }
+    let (minutes, len) = match words {
+        ["half", "an", "hour", ..] => (30.0, 3),
+        ["an" | "a" | "one", "hour", "and", "a", "half", ..] => (90.0, 5),
+        [amount, unit, ..] if unit_minutes(unit).is_some() => {
+            let amount = match *amount {
+                "a" | "an" => 1.0,
+                amount => parse_number(amount).map(f64::from).or_else(|| amount.parse().ok())?
+            };
+            (amount * unit_minutes(unit)?, 2)
+        },
+        [compact, ..] => (parse_compact_duration(compact)?, 1),
+        [] => return None
+    };
+    let minutes = minutes.round() as i64;
+    (1..=24 * 60).contains(&minutes).then(|| (Duration::minutes(minutes), len))
+}
+
+/// Parse a duration written as one word, like "45min", "1.5h" or "1h30".
+fn parse_compact_duration(word: &str) -> Option<f64> {
+    let unit_start = word.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (amount, rest) = word.split_at(unit_start);
+    let amount: f64 = amount.parse().ok()?;
+    let unit_end = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+    let (unit, minutes) = rest.split_at(unit_end);
+    let unit_minutes = unit_minutes(unit)?;
+    if minutes.is_empty() {
+        return Some(amount * unit_minutes);
+    }
+    // "1h30" or "1h30m"
+    if !unit.starts_with('h') {
+        return None;
+    }
+    let minutes: f64 = minutes.strip_suffix("min").or_else(|| minutes.strip_suffix('m')).unwrap_or(minutes).parse().ok()?;
+    Some(amount * 60.0 + minutes)
+}
+
+/// How many minutes a unit of duration is.
+fn unit_minutes(unit: &str) -> Option<f64> {
+    match unit {
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(1.0),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(60.0),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::recurrence_phrase::RecurrenceEnd;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /// Monday 19 October 2026, 10am.
+    fn now() -> NaiveDateTime {
+        at(2026, 10, 19, 10, 0)
+    }
+
+    fn parse(text: &str) -> QuickAddEvent {
+        parse_quick_add(text, now()).unwrap_or_else(|| panic!("`{text}` wasn't parsed"))
+    }
+
+    #[test]
+    fn title_date_time_duration_and_location() {
+        let event = parse("Dentist tomorrow 3pm for 45 min at Main St");
+        assert_eq!(event.title, "Dentist");
+        assert_eq!(event.location.as_deref(), Some("Main St"));
+        assert_eq!(event.start, at(2026, 10, 20, 15, 0));
+        assert_eq!(event.end, at(2026, 10, 20, 15, 45));
+        assert!(event.recurrence.is_none());
+
+        // the location can come before the time, and keeps its case
+        let event = parse("Coffee with Sam @ The Old Bakery, friday at 11am");
+        assert_eq!(event.title, "Coffee with Sam");
+        assert_eq!(event.location.as_deref(), Some("The Old Bakery"));
+        assert_eq!(event.start, at(2026, 10, 23, 11, 0));
+        assert_eq!(event.end, at(2026, 10, 23, 12, 0));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse("Call mum in 3 days at 6pm").start, at(2026, 10, 22, 18, 0));
+        assert_eq!(parse("Review the day after tomorrow 9am").start, at(2026, 10, 21, 9, 0));
+        assert_eq!(parse("Party on 2026-11-02 at 8pm").start, at(2026, 11, 2, 20, 0));
+        assert_eq!(parse("Flight the 3rd of december 6am").start, at(2026, 12, 3, 6, 0));
+        // a day already passed this year is next year's
+        assert_eq!(parse("Barbecue 5 june 1pm").start, at(2027, 6, 5, 13, 0));
+        // a weekday is today if the time hasn't passed yet, or next week's otherwise
+        assert_eq!(parse("Standup monday 10:30am").start, at(2026, 10, 19, 10, 30));
+        assert_eq!(parse("Standup this monday 9am").start, at(2026, 10, 26, 9, 0));
+    }
+
+    #[test]
+    fn times_and_ranges() {
+        let event = parse("Lunch friday 12:30-1:30pm");
+        assert_eq!(event.start, at(2026, 10, 23, 12, 30));
+        assert_eq!(event.end, at(2026, 10, 23, 13, 30));
+
+        let event = parse("Workshop tomorrow from 11 to 1pm");
+        assert_eq!(event.start, at(2026, 10, 20, 11, 0));
+        assert_eq!(event.end, at(2026, 10, 20, 13, 0));
+
+        // ending after midnight
+        let event = parse("Party tomorrow 10pm-1am");
+        assert_eq!(event.end, at(2026, 10, 21, 1, 0));
+
+        assert_eq!(parse("Gym tomorrow 07:15 for 1h30").end, at(2026, 10, 20, 8, 45));
+        assert_eq!(parse("Planning tomorrow noon for half an hour").end, at(2026, 10, 20, 12, 30));
+        // without a date, a time still to come today
+        assert_eq!(parse("Call the bank 4 pm").start, at(2026, 10, 19, 16, 0));
+    }
+
+    #[test]
+    fn recurrences() {
+        let event = parse("Gym every monday and thursday 7am");
+        assert_eq!(event.title, "Gym");
+        // 7am today has passed, so it starts on thursday
+        assert_eq!(event.start, at(2026, 10, 22, 7, 0));
+        let recurrence = event.recurrence.unwrap();
+        assert_eq!(recurrence.rule.get_by_weekday().len(), 2);
+        assert_eq!(recurrence.end, RecurrenceEnd::Never);
+
+        let event = parse("Book club every other Tuesday until the end of June 7pm");
+        assert_eq!(event.start, at(2026, 10, 20, 19, 0));
+        let recurrence = event.recurrence.unwrap();
+        assert_eq!(recurrence.rule.get_interval(), 2);
+        assert_eq!(recurrence.end, RecurrenceEnd::Until(NaiveDate::from_ymd_opt(2027, 6, 30).unwrap()));
+    }
+
+    #[test]
+    fn falls_through_to_the_llm() {
+        for text in [
+            "",
+            // no time, or one that could be morning or afternoon
+            "Dentist tomorrow",
+            "Meeting tomorrow at 3",
+            // "next friday" could be this week's or next week's
+            "Lunch next friday 1pm",
+            // numeric dates could be either way round
+            "Dinner 3/6 7pm",
+            // likely several events
+            "Team sync tomorrow 10am\nRetro tomorrow 4pm",
+            "Team sync tomorrow 10am; retro 4pm",
+            // already passed today
+            "Breakfast 8am",
+            // given twice, or conflicting
+            "Lunch tomorrow friday 1pm",
+            "Lunch tomorrow 12-1pm for 2 hours",
+            "Coffee at home tomorrow 9am at work",
+            // exceptions need the LLM to create them
+            "Yoga every wednesday except school holidays 6pm",
+            // a recurrence we can't find the first instance of
+            "Board meeting the first monday of every month 9am",
+            // no title
+            "tomorrow 3pm",
+            // too long to be a single simple event
+            "Dinner tomorrow 7pm with everyone who's around, and then a movie afterwards if the weather is good enough for us to walk there and back"
+        ] {
+            assert!(parse_quick_add(text, now()).is_none(), "`{text}` was parsed");
+        }
+    }
+}
@@ -0,0 +1,519 @@
+//! A deterministic parser for common ways of describing a recurrence in English, like "every other Tuesday and
+//! Thursday until the end of June" or "the first Monday of every month for 6 months".
+//!
+//! Anything it doesn't fully understand is left for the LLM.
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+use rrule::{Frequency, NWeekday, RRule, Unvalidated, Weekday};
+
+/// Words which start a clause ending the recurrence.
+const END_MARKERS: [&str; 7] = ["until", "till", "til", "through", "thru", "ending", "for"];
+
+/// Words which start a clause of exceptions, which can't be expressed in the rule itself.
+const EXCEPTION_MARKERS: [&str; 4] = ["except", "excluding", "skipping", "but"];
+
+/// A recurrence parsed from a phrase, in the user's local terms.
+#[derive(Debug, Clone)]
+pub struct ParsedRecurrence {
+    /// The rule, without an end.
+    pub rule: RRule<Unvalidated>,
+    pub end: RecurrenceEnd,
+    /// Parts of the phrase which couldn't be expressed in the rule.
+    pub notes: Vec<String>
+}
+
+/// How a recurrence ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecurrenceEnd {
+    Never,
+    /// After the given (local) date.
+    Until(NaiveDate),
+    /// After this many instances.
+    Count(u32)
+}
+
+/// Parse a recurrence phrase, given the (local) date it starts on, returning `None` if any of it isn't understood.
+pub fn parse_recurrence_phrase(phrase: &str, start: NaiveDate) -> Option<ParsedRecurrence> {
+    let normalized = phrase
+        .to_lowercase()
+        .replace([',', '/', ';', '.'], " ")
+        .replace('&', " and ");
+    let words: Vec<&str> = normalized.split_whitespace().collect();
+
+    // split the phrase into its core, then any end and exception clauses (in either order)
+    let clause_starts: Vec<usize> = words
+        .iter()
+        .enumerate()
+        .filter(|(i, word)| *i > 0 && (END_MARKERS.contains(word) || EXCEPTION_MARKERS.contains(word)))
+        .map(|(i, _)| i)
+        .collect();
+    let core_end = clause_starts.first().copied().unwrap_or(words.len());
+
+    let mut end = RecurrenceEnd::Never;
+    let mut notes = Vec::new();
+    let mut core = &words[..core_end];
+    if let [rest @ .., count, "times"] = core {
+        end = RecurrenceEnd::Count(parse_number(count)?);
+        core = rest;
+    }
+    for (i, &clause_start) in clause_starts.iter().enumerate() {
+        let clause = &words[clause_start..clause_starts.get(i + 1).copied().unwrap_or(words.len())];
+        if EXCEPTION_MARKERS.contains(&clause[0]) {
+            notes.push(format!(
+                "\"{}\" can't be part of a recurrence rule; cancel those instances once the event is created",
+                clause.join(" ")
+            ));
+        } else if end == RecurrenceEnd::Never {
+            end = parse_end(clause, start)?;
+        } else {
+            return None;
+        }
+    }
+
+    let mut tokens = Tokens { words: core, pos: 0 };
+    let rule = parse_rule(&mut tokens)?;
+    tokens.is_done().then_some(ParsedRecurrence { rule, end, notes })
+}
+
+/// A cursor over the words of a phrase.
+#[derive(Clone, Copy)]
+struct Tokens<'a> {
+    words: &'a [&'a str],
+    pos: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.peek();
+        self.pos += 1;
+        word
+    }
+
+    /// Skip the next word if it's one of `words`, returning whether it was.
+    fn eat(&mut self, words: &[&str]) -> bool {
+        let matches = self.peek().is_some_and(|word| words.contains(&word));
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.words.len()
+    }
+}
+
+fn parse_rule(tokens: &mut Tokens) -> Option<RRule<Unvalidated>> {
+    tokens.eat(&["repeats", "repeating", "recurring"]);
+    tokens.eat(&["on"]);
+
+    // "the first monday of every month", "the 1st and 15th of each month"
+    if tokens.eat(&["the"]) {
+        let positions = parse_month_positions(tokens)?;
+        if !tokens.eat(&["of"]) {
+            return None;
+        }
+        tokens.eat(&["the", "every", "each"]);
+        let interval = parse_interval(tokens).unwrap_or(1);
+        if !tokens.eat(&["month", "months"]) {
+            return None;
+        }
+        return Some(positions.apply(RRule::new(Frequency::Monthly).interval(interval)));
+    }
+
+    let rule = match tokens.next()? {
+        "daily" => RRule::new(Frequency::Daily),
+        "weekly" => RRule::new(Frequency::Weekly),
+        "fortnightly" | "biweekly" => RRule::new(Frequency::Weekly).interval(2),
+        "monthly" => RRule::new(Frequency::Monthly),
+        "yearly" | "annually" => RRule::new(Frequency::Yearly),
+        "weekdays" => RRule::new(Frequency::Weekly).by_weekday(every_weekday()),
+        "weekends" => RRule::new(Frequency::Weekly).by_weekday(every_weekend()),
+        "every" | "each" => {
+            // "every first monday of the month"
+            let mut lookahead = *tokens;
+            if let Some(positions) = parse_month_positions(&mut lookahead)
+                && lookahead.eat(&["of"])
+                && lookahead.eat(&["the", "every", "each"])
+                && lookahead.eat(&["month"])
+            {
+                *tokens = lookahead;
+                return Some(positions.apply(RRule::new(Frequency::Monthly)));
+            }
+            let interval = parse_interval(tokens).unwrap_or(1);
+            let rule = match tokens.peek()? {
+                "day" | "days" => RRule::new(Frequency::Daily),
+                "week" | "weeks" => RRule::new(Frequency::Weekly),
+                "month" | "months" => RRule::new(Frequency::Monthly),
+                "year" | "years" => RRule::new(Frequency::Yearly),
+                "weekday" | "weekdays" => RRule::new(Frequency::Weekly).by_weekday(every_weekday()),
+                "weekend" | "weekends" => RRule::new(Frequency::Weekly).by_weekday(every_weekend()),
+                _ => {
+                    let weekdays = parse_weekdays(tokens)?;
+                    return Some(RRule::new(Frequency::Weekly).interval(interval).by_weekday(weekdays));
+                }
+            };
+            tokens.next();
+            rule.interval(interval)
+        },
+        _ => return None
+    };
+
+    // "every week on monday and wednesday", "every month on the 15th"
+    if !tokens.eat(&["on"]) {
+        return Some(rule);
+    }
+    match rule.get_freq() {
+        Frequency::Weekly => Some(rule.by_weekday(parse_weekdays(tokens)?)),
+        Frequency::Monthly => {
+            tokens.eat(&["the"]);
+            let positions = parse_month_positions(tokens)?;
+            if tokens.eat(&["of"]) {
+                tokens.eat(&["the", "each", "every"]);
+                if !tokens.eat(&["month"]) {
+                    return None;
+                }
+            }
+            Some(positions.apply(rule))
+        },
+        _ => None
+    }
+}
+
+/// Days of the month, like "the 15th" or "the last friday".
+enum MonthPositions {
+    Days(Vec<i8>),
+    Weekdays(Vec<NWeekday>)
+}
+
+impl MonthPositions {
+    fn apply(self, rule: RRule<Unvalidated>) -> RRule<Unvalidated> {
+        match self {
+            Self::Days(days) => rule.by_month_day(days),
+            Self::Weekdays(weekdays) => rule.by_weekday(weekdays)
+        }
+    }
+}
+
+/// Parse a list of (e.g.) "1st", "15th", "last day", or "first and third monday", which must all be of the same kind.
+fn parse_month_positions(tokens: &mut Tokens) -> Option<MonthPositions> {
+    let mut days = Vec::new();
+    let mut weekdays = Vec::new();
+    // ordinals waiting for the weekday (or "day") they're of, as in "the first and third monday"
+    let mut pending = Vec::new();
+    loop {
+        pending.push(parse_ordinal(tokens.next()?)?);
+        match tokens.peek() {
+            Some("day" | "days") => {
+                tokens.next();
+                days.append(&mut pending);
+            },
+            Some(word) => if let Some(weekday) = parse_weekday(word) {
+                // there's no such thing as (e.g.) "the 15th friday"
+                if pending.iter().any(|&n| n > 5) {
+                    return None;
+                }
+                tokens.next();
+                weekdays.extend(pending.drain(..).map(|n| NWeekday::Nth(n.into(), weekday)));
+            },
+            None => {}
+        }
+        if !tokens.eat(&["and"]) {
+            break;
+        }
+    }
+    // any left are days of the month, though a bare "last" is ambiguous
+    if pending.contains(&-1) {
+        return None;
+    }
+    days.append(&mut pending);
+    match (days.is_empty(), weekdays.is_empty()) {
+        (false, true) => Some(MonthPositions::Days(days)),
+        (true, false) => Some(MonthPositions::Weekdays(weekdays)),
+        _ => None
+    }
+}
+
+/// Parse an interval, like the "other" in "every other week" or the "3" in "every 3 days".
+fn parse_interval(tokens: &mut Tokens) -> Option<u16> {
+    let word = tokens.peek()?;
+    let interval = match word {
+        "other" => 2,
+        _ => parse_number(word).or_else(|| parse_ordinal(word).filter(|&n| n > 1).map(|n| n as u32))?
+    };
+    let interval = u16::try_from(interval).ok().filter(|&interval| interval > 0)?;
+    tokens.next();
+    Some(interval)
+}
+
+/// Parse a list of weekdays, like "tuesday and thursday" or "mon wed fri".
+fn parse_weekdays(tokens: &mut Tokens) -> Option<Vec<NWeekday>> {
+    let mut weekdays = vec![NWeekday::Every(parse_weekday(tokens.next()?)?)];
+    loop {
+        let mut lookahead = *tokens;
+        lookahead.eat(&["and"]);
+        match lookahead.peek().and_then(parse_weekday) {
+            Some(weekday) => {
+                lookahead.next();
+                weekdays.push(NWeekday::Every(weekday));
+                *tokens = lookahead;
+            },
+            None => break
+        }
+    }
+    Some(weekdays)
+}
+
+pub fn parse_weekday(word: &str) -> Option<Weekday> {
+    let weekday = match word.strip_suffix('s').filter(|word| word.len() > 2).unwrap_or(word) {
+        "monday" | "mon" => Weekday::Mon,
+        "tuesday" | "tue" | "tues" => Weekday::Tue,
+        "wednesday" | "wed" | "weds" => Weekday::Wed,
+        "thursday" | "thu" | "thur" | "thurs" => Weekday::Thu,
+        "friday" | "fri" => Weekday::Fri,
+        "saturday" | "sat" => Weekday::Sat,
+        "sunday" | "sun" => Weekday::Sun,
+        _ => return None
+    };
+    Some(weekday)
+}
+
+fn every_weekday() -> Vec<NWeekday> {
+    [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri].map(NWeekday::Every).to_vec()
+}
+
+fn every_weekend() -> Vec<NWeekday> {
+    [Weekday::Sat, Weekday::Sun].map(NWeekday::Every).to_vec()
+}
+
+/// Parse a (small) number, as digits or a word.
+pub fn parse_number(word: &str) -> Option<u32> {
+    const WORDS: [&str; 12] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven", "twelve"
+    ];
+    match WORDS.iter().position(|&number| number == word) {
+        Some(i) => Some(i as u32 + 1),
+        None => word.parse().ok()
+    }
+}
+
+/// Parse an ordinal, like "first", "2nd" or "last" (as -1).
+fn parse_ordinal(word: &str) -> Option<i8> {
+    const WORDS: [&str; 5] = ["first", "second", "third", "fourth", "fifth"];
+    if word == "last" {
+        return Some(-1);
+    }
+    if let Some(i) = WORDS.iter().position(|&ordinal| ordinal == word) {
+        return Some(i as i8 + 1);
+    }
+    let digits = ["st", "nd", "rd", "th"].iter().find_map(|suffix| word.strip_suffix(suffix))?;
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+/// Parse a clause ending the recurrence, like "until the end of june" or "for 6 weeks".
+fn parse_end(clause: &[&str], start: NaiveDate) -> Option<RecurrenceEnd> {
+    match clause {
+        ["for", count, "times" | "occurrences" | "sessions"] => Some(RecurrenceEnd::Count(parse_number(count)?)),
+        ["for", count, unit] => {
+            let count = parse_number(count)?;
+            let end = match *unit {
+                "day" | "days" => start.checked_add_days(Days::new(count.into()))?,
+                "week" | "weeks" => start.checked_add_days(Days::new(u64::from(count) * 7))?,
+                "month" | "months" => start.checked_add_months(Months::new(count))?,
+                "year" | "years" => start.checked_add_months(Months::new(count * 12))?,
+                _ => return None
+            };
+            // "for a week" from a monday means up to (and including) the sunday
+            Some(RecurrenceEnd::Until(end.pred_opt()?))
+        },
+        ["for", ..] => None,
+        [_marker, rest @ ..] => {
+            let rest = match rest {
+                ["on", rest @ ..] => rest,
+                _ => rest
+            };
+            parse_end_date(rest, start).map(RecurrenceEnd::Until)
+        },
+        [] => None
+    }
+}
+
+/// Parse a date (on or after `start`), like "the end of june", "30 june 2027", "june 30th" or "2027-06-30".
+fn parse_end_date(words: &[&str], start: NaiveDate) -> Option<NaiveDate> {
+    let words = match words {
+        ["the", rest @ ..] => rest,
+        _ => words
+    };
+    match words {
+        [iso] if iso.contains('-') => NaiveDate::parse_from_str(iso, "%Y-%m-%d").ok(),
+        ["end", "of", "the", "month"] | ["end", "of", "this", "month"] => last_day_of_month(start.year(), start.month()),
+        ["end", "of", "the", "year"] | ["end", "of", "this", "year"] => NaiveDate::from_ymd_opt(start.year(), 12, 31),
+        ["end", "of", month, year @ ..] => {
+            let month = parse_month(month)?;
+            let year = match year {
+                [] => next_year_for(start, month, last_day_of_month(start.year(), month)?.day()),
+                [year] => year.parse().ok()?,
+                _ => return None
+            };
+            last_day_of_month(year, month)
+        },
+        [day, "of", month, year @ ..] => parse_day_month_year(day, month, year, start),
+        [month, day, year @ ..] if parse_month(month).is_some() => parse_day_month_year(day, month, year, start),
+        [day, month, year @ ..] => parse_day_month_year(day, month, year, start),
+        _ => None
+    }
+}
+
+/// Parse a date given as its parts; without a year, it's the first such date on or after `start`.
+pub fn parse_day_month_year(day: &str, month: &str, year: &[&str], start: NaiveDate) -> Option<NaiveDate> {
+    let month = parse_month(month)?;
+    let day = parse_day(day)?;
+    let year = match year {
+        [] => next_year_for(start, month, day),
+        [year] => year.parse().ok()?,
+        _ => return None
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+/// The year in which the given month and day is first on or after `start`.
+fn next_year_for(start: NaiveDate, month: u32, day: u32) -> i32 {
+    if (month, day) >= (start.month(), start.day()) { start.year() } else { start.year() + 1 }
+}
+
+fn last_day_of_month(year: i32, month: u32) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(year, month, 1)?
+        .checked_add_months(Months::new(1))?
+        .pred_opt()
+}
+
+/// Parse a day of the month, like "30" or "30th".
+fn parse_day(word: &str) -> Option<u32> {
+    let day = word.parse().ok().or_else(|| parse_ordinal(word).filter(|&day| day > 0).map(|day| day as u32))?;
+    (1..=31).contains(&day).then_some(day)
+}
+
+pub fn parse_month(word: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "january", "february", "march", "april", "may", "june",
+        "july", "august", "september", "october", "november", "december"
+    ];
+    MONTHS
+        .iter()
+        .position(|&month| month == word || (word.len() >= 3 && month.starts_with(word)))
+        .map(|i| i as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Tuesday 20 October 2026.
+    fn start() -> NaiveDate {
+        date(2026, 10, 20)
+    }
+
+    fn parse(phrase: &str) -> ParsedRecurrence {
+        parse_recurrence_phrase(phrase, start()).unwrap_or_else(|| panic!("`{phrase}` wasn't parsed"))
+    }
+
+    #[test]
+    fn every_other_weekday_until_end_of_month() {
+        let recurrence = parse("every other Tuesday until the end of June");
+        assert_eq!(recurrence.rule.get_freq(), Frequency::Weekly);
+        assert_eq!(recurrence.rule.get_interval(), 2);
+        assert_eq!(recurrence.rule.get_by_weekday(), [NWeekday::Every(Weekday::Tue)]);
+        assert_eq!(recurrence.end, RecurrenceEnd::Until(date(2027, 6, 30)));
+        assert!(recurrence.notes.is_empty());
+    }
+
+    #[test]
+    fn several_weekdays_until_date() {
+        let recurrence = parse("every other tuesday & thursday until 3rd december");
+        assert_eq!(recurrence.rule.get_interval(), 2);
+        assert_eq!(
+            recurrence.rule.get_by_weekday(),
+            [NWeekday::Every(Weekday::Tue), NWeekday::Every(Weekday::Thu)]
+        );
+        assert_eq!(recurrence.end, RecurrenceEnd::Until(date(2026, 12, 3)));
+
+        // a date already passed this year is next year's
+        assert_eq!(parse("weekly until june 30th").end, RecurrenceEnd::Until(date(2027, 6, 30)));
+        assert_eq!(parse("daily through 2026-11-01").end, RecurrenceEnd::Until(date(2026, 11, 1)));
+    }
+
+    #[test]
+    fn monthly_positions() {
+        let recurrence = parse("the first Monday of every month for 6 months");
+        assert_eq!(recurrence.rule.get_freq(), Frequency::Monthly);
+        assert_eq!(recurrence.rule.get_by_weekday(), [NWeekday::Nth(1, Weekday::Mon)]);
+        // up to the day before six months on
+        assert_eq!(recurrence.end, RecurrenceEnd::Until(date(2027, 4, 19)));
+
+        let recurrence = parse("every month on the 1st and 15th");
+        assert_eq!(recurrence.rule.get_by_month_day(), [1, 15]);
+
+        let recurrence = parse("every last friday of the month");
+        assert_eq!(recurrence.rule.get_by_weekday(), [NWeekday::Nth(-1, Weekday::Fri)]);
+    }
+
+    #[test]
+    fn counts_and_intervals() {
+        let recurrence = parse("weekdays 10 times");
+        assert_eq!(recurrence.rule.get_by_weekday().len(), 5);
+        assert_eq!(recurrence.end, RecurrenceEnd::Count(10));
+
+        let recurrence = parse("every 3 days for five sessions");
+        assert_eq!(recurrence.rule.get_freq(), Frequency::Daily);
+        assert_eq!(recurrence.rule.get_interval(), 3);
+        assert_eq!(recurrence.end, RecurrenceEnd::Count(5));
+
+        assert_eq!(parse("fortnightly").rule.get_interval(), 2);
+        assert_eq!(parse("annually").end, RecurrenceEnd::Never);
+    }
+
+    #[test]
+    fn exceptions_become_notes() {
+        let recurrence = parse("every wednesday except school holidays");
+        assert_eq!(recurrence.rule.get_by_weekday(), [NWeekday::Every(Weekday::Wed)]);
+        assert_eq!(recurrence.notes.len(), 1);
+        assert!(recurrence.notes[0].contains("except school holidays"));
+    }
+
+    #[test]
+    fn falls_through_on_anything_unknown() {
+        for phrase in [
+            "",
+            "every blue moon",
+            "every tuesday until whenever",
+            "every day until june 30 for 3 weeks",
+            // "last" on its own could be the last day or the last weekday
+            "the last of each month",
+            "every month on the 15th and last friday",
+            "every tuesday at the office",
+            "every 0 days"
+        ] {
+            assert!(parse_recurrence_phrase(phrase, start()).is_none(), "`{phrase}` was parsed");
+        }
+    }
+
+    #[test]
+    fn parses_words() {
+        assert_eq!(parse_weekday("thurs"), Some(Weekday::Thu));
+        assert_eq!(parse_weekday("mondays"), Some(Weekday::Mon));
+        assert_eq!(parse_weekday("mo"), None);
+        assert_eq!(parse_month("sept"), Some(9));
+        assert_eq!(parse_month("ma"), None);
+        assert_eq!(parse_number("twelve"), Some(12));
+        assert_eq!(parse_day_month_year("31st", "feb", &[], start()), None);
+        assert_eq!(parse_day_month_year("29", "february", &["2028"], start()), Some(date(2028, 2, 29)));
+    }
+}